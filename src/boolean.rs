@@ -0,0 +1,75 @@
+//! Recognition of booleans in bare words.
+//!
+//! Khi has no boolean type: `true` is text. A [BoolFormat] decides which
+//! words are booleans, like [NumberFormat](crate::number::NumberFormat) does
+//! for numbers, and is shared by the deserializer and the decoding helpers.
+//! The [TryFrom] conversions of [ParsedValue] use the default format.
+//! Booleans are always written as `true` and `false`.
+
+use crate::pdm::{ConversionError, ParsedValue};
+
+/// Words of booleans.
+///
+/// `true` and `false` are always booleans. The options allow more words.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BoolFormat {
+    /// Allow `yes` and `no`.
+    pub yes_no: bool,
+    /// Allow `on` and `off`.
+    pub on_off: bool,
+    /// Allow `1` and `0`.
+    pub digits: bool,
+    /// Allow words in any case, like `True` and `NO`.
+    pub any_case: bool,
+}
+
+impl BoolFormat {
+    /// `true` and `false` only.
+    pub const STRICT: BoolFormat = BoolFormat { yes_no: false, on_off: false, digits: false, any_case: false };
+
+    /// Every word that can be a boolean.
+    pub const LENIENT: BoolFormat = BoolFormat { yes_no: true, on_off: true, digits: true, any_case: true };
+
+    /// Parse text as a boolean.
+    pub fn parse(&self, text: &str) -> Option<bool> {
+        let lower;
+        let text = if self.any_case {
+            lower = text.to_ascii_lowercase();
+            &lower
+        } else {
+            text
+        };
+        match text {
+            "true" => Some(true),
+            "false" => Some(false),
+            "yes" if self.yes_no => Some(true),
+            "no" if self.yes_no => Some(false),
+            "on" if self.on_off => Some(true),
+            "off" if self.on_off => Some(false),
+            "1" if self.digits => Some(true),
+            "0" if self.digits => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Check if text is a boolean.
+    pub fn is_bool(&self, text: &str) -> bool {
+        self.parse(text).is_some()
+    }
+}
+
+impl Default for BoolFormat {
+    fn default() -> Self {
+        BoolFormat::STRICT
+    }
+}
+
+impl ParsedValue {
+    /// Convert text to a boolean in a format.
+    pub fn to_bool_with(&self, format: &BoolFormat) -> Result<bool, ConversionError> {
+        match self {
+            ParsedValue::Text(text, ..) => format.parse(&text.str).ok_or_else(|| ConversionError::Invalid("bool", self.from(), text.str.to_string())),
+            _ => Err(ConversionError::Expected("text", self.from())),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Configuration loading and hot reloading.
+//!
+//! A configuration is a dictionary document that is converted to a typed
+//! configuration with [TryFrom]. The conversion acts as the schema: a document
+//! is valid if it converts without error.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use crate::parse::{parse_dictionary_str_with, ParseOptions};
+use crate::parse::parser::ParseError;
+use crate::pdm::{ConversionError, ParsedValue, Position};
+
+/// Load a typed configuration from a file.
+pub fn load<T>(path: impl AsRef<Path>) -> Result<T, ConfigError> where for<'a> T: TryFrom<&'a ParsedValue, Error = ConversionError> {
+    load_with(path, &ParseOptions::default())
+}
+
+/// Load a typed configuration from a file with parser options.
+pub fn load_with<T>(path: impl AsRef<Path>, options: &ParseOptions) -> Result<T, ConfigError> where for<'a> T: TryFrom<&'a ParsedValue, Error = ConversionError> {
+    let source = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+    let dictionary = parse_dictionary_str_with(&source, options).map_err(ConfigError::Parse)?;
+    let at = Position { index: 0, line: 1, column: 1 };
+    let document = ParsedValue::Dictionary(dictionary, at, at);
+    T::try_from(&document).map_err(ConfigError::Conversion)
+}
+
+/// Watch a configuration file.
+///
+/// Loads the configuration, and loads it again whenever the file changes. The
+/// callback is only invoked with valid configurations; invalid configurations
+/// are ignored. Watching stops when the returned watcher is dropped.
+pub fn watch<T, F>(path: impl AsRef<Path>, callback: F) -> Result<ConfigWatcher, notify::Error>
+where for<'a> T: TryFrom<&'a ParsedValue, Error = ConversionError>, F: FnMut(T) + Send + 'static {
+    watch_with(path, WatchOptions::default(), callback, |_| {})
+}
+
+/// Watch a configuration file with options.
+///
+/// Like [watch], but errors from loading invalid configurations are passed to
+/// a second callback.
+pub fn watch_with<T, F, E>(path: impl AsRef<Path>, options: WatchOptions, mut callback: F, mut errors: E) -> Result<ConfigWatcher, notify::Error>
+where for<'a> T: TryFrom<&'a ParsedValue, Error = ConversionError>, F: FnMut(T) + Send + 'static, E: FnMut(ConfigError) + Send + 'static {
+    let path = absolute(path.as_ref());
+    // Watch the directory, since editors often replace the file when saving.
+    let directory = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    let file = watched_file(&path);
+    let debounce = options.debounce;
+    let mut reload = move || match load_with(&path, &options.parse) {
+        Ok(config) => callback(config),
+        Err(error) => errors(error),
+    };
+    std::thread::spawn(move || {
+        reload();
+        let is_change = |event: &notify::Result<notify::Event>| match event {
+            Ok(event) => !event.kind.is_access() && event.paths.iter().any(|p| watched_file(p) == file),
+            Err(_) => false,
+        };
+        // Ends when the watcher is dropped and the channel disconnects.
+        while let Ok(event) = receiver.recv() {
+            if !is_change(&event) {
+                continue;
+            };
+            loop {
+                match receiver.recv_timeout(debounce) {
+                    Ok(_) => continue,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            };
+            reload();
+        };
+    });
+    Ok(ConfigWatcher { _watcher: watcher })
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().map(|d| d.join(path)).unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+fn watched_file(path: &Path) -> Option<std::ffi::OsString> {
+    path.file_name().map(|name| name.to_os_string())
+}
+
+/// Options for watching a configuration file.
+#[derive(Clone)]
+pub struct WatchOptions {
+    /// Time to wait for further changes before loading the configuration.
+    pub debounce: Duration,
+    /// Parser options.
+    pub parse: ParseOptions,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions { debounce: Duration::from_millis(100), parse: ParseOptions::default() }
+    }
+}
+
+/// A configuration file watcher.
+///
+/// Stops watching when dropped.
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// An error loading a configuration.
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(Vec<ParseError>),
+    Conversion(ConversionError),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "{}", error),
+            ConfigError::Parse(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i != 0 {
+                        writeln!(f)?;
+                    };
+                    write!(f, "{:?}", error)?;
+                };
+                Ok(())
+            }
+            ConfigError::Conversion(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Debug for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ConfigError {}
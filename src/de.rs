@@ -1,657 +1,1072 @@
 //! Serde deserializer for the Khi data format.
+//!
+//! Data is deserialized from a [ParsedValue], in the mapping written by the
+//! [serializer](crate::ser):
+//!
+//! - Booleans, numbers, characters and strings are text. Numbers are
+//!   recognized in the default [NumberFormat]. Byte arrays are text in
+//!   hexadecimal, or another [ByteRepresentation].
+//! - `None` is nil `~`, and any other value is `Some`. Other representations
+//!   can be selected with [OptionRepresentation].
+//! - Unit and unit structs are the empty tuple `<>` or nil `~`, and unit
+//!   structs may also be their name, as text or a tag. Newtype structs are the
+//!   value they wrap, which may be tagged with the name of the struct.
+//! - Sequences are lists or tuples. Tuples are tuples or lists.
+//! - Maps and structs are dictionaries, whose entries are read in source
+//!   order. Structs may be tagged with their name, like `<Fruit>:{…}`, and
+//!   the attributes of the tags of structs and struct variants are fields.
+//! - Enum variants are tags, `<Variant>` or `<Variant>:value`. Unit variants
+//!   may also be written as text. Other representations can be selected with
+//!   [EnumRepresentation], and tags are read as variants in all of them.
+//!
+//! A [SimpleValue](crate::model::SimpleValue) is deserialized from the value
+//! as it is written, rather than from the derived representation of its
+//! variants, so that any part of a document can be kept as is.
+//!
+//! Keys of dictionaries are exposed as strings, so `#[serde(rename)]`,
+//! `#[serde(alias)]` and `#[serde(default)]` work as for any other format.
+//! Unknown entries are skipped without being deserialized, unless denied by
+//! `#[serde(deny_unknown_fields)]` or [UnknownFields::Deny]. Attributes that
+//! buffer values, like `#[serde(flatten)]`, `#[serde(untagged)]` and
+//! `#[serde(tag)]`, read text as strings, since Khi text does not know the
+//! type it is read as. Fields read through them must deserialize from strings,
+//! like `String` or maps and structs of strings, and numbers and booleans
+//! cannot be flattened.
+//!
+//! Booleans are `true` and `false`. [DeserializerOptions::bools] allows other
+//! words, like `yes` and `off`.
+//!
+//! Errors carry the position of the value that could not be deserialized,
+//! and the [Path] to it from the deserialized value, like `servers[2]:port`.
+//!
+//! Text is borrowed from the value that is deserialized, so that data read
+//! with [from_value] can hold `&str` and `Cow<str>` fields without copying
+//! strings. Data read from a document string is owned, since the parsed value
+//! does not outlive the call.
 
 use std::fmt::{Debug, Display, Formatter};
-use std::num::{ParseFloatError, ParseIntError};
-use std::str::FromStr;
-use hex::FromHexError;
-use serde::de::{DeserializeSeed, MapAccess, SeqAccess, StdError, Visitor};
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde::de::value::StrDeserializer;
-use crate::{Component, Dictionary, Directive, Expression, Table, Text};
-use crate::enc::{DecodeTextExt, normalize_number_str};
-use crate::model::SimpleStructure;
-use crate::parse::{parse_expression_str, parse_dictionary_str, parse_table_str, ParsedValue, ParseError};
-
-/// Deserialize a Khi structure string.
-pub fn from_structure_str<T: Deserialize>(str: &str) -> Result<T> {
-    match parse_structure(str) {
-        Ok(x) => from_structure(parse),
-        Err(e) => Err(Error::ParseError(e)),
-    }
+use std::marker::PhantomData;
+#[cfg(feature = "parse")]
+use std::io;
+use base64::Engine;
+#[cfg(feature = "parse")]
+use serde::de::DeserializeOwned;
+use serde::de::{self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::de::value::{BorrowedStrDeserializer, MapAccessDeserializer, SeqAccessDeserializer, SeqDeserializer};
+use serde::Deserialize;
+use crate::boolean::BoolFormat;
+use crate::model::SIMPLE_VALUE;
+use crate::number::{Number, NumberFormat};
+#[cfg(feature = "parse")]
+use crate::parse::{parse_dictionary_str, parse_list_str, parse_value_str};
+#[cfg(feature = "parse")]
+use crate::parse::parser::{error_to_string, ParseError};
+use crate::pdm::{ConversionError, KeyOrder, ParsedAttribute, ParsedAttributeValue, ParsedDictionary, ParsedList, ParsedValue, Position, SortedEntryIterator};
+use crate::{Dictionary, Value};
+pub use crate::ser::{ByteRepresentation, EnumRepresentation, OptionRepresentation};
+
+/// Deserialize data from a Khi value. Text in the data may borrow from the
+/// value.
+pub fn from_value<'de, T: Deserialize<'de>>(value: &'de ParsedValue) -> Result<T> {
+    from_value_with(value, &DeserializerOptions::default())
 }
 
-/// Deserialize a Khi table string.
-pub fn from_table_str<T: Deserialize>(str: &str) -> Result<T> {
-    match parse_table(str) {
-        Ok(x) => from_structure(parse),
-        Err(e) => Err(),
-    }
+/// Deserialize data from a Khi value with options.
+pub fn from_value_with<'de, T: Deserialize<'de>>(value: &'de ParsedValue, options: &DeserializerOptions) -> Result<T> {
+    from_value_seed_with(PhantomData, value, options)
 }
 
-/// Deserialize a Khi dictionary string.
-pub fn from_dictionary_str<T: Deserialize>(str: &str) -> Result<T> {
-    match parse_nullable_dictionary(string) {
-        Ok(x) => from_structure(parse),
-        Err(e) => Err(),
-    }
+/// Deserialize data from a Khi value with a seed, for data that needs state
+/// to be deserialized, like an interner or an arena.
+pub fn from_value_seed<'de, S: DeserializeSeed<'de>>(seed: S, value: &'de ParsedValue) -> Result<S::Value> {
+    from_value_seed_with(seed, value, &DeserializerOptions::default())
 }
 
-/// Deserialize a Khi structure.
-pub fn from_structure<S: Expression<_, _, _, _, _>, T: Deserialize>(structure: &S) -> Result<T> {
-    let des = StructureDeserializer::new(structure);
-    T::deserialize(des)
+/// Deserialize data from a Khi value with a seed and options.
+pub fn from_value_seed_with<'de, S: DeserializeSeed<'de>>(seed: S, value: &'de ParsedValue, options: &DeserializerOptions) -> Result<S::Value> {
+    seed.deserialize(ValueDeserializer::with_options(value, *options))
 }
 
-/// An extension trait adding specific deserialization functions to Deserialize types.
-trait KhiDeserializableData: Deserialize {
-    fn deserialize_khi_structure<St: Expression<_, _, _, _, _>>(structure: &St) -> Result<Self>;
-    fn deserialize_khi_structure_str(str: &str) -> Result<Self>;
-    fn deserialize_khi_table_str(str: &str) -> Result<Self>;
-    fn deserialize_khi_dictionary_str(str: &str) -> Result<Self>;
+/// Deserialize data from a parsed dictionary document. Text in the data may
+/// borrow from the dictionary.
+pub fn from_dictionary<'de, T: Deserialize<'de>>(dictionary: &'de ParsedDictionary) -> Result<T> {
+    from_dictionary_with(dictionary, &DeserializerOptions::default())
 }
 
-impl <T: Deserialize> KhiDeserializableData for T {
-
-    fn deserialize_khi_structure<St: Expression<_, _, _, _, _>>(structure: &St) -> Result<Self> {
-        let deserializer = StructureDeserializer::new(structure);
-        Self::deserialize(deserializer)
-    }
-
-    fn deserialize_khi_structure_str(str: &str) -> Result<Self> {
-        todo!()let g: SimpleStructure;
-
-    }
-
-    fn deserialize_khi_table_str(str: &str) -> Result<Self> {
-        todo!()
-    }
-
-    fn deserialize_khi_dictionary_str(str: &str) -> Result<Self> {
-        todo!()
-    }
+/// Deserialize data from a parsed dictionary document with options.
+pub fn from_dictionary_with<'de, T: Deserialize<'de>>(dictionary: &'de ParsedDictionary, options: &DeserializerOptions) -> Result<T> {
+    T::deserialize(MapAccessDeserializer::new(Entries::new(dictionary, *options)))
+}
 
+/// Deserialize data from a parsed list document. Text in the data may borrow
+/// from the list.
+pub fn from_list<'de, T: Deserialize<'de>>(list: &'de ParsedList) -> Result<T> {
+    from_list_with(list, &DeserializerOptions::default())
 }
 
-trait DeserializableKhiStructure: Expression<> {
-    fn deserialize<T: Deserialize>(&self) -> Result<T>;
+/// Deserialize data from a parsed list document with options.
+pub fn from_list_with<'de, T: Deserialize<'de>>(list: &'de ParsedList, options: &DeserializerOptions) -> Result<T> {
+    T::deserialize(SeqAccessDeserializer::new(Elements::new(list.elements.iter(), *options)))
 }
 
-impl <St: Expression> DeserializableKhiStructure for St {
+/// Deserialize data from a Khi value document.
+#[cfg(feature = "parse")]
+pub fn from_str<T: DeserializeOwned>(document: &str) -> Result<T> {
+    from_str_with(document, &DeserializerOptions::default())
+}
 
+/// Deserialize data from a Khi value document with options.
+#[cfg(feature = "parse")]
+pub fn from_str_with<T: DeserializeOwned>(document: &str, options: &DeserializerOptions) -> Result<T> {
+    let value = parse_value_str(document).map_err(Error::Parse)?;
+    from_value_with(&value, options)
 }
 
-pub type Result<'a, T> = std::result::Result<T, Error<'a>>;
+/// Deserialize data from a Khi value document read from a buffered reader,
+/// like a [BufReader](io::BufReader) over a file.
+///
+/// The reader is read to the end before the document is parsed.
+#[cfg(feature = "parse")]
+pub fn from_reader<R: io::BufRead, T: DeserializeOwned>(reader: R) -> Result<T> {
+    from_reader_with(reader, &DeserializerOptions::default())
+}
 
-/// Khi deserialization error.
-pub enum Error<'a> {
-    ParseError(ParseError),
+/// Deserialize data from a Khi value document read from a reader with
+/// options.
+#[cfg(feature = "parse")]
+pub fn from_reader_with<R: io::BufRead, T: DeserializeOwned>(mut reader: R, options: &DeserializerOptions) -> Result<T> {
+    let mut document = String::new();
+    reader.read_to_string(&mut document).map_err(Error::Io)?;
+    from_str_with(&document, options)
+}
 
-    NotSelfDescribing,
-    InvalidBoolValue,
-    InvalidBoolStructure,
+/// Deserialize data from a Khi dictionary document, like a configuration file
+/// of entries. The data must deserialize from a dictionary, like a struct or a
+/// map.
+#[cfg(feature = "parse")]
+pub fn from_dictionary_str<T: DeserializeOwned>(document: &str) -> Result<T> {
+    from_dictionary_str_with(document, &DeserializerOptions::default())
+}
 
-    InvalidUnitStructure,
-    InvalidUnitStructStructure,
+/// Deserialize data from a Khi dictionary document with options.
+#[cfg(feature = "parse")]
+pub fn from_dictionary_str_with<T: DeserializeOwned>(document: &str, options: &DeserializerOptions) -> Result<T> {
+    let dictionary = parse_dictionary_str(document).map_err(Error::Parse)?;
+    let at = Position { index: 0, line: 1, column: 1 };
+    from_value_with(&ParsedValue::Dictionary(dictionary, at, at), options)
+}
 
-    SeqNotList,
-    InvalidSeqStructure,
+/// Deserialize data from a Khi list document, where every element is on a
+/// line of its own or separated by `;`. The data must deserialize from a
+/// sequence, like `Vec<T>`.
+#[cfg(feature = "parse")]
+pub fn from_list_str<T: DeserializeOwned>(document: &str) -> Result<T> {
+    from_list_str_with(document, &DeserializerOptions::default())
+}
 
-    InvalidMapStructure,
-    InvalidStructStructure,
-    InvalidStructNameComponent,
-    StructNameMismatch,
+/// Deserialize data from a Khi list document with options.
+#[cfg(feature = "parse")]
+pub fn from_list_str_with<T: DeserializeOwned>(document: &str, options: &DeserializerOptions) -> Result<T> {
+    let list = parse_list_str(document).map_err(Error::Parse)?;
+    let at = Position { index: 0, line: 1, column: 1 };
+    from_value_with(&ParsedValue::List(list, at, at), options)
+}
 
-    InvalidTupleStructStructure,
+/// Deserializer options.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct DeserializerOptions {
+    /// Representation of enum variants.
+    ///
+    /// Untagged, only unit variants can be deserialized, since the variant of
+    /// other data is unknown. Enums with data can use `#[serde(untagged)]`
+    /// instead.
+    pub enums: EnumRepresentation,
+    /// Representation of optional values.
+    pub optionals: OptionRepresentation,
+    /// Representation of byte arrays.
+    pub bytes: ByteRepresentation,
+    /// Handling of dictionary entries that are not fields of a struct.
+    pub unknown_fields: UnknownFields,
+    /// Words of booleans.
+    pub bools: BoolFormat,
+}
 
-    InvalidEnumStructure,
+/// Handling of dictionary entries that are not fields of the struct they are
+/// deserialized as.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum UnknownFields {
+    /// Skip unknown entries.
+    #[default]
+    Ignore,
+    /// Fail on the first unknown entry in source order, as with
+    /// `#[serde(deny_unknown_fields)]` on every struct.
+    Deny,
+}
 
-    InvalidOptionStructure,
-    InvalidOptionDirectiveLabel,
-    InvalidOptionDirectiveAttributes,
+pub type Result<T> = std::result::Result<T, Error>;
 
-    InvalidTupleStructure,
-    SeqNotTuple,
+/// Khi deserialization error.
+pub enum Error {
+    /// The document does not parse.
+    #[cfg(feature = "parse")]
+    Parse(Vec<ParseError>),
+    /// A value is not of the expected kind, or is invalid text.
+    Conversion(ConversionError),
+    /// Error raised by a `Deserialize` implementation, with the position of
+    /// the value being deserialized.
+    Custom(String, Option<Position>),
+    /// Error in a value inside the deserialized value, with the path to it.
+    Within(Path, Box<Error>),
+    /// The document could not be read, or is not UTF-8.
+    #[cfg(feature = "parse")]
+    Io(io::Error),
+}
 
-    EnumNameMismatch,
+impl Error {
+    /// Position of the value that could not be deserialized.
+    pub fn at(&self) -> Option<Position> {
+        match self {
+            #[cfg(feature = "parse")]
+            Error::Parse(..) => None,
+            #[cfg(feature = "parse")]
+            Error::Io(..) => None,
+            Error::Conversion(error) => Some(error.at()),
+            Error::Custom(_, at) => *at,
+            Error::Within(_, error) => error.at(),
+        }
+    }
 
-    InvalidOptionDirectiveArguments,
+    /// Path to the value that could not be deserialized, if it is inside the
+    /// deserialized value.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::Within(path, _) => Some(path),
+            _ => None,
+        }
+    }
 
-    HexDecodeError,
+    /// Set the position of a custom error that has none.
+    fn locate(self, at: Position) -> Self {
+        match self {
+            Error::Custom(message, None) => Error::Custom(message, Some(at)),
+            Error::Within(path, error) => Error::Within(path, Box::new(error.locate(at))),
+            error => error,
+        }
+    }
 
-    ParseIntError { structure: &'a ParsedExpression, error: ParseIntError },
-    ParseFloatError { structure: &'a ParsedExpression, error: ParseFloatError },
-    InvalidStructure { structure: &'a ParsedExpression, expected: &'a str },
+    /// Place an error inside a value at a segment.
+    fn within(self, segment: Segment) -> Self {
+        match self {
+            Error::Within(mut path, error) => {
+                path.0.insert(0, segment);
+                Error::Within(path, error)
+            }
+            error => Error::Within(Path(vec![segment]), Box::new(error)),
+        }
+    }
+}
 
+/// Path to a value inside another value, like `servers[2]:port`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Path(pub Vec<Segment>);
+
+/// Step of a [Path].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Segment {
+    /// Entry of a dictionary, or the value of a tag.
+    Key(String),
+    /// Element of a list, tuple or compound.
+    Index(usize),
 }
 
-impl Debug for Error {
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if i == 0 => write!(f, "{}", key)?,
+                Segment::Key(key) => write!(f, ":{}", key)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            };
+        };
+        Ok(())
+    }
+}
 
+impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-
+            #[cfg(feature = "parse")]
+            Error::Parse(errors) => {
+                let errors: Vec<String> = errors.iter().map(error_to_string).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            Error::Conversion(error) => write!(f, "{}", error),
+            Error::Custom(message, Some(at)) => write!(f, "{} at {}:{}.", message, at.line, at.column),
+            Error::Custom(message, None) => write!(f, "{}.", message),
+            Error::Within(path, error) => write!(f, "{}: {}", path, error),
+            #[cfg(feature = "parse")]
+            Error::Io(error) => write!(f, "{}", error),
         }
     }
-
 }
 
-impl Display for Error {
+impl Debug for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        write!(f, "{}", self)
     }
 }
 
-impl StdError for Error { }
-
-impl serde::de::Error for Error {
-
-    fn custom<T>(msg: T) -> Self where T: Display {
-        todo!()
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "parse")]
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
     }
-
 }
 
-pub struct StructureDeserializer<'a,
-    Vl: Value<Vl, Tx, Dc, Tb, Cm, Pt>,
-    Tx: Text<Vl, Tx, Dc, Tb, Cm, Pt>,
-    Dc: Dictionary<Vl, Tx, Dc, Tb, Cm, Pt>,
-    Tb: Table<Vl, Tx, Dc, Tb, Cm, Pt>,
-    Cm: Composition<Vl, Tx, Dc, Tb, Cm, Pt>,
-    Pt: Pattern<Vl, Tx, Dc, Tb, Cm, Pt>,
-> {
-    value: &'a Vl,
+impl de::Error for Error {
+    fn custom<T: Display>(message: T) -> Self {
+        Error::Custom(message.to_string(), None)
+    }
 }
 
-impl <'a,
-    St: Expression<Tx, Tb, Dc, Dr, Cm>,
-    Tx: Text<St, Tb, Dc, Dr, Cm>,
-    Tb: Table<St, Tx, Dc, Dr, Cm>,
-    Dc: Dictionary<St, Tx, Tb, Dr, Cm>,
-    Cm: Composition<St, Tx, Tb, Dc, Dr>,
-    Dr: Pattern<St, Tx, Tb, Dc, Cm>,
-> StructureDeserializer<St, Tx, Tb, Dc, Dr, Cm> {
+/// Deserializer reading a [ParsedValue].
+pub struct ValueDeserializer<'de> {
+    value: &'de ParsedValue,
+    options: DeserializerOptions,
+}
 
-    pub fn new(structure: &'a St) -> Self {
-        Self { value: structure }
+impl<'de> ValueDeserializer<'de> {
+    pub fn new(value: &'de ParsedValue) -> Self {
+        ValueDeserializer::with_options(value, DeserializerOptions::default())
     }
 
-}
+    pub fn with_options(value: &'de ParsedValue, options: DeserializerOptions) -> Self {
+        ValueDeserializer { value, options }
+    }
 
-macro_rules! des_num {
-    ($self:ident, $visitor:ident, $ty:ty, $visit:ident, $errvar:ident) => {
-        {
-            if let Some(text) = $self.structure.conform_text() {
-                let text = normalize_number_str(text.as_str());
-                match $ty::from_str(&text) {
-                    Ok(v) => $visitor.$visit(v),
-                    Err(e) => Err(Error::$errvar { structure: $self.structure, error: e }),
-                }
-            } else {
-                Err(Error::InvalStructure { structure: $self.structure, expected: "Tx" })
-            }
+    fn text(&self) -> Result<&'de str> {
+        match self.value {
+            ParsedValue::Text(text, ..) => Ok(&text.str),
+            value => Err(Error::Conversion(ConversionError::Expected("text", value.from()))),
         }
     }
-}
 
-impl <'de,
-    St: Expression<Tx, Tb, Dc, Dr, Cm>,
-    Tx: Text<St, Tb, Dc, Dr, Cm>,
-    Tb: Table<St, Tx, Dc, Dr, Cm>,
-    Dc: Dictionary<St, Tx, Tb, Dr, Cm>,
-    Dr: Directive<St, Tx, Tb, Dc, Cm>,
-    Cm: Component<St, Tx, Tb, Dc, Dr>,
-> Deserializer for StructureDeserializer<St, Tx, Tb, Dc, Dr, Cm> {
+    fn number<T: Number>(&self) -> Result<T> {
+        self.value.to_number_with(&NumberFormat::default()).map_err(Error::Conversion)
+    }
 
-    type Error = Error;
+    fn expected(&self, expected: &'static str) -> Error {
+        Error::Conversion(ConversionError::Expected(expected, self.value.from()))
+    }
 
-    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        Err(Error::NotSelfDescribing)
+    fn visit_tuple<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            ParsedValue::List(list, ..) => visitor.visit_seq(Elements::new(list.elements.iter(), self.options)),
+            value => visitor.visit_seq(Elements::new(value.iter_as_tuple(), self.options)),
+        }
     }
+}
+
+/// Locate custom errors of a visitor at the value being deserialized.
+macro_rules! located {
+    ($self:ident, $result:expr) => {{
+        let at = $self.value.from();
+        $result.map_err(|error: Error| error.locate(at))
+    }};
+}
 
-    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(text) = self.value.conform_text() {
-            if text.eq("1") {
-                visitor.visit_bool(true)
-            } else if text.eq("0") {
-                visitor.visit_bool(false)
-            } else {
-                Err(Error::InvalidBoolValue)
+macro_rules! deserialize_number {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                let number = self.number()?;
+                located!(self, visitor.$visit(number))
             }
-        } else {
-            Err(Error::InvalidBoolStructure)
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            ParsedValue::Text(text, ..) => located!(self, visitor.visit_borrowed_str(&text.str)),
+            ParsedValue::Nil(..) => located!(self, visitor.visit_none()),
+            value if value.is_unit() => located!(self, visitor.visit_unit()),
+            ParsedValue::Tuple(..) | ParsedValue::List(..) => located!(self, self.visit_tuple(visitor)),
+            ParsedValue::Compound(compound, ..) => located!(self, visitor.visit_seq(Elements::new(compound.components.iter(), self.options))),
+            ParsedValue::Dictionary(dictionary, ..) => located!(self, visitor.visit_map(Entries::new(dictionary, self.options))),
+            // A tagged value is a map from the tag name to the value.
+            ParsedValue::Tagged(tagged, ..) => located!(self, visitor.visit_map(Tag { entry: Some((&tagged.name, &tagged.value)), options: self.options })),
         }
     }
 
-    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, i8, visit_i8, ParseIntError)
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let text = self.text()?;
+        let value = match self.options.bools.parse(text) {
+            Some(value) => value,
+            None => return Err(Error::Conversion(ConversionError::Invalid("bool", self.value.from(), text.to_string()))),
+        };
+        located!(self, visitor.visit_bool(value))
     }
 
-    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, i16, visit_i16, ParseIntError)
+    deserialize_number! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
     }
 
-    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, i32, visit_i32, ParseIntError)
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let text = self.text()?;
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => located!(self, visitor.visit_char(c)),
+            _ => Err(Error::Conversion(ConversionError::Invalid("char", self.value.from(), text.to_string()))),
+        }
     }
 
-    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, i64, visit_i64, ParseIntError)
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let text = self.text()?;
+        located!(self, visitor.visit_borrowed_str(text))
     }
 
-    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, u8, visit_u8, ParseIntError)
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
     }
 
-    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, u16, visit_u16, ParseIntError)
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bytes = match self.options.bytes {
+            ByteRepresentation::Hex => {
+                let text = self.text()?;
+                hex::decode(text).map_err(|_| Error::Conversion(ConversionError::Invalid("bytes", self.value.from(), text.to_string())))?
+            }
+            ByteRepresentation::Base64 => {
+                let text = self.text()?;
+                base64::engine::general_purpose::STANDARD.decode(text).map_err(|_| Error::Conversion(ConversionError::Invalid("bytes", self.value.from(), text.to_string())))?
+            }
+            ByteRepresentation::List => match self.value {
+                ParsedValue::List(list, ..) => list.elements.iter().enumerate().map(|(i, element)| {
+                    element.to_number_with(&NumberFormat::default()).map_err(|error| Error::Conversion(error).within(Segment::Index(i)))
+                }).collect::<Result<_>>()?,
+                _ => return Err(self.expected("list")),
+            },
+        };
+        located!(self, visitor.visit_byte_buf(bytes))
     }
 
-    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, u32, visit_u32, ParseIntError)
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
     }
 
-    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, u64, visit_u64, ParseIntError)
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match (self.options.optionals, self.value) {
+            (OptionRepresentation::Nil | OptionRepresentation::Empty, ParsedValue::Nil(..)) => located!(self, visitor.visit_none()),
+            (OptionRepresentation::Empty, value) if value.is_unit() => located!(self, visitor.visit_none()),
+            (OptionRepresentation::Nil | OptionRepresentation::Empty, _) => located!(self, visitor.visit_some(self)),
+            (OptionRepresentation::Tagged, ParsedValue::Tagged(tagged, ..)) if &*tagged.name == "?" => {
+                if tagged.value.is_unit() {
+                    located!(self, visitor.visit_none())
+                } else {
+                    located!(self, visitor.visit_some(ValueDeserializer::with_options(&tagged.value, self.options)))
+                }
+            }
+            (OptionRepresentation::Tagged, _) => Err(self.expected("optional tag")),
+        }
     }
 
-    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, f32, visit_f32, ParseFloatError)
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            ParsedValue::Nil(..) => located!(self, visitor.visit_unit()),
+            value if value.is_unit() => located!(self, visitor.visit_unit()),
+            _ => Err(self.expected("unit")),
+        }
     }
 
-    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        des_num!(self, visitor, f64, visit_f64, ParseFloatError)
+    /// A unit struct is also read from its name, as text or as a tag.
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value> {
+        match self.value {
+            ParsedValue::Text(text, ..) if &*text.str == name => located!(self, visitor.visit_unit()),
+            ParsedValue::Tagged(tagged, ..) if &*tagged.name == name && tagged.value.is_unit() => located!(self, visitor.visit_unit()),
+            _ => self.deserialize_unit(visitor),
+        }
     }
 
-    //todo +
-    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(char) = self.value.conform_text() {
-            let char = char.as_str();
-            let mut chars = char.chars();
-            let char = if let Some(char) = chars.next() {
-                if chars.next().is_some() {
-                    return Err(Error::CharDecodeError);
-                };
-                char
-            } else {
-                return Err(Error::CharDecodeError);
-            };
-            visitor.visit_char(char)
-        } else {
-            Err(Error::InvalidF64Structure)
+    /// A newtype struct is also read from a tag named after it.
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value> {
+        match self.value {
+            _ if name == SIMPLE_VALUE => located!(self, visitor.visit_enum(Model { value: self.value, options: self.options })),
+            ParsedValue::Tagged(tagged, ..) if &*tagged.name == name => {
+                let inner = ValueDeserializer::with_options(&tagged.value, self.options);
+                located!(self, visitor.visit_newtype_struct(inner))
+            }
+            _ => located!(self, visitor.visit_newtype_struct(self)),
         }
     }
 
-    //todo +
-    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(str) = self.value.conform_text() {
-            let str = str.as_str();
-            visitor.visit_str(str)
-        } else {
-            Err(Error::InvalidStructure)
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            ParsedValue::List(..) | ParsedValue::Tuple(..) => located!(self, self.visit_tuple(visitor)),
+            _ => Err(self.expected("list")),
         }
     }
 
-    //todo +
-    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(string) = self.value.conform_text() {
-            let string = String::from(string.as_str());
-            visitor.visit_string(string)
-        } else {
-            Err(Error::InvalidStructure)
-        }
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        located!(self, self.visit_tuple(visitor))
     }
 
-    //todo +
-    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(hex) = self.value.conform_text() {
-            let hex = hex.as_str();
-            let bytes = match hex::decode(hex) {
-                Ok(b) => b,
-                Err(_) => return Err(Error::HexDecodeError),
-            };
-            visitor.visit_bytes(&bytes)
-        } else {
-            Err(Error::InvalidF64Structure)
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value> {
+        located!(self, self.visit_tuple(visitor))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            ParsedValue::Dictionary(dictionary, ..) => located!(self, visitor.visit_map(Entries::new(dictionary, self.options))),
+            _ => Err(self.expected("dictionary")),
         }
     }
 
-    //todo +
-    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(hex) = self.value.conform_text() {
-            let hex = hex.as_str();
-            let bytes = match hex::decode(hex) {
-                Ok(b) => b,
-                Err(_) => return Err(Error::HexDecodeError),
-            };
-            visitor.visit_byte_buf(bytes)
-        } else {
-            Err(Error::InvalidF64Structure)
+    /// A struct is also read from a dictionary tagged with the name of the
+    /// struct, and the attributes of the tag are read as fields.
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        match self.value {
+            ParsedValue::Tagged(tagged, ..) if &*tagged.name == name => match tagged.value.as_ref() {
+                ParsedValue::Dictionary(dictionary, ..) => visit_struct(dictionary, &tagged.attributes, self.value.from(), fields, visitor, self.options),
+                _ => self.deserialize_map(visitor),
+            },
+            ParsedValue::Dictionary(dictionary, ..) => {
+                check_fields(dictionary, fields, None, &self.options)?;
+                self.deserialize_map(visitor)
+            }
+            _ => self.deserialize_map(visitor),
         }
     }
 
-    // `Some(a)` is encoded as `<?>:a` and `None` is encoded as `<?>`.
-    //todo +
-    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(option) = self.value.conform_directive() {
-            let label = &option.label;
-            if !label.eq("?") {
-                return Err(Error::InvalidOptionDirectiveLabel);
-            };
-            if option.has_attributes() {
-                return Err(Error::InvalidOptionDirectiveAttributes);
-            };
-            let len = option.length();
-            if len == 0 {
-                visitor.visit_none()
-            } else if len == 1 {
-                let content = option.get(0).unwrap().as_structure();
-                let deserializer = StructureDeserializer { value: content };
-                visitor.visit_some(deserializer)
-            } else {
-                Err(Error::InvalidOptionDirectiveArguments)
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        let options = self.options;
+        match (self.options.enums, self.value) {
+            // A tag is read as the variant in any representation.
+            (_, ParsedValue::Tagged(tagged, ..)) => {
+                located!(self, visitor.visit_enum(Variant { name: &tagged.name, value: Some(&tagged.value), attributes: &tagged.attributes, at: self.value.from(), tag: None, options }))
+            }
+            (EnumRepresentation::External | EnumRepresentation::Untagged, ParsedValue::Text(text, ..)) => {
+                located!(self, visitor.visit_enum(Variant { name: &text.str, value: None, attributes: &[], at: self.value.from(), tag: None, options }))
+            }
+            (EnumRepresentation::External, _) => Err(self.expected("tag")),
+            (EnumRepresentation::Internal { tag }, ParsedValue::Dictionary(dictionary, ..)) => {
+                let name = match dictionary.get(tag) {
+                    Some(ParsedValue::Text(text, ..)) => &text.str,
+                    Some(value) => return Err(Error::Conversion(ConversionError::Expected("text", value.from()))),
+                    None => return Err(Error::Custom(format!("Dictionary must have a variant entry {}", tag), Some(self.value.from()))),
+                };
+                located!(self, visitor.visit_enum(Variant { name, value: Some(self.value), attributes: &[], at: self.value.from(), tag: Some(tag), options }))
             }
-        } else {
-            Err(Error::InvalidOptionStructure)
+            (EnumRepresentation::Internal { .. }, _) => Err(self.expected("dictionary")),
+            (EnumRepresentation::Untagged, _) => Err(self.expected("unit variant")),
         }
     }
 
-    // todo +
-    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if !self.value.is_empty() {
-            return Err(Error::InvalidUnitStructure);
-        };
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    /// Ignored values are skipped without being read, since the whole value
+    /// is already parsed.
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
         visitor.visit_unit()
     }
+}
 
-    //todo +
-    fn deserialize_unit_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        let len = self.value.length();
-        if len == 0 {
-            visitor.visit_unit()
-        } else if len == 1 {
-            if let Some(text) = self.value.conform_text() {
-                let mName = text.as_str();
-                if name.eq(mName) {
-                    visitor.visit_unit()
-                } else {
-                    Err(Error::StructNameMismatch)
-                }
-            } else {
-                Err(Error::InvalidUnitStructStructure)
-            }
-        } else {
-            Err(Error::InvalidUnitStructStructure)
+/// A value deserialized as a [SimpleValue](crate::model::SimpleValue), whose
+/// variant is the kind of the value rather than a tag, so that a document
+/// deserializes into the simple value it is.
+struct Model<'de> {
+    value: &'de ParsedValue,
+    options: DeserializerOptions,
+}
+
+impl<'de> EnumAccess<'de> for Model<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self)> {
+        let variant = match self.value {
+            ParsedValue::Text(..) => "Text",
+            ParsedValue::Tagged(..) => "Tagged",
+            ParsedValue::Tuple(..) => "Tuple",
+            ParsedValue::Dictionary(..) => "Dictionary",
+            ParsedValue::List(..) => "List",
+            ParsedValue::Compound(..) => "Compound",
+            ParsedValue::Nil(..) => "Nil",
+        };
+        Ok((seed.deserialize(BorrowedStrDeserializer::new(variant))?, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Model<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        let options = self.options;
+        match self.value {
+            ParsedValue::Text(text, ..) => seed.deserialize(BorrowedStrDeserializer::new(&text.str)),
+            ParsedValue::Tagged(tagged, ..) => seed.deserialize(MapAccessDeserializer::new(Fields::new([
+                ("name", Field::Str(&tagged.name)),
+                ("attributes", Field::Attributes(&tagged.attributes)),
+                ("value", Field::Value(&tagged.value)),
+            ], options))),
+            ParsedValue::Tuple(..) => seed.deserialize(SeqAccessDeserializer::new(Elements::new(self.value.iter_as_tuple(), options))),
+            ParsedValue::Dictionary(dictionary, ..) => seed.deserialize(MapAccessDeserializer::new(Entries::new(dictionary, options))),
+            ParsedValue::List(list, ..) => seed.deserialize(SeqAccessDeserializer::new(Elements::new(list.elements.iter(), options))),
+            ParsedValue::Compound(compound, ..) => seed.deserialize(MapAccessDeserializer::new(Fields::new([
+                ("components", Field::Values(&compound.components)),
+                ("whitespace", Field::Flags(&compound.whitespace)),
+            ], options))),
+            ParsedValue::Nil(..) => Err(self.unexpected()),
         }
     }
 
-    //todo +
-    fn deserialize_newtype_struct<V>(self, _: &'static str, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        visitor.visit_newtype_struct(self)
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value> {
+        Err(self.unexpected())
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], _visitor: V) -> Result<V::Value> {
+        Err(self.unexpected())
+    }
+}
+
+impl Model<'_> {
+    fn unexpected(&self) -> Error {
+        Error::Conversion(ConversionError::Expected("simple value", self.value.from()))
+    }
+}
+
+/// Fields of a struct in the simple value model.
+struct Fields<'de, const N: usize> {
+    fields: std::array::IntoIter<(&'static str, Field<'de>), N>,
+    next: Option<Field<'de>>,
+    options: DeserializerOptions,
+}
+
+enum Field<'de> {
+    Str(&'de str),
+    Attributes(&'de [ParsedAttribute]),
+    Value(&'de ParsedValue),
+    Values(&'de [ParsedValue]),
+    Flags(&'de [bool]),
+}
+
+impl<'de, const N: usize> Fields<'de, N> {
+    fn new(fields: [(&'static str, Field<'de>); N], options: DeserializerOptions) -> Self {
+        Fields { fields: fields.into_iter(), next: None, options }
     }
+}
 
-    //todo +
-    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(table) = self.value.conform_table() {
-            if table.is_list() {
-                visitor.visit_seq(TableAccess { iter: table.iter() })
-            } else {
-                Err(Error::SeqNotList)
+impl<'de, const N: usize> MapAccess<'de> for Fields<'de, N> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.fields.next() {
+            Some((key, field)) => {
+                self.next = Some(field);
+                seed.deserialize(BorrowedStrDeserializer::new(key)).map(Some)
             }
-        } else {
-            Err(Error::InvalidSeqStructure)
+            None => Ok(None),
         }
     }
 
-    //todo +
-    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(table) = self.value.conform_table() {
-            if table.is_tuple() {
-                visitor.visit_seq(TableAccess { iter: table.iter() })
-            } else {
-                Err(Error::SeqNotTuple)
-            }
-        } else {
-            Err(Error::InvalidTupleStructure)
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        match self.next.take() {
+            Some(Field::Str(str)) => seed.deserialize(BorrowedStrDeserializer::new(str)),
+            Some(Field::Attributes(attributes)) => seed.deserialize(SeqDeserializer::new(attributes.iter().map(Attribute))),
+            Some(Field::Value(value)) => seed.deserialize(ValueDeserializer::with_options(value, self.options)),
+            Some(Field::Values(values)) => seed.deserialize(SeqAccessDeserializer::new(Elements::new(values.iter(), self.options))),
+            Some(Field::Flags(flags)) => seed.deserialize(SeqDeserializer::new(flags.iter().copied())),
+            None => Err(Error::Custom(String::from("Value requested before key"), None)),
         }
     }
+}
 
-    //todo +
-    fn deserialize_tuple_struct<V>(self, name: &'static str, len: usize, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        let len = self.value.length();
-        if len == 1 {
-            self.deserialize_tuple(visitor)
-        } else if len == 2 {
-            let c0 = self.value.get(0).unwrap();
-            if let Some(c0) = c0.as_text() {
-                let c0 = c0.as_str();
-                if name.eq(c0) {
-                    let c1 = self.value.get(1).unwrap().as_expression();
-                    let deserializer = StructureDeserializer { value: c1 };
-                    deserializer.deserialize_tuple(visitor)
-                } else {
-                    Err(Error::StructNameMismatch)
-                }
-            } else {
-                Err(Error::InvalidTupleStructStructure)
-            }
-        } else {
-            Err(Error::InvalidTupleStructStructure)
+/// An attribute of a tag, as a key and an optional value.
+struct Attribute<'de>(&'de ParsedAttribute);
+
+impl<'de> IntoDeserializer<'de, Error> for Attribute<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Attribute<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let ParsedAttribute(key, value) = self.0;
+        visitor.visit_seq(SeqDeserializer::new([AttributePart::Key(key), AttributePart::Value(value.as_ref())].into_iter()))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+        newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// The key or the optional value of an attribute.
+enum AttributePart<'de> {
+    Key(&'de str),
+    Value(Option<&'de ParsedAttributeValue>),
+}
+
+impl<'de> IntoDeserializer<'de, Error> for AttributePart<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for AttributePart<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            AttributePart::Key(key) => visitor.visit_borrowed_str(key),
+            AttributePart::Value(Some(ParsedAttributeValue::Text(text))) => visitor.visit_borrowed_str(text),
+            AttributePart::Value(Some(ParsedAttributeValue::List(strings))) => visitor.visit_seq(SeqDeserializer::new(strings.iter().map(|s| &**s))),
+            AttributePart::Value(None) => visitor.visit_none(),
         }
     }
 
-    //todo +
-    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(dictionary) = self.value.conform_dictionary() {
-            visitor.visit_map(DictionaryAccess { iter: dictionary.iter(), current: None })
-        } else {
-            Err(Error::InvalidMapStructure)
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            AttributePart::Value(None) => visitor.visit_none(),
+            part => visitor.visit_some(part),
         }
     }
 
-    //todo +
-    fn deserialize_struct<V>(self, name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        let len = self.value.length();
-        if len == 1 {
-            self.deserialize_map(visitor)
-        } else if len == 2 {
-            let c0 = self.value.get(0).unwrap();
-            if let Some(c0) = c0.as_text() {
-                let c0 = c0.as_str();
-                if c0.eq(name) {
-                    let c1 = self.value.get(1).unwrap().as_expression();
-                    let deserializer = StructureDeserializer { value: c1 };
-                    deserializer.deserialize_map(visitor)
-                } else {
-                    Err(Error::StructNameMismatch)
-                }
-            } else {
-                Err(Error::InvalidStructStructure)
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit unit_struct
+        newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Check that the entries of a dictionary are fields of a struct, except for
+/// the variant entry of an internally tagged variant, unless unknown fields
+/// are ignored.
+fn check_fields(dictionary: &ParsedDictionary, fields: &'static [&'static str], tag: Option<&'static str>, options: &DeserializerOptions) -> Result<()> {
+    if options.unknown_fields == UnknownFields::Ignore {
+        return Ok(());
+    };
+    match dictionary.iter_sorted(KeyOrder::Source).find(|(key, _)| !fields.contains(key) && Some(*key) != tag) {
+        Some((key, value)) => Err(<Error as de::Error>::unknown_field(key, fields).locate(value.from()).within(Segment::Key(key.to_string()))),
+        None => Ok(()),
+    }
+}
+
+/// Read a dictionary as a struct, with the attributes of its tag as fields
+/// before the entries. Errors without a position are located at the tag.
+fn visit_struct<'de, V: Visitor<'de>>(dictionary: &'de ParsedDictionary, attributes: &'de [ParsedAttribute], at: Position, fields: &'static [&'static str], visitor: V, options: DeserializerOptions) -> Result<V::Value> {
+    if options.unknown_fields == UnknownFields::Deny {
+        if let Some(ParsedAttribute(key, _)) = attributes.iter().find(|attribute| !fields.contains(&&*attribute.0)) {
+            return Err(<Error as de::Error>::unknown_field(key, fields).locate(at).within(Segment::Key(key.to_string())));
+        };
+    };
+    check_fields(dictionary, fields, None, &options)?;
+    visitor.visit_map(Entries { attributes: attributes.iter(), at, ..Entries::new(dictionary, options) }).map_err(|error| error.locate(at))
+}
+
+/// Elements of a list, tuple or compound.
+struct Elements<I> {
+    elements: I,
+    /// Index of the next element.
+    index: usize,
+    options: DeserializerOptions,
+}
+
+impl<I> Elements<I> {
+    fn new(elements: I, options: DeserializerOptions) -> Self {
+        Elements { elements, index: 0, options }
+    }
+}
+
+impl<'de, I: Iterator<Item=&'de ParsedValue>> SeqAccess<'de> for Elements<I> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.elements.next() {
+            Some(value) => {
+                let index = self.index;
+                self.index += 1;
+                seed.deserialize(ValueDeserializer::with_options(value, self.options)).map(Some).map_err(|error| error.within(Segment::Index(index)))
             }
-        } else {
-            Err(Error::InvalidStructStructure)
+            None => Ok(None),
         }
     }
+}
 
-    //todo +
-    fn deserialize_enum<V>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        let len = self.value.length();
-        let content;
-        if len == 1 {
-            content = self.value.get(0).unwrap();
-        } else if len == 2 {
-            content = self.value.get(1).unwrap();
-            let found_name = self.value.get(0).unwrap();
-            if let Some(found_name) = found_name.as_text() {
-                let found_name = found_name.as_str();
-                if !name.eq(found_name) {
-                    return Err(Error::EnumNameMismatch);
-                };
-            } else {
-                return Err(Error::InvalidEnumStructure);
-            };
-        } else {
-            return Err(Error::InvalidEnumStructure);
+/// Entries of a dictionary.
+struct Entries<'de> {
+    /// Entries in source order.
+    entries: SortedEntryIterator<'de>,
+    /// Entry whose key has been deserialized.
+    entry: Option<(&'de str, &'de ParsedValue)>,
+    /// Attributes of the tag of a struct, which are read before the entries.
+    attributes: std::slice::Iter<'de, ParsedAttribute>,
+    /// Attribute whose key has been deserialized.
+    attribute: Option<(&'de str, Option<&'de ParsedAttributeValue>)>,
+    /// Position of the tag, where attributes are located.
+    at: Position,
+    /// Key of the variant entry of an internally tagged variant, which is
+    /// skipped.
+    tag: Option<&'static str>,
+    options: DeserializerOptions,
+}
+
+impl<'de> Entries<'de> {
+    fn new(dictionary: &'de ParsedDictionary, options: DeserializerOptions) -> Self {
+        Entries { entries: dictionary.iter_sorted(KeyOrder::Source), entry: None, attributes: [].iter(), attribute: None, at: Position::ZERO, tag: None, options }
+    }
+}
+
+impl<'de> MapAccess<'de> for Entries<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if let Some(ParsedAttribute(key, value)) = self.attributes.next() {
+            self.attribute = Some((key, value.as_ref()));
+            return seed.deserialize(KeyDeserializer { key, at: self.at, bools: self.options.bools }).map(Some).map_err(|error| error.locate(self.at).within(Segment::Key(key.to_string())));
         };
-        if let Some(content) = content.as_directive() {
-            if content.has_attributes() {
-                return Err(Error::InvalidEnumStructure);
-            };
-            let variant = content.label();
-            let content_len = content.length();
-            if content_len == 0 {
-                Ok(EnumAccess { variant, content: None })
-            } else if content_len == 1 {
-                let content = content.get_argument(0).unwrap().as_expression();
-                Ok(EnumAccess { variant, content: Some(content) })
-            } else {
-                Err(Error::InvalidEnumStructure)
+        let tag = self.tag;
+        match self.entries.find(|(key, _)| Some(*key) != tag) {
+            Some((key, value)) => {
+                self.entry = Some((key, value));
+                seed.deserialize(KeyDeserializer { key, at: value.from(), bools: self.options.bools }).map(Some).map_err(|error| error.locate(value.from()).within(Segment::Key(key.to_string())))
             }
-        } else {
-            Err(Error::InvalidEnumStructure)
+            None => Ok(None),
         }
     }
 
-    //todo +
-    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        self.deserialize_str(visitor)
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        if let Some((key, value)) = self.attribute.take() {
+            let value = match value {
+                Some(ParsedAttributeValue::Text(value)) => seed.deserialize(KeyDeserializer { key: value, at: self.at, bools: self.options.bools }),
+                Some(ParsedAttributeValue::List(strings)) => seed.deserialize(SeqDeserializer::new(strings.iter().map(|s| &**s))),
+                None => seed.deserialize(Flag),
+            };
+            return value.map_err(|error| error.locate(self.at).within(Segment::Key(key.to_string())));
+        };
+        let (key, value) = self.entry.take().ok_or_else(|| Error::Custom(String::from("Value deserialized before key"), None))?;
+        seed.deserialize(ValueDeserializer::with_options(value, self.options)).map_err(|error| error.within(Segment::Key(key.to_string())))
     }
+}
 
-    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        Err(Error::NotSelfDescribing)
+/// An attribute without a value, which is read as the empty tuple, and as
+/// present when optional.
+struct Flag;
+
+impl<'de> de::Deserializer<'de> for Flag {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
     }
 
-    fn is_human_readable(&self) -> bool {
-        true
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
     }
 
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit unit_struct
+        newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
 }
 
-pub struct TableAccess<E, F> where E: Expression<_, _, _, _, _>, F: Iterator<Item=E> {
-    iter: U,
+/// A tagged value as a map with one entry.
+struct Tag<'de> {
+    entry: Option<(&'de str, &'de ParsedValue)>,
+    options: DeserializerOptions,
 }
 
-impl <'de, E, F> SeqAccess for TableAccess<E, F> {
-
+impl<'de> MapAccess<'de> for Tag<'de> {
     type Error = Error;
 
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>> where T: DeserializeSeed<'de> {
-        Ok(self.iter.next())
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.entry {
+            Some((name, value)) => seed.deserialize(KeyDeserializer { key: name, at: value.from(), bools: self.options.bools }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let (name, value) = self.entry.take().ok_or_else(|| Error::Custom(String::from("Value deserialized before key"), None))?;
+        seed.deserialize(ValueDeserializer::with_options(value, self.options)).map_err(|error| error.within(Segment::Key(name.to_string())))
     }
+}
 
+/// Deserializer reading a dictionary key. Keys are text, and may be numbers.
+/// Entries and mapped keys, like `1 => a`, are read the same.
+struct KeyDeserializer<'de> {
+    key: &'de str,
+    /// Position of the value at the key.
+    at: Position,
+    /// Words of booleans.
+    bools: BoolFormat,
 }
 
-pub struct DictionaryAccess<
-    St: Expression<Tx, Tb, Dc, Dr, Cm>,
-    Tx: Text<St, Tb, Dc, Dr, Cm>,
-    Tb: Table<St, Tx, Dc, Dr, Cm>,
-    Dc: Dictionary<St, Tx, Tb, Dr, Cm>,
-    Dr: Directive<St, Tx, Tb, Dc, Cm>,
-    Cm: Component<St, Tx, Tb, Dc, Dr>,
-    It: Iterator<Item=(&str, &St)>,
-> {
-    iter: I,
-    current: Option<E>,
+impl KeyDeserializer<'_> {
+    fn number<T: Number>(&self) -> Result<T> {
+        NumberFormat::default().parse(self.key).ok_or_else(|| Error::Conversion(ConversionError::Invalid(T::NAME, self.at, self.key.to_string())))
+    }
 }
 
-impl <'de,
-    St: Expression<Tx, Tb, Dc, Dr, Cm>,
-    Tx: Text<St, Tb, Dc, Dr, Cm>,
-    Tb: Table<St, Tx, Dc, Dr, Cm>,
-    Dc: Dictionary<St, Tx, Tb, Dr, Cm>,
-    Dr: Directive<St, Tx, Tb, Dc, Cm>,
-    Cm: Component<St, Tx, Tb, Dc, Dr>,
-    It: Iterator<Item=(&str, &St)>,
-> MapAccess for DictionaryAccess<St, Tx, Tb, Dc, Dr, Cm, It> {
+macro_rules! deserialize_key_number {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                visitor.$visit(self.number()?)
+            }
+        )*
+    };
+}
 
+impl<'de> de::Deserializer<'de> for KeyDeserializer<'de> {
     type Error = Error;
 
-    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>> where K: DeserializeSeed<'de> {
-        self.current = self.iter.next();
-        if let Some((k, _)) = self.current {
-            let deserializer = StrDeserializer::new(k);
-            seed.deserialize(deserializer)
-        } else {
-            Ok(None)
-        }
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_borrowed_str(self.key)
     }
 
-    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value> where V: DeserializeSeed<'de> {
-        if let Some((_, v)) = self.current {
-            let deserializer = StructureDeserializer { value: v };
-            seed.deserialize(deserializer)
-        } else {
-            Ok(None)
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.bools.parse(self.key) {
+            Some(value) => visitor.visit_bool(value),
+            None => Err(Error::Conversion(ConversionError::Invalid("bool", self.at, self.key.to_string()))),
         }
     }
 
-    fn next_entry_seed<K, V>(&mut self, kseed: K, vseed: V) -> Result<Option<(K::Value, V::Value)>> where K: DeserializeSeed<'de>, V: DeserializeSeed<'de> {
-        self.current = self.iter.next();
-        if let Some((k, v)) = self.current {
-            let kdeserializer = StrDeserializer::new(k);
-            let vdeserializer = StructureDeserializer { value: v };
-            Ok(Some((kseed.deserialize(kdeserializer), vseed.deserialize(vdeserializer))))
-        } else {
-            Ok(None)
-        }
+    deserialize_key_number! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
     }
 
-}
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        visitor.visit_enum(BorrowedStrDeserializer::new(self.key))
+    }
 
-pub struct EnumAccess<'a, S: Expression<_, _, _, _, _>> {
-    variant: &'a str,
-    content: Option<&'a S>,
+    serde::forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
 }
 
-impl <'de, S> serde::de::EnumAccess for EnumAccess<S> {
+/// An enum variant: a tag, text for a unit variant, or a dictionary with a
+/// variant entry.
+struct Variant<'de> {
+    name: &'de str,
+    value: Option<&'de ParsedValue>,
+    /// Attributes of the tag, which are fields of a struct variant.
+    attributes: &'de [ParsedAttribute],
+    /// Position of the variant.
+    at: Position,
+    /// Key of the variant entry, when the value is an internally tagged
+    /// dictionary.
+    tag: Option<&'static str>,
+    options: DeserializerOptions,
+}
 
+impl<'de> EnumAccess<'de> for Variant<'de> {
     type Error = Error;
-
     type Variant = Self;
 
-    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)> where V: DeserializeSeed<'de> {
-        let variant = StrDeserializer::new(self.variant);
-        let variant = seed.deserialize( variant);
-        Ok((variant, self))
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self)> {
+        Ok((seed.deserialize(BorrowedStrDeserializer::new(self.name))?, self))
     }
-
 }
 
-impl <'de, S: Expression<_, _, _, _, _>> serde::de::VariantAccess for EnumAccess<S> {
-
+impl<'de> VariantAccess<'de> for Variant<'de> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
-        if self.content.is_none() {
-            Ok(())
-        } else {
-            Err(Error::InvalidEnumStructure)
+        match (self.value, self.tag) {
+            (Some(value @ ParsedValue::Dictionary(dictionary, ..)), Some(_)) if dictionary.len() > 1 => Err(Error::Conversion(ConversionError::Expected("unit variant", value.from()))),
+            (Some(value), None) if !value.is_unit() => Err(Error::Conversion(ConversionError::Expected("unit variant", value.from()))),
+            _ => Ok(()),
         }
     }
 
-    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value> where T: DeserializeSeed<'de> {
-        if let Some(inner) = self.content {
-            let inner = StructureDeserializer { value: inner };
-            seed.deserialize(inner)
-        } else {
-            Err(Error::InvalidEnumStructure)
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        match self.entries() {
+            Some(entries) => seed.deserialize(MapAccessDeserializer::new(entries)),
+            None => seed.deserialize(ValueDeserializer::with_options(self.value()?, self.options)),
         }
     }
 
-    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(inner) = self.content {
-            let inner = StructureDeserializer { value: inner };
-            inner.deserialize_seq(visitor)
-        } else {
-            Err(Error::InvalidEnumStructure)
-        }
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        if self.tag.is_some() {
+            return Err(Error::Custom(format!("Tuple variant {} cannot be internally tagged", self.name), None));
+        };
+        de::Deserializer::deserialize_tuple(ValueDeserializer::with_options(self.value()?, self.options), len, visitor)
     }
 
-    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> where V: Visitor<'de> {
-        if let Some(inner) = self.content {
-            let inner = StructureDeserializer { value: inner };
-            inner.deserialize_seq(visitor)
-        } else {
-            Err(Error::InvalidEnumStructure)
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        match self.entries() {
+            Some(entries) => {
+                if let Some(ParsedValue::Dictionary(dictionary, ..)) = self.value {
+                    check_fields(dictionary, fields, self.tag, &self.options)?;
+                };
+                visitor.visit_map(entries)
+            }
+            None => match self.value()? {
+                ParsedValue::Dictionary(dictionary, ..) => visit_struct(dictionary, self.attributes, self.at, fields, visitor, self.options),
+                value => de::Deserializer::deserialize_struct(ValueDeserializer::with_options(value, self.options), "", fields, visitor),
+            },
         }
     }
+}
 
+impl<'de> Variant<'de> {
+    fn value(&self) -> Result<&'de ParsedValue> {
+        self.value.ok_or_else(|| Error::Custom(format!("Variant {} must be a tag with a value", self.name), None))
+    }
+
+    /// Entries of an internally tagged dictionary, without the variant entry.
+    fn entries(&self) -> Option<Entries<'de>> {
+        match (self.value, self.tag) {
+            (Some(ParsedValue::Dictionary(dictionary, ..)), Some(tag)) => Some(Entries { tag: Some(tag), ..Entries::new(dictionary, self.options) }),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,258 @@
+//! Demo binary. Generates a sample document for a target, processes it
+//! through the target's backend, and writes the source and the output side by
+//! side.
+//!
+//! The targets are `html` and `tex`, which use the preprocessors, and `json`,
+//! which converts a dictionary document to JSON. Files are written to the
+//! current directory unless another directory is given.
+//!
+//! Test: cargo run --bin khi-demo --features="html tex" -- html
+//! Test: cargo run --bin khi-demo --features="html tex" -- tex build
+//! Test: cargo run --bin khi-demo --features="html tex" -- json build
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use khi::fmt::{format_dictionary_with, format_value, preview, Indent, Style};
+use khi::html::write_html;
+use khi::model::{SimpleAttributeValue, SimpleValue};
+use khi::number::NumberFormat;
+use khi::parse::{parse_dictionary_str, parse_value_str};
+use khi::parse::parser::error_to_string;
+use khi::pdm::{ParsedValue, Position};
+use khi::tex::write_tex;
+use khi::Value;
+
+/// Sample data: name, symbol, density in g/cm³ and melting point in °C.
+const ELEMENTS: [(&str, &str, &str, &str); 4] = [
+    ("Aluminium", "Al", "2.70", "660.3"),
+    ("Iron", "Fe", "7.87", "1538"),
+    ("Copper", "Cu", "8.96", "1084.6"),
+    ("Gold", "Au", "19.3", "1064.2"),
+];
+
+fn main() {
+    let mut args = env::args();
+    args.next(); // The first arg is the binary. Skip.
+    let args: Vec<String> = args.collect();
+    match demo(&args) {
+        Ok(output) => print!("{}\n\n", output),
+        Err(error) => eprint!("{}\n\n", error),
+    };
+}
+
+fn demo(args: &[String]) -> Result<String, String> {
+    let target = match args.first() {
+        Some(target) => target.as_str(),
+        None => return Err(String::from("Specify target html, tex or json as first argument.")),
+    };
+    let directory = PathBuf::from(args.get(1).map(String::as_str).unwrap_or("."));
+    let (source_name, output_name, source, output) = match target {
+        "html" => {
+            let source = html_sample();
+            let output = write_html(&parse(&source)?).map_err(|_| String::from("Cannot preprocess generated HTML document."))?;
+            ("demo.html.khi", "demo.html", source, output)
+        }
+        "tex" => {
+            let source = tex_sample();
+            let output = write_tex(&parse(&source)?).map_err(|_| String::from("Cannot preprocess generated TeX document."))?;
+            ("demo.tex.khi", "demo.tex", source, output)
+        }
+        "json" => {
+            let source = json_sample();
+            let document = parse_dictionary_str(&source).map_err(|_| String::from("Cannot parse generated dictionary document."))?;
+            let at = Position { index: 0, line: 1, column: 1 };
+            let mut output = String::new();
+            write_json(&mut output, &ParsedValue::Dictionary(document, at, at).strip_spans());
+            ("demo.khi", "demo.json", source, output)
+        }
+        target => return Err(format!("Unknown target {}. Use html, tex or json.", target)),
+    };
+    fs::create_dir_all(&directory).map_err(|e| format!("Cannot create {}: {}", directory.display(), e))?;
+    for (name, content) in [(source_name, &source), (output_name, &output)] {
+        let path = directory.join(name);
+        fs::write(&path, content).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+    };
+    Ok(format!("Wrote {} and {} to {}.", source_name, output_name, directory.display()))
+}
+
+/// Parse a generated value document, and check that it survives formatting.
+fn parse(source: &str) -> Result<ParsedValue, String> {
+    let document = parse_value_str(source).map_err(|errors| {
+        errors.iter().map(error_to_string).collect::<Vec<_>>().join("\n")
+    })?;
+    let formatted = parse_value_str(&format_value(&document)).map_err(|_| String::from("Formatted document does not parse."))?;
+    if !formatted.canonical_eq(&document) {
+        return Err(format!("Formatting changed the document {}.", preview(&document, 60)));
+    };
+    Ok(document)
+}
+
+/// An HTML page with a table of the sample data.
+fn html_sample() -> String {
+    let zero = Position::ZERO;
+    let cells = |cells: [&str; 4], tag: &str| {
+        let cells = cells.iter().map(|c| ParsedValue::tagged(tag, vec![], ParsedValue::text(c))).collect();
+        ParsedValue::tagged("tr", vec![], ParsedValue::from_terms(zero, zero, cells, vec![true; 3]))
+    };
+    let mut rows = vec![cells(["Element", "Symbol", "Density", "Melting point"], "th")];
+    rows.extend(ELEMENTS.iter().map(|&(name, symbol, density, melting)| cells([name, symbol, density, melting], "td")));
+    let rows: Vec<String> = rows.iter().map(|row| format!("      {}", format_value(row))).collect();
+    format!(
+"# Generated by khi-demo.
+# Document type: Value
+
+<doctype!>:html
+<html>:{{
+  <head>:{{
+    <title>:{{Elements}}
+  }}
+  <body>:{{
+    <h1>:{{Elements}}
+    <p>:{{Density in g/cm³ and melting point in °C.}}
+    <table class:elements>:{{
+{}
+    }}
+  }}
+}}
+", rows.join("\n"))
+}
+
+/// A LaTeX document with a table of the sample data and an equation.
+fn tex_sample() -> String {
+    let zero = Position::ZERO;
+    let mut rows = vec![ParsedValue::from_tuple(["Element", "Density", "Melting point"].iter().map(|c| ParsedValue::text(c)).collect(), zero, zero)];
+    rows.extend(ELEMENTS.iter().map(|&(name, _, density, melting)| {
+        ParsedValue::from_tuple(vec![ParsedValue::text(name), ParsedValue::text(density), ParsedValue::text(melting)], zero, zero)
+    }));
+    format!(
+"# Generated by khi-demo.
+# Document type: Value
+
+<documentclass>:article
+<usepackage>:amsmath
+
+<begin>:document
+
+<section>:Elements
+
+Density in g/cm³ and melting point in °C.
+
+<begin>:tabular:lrr {} <end>:tabular
+
+The density <def!>:Density:0:{{<rho>}} <$>:<Density> of a body is its mass per volume,
+
+<begin>:equation* <Density> = <frac>:m:V <end>:equation*
+
+<end>:document
+", format_value(&ParsedValue::list(rows)))
+}
+
+/// A dictionary document of the sample data, indented with two spaces.
+fn json_sample() -> String {
+    let elements = ELEMENTS.iter().map(|&(name, symbol, density, melting)| {
+        ParsedValue::dictionary(vec![
+            ("name", ParsedValue::text(name)),
+            ("symbol", ParsedValue::text(symbol)),
+            ("density", ParsedValue::text(density)),
+            ("melting-point", ParsedValue::text(melting)),
+        ])
+    }).collect();
+    let document = ParsedValue::dictionary(vec![
+        ("title", ParsedValue::text("Elements")),
+        ("units", ParsedValue::dictionary(vec![("density", ParsedValue::text("g/cm³")), ("melting-point", ParsedValue::text("°C"))])),
+        ("elements", ParsedValue::list(elements)),
+    ]);
+    let style = Style { indent: Some(Indent::Spaces(2)), ..Style::default() };
+    format!("# Generated by khi-demo.\n# Document type: Dictionary\n\n{}\n", format_dictionary_with(document.as_dictionary().unwrap(), &style))
+}
+
+/// Write a value as JSON.
+///
+/// Text that is a number is written as a number. Tuples, lists and compounds
+/// are written as arrays, and tagged values as objects with the entries
+/// `tag`, `attributes` and `value`.
+fn write_json(output: &mut String, value: &SimpleValue) {
+    match value {
+        SimpleValue::Text(text) if is_json_number(text) => output.push_str(text),
+        SimpleValue::Text(text) => write_json_string(output, text),
+        SimpleValue::Tagged(tagged) => {
+            output.push_str("{\"tag\": ");
+            write_json_string(output, &tagged.name);
+            output.push_str(", \"attributes\": {");
+            for (i, (key, value)) in tagged.attributes.iter().enumerate() {
+                if i != 0 {
+                    output.push_str(", ");
+                };
+                write_json_string(output, key);
+                output.push_str(": ");
+                match value {
+                    Some(SimpleAttributeValue::Text(value)) => write_json_string(output, value),
+                    Some(SimpleAttributeValue::List(strings)) => {
+                        output.push('[');
+                        for (i, string) in strings.iter().enumerate() {
+                            if i != 0 {
+                                output.push_str(", ");
+                            };
+                            write_json_string(output, string);
+                        };
+                        output.push(']');
+                    }
+                    None => output.push_str("true"),
+                };
+            };
+            output.push_str("}, \"value\": ");
+            write_json(output, &tagged.value);
+            output.push('}');
+        }
+        SimpleValue::Dictionary(dictionary) => {
+            output.push('{');
+            for (i, (key, value)) in dictionary.entries.iter().enumerate() {
+                if i != 0 {
+                    output.push_str(", ");
+                };
+                write_json_string(output, key);
+                output.push_str(": ");
+                write_json(output, value);
+            };
+            output.push('}');
+        }
+        SimpleValue::Tuple(elements) | SimpleValue::List(elements) => write_json_array(output, elements),
+        SimpleValue::Compound(compound) => write_json_array(output, &compound.components),
+        SimpleValue::Nil => output.push_str("null"),
+    };
+}
+
+/// Check if text is a number in JSON syntax, which is stricter than the
+/// strict number format about leading zeros and decimal points.
+fn is_json_number(text: &str) -> bool {
+    let unsigned = text.strip_prefix('-').unwrap_or(text);
+    let leading_zero = unsigned.len() > 1 && unsigned.starts_with('0') && !unsigned.starts_with("0.");
+    NumberFormat::STRICT.is_number(text) && !unsigned.starts_with('.') && !unsigned.ends_with('.') && !leading_zero
+}
+
+fn write_json_array(output: &mut String, elements: &[SimpleValue]) {
+    output.push('[');
+    for (i, element) in elements.iter().enumerate() {
+        if i != 0 {
+            output.push_str(", ");
+        };
+        write_json(output, element);
+    };
+    output.push(']');
+}
+
+fn write_json_string(output: &mut String, text: &str) {
+    output.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        };
+    };
+    output.push('"');
+}
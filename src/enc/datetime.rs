@@ -0,0 +1,556 @@
+//! Dates, times and durations in text.
+//!
+//! Documents store timestamps as text, which is read with
+//! [decode_datetime](crate::enc::DecodeTextExt::decode_datetime) and the
+//! related methods, and written with the `encode_` functions of
+//! [encode](crate::enc::encode). The types are those of the `chrono` and
+//! `time` features, and [SystemTime](std::time::SystemTime) and
+//! [Duration](std::time::Duration) of the standard library.
+//!
+//! Timestamps are read in RFC 3339, like `2024-05-01T12:00:00Z`, and in a few
+//! forms that people write:
+//!
+//! - `/` between the parts of a date, like `2024/05/01`.
+//! - A space between date and time, and a time without seconds, like
+//!   `2024-05-01 12:00`.
+//! - No offset, or `UTC`, for times in UTC, like `2024-05-01 12:00 UTC`.
+//! - An offset without `:`, like `+0200`.
+//! - A date alone, for midnight.
+//!
+//! Dates are also read with the name of the month, like `1 May 2024` and
+//! `May 1, 2024`. Durations are read as amounts of units like `5m 30s`, in
+//! ISO 8601 like `PT5M30S`, and as a clock like `1:05:30`, with an optional
+//! `-` for types that can be negative.
+//!
+//! Timestamps are written in RFC 3339, dates as `2024-05-01`, and durations
+//! in units like `5m 30s`. A leap second is read as the second before it.
+
+const MONTHS: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june", "july", "august", "september", "october", "november", "december",
+];
+
+const UNITS: [(&str, i128); 7] = [
+    ("d", 86_400_000_000_000), ("h", 3_600_000_000_000), ("m", 60_000_000_000), ("s", 1_000_000_000),
+    ("ms", 1_000_000), ("us", 1_000), ("ns", 1),
+];
+
+/// A date in the proleptic Gregorian calendar.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateParts {
+    pub year: i32,
+    /// From 1 to 12.
+    pub month: u32,
+    /// From 1.
+    pub day: u32,
+}
+
+/// A date and a time of day, with an offset from UTC in seconds if known.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateTimeParts {
+    pub date: DateParts,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub nanosecond: u32,
+    pub offset: Option<i32>,
+}
+
+/// A date type.
+pub trait Date: Sized {
+    /// Name of the type in errors.
+    const NAME: &'static str;
+
+    /// Get the date, if it is valid for the type.
+    fn from_parts(parts: DateParts) -> Option<Self>;
+
+    fn to_parts(&self) -> DateParts;
+}
+
+/// A date and time type.
+pub trait DateTime: Sized {
+    /// Name of the type in errors.
+    const NAME: &'static str;
+
+    /// Get the date and time, if it is valid for the type. Types without an
+    /// offset take the time as it is, and types with one take a time without
+    /// an offset as UTC.
+    fn from_parts(parts: DateTimeParts) -> Option<Self>;
+
+    fn to_parts(&self) -> DateTimeParts;
+}
+
+/// A duration type.
+pub trait Duration: Sized {
+    /// Name of the type in errors.
+    const NAME: &'static str;
+
+    /// Get a duration of a number of nanoseconds, if the type can hold it.
+    fn from_nanos(nanos: i128) -> Option<Self>;
+
+    fn to_nanos(&self) -> i128;
+}
+
+/// Read a date.
+pub fn parse_date(text: &str) -> Option<DateParts> {
+    parse_numeric_date(text).or_else(|| parse_named_date(text))
+}
+
+/// Read a date and time.
+pub fn parse_datetime(text: &str) -> Option<DateTimeParts> {
+    let date = parse_numeric_date(text.get(..10)?)?;
+    let rest = &text[10..];
+    if rest.is_empty() {
+        return Some(DateTimeParts { date, hour: 0, minute: 0, second: 0, nanosecond: 0, offset: None });
+    };
+    let rest = rest.strip_prefix(['T', 't', ' '])?;
+    let (hour, minute) = (digits(rest.get(0..2)?)?, digits(rest.get(3..5)?)?);
+    if rest.as_bytes()[2] != b':' {
+        return None;
+    };
+    let mut rest = &rest[5..];
+    let mut second = 0;
+    let mut nanosecond = 0;
+    if let Some(seconds) = rest.strip_prefix(':') {
+        second = digits(seconds.get(0..2)?)?;
+        rest = &seconds[2..];
+        if let Some(fraction) = rest.strip_prefix('.') {
+            let count = fraction.bytes().take_while(u8::is_ascii_digit).count();
+            if count == 0 {
+                return None;
+            };
+            for (i, digit) in fraction[..count].bytes().take(9).enumerate() {
+                nanosecond += (digit - b'0') as u32 * 10u32.pow(8 - i as u32);
+            };
+            rest = &fraction[count..];
+        };
+    };
+    let offset = match rest.trim_start_matches(' ') {
+        "" => None,
+        "Z" | "z" | "UTC" => Some(0),
+        offset => {
+            let (sign, offset) = match offset.as_bytes()[0] {
+                b'+' => (1, &offset[1..]),
+                b'-' => (-1, &offset[1..]),
+                _ => return None,
+            };
+            let (hours, minutes) = match offset.len() {
+                2 => (digits(offset)?, 0),
+                4 => (digits(&offset[..2])?, digits(&offset[2..])?),
+                5 if offset.as_bytes()[2] == b':' => (digits(&offset[..2])?, digits(&offset[3..])?),
+                _ => return None,
+            };
+            if hours > 23 || minutes > 59 {
+                return None;
+            };
+            Some(sign * (hours * 3600 + minutes * 60) as i32)
+        }
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    };
+    Some(DateTimeParts { date, hour, minute, second: second.min(59), nanosecond, offset })
+}
+
+/// Read a duration as a number of nanoseconds.
+pub fn parse_duration(text: &str) -> Option<i128> {
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(text) => (-1, text),
+        None => (1, text),
+    };
+    let nanos = if text.starts_with(['P', 'p']) {
+        parse_iso_duration(&text[1..])?
+    } else if text.contains(':') {
+        parse_clock_duration(text)?
+    } else {
+        parse_unit_duration(text)?
+    };
+    Some(sign * nanos)
+}
+
+/// Write a date as `2024-05-01`.
+pub fn format_date(date: DateParts) -> String {
+    format!("{:04}-{:02}-{:02}", date.year, date.month, date.day)
+}
+
+/// Write a date and time in RFC 3339, with as many fractional digits as
+/// needed. A time without an offset is written in UTC.
+pub fn format_datetime(datetime: DateTimeParts) -> String {
+    let mut text = format!("{}T{:02}:{:02}:{:02}", format_date(datetime.date), datetime.hour, datetime.minute, datetime.second);
+    if datetime.nanosecond != 0 {
+        text.push_str(format!(".{:09}", datetime.nanosecond).trim_end_matches('0'));
+    };
+    match datetime.offset.unwrap_or(0) {
+        0 => text.push('Z'),
+        offset => {
+            let minutes = offset.unsigned_abs() / 60;
+            text.push_str(&format!("{}{:02}:{:02}", if offset < 0 { '-' } else { '+' }, minutes / 60, minutes % 60));
+        }
+    };
+    text
+}
+
+/// Write a duration with the largest units first, like `5m 30s`. Zero is
+/// `0s`.
+pub fn format_duration(nanos: i128) -> String {
+    if nanos == 0 {
+        return String::from("0s");
+    };
+    let mut rest = nanos.unsigned_abs();
+    let mut parts = vec![];
+    for (unit, size) in UNITS {
+        let size = size as u128;
+        if rest >= size {
+            parts.push(format!("{}{}", rest / size, unit));
+            rest %= size;
+        };
+    };
+    let text = parts.join(" ");
+    if nanos < 0 { format!("-{}", text) } else { text }
+}
+
+/// Read `2024-05-01` or `2024/05/01`.
+fn parse_numeric_date(text: &str) -> Option<DateParts> {
+    let bytes = text.as_bytes();
+    if bytes.len() != 10 || !matches!(bytes[4], b'-' | b'/') || bytes[7] != bytes[4] {
+        return None;
+    };
+    date(digits(&text[0..4])? as i32, digits(&text[5..7])?, digits(&text[8..10])?)
+}
+
+/// Read `1 May 2024` or `May 1, 2024`, with the name of the month in any
+/// case, or its first three letters.
+fn parse_named_date(text: &str) -> Option<DateParts> {
+    let words: Vec<&str> = text.split(' ').filter(|word| !word.is_empty()).collect();
+    let (day, month, year) = match words[..] {
+        [day, month, year] if day.starts_with(|c: char| c.is_ascii_digit()) => (day, month, year),
+        [month, day, year] => (day.strip_suffix(',')?, month, year),
+        _ => return None,
+    };
+    let month = month.to_lowercase();
+    let month = MONTHS.iter().position(|name| *name == month || (month.len() == 3 && name.starts_with(&month)))? as u32 + 1;
+    if day.len() > 2 || year.len() != 4 {
+        return None;
+    };
+    date(digits(year)? as i32, month, digits(day)?)
+}
+
+fn date(year: i32, month: u32, day: u32) -> Option<DateParts> {
+    if (1..=12).contains(&month) && day >= 1 && day <= days_in_month(year, month) {
+        Some(DateParts { year, month, day })
+    } else {
+        None
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Read ASCII digits.
+fn digits(text: &str) -> Option<u32> {
+    if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) { text.parse().ok() } else { None }
+}
+
+/// Read amounts of units separated by spaces, like `5m 30s`.
+fn parse_unit_duration(text: &str) -> Option<i128> {
+    let mut nanos: i128 = 0;
+    let mut parts = text.split_whitespace().peekable();
+    parts.peek()?;
+    for part in parts {
+        let count = part.bytes().take_while(u8::is_ascii_digit).count();
+        let amount: i128 = digits(&part[..count])?.into();
+        let (_, size) = UNITS.iter().find(|(unit, _)| *unit == &part[count..])?;
+        nanos = nanos.checked_add(amount * size)?;
+    };
+    Some(nanos)
+}
+
+/// Read an ISO 8601 duration after the `P`, like `1DT2H30M` or `T0.5S`.
+/// Years and months are not read, as they have no fixed length.
+fn parse_iso_duration(text: &str) -> Option<i128> {
+    let (days, time) = match text.split_once(['T', 't']) {
+        Some((days, time)) if !time.is_empty() => (days, Some(time)),
+        Some(_) => return None,
+        None => (text, None),
+    };
+    let mut nanos: i128 = 0;
+    if !days.is_empty() {
+        let days = days.strip_suffix(['D', 'd']).and_then(digits)?;
+        nanos += i128::from(days) * UNITS[0].1;
+    };
+    let mut rest = time.unwrap_or("");
+    for (designator, size) in [('H', UNITS[1].1), ('M', UNITS[2].1), ('S', UNITS[3].1)] {
+        let Some((amount, after)) = rest.split_once([designator, designator.to_ascii_lowercase()]) else {
+            continue;
+        };
+        nanos = nanos.checked_add(if designator == 'S' { seconds(amount)? } else { i128::from(digits(amount)?) * size })?;
+        rest = after;
+    };
+    if !rest.is_empty() || (days.is_empty() && time.is_none()) {
+        return None;
+    };
+    Some(nanos)
+}
+
+/// Read a clock like `1:05:30` or `1:05:30.5`.
+fn parse_clock_duration(text: &str) -> Option<i128> {
+    let mut parts = text.split(':');
+    let (hours, minutes, rest) = (digits(parts.next()?)?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || minutes.len() != 2 || rest.get(..2).map_or(true, |s| s.contains('.')) {
+        return None;
+    };
+    let minutes = digits(minutes)?;
+    let seconds = seconds(rest)?;
+    if minutes > 59 || seconds >= UNITS[2].1 {
+        return None;
+    };
+    Some(i128::from(hours) * UNITS[1].1 + i128::from(minutes) * UNITS[2].1 + seconds)
+}
+
+/// Read seconds with an optional fraction as nanoseconds.
+fn seconds(text: &str) -> Option<i128> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let mut nanos = i128::from(digits(whole)?) * UNITS[3].1;
+    if text.contains('.') && digits(fraction).is_none() {
+        return None;
+    };
+    for (i, digit) in fraction.bytes().take(9).enumerate() {
+        nanos += i128::from(digit - b'0') * 10i128.pow(8 - i as u32);
+    };
+    Some(nanos)
+}
+
+/// Days since 1970-01-01 of a date.
+fn days_from_civil(date: DateParts) -> i64 {
+    let (year, month, day) = (i64::from(date.year), i64::from(date.month), i64::from(date.day));
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Date of a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> Option<DateParts> {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    Some(DateParts { year: i32::try_from(year).ok()?, month: month as u32, day: day as u32 })
+}
+
+impl DateTime for std::time::SystemTime {
+    const NAME: &'static str = "timestamp";
+
+    fn from_parts(parts: DateTimeParts) -> Option<Self> {
+        let seconds = days_from_civil(parts.date) * 86400
+            + i64::from(parts.hour * 3600 + parts.minute * 60 + parts.second) - i64::from(parts.offset.unwrap_or(0));
+        let epoch = std::time::UNIX_EPOCH;
+        if seconds >= 0 {
+            epoch.checked_add(std::time::Duration::new(seconds as u64, parts.nanosecond))
+        } else {
+            epoch.checked_sub(std::time::Duration::from_secs(seconds.unsigned_abs()))?.checked_add(std::time::Duration::new(0, parts.nanosecond))
+        }
+    }
+
+    fn to_parts(&self) -> DateTimeParts {
+        // Seconds and nanoseconds since the epoch, with non-negative
+        // nanoseconds.
+        let (seconds, nanosecond) = match self.duration_since(std::time::UNIX_EPOCH) {
+            Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+            Err(error) => {
+                let until = error.duration();
+                match until.subsec_nanos() {
+                    0 => (-(until.as_secs() as i64), 0),
+                    nanos => (-(until.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+                }
+            }
+        };
+        let date = civil_from_days(seconds.div_euclid(86400)).expect("a system time is within the years of i32");
+        let second = seconds.rem_euclid(86400) as u32;
+        DateTimeParts { date, hour: second / 3600, minute: second / 60 % 60, second: second % 60, nanosecond, offset: Some(0) }
+    }
+}
+
+impl Duration for std::time::Duration {
+    const NAME: &'static str = "duration";
+
+    fn from_nanos(nanos: i128) -> Option<Self> {
+        let seconds = u64::try_from(nanos.div_euclid(1_000_000_000)).ok()?;
+        Some(std::time::Duration::new(seconds, nanos.rem_euclid(1_000_000_000) as u32))
+    }
+
+    fn to_nanos(&self) -> i128 {
+        self.as_nanos() as i128
+    }
+}
+
+#[cfg(feature = "chrono")]
+mod chrono_types {
+    use chrono::{Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike, Utc};
+    use super::{Date, DateParts, DateTime, DateTimeParts, Duration};
+
+    fn naive(parts: DateTimeParts) -> Option<NaiveDateTime> {
+        let date = NaiveDate::from_parts(parts.date)?;
+        let time = NaiveTime::from_hms_nano_opt(parts.hour, parts.minute, parts.second, parts.nanosecond)?;
+        Some(NaiveDateTime::new(date, time))
+    }
+
+    fn parts(datetime: NaiveDateTime, offset: Option<i32>) -> DateTimeParts {
+        DateTimeParts {
+            date: datetime.date().to_parts(),
+            hour: datetime.hour(),
+            minute: datetime.minute(),
+            // A leap second is a nanosecond of a second or more.
+            second: datetime.second(),
+            nanosecond: datetime.nanosecond().min(999_999_999),
+            offset,
+        }
+    }
+
+    impl Date for NaiveDate {
+        const NAME: &'static str = "date";
+
+        fn from_parts(parts: DateParts) -> Option<Self> {
+            NaiveDate::from_ymd_opt(parts.year, parts.month, parts.day)
+        }
+
+        fn to_parts(&self) -> DateParts {
+            DateParts { year: self.year(), month: self.month(), day: self.day() }
+        }
+    }
+
+    impl DateTime for NaiveDateTime {
+        const NAME: &'static str = "date and time";
+
+        fn from_parts(parts: DateTimeParts) -> Option<Self> {
+            naive(parts)
+        }
+
+        fn to_parts(&self) -> DateTimeParts {
+            parts(*self, None)
+        }
+    }
+
+    impl DateTime for chrono::DateTime<FixedOffset> {
+        const NAME: &'static str = "timestamp";
+
+        fn from_parts(parts: DateTimeParts) -> Option<Self> {
+            FixedOffset::east_opt(parts.offset.unwrap_or(0))?.from_local_datetime(&naive(parts)?).single()
+        }
+
+        fn to_parts(&self) -> DateTimeParts {
+            parts(self.naive_local(), Some(self.offset().local_minus_utc()))
+        }
+    }
+
+    impl DateTime for chrono::DateTime<Utc> {
+        const NAME: &'static str = "timestamp";
+
+        fn from_parts(parts: DateTimeParts) -> Option<Self> {
+            Some(chrono::DateTime::<FixedOffset>::from_parts(parts)?.with_timezone(&Utc))
+        }
+
+        fn to_parts(&self) -> DateTimeParts {
+            parts(self.naive_utc(), Some(0))
+        }
+    }
+
+    impl Duration for TimeDelta {
+        const NAME: &'static str = "duration";
+
+        fn from_nanos(nanos: i128) -> Option<Self> {
+            let seconds = i64::try_from(nanos.div_euclid(1_000_000_000)).ok()?;
+            TimeDelta::new(seconds, nanos.rem_euclid(1_000_000_000) as u32)
+        }
+
+        fn to_nanos(&self) -> i128 {
+            i128::from(self.num_seconds()) * 1_000_000_000 + i128::from(self.subsec_nanos())
+        }
+    }
+}
+
+#[cfg(feature = "time")]
+mod time_types {
+    use time::{Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+    use super::{Date, DateParts, DateTime, DateTimeParts, Duration};
+
+    fn primitive(parts: DateTimeParts) -> Option<PrimitiveDateTime> {
+        let date = time::Date::from_parts(parts.date)?;
+        let time = Time::from_hms_nano(parts.hour as u8, parts.minute as u8, parts.second as u8, parts.nanosecond).ok()?;
+        Some(PrimitiveDateTime::new(date, time))
+    }
+
+    fn parts(datetime: PrimitiveDateTime, offset: Option<i32>) -> DateTimeParts {
+        DateTimeParts {
+            date: datetime.date().to_parts(),
+            hour: datetime.hour().into(),
+            minute: datetime.minute().into(),
+            second: datetime.second().into(),
+            nanosecond: datetime.nanosecond(),
+            offset,
+        }
+    }
+
+    impl Date for time::Date {
+        const NAME: &'static str = "date";
+
+        fn from_parts(parts: DateParts) -> Option<Self> {
+            let month = Month::try_from(u8::try_from(parts.month).ok()?).ok()?;
+            time::Date::from_calendar_date(parts.year, month, u8::try_from(parts.day).ok()?).ok()
+        }
+
+        fn to_parts(&self) -> DateParts {
+            DateParts { year: self.year(), month: u8::from(self.month()).into(), day: self.day().into() }
+        }
+    }
+
+    impl DateTime for PrimitiveDateTime {
+        const NAME: &'static str = "date and time";
+
+        fn from_parts(parts: DateTimeParts) -> Option<Self> {
+            primitive(parts)
+        }
+
+        fn to_parts(&self) -> DateTimeParts {
+            parts(*self, None)
+        }
+    }
+
+    impl DateTime for OffsetDateTime {
+        const NAME: &'static str = "timestamp";
+
+        fn from_parts(parts: DateTimeParts) -> Option<Self> {
+            let offset = UtcOffset::from_whole_seconds(parts.offset.unwrap_or(0)).ok()?;
+            Some(primitive(parts)?.assume_offset(offset))
+        }
+
+        fn to_parts(&self) -> DateTimeParts {
+            parts(PrimitiveDateTime::new(self.date(), self.time()), Some(self.offset().whole_seconds()))
+        }
+    }
+
+    impl Duration for time::Duration {
+        const NAME: &'static str = "duration";
+
+        fn from_nanos(nanos: i128) -> Option<Self> {
+            let seconds = i64::try_from(nanos / 1_000_000_000).ok()?;
+            Some(time::Duration::new(seconds, (nanos % 1_000_000_000) as i32))
+        }
+
+        fn to_nanos(&self) -> i128 {
+            self.whole_nanoseconds()
+        }
+    }
+}
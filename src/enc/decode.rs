@@ -0,0 +1,339 @@
+//! Typed decoding of values, for programs that do not use serde.
+//!
+//! [DecodeTextExt] and [DecodeValueExt] are implemented for every [Text] and
+//! [Value], so they work with [ParsedValue](crate::pdm::ParsedValue) and any
+//! other implementation of the value traits:
+//!
+//! ```
+//! # use khi::enc::DecodeValueExt;
+//! # use khi::Dictionary;
+//! let document = khi::parse::parse_dictionary_str("port: 8080; hosts: [a; b]; ratio: 0.5").unwrap();
+//! assert_eq!(document.get("port").unwrap().decode_int(), Ok(8080));
+//! assert_eq!(document.get("hosts").unwrap().decode_list(|host| host.decode_str().map(String::from)), Ok(vec![String::from("a"), String::from("b")]));
+//! assert_eq!(document.get("ratio").unwrap().decode_float(), Ok(0.5));
+//! ```
+//!
+//! Numbers are recognized in the default [NumberFormat], like in the other
+//! conversions of the crate. `decode_number_with` reads numbers in another
+//! format, like [NumberFormat::LENIENT] for data exported from programming
+//! languages, with numbers like `0x1F`, `+5` and `1_000_000`, or
+//! [NumberFormat::STRICT] for plain decimal numbers only. Likewise,
+//! `decode_bool_with` reads booleans like `yes` and `off` in a [BoolFormat]. The values have no positions, so errors do not
+//! say where a value is. [TryFrom] conversions of parsed values report
+//! positions.
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crate::boolean::BoolFormat;
+use crate::enc::datetime::{parse_date, parse_datetime, parse_duration, Date, DateTime, Duration};
+use crate::enc::quantity::{parse_quantity, Units};
+use crate::number::{Number, NumberFormat};
+use crate::{Compound, Dictionary, List, Tagged, Text, Tuple, Value};
+
+/// An error decoding a value to a Rust type.
+#[derive(PartialEq, Eq, Clone)]
+pub enum DecodeError {
+    /// Expected a kind of value, such as text or a list.
+    Expected(&'static str),
+    /// Text is not a valid value of a type.
+    Invalid(&'static str, String),
+    /// Text is not a valid value of a type, from a byte offset in the text.
+    InvalidAt(&'static str, String, usize),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Expected(expected) => write!(f, "Expected {}.", expected),
+            DecodeError::Invalid(ty, text) => write!(f, "Invalid {} ⟨{}⟩.", ty, text),
+            DecodeError::InvalidAt(ty, text, offset) => write!(f, "Invalid {} ⟨{}⟩ at byte {}.", ty, text, offset),
+        }
+    }
+}
+
+impl Debug for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decoding of primitives from text.
+pub trait DecodeTextExt<
+    Vl: Value<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tx: Text<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Dc: Dictionary<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Ls: List<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Cm: Compound<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tp: Tuple<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tg: Tagged<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+>: Text<Vl, Tx, Dc, Ls, Cm, Tp, Tg> {
+    /// Decode `true` or `false`.
+    fn decode_bool(&self) -> Result<bool, DecodeError> {
+        self.decode_bool_with(&BoolFormat::default())
+    }
+
+    /// Decode a boolean in a format, like `yes` or `off`.
+    fn decode_bool_with(&self, format: &BoolFormat) -> Result<bool, DecodeError> {
+        format.parse(self.as_str()).ok_or_else(|| DecodeError::Invalid("bool", self.as_str().to_string()))
+    }
+
+    /// Decode a number of any [Number] type, like `u8`, `i128`, `f64` or
+    /// `NonZeroU32`.
+    fn decode_number<T: Number>(&self) -> Result<T, DecodeError> {
+        self.decode_number_with(&NumberFormat::default())
+    }
+
+    /// Decode a number of any [Number] type in a format.
+    fn decode_number_with<T: Number>(&self, format: &NumberFormat) -> Result<T, DecodeError> {
+        format.parse(self.as_str()).ok_or_else(|| DecodeError::Invalid(T::NAME, self.as_str().to_string()))
+    }
+
+    /// Decode an integer.
+    fn decode_int(&self) -> Result<i64, DecodeError> {
+        self.decode_number()
+    }
+
+    /// Decode a floating point number.
+    fn decode_float(&self) -> Result<f64, DecodeError> {
+        self.decode_number()
+    }
+
+    /// Decode a decimal number without rounding, like `19.99`. Text with
+    /// more digits than a `Decimal` holds is not a decimal.
+    #[cfg(feature = "decimal")]
+    fn decode_decimal(&self) -> Result<rust_decimal::Decimal, DecodeError> {
+        self.decode_number()
+    }
+
+    /// Decode a single character.
+    fn decode_char(&self) -> Result<char, DecodeError> {
+        let mut chars = self.as_str().chars();
+        match (chars.next(), chars.next()) {
+            (Some(char), None) => Ok(char),
+            _ => Err(DecodeError::Invalid("char", self.as_str().to_string())),
+        }
+    }
+
+    /// Decode text with [FromStr], for types like identifiers and addresses
+    /// that have no decoding method of their own. Errors name the type
+    /// without its path.
+    fn decode_from_str<T: FromStr>(&self) -> Result<T, DecodeError> {
+        let name = std::any::type_name::<T>();
+        let name = name.rsplit("::").next().unwrap_or(name);
+        self.as_str().parse().map_err(|_| DecodeError::Invalid(name, self.as_str().to_string()))
+    }
+
+    /// Decode a UUID, hyphenated like `67e55044-10b1-426f-9247-bb680e5fe0c8`,
+    /// or in the other forms of [Uuid::parse_str](uuid::Uuid::parse_str).
+    #[cfg(feature = "uuid")]
+    fn decode_uuid(&self) -> Result<uuid::Uuid, DecodeError> {
+        uuid::Uuid::parse_str(self.as_str()).map_err(|_| DecodeError::Invalid("uuid", self.as_str().to_string()))
+    }
+
+    /// Decode hexadecimal bytes, like `00ff`. Errors point at the first
+    /// invalid character, or at the end of text of odd length.
+    fn decode_hex(&self) -> Result<Vec<u8>, DecodeError> {
+        let text = self.as_str();
+        hex::decode(text).map_err(|error| {
+            let offset = match error {
+                hex::FromHexError::InvalidHexCharacter { index, .. } => index,
+                _ => text.len(),
+            };
+            DecodeError::InvalidAt("hex", text.to_string(), offset)
+        })
+    }
+
+    /// Decode standard base64 bytes with padding, like `AP8=`. Errors point
+    /// at the first invalid character, or at the end of text of invalid
+    /// length or padding.
+    fn decode_base64(&self) -> Result<Vec<u8>, DecodeError> {
+        let text = self.as_str();
+        STANDARD.decode(text).map_err(|error| {
+            let offset = match error {
+                base64::DecodeError::InvalidByte(offset, _) | base64::DecodeError::InvalidLastSymbol(offset, _) => offset,
+                _ => text.len(),
+            };
+            DecodeError::InvalidAt("base64", text.to_string(), offset)
+        })
+    }
+
+    /// Decode a number and a unit, like `2.70 g/cm3`. The unit is empty if
+    /// there is none.
+    fn decode_quantity(&self) -> Result<(f64, &str), DecodeError> {
+        parse_quantity(self.as_str()).ok_or_else(|| DecodeError::Invalid("quantity", self.as_str().to_string()))
+    }
+
+    /// Decode a quantity, and convert it to a unit.
+    fn decode_quantity_in(&self, unit: &str, units: &Units) -> Result<f64, DecodeError> {
+        let (value, from) = self.decode_quantity()?;
+        units.convert(value, from, unit).ok_or_else(|| DecodeError::Invalid("quantity", self.as_str().to_string()))
+    }
+
+    /// Decode a date, like `2024-05-01` or `1 May 2024`. The forms are
+    /// listed in [datetime](crate::enc::datetime).
+    fn decode_date<T: Date>(&self) -> Result<T, DecodeError> {
+        parse_date(self.as_str()).and_then(T::from_parts).ok_or_else(|| DecodeError::Invalid(T::NAME, self.as_str().to_string()))
+    }
+
+    /// Decode a date and time, like `2024-05-01T12:00:00Z` or
+    /// `2024-05-01 12:00`.
+    fn decode_datetime<T: DateTime>(&self) -> Result<T, DecodeError> {
+        parse_datetime(self.as_str()).and_then(T::from_parts).ok_or_else(|| DecodeError::Invalid(T::NAME, self.as_str().to_string()))
+    }
+
+    /// Decode a duration, like `5m 30s`, `PT5M30S` or `0:05:30`.
+    fn decode_duration<T: Duration>(&self) -> Result<T, DecodeError> {
+        parse_duration(self.as_str()).and_then(T::from_nanos).ok_or_else(|| DecodeError::Invalid(T::NAME, self.as_str().to_string()))
+    }
+}
+
+impl<
+    Vl: Value<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tx: Text<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Dc: Dictionary<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Ls: List<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Cm: Compound<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tp: Tuple<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tg: Tagged<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    T: Text<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+> DecodeTextExt<Vl, Tx, Dc, Ls, Cm, Tp, Tg> for T {}
+
+/// Decoding of values.
+///
+/// Primitives are decoded from text. Lists are decoded from lists or tuples,
+/// and dictionaries from dictionaries, with a function that decodes each
+/// element.
+pub trait DecodeValueExt<
+    Vl: Value<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tx: Text<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Dc: Dictionary<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Ls: List<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Cm: Compound<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tp: Tuple<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tg: Tagged<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+>: Value<Vl, Tx, Dc, Ls, Cm, Tp, Tg> {
+    /// Get the text of a text value.
+    fn decode_str<'a>(&'a self) -> Result<&'a str, DecodeError> where Tx: 'a {
+        self.as_text().map(Text::as_str).ok_or(DecodeError::Expected("text"))
+    }
+
+    /// Decode text as `true` or `false`.
+    fn decode_bool(&self) -> Result<bool, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_bool()
+    }
+
+    /// Decode text as a boolean in a format.
+    fn decode_bool_with(&self, format: &BoolFormat) -> Result<bool, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_bool_with(format)
+    }
+
+    /// Decode text as a number of any [Number] type.
+    fn decode_number<T: Number>(&self) -> Result<T, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_number()
+    }
+
+    /// Decode text as a number of any [Number] type in a format.
+    fn decode_number_with<T: Number>(&self, format: &NumberFormat) -> Result<T, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_number_with(format)
+    }
+
+    /// Decode text as an integer.
+    fn decode_int(&self) -> Result<i64, DecodeError> {
+        self.decode_number()
+    }
+
+    /// Decode text as a floating point number.
+    fn decode_float(&self) -> Result<f64, DecodeError> {
+        self.decode_number()
+    }
+
+    /// Decode text as a decimal number.
+    #[cfg(feature = "decimal")]
+    fn decode_decimal(&self) -> Result<rust_decimal::Decimal, DecodeError> {
+        self.decode_number()
+    }
+
+    /// Decode text as a single character.
+    fn decode_char(&self) -> Result<char, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_char()
+    }
+
+    /// Decode text with [FromStr].
+    fn decode_from_str<T: FromStr>(&self) -> Result<T, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_from_str()
+    }
+
+    /// Decode text as a UUID.
+    #[cfg(feature = "uuid")]
+    fn decode_uuid(&self) -> Result<uuid::Uuid, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_uuid()
+    }
+
+    /// Decode text as hexadecimal bytes.
+    fn decode_hex(&self) -> Result<Vec<u8>, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_hex()
+    }
+
+    /// Decode text as standard base64 bytes.
+    fn decode_base64(&self) -> Result<Vec<u8>, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_base64()
+    }
+
+    /// Decode text as a number and a unit.
+    fn decode_quantity<'a>(&'a self) -> Result<(f64, &'a str), DecodeError> where Tx: 'a {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_quantity()
+    }
+
+    /// Decode text as a quantity in a unit.
+    fn decode_quantity_in(&self, unit: &str, units: &Units) -> Result<f64, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_quantity_in(unit, units)
+    }
+
+    /// Decode text as a date.
+    fn decode_date<T: Date>(&self) -> Result<T, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_date()
+    }
+
+    /// Decode text as a date and time.
+    fn decode_datetime<T: DateTime>(&self) -> Result<T, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_datetime()
+    }
+
+    /// Decode text as a duration.
+    fn decode_duration<T: Duration>(&self) -> Result<T, DecodeError> {
+        self.as_text().ok_or(DecodeError::Expected("text"))?.decode_duration()
+    }
+
+    /// Decode the elements of a list or a tuple.
+    fn decode_list<T, F: FnMut(&Vl) -> Result<T, DecodeError>>(&self, decode: F) -> Result<Vec<T>, DecodeError> {
+        if let Some(list) = self.as_list() {
+            list.iter().map(decode).collect()
+        } else if self.is_tuple() {
+            self.iter_as_tuple().map(decode).collect()
+        } else {
+            Err(DecodeError::Expected("list"))
+        }
+    }
+
+    /// Decode the values of a dictionary.
+    fn decode_dictionary<T, F: FnMut(&Vl) -> Result<T, DecodeError>>(&self, mut decode: F) -> Result<BTreeMap<String, T>, DecodeError> {
+        let dictionary = self.as_dictionary().ok_or(DecodeError::Expected("dictionary"))?;
+        dictionary.iter().map(|(key, value)| Ok((key.to_string(), decode(value)?))).collect()
+    }
+}
+
+impl<
+    Vl: Value<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tx: Text<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Dc: Dictionary<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Ls: List<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Cm: Compound<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tp: Tuple<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    Tg: Tagged<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+    T: Value<Vl, Tx, Dc, Ls, Cm, Tp, Tg>,
+> DecodeValueExt<Vl, Tx, Dc, Ls, Cm, Tp, Tg> for T {}
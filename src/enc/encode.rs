@@ -0,0 +1,211 @@
+//! Encoding of primitives as Khi text.
+//!
+//! The functions write text that reads back as the same text: a word where
+//! the text is a word, and a transcription like `\a: b\` otherwise. Numbers,
+//! booleans and characters are written in the syntax that the conversions of
+//! the crate read.
+//!
+//! ```
+//! # use khi::enc::encode::{encode_char, encode_number, encode_str};
+//! # use khi::fmt::Quoting;
+//! assert_eq!(encode_str("a b", Quoting::Minimal), "a b");
+//! assert_eq!(encode_str("a b", Quoting::Canonical), "\\a b\\");
+//! assert_eq!(encode_str("a: b", Quoting::Minimal), "\\a: b\\");
+//! assert_eq!(encode_char(';'), "\\;\\");
+//! assert_eq!(encode_number(-1234567, true), "-1_234_567");
+//! ```
+
+use std::fmt::{self, Display, Write};
+#[cfg(feature = "enc")]
+use crate::enc::datetime::{format_date, format_datetime, format_duration, Date, DateTime, Duration};
+use crate::fmt::Quoting;
+use crate::number::{Number, NumberFormat};
+
+/// Encode text as words if possible, and otherwise as a transcription.
+pub fn encode_str(text: &str, quoting: Quoting) -> String {
+    let mut output = String::new();
+    // Writing to a string does not fail.
+    write_text(&mut output, text, quoting).unwrap();
+    output
+}
+
+/// Encode `true` or `false`.
+pub fn encode_bool(value: bool) -> &'static str {
+    if value { "true" } else { "false" }
+}
+
+/// Encode a character as a word if possible, and otherwise as a
+/// transcription.
+pub fn encode_char(value: char) -> String {
+    let mut output = String::new();
+    write_word(&mut output, value.encode_utf8(&mut [0; 4])).unwrap();
+    output
+}
+
+/// Encode a number of any [Number] type.
+///
+/// With grouping, the integer digits are grouped by three with `_`, like
+/// `1_000_000`, which reads as a number in a [NumberFormat] with
+/// [separators](NumberFormat::separators).
+pub fn encode_number<T: Number + Display>(value: T, grouping: bool) -> String {
+    let mut output = String::new();
+    write_number(&mut output, value, grouping).unwrap();
+    output
+}
+
+/// Write a number like [encode_number], without an intermediate string.
+pub fn write_number<W: Write + ?Sized, T: Number + Display>(output: &mut W, value: T, grouping: bool) -> fmt::Result {
+    if !grouping {
+        return write!(output, "{}", value);
+    };
+    // The number is displayed twice: once to count the integer digits, and
+    // once to write them with separators.
+    let mut count = Grouping::<W> { output: None, digits: 0, written: 0, state: GroupingState::Sign };
+    write!(count, "{}", value)?;
+    let mut grouped = Grouping { output: Some(output), digits: count.written, written: 0, state: GroupingState::Sign };
+    write!(grouped, "{}", value)
+}
+
+/// Writes a displayed number with its integer digits grouped by three, or
+/// only counts the integer digits without an output.
+struct Grouping<'a, W: Write + ?Sized> {
+    output: Option<&'a mut W>,
+    /// The number of integer digits.
+    digits: usize,
+    /// The number of integer digits seen so far.
+    written: usize,
+    state: GroupingState,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum GroupingState {
+    Sign,
+    Integer,
+    Rest,
+}
+
+impl<W: Write + ?Sized> Write for Grouping<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.state = match (self.state, c.is_ascii_digit()) {
+                (GroupingState::Sign | GroupingState::Integer, true) => GroupingState::Integer,
+                (GroupingState::Sign, false) => GroupingState::Sign,
+                _ => GroupingState::Rest,
+            };
+            if self.state == GroupingState::Integer {
+                if let Some(output) = &mut self.output {
+                    if self.written != 0 && (self.digits - self.written) % 3 == 0 {
+                        output.write_char('_')?;
+                    };
+                };
+                self.written += 1;
+            };
+            if let Some(output) = &mut self.output {
+                output.write_char(c)?;
+            };
+        };
+        Ok(())
+    }
+}
+
+/// Encode a date as `2024-05-01`.
+#[cfg(feature = "enc")]
+pub fn encode_date<T: Date>(date: &T) -> String {
+    format_date(date.to_parts())
+}
+
+/// Encode a date and time in RFC 3339, like `2024-05-01T12:00:00Z`. The
+/// text contains `:`, so it is written to documents with [encode_str], which
+/// transcribes it.
+#[cfg(feature = "enc")]
+pub fn encode_datetime<T: DateTime>(datetime: &T) -> String {
+    format_datetime(datetime.to_parts())
+}
+
+/// Encode a duration in units, like `5m 30s`.
+#[cfg(feature = "enc")]
+pub fn encode_duration<T: Duration>(duration: &T) -> String {
+    format_duration(duration.to_nanos())
+}
+
+/// Write text as words if possible, and otherwise as a transcription.
+pub fn write_text<W: Write + ?Sized>(output: &mut W, text: &str, quoting: Quoting) -> fmt::Result {
+    if !text.is_empty() && text.split(' ').all(is_word) && !is_quoted(text, quoting) {
+        output.write_str(text)
+    } else {
+        write_transcription(output, text)
+    }
+}
+
+/// Write text as a single word if possible, and otherwise as a transcription.
+pub fn write_word<W: Write + ?Sized>(output: &mut W, text: &str) -> fmt::Result {
+    if is_word(text) {
+        output.write_str(text)
+    } else {
+        write_transcription(output, text)
+    }
+}
+
+/// Write text as a transcription, escaping `\`, `` ` ``, newlines and tabs.
+/// Carriage returns are written as they are.
+pub fn write_transcription<W: Write + ?Sized>(output: &mut W, text: &str) -> fmt::Result {
+    output.write_char('\\')?;
+    for c in text.chars() {
+        match c {
+            '\\' => output.write_str("`\\")?,
+            '`' => output.write_str("``")?,
+            '\n' => output.write_str("`n")?,
+            '\t' => output.write_str("`t")?,
+            c => output.write_char(c)?,
+        };
+    };
+    output.write_char('\\')
+}
+
+/// Write text with newlines as a text block, and return whether it can be
+/// written as one.
+///
+/// Every line is written after `newline` and `indent`, which must be deeper
+/// than the indentation of the line the block starts on. Text that ends with
+/// a newline ends the block on a line of its own, indented by `outer`. Other
+/// text ends the block on its last line, and deletes leading whitespace with
+/// the `l` flag, so none of its lines may start with whitespace.
+pub fn write_text_block<W: Write + ?Sized>(output: &mut W, text: &str, newline: &str, indent: &str, outer: &str) -> Result<bool, fmt::Error> {
+    if !text.contains('\n') || text.contains('\r') || text.contains("<#>") {
+        return Ok(false);
+    };
+    let indented = |line: &str| line.starts_with(' ') || line.starts_with('\t');
+    let (lines, flags) = match text.strip_suffix('\n') {
+        Some(lines) if lines.split('\n').any(|line| !line.is_empty() && !indented(line)) => (lines, "<#>"),
+        None if !text.split('\n').any(indented) => (text, "<# l>"),
+        _ => return Ok(false),
+    };
+    output.write_str(flags)?;
+    for line in lines.split('\n') {
+        output.write_str(newline)?;
+        output.write_str(indent)?;
+        output.write_str(line)?;
+    };
+    if flags == "<#>" {
+        output.write_str(newline)?;
+        output.write_str(outer)?;
+    };
+    output.write_str("<#>")?;
+    Ok(true)
+}
+
+/// Check if text that can be written as words is transcribed when quoting.
+pub fn is_quoted(text: &str, quoting: Quoting) -> bool {
+    match quoting {
+        Quoting::Minimal => false,
+        Quoting::Canonical => text.contains(' ') || NumberFormat::LENIENT.is_number(text),
+    }
+}
+
+/// Check if text can be written as a single word.
+pub fn is_word(text: &str) -> bool {
+    !text.is_empty()
+        && !text.ends_with('#')
+        && !text.contains("##")
+        && !text.chars().any(|c| matches!(c, ' ' | '\t' | '\n' | '\r' | '\\' | '`' | '{' | '}' | '[' | ']' | ':' | ';' | '|' | '~' | '<' | '>'))
+}
@@ -0,0 +1,20 @@
+//! Encoding and decoding of primitives without serde.
+//!
+//! [encode] writes numbers, booleans, characters and strings as Khi text that
+//! reads back as the same text. The formatter and the serializer write text
+//! with it. [decode] reads typed values from the value traits, and needs the
+//! `enc` feature. Its items are also available here. [datetime] reads and
+//! writes dates, times and durations, of the types of the standard library,
+//! and of `chrono` and `time` with the features of the same names. [quantity] reads
+//! numbers with units, like `2.70 g/cm3`.
+
+pub mod encode;
+#[cfg(feature = "enc")]
+pub mod decode;
+#[cfg(feature = "enc")]
+pub mod datetime;
+#[cfg(feature = "enc")]
+pub mod quantity;
+
+#[cfg(feature = "enc")]
+pub use decode::{DecodeError, DecodeTextExt, DecodeValueExt};
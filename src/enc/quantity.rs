@@ -0,0 +1,76 @@
+//! Quantities with units, like `2.70 g/cm3`.
+//!
+//! A quantity is a number in the default [NumberFormat], followed by a unit,
+//! with or without a space between them. [Units] converts quantities between
+//! units:
+//!
+//! ```
+//! # use khi::enc::quantity::{parse_quantity, Units};
+//! assert_eq!(parse_quantity("2.70 g/cm3"), Some((2.7, "g/cm3")));
+//! assert_eq!(parse_quantity("5kg"), Some((5.0, "kg")));
+//! let units = Units::new().with("g", 0.001, "kg").with("t", 1000.0, "kg");
+//! assert_eq!(units.convert(2.5, "t", "g"), Some(2_500_000.0));
+//! assert_eq!(units.convert(2.5, "t", "m"), None);
+//! ```
+
+use std::collections::HashMap;
+use crate::number::NumberFormat;
+
+/// Read a number and a unit. The unit is empty if there is none.
+pub fn parse_quantity(text: &str) -> Option<(f64, &str)> {
+    let format = NumberFormat::default();
+    let text = text.trim();
+    if let Some((number, unit)) = text.split_once(' ') {
+        return Some((format.parse(number)?, unit.trim_start()));
+    };
+    // The longest number that starts the text, so that `1e5m` is `1e5` in
+    // `m`, and `2em` is `2` in `em`.
+    text.char_indices().map(|(i, _)| i).chain([text.len()]).rev()
+        .find_map(|i| Some((format.parse(&text[..i])?, &text[i..])))
+}
+
+/// A table of units, each defined as a factor of another unit.
+///
+/// Units that are not defined in terms of another unit are base units. Units
+/// convert to each other if they are defined in the same base unit.
+#[derive(Clone, Debug, Default)]
+pub struct Units {
+    /// Factor and base unit of each unit.
+    units: HashMap<String, (f64, String)>,
+}
+
+impl Units {
+    /// A table without units.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define a unit as a factor of another unit, like `g` as `0.001` of
+    /// `kg`. The other unit may itself be defined.
+    pub fn with(mut self, unit: &str, factor: f64, of: &str) -> Self {
+        self.insert(unit, factor, of);
+        self
+    }
+
+    /// Define a unit as a factor of another unit.
+    pub fn insert(&mut self, unit: &str, factor: f64, of: &str) {
+        let (base_factor, base) = self.resolve(of);
+        let base = base.to_string();
+        self.units.insert(unit.to_string(), (factor * base_factor, base));
+    }
+
+    /// Convert a value from a unit to another, if they have the same base.
+    pub fn convert(&self, value: f64, from: &str, to: &str) -> Option<f64> {
+        let (from_factor, from_base) = self.resolve(from);
+        let (to_factor, to_base) = self.resolve(to);
+        if from_base == to_base { Some(value * from_factor / to_factor) } else { None }
+    }
+
+    /// Get the factor and base unit of a unit.
+    fn resolve<'a>(&'a self, unit: &'a str) -> (f64, &'a str) {
+        match self.units.get(unit) {
+            Some((factor, base)) => (*factor, base),
+            None => (1.0, unit),
+        }
+    }
+}
@@ -1,211 +1,687 @@
-//! Khi structure formatter. Writes Khi structures to strings.
-
-use std::fmt::Display;
-use crate::{Component, Pattern, Expression, Table, Text};
-
-pub fn fast_format_expression<
-    Ex: Value<Tx, Dc, Tb, Dr, Cm>,
-    Tx: Text<Ex, Dc, Tb, Dr, Cm>,
-    Dc: Dictionry<Ex, Tx, Tb, Dr, Cm>,
-    Tb: Table<Ex, Tx, Dc, Dr, Cm>,
-    Cm: Component<Ex, Tx, Dc, Tb, Dr>,
-    Dr: Pattern<Ex, Tx, Dc, Tb, Cm>,
->(expression: Ex) {
-    let len = expression.length();
-    if len == 0 {
-
-    } else if len == 1 {
+//! Khi formatter. Writes parsed values as Khi documents.
+//!
+//! # Stability
+//!
+//! The formatter maintains two invariants, and keeps them across releases:
+//!
+//! - Formatting does not change parse results. Parsing the output gives a
+//!   value that is canonically equal to the formatted value (see
+//!   [Canonical](crate::pdm::Canonical)).
+//! - Formatting is idempotent. Formatting the parsed output gives the same
+//!   output again, so `format(format(x)) == format(x)`.
+//!
+//! The invariants hold for every value produced by the parser with the
+//! default [ParseOptions](crate::parse::ParseOptions). They do not hold for
+//! values that no document can express, such as a tag name that is not a
+//! word. The exact layout of the output
+//! may change between releases, as long as the invariants are kept.
+//!
+//! Keeping the first invariant sometimes decides the layout. A value that ends
+//! with `~` joins the newline after it, so entries and elements on separate
+//! lines are separated with `;` wherever one of them ends with `~`.
+//!
+//! # Style
+//!
+//! The `_with` functions write a document in a [Style], which is usually
+//! detected from the source with [Style::detect], so that rewriting a document
+//! keeps its indentation and newlines. Without indentation, nested
+//! dictionaries and lists are written on one line. With indentation,
+//! dictionaries, and lists that contain dictionaries or lists, are written as
+//! indented blocks. Dictionaries on one line with a key that is not a word
+//! are written with mapped keys, like `{a => 1 | \b c\ => 2}`.
+//!
+//! [Style::pretty] is meant for documents that are edited by hand. Entries of
+//! dictionary blocks are aligned, dictionaries in a dictionary document are
+//! written as sections, text with newlines is written as text blocks, and
+//! lists of rows are written as tables. Other list notations are selected with
+//! [ListNotation].
+//!
+//! Text is written as bare words where it reads as the same text. Documents
+//! that are read by other programs, which may take `1.5` for a number or
+//! `a b` for two words, can quote such text with [Quoting::Canonical].
+
+use crate::enc::encode::{is_quoted, is_word, write_text, write_text_block, write_word};
+use std::fmt::{self, Write};
+use crate::pdm::{KeyOrder, ParsedAttributeValue, ParsedDictionary, ParsedList, ParsedTaggedValue, ParsedTuple, ParsedValue};
+
+/// Format a value document.
+pub fn format_value(value: &ParsedValue) -> String {
+    format_value_with(value, &Style::default())
+}
 
-    } else {
+/// Format a value document in a style.
+pub fn format_value_with(value: &ParsedValue, style: &Style) -> String {
+    let mut output = String::new();
+    write_value_with(&mut output, value, style).unwrap();
+    output
+}
 
-    }
+/// Write a value document in a style to a [Write] target, like a
+/// [Formatter](fmt::Formatter), without formatting it to a string first.
+pub fn write_value_with<W: Write + ?Sized>(output: &mut W, value: &ParsedValue, style: &Style) -> fmt::Result {
+    if !matches!(value, ParsedValue::Tuple(ParsedTuple::Unit, ..)) {
+        Writer::new(output, style).write_value(value, Context::Value)?;
+    };
+    Ok(())
 }
 
-pub fn fast_format_table<Ta: Table>(table: Ta) {
+/// Format a dictionary document.
+///
+/// Entries are written one per line, in source order.
+pub fn format_dictionary(dictionary: &ParsedDictionary) -> String {
+    format_dictionary_with(dictionary, &Style::default())
+}
 
+/// Format a dictionary document in a style.
+pub fn format_dictionary_with(dictionary: &ParsedDictionary, style: &Style) -> String {
+    let mut output = String::new();
+    Writer::new(&mut output, style).write_dictionary_document(dictionary).unwrap();
+    output
 }
 
-pub fn fast_format_dictionary() {
+/// Format a list document.
+///
+/// Elements, or rows of a tabular list, are written one per line.
+pub fn format_list(list: &ParsedList) -> String {
+    format_list_with(list, &Style::default())
+}
 
+/// Format a list document in a style.
+pub fn format_list_with(list: &ParsedList, style: &Style) -> String {
+    let mut output = String::new();
+    Writer::new(&mut output, style).write_list_document(list).unwrap();
+    output
 }
 
-pub trait FastFormatter {
-    fn format_compact(&self) -> String;
+/// Length of the previews of values in error messages.
+pub const PREVIEW_LENGTH: usize = 40;
+
+/// Write a value on one line for diagnostics, truncated to at most
+/// `max_chars` characters.
+///
+/// Truncated output ends with `...`. A preview shows what a value is, and is
+/// not a document.
+pub fn preview(value: &ParsedValue, max_chars: usize) -> String {
+    let style = Style::default();
+    let mut preview = String::new();
+    Writer::new(&mut preview, &style).write_value(value, Context::Value).unwrap();
+    if preview.chars().count() > max_chars {
+        let kept = max_chars.saturating_sub(3);
+        preview = preview.chars().take(kept).chain("...".chars().take(max_chars - kept)).collect();
+    };
+    preview
 }
 
-impl <St: Expression<_, _, _, _, _>> FastFormatter for St {
+/// Layout of a document.
+///
+/// The default style writes nested dictionaries and lists on one line, and
+/// uses `\n` newlines.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Style {
+    /// Indentation of nested blocks. Without indentation, nested dictionaries
+    /// and lists are written on one line.
+    pub indent: Option<Indent>,
+    pub newline: Newline,
+    /// Align the values of the entries of dictionaries written on separate
+    /// lines.
+    pub align: bool,
+    /// Write dictionaries in a dictionary document as sections, like
+    /// `{server}:` followed by the entries of the dictionary.
+    pub sections: bool,
+    /// Write text with newlines as text blocks, when a text block reads as the
+    /// same text.
+    pub text_blocks: bool,
+    /// Notation of lists.
+    pub lists: ListNotation,
+    /// Quoting of text.
+    pub quoting: Quoting,
+}
 
-    fn format_compact(&self) -> String {
-        todo!()
-    }
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Indent {
+    /// A number of spaces per level.
+    Spaces(usize),
+    /// A tab per level.
+    Tabs,
+}
 
+/// Quoting of text values.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Quoting {
+    /// Text is written as words where it reads as the same text, like
+    /// `1.5` or `a b`, and is transcribed otherwise.
+    #[default]
+    Minimal,
+    /// Text is written as a word only if it is a single word that is not a
+    /// number in any [NumberFormat](crate::number::NumberFormat), and is transcribed otherwise, like
+    /// `\1.5\` or `\a b\`. Text with newlines may still be a text block.
+    Canonical,
 }
 
-pub fn pretty_format_structure() {
+/// Notation of lists. Lists that cannot be written in a notation are
+/// delimited.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ListNotation {
+    /// Elements separated by `;`, like `[a; b]`.
+    #[default]
+    Delimited,
+    /// Elements after bullets, like `[> a > b]`. With indentation, every
+    /// element is on a line of its own.
+    Aligned,
+    /// Lists of two or more tuples of the same length as rows of a table,
+    /// like `[|a|b| |c|d|]`. With indentation, every row is on a line of its
+    /// own.
+    Tabular,
+}
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Newline {
+    #[default]
+    Lf,
+    CrLf,
 }
 
-pub fn pretty_format_table() {
+impl Style {
+    /// Style for documents that are edited by hand. Indents by two spaces,
+    /// aligns entries, writes sections and text blocks, and writes lists of
+    /// rows as tables.
+    pub const fn pretty() -> Style {
+        Style { indent: Some(Indent::Spaces(2)), newline: Newline::Lf, align: true, sections: true, text_blocks: true, lists: ListNotation::Tabular, quoting: Quoting::Minimal }
+    }
 
+    /// Detect the prevailing style of a source document.
+    ///
+    /// Indentation is detected from the lines that start with whitespace: tabs
+    /// if most of them start with a tab, and otherwise the greatest common
+    /// width of the space indented lines. A document without indented lines
+    /// has no indentation. Newlines are `\r\n` if most lines end with `\r\n`.
+    pub fn detect(source: &str) -> Style {
+        let mut tabs = 0;
+        let mut spaces = 0;
+        let mut width = 0;
+        let mut lf = 0;
+        let mut crlf = 0;
+        for line in source.split_inclusive('\n') {
+            if line.ends_with("\r\n") {
+                crlf += 1;
+            } else if line.ends_with('\n') {
+                lf += 1;
+            };
+            if line.trim().is_empty() {
+                continue;
+            };
+            if line.starts_with('\t') {
+                tabs += 1;
+            } else {
+                let n = line.len() - line.trim_start_matches(' ').len();
+                if n != 0 {
+                    spaces += 1;
+                    width = gcd(width, n);
+                };
+            };
+        };
+        let indent = if tabs == 0 && spaces == 0 {
+            None
+        } else if tabs > spaces {
+            Some(Indent::Tabs)
+        } else {
+            Some(Indent::Spaces(width))
+        };
+        let newline = if crlf > lf { Newline::CrLf } else { Newline::Lf };
+        Style { indent, newline, ..Style::default() }
+    }
 }
 
-pub fn pretty_format_dictionary() {
-
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
 }
 
-pub trait PrettyFormatter {
+/// Position of a value, which determines the constructs it can be written as.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+    /// Document, dictionary entry or list element. Any value.
+    Value,
+    /// Tuple element. Anything but a bare tuple.
+    Element,
+    /// Compound component or tag argument. A single term.
+    Term,
+}
 
+struct Writer<'a, W: Write> {
+    output: W,
+    style: &'a Style,
+    /// Nesting of blocks.
+    depth: usize,
 }
 
-impl <St: Expression<_, _, _, _, _>> PrettyFormatter for St {
-    fn g(&self) {
-        self.as
+impl<'a, W: Write> Writer<'a, W> {
+
+    fn new(output: W, style: &'a Style) -> Self {
+        Writer { output, style, depth: 0 }
     }
-}
 
+    fn newline(&mut self) -> fmt::Result {
+        match self.style.newline {
+            Newline::Lf => self.output.write_char('\n'),
+            Newline::CrLf => self.output.write_str("\r\n"),
+        }
+    }
 
-// impl Display for ParsedExpression {
-//     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-//         let len = self.arguments.len();
-//         if len > 0 {
-//             write!(f, "{}", self.arguments.get(0).unwrap())?;
-//         };
-//         let mut i = 1;
-//         while i < self.arguments.len() {
-//             write!(f, " {}", self.arguments.get(i).unwrap())?;
-//             i += 1;
-//         };
-//         Ok(())
-//     }
-// }
+    /// Start a line in a block.
+    fn line(&mut self) -> fmt::Result {
+        self.newline()?;
+        self.indent(self.depth)
+    }
 
-impl Display for ParsedComponent {
+    fn indent(&mut self, depth: usize) -> fmt::Result {
+        let indentation = self.indentation(depth);
+        self.output.write_str(&indentation)
+    }
 
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ParsedComponent::Empty(_, _) => write!(f, "{{}}"),
-            ParsedComponent::Text(s) => s.fmt(f),
-            ParsedComponent::Table(s) => s.fmt(f),
-            ParsedComponent::Dictionary(d) => d.fmt(f),
-            ParsedComponent::Directive(c) => c.fmt(f),
-            ParsedComponent::Compound(c, _, _) => c.fmt(f),
+    fn indentation(&self, depth: usize) -> String {
+        match self.style.indent {
+            Some(Indent::Spaces(n)) => " ".repeat(n * depth),
+            Some(Indent::Tabs) => "\t".repeat(depth),
+            None => String::new(),
         }
     }
 
-}
-
-//// Display
+    fn write_dictionary_document(&mut self, dictionary: &ParsedDictionary) -> fmt::Result {
+        let (sections, entries): (Vec<_>, Vec<_>) = sorted_entries(dictionary).into_iter()
+            .partition(|(_, value)| self.style.sections && matches!(value, ParsedValue::Dictionary(d, ..) if !d.entries.is_empty()));
+        // Entries after a section header belong to the section, so sections
+        // follow the other entries.
+        let mut semicolons = self.write_entries(&entries)?;
+        for (i, (key, value)) in sections.into_iter().enumerate() {
+            if i != 0 || !entries.is_empty() {
+                if semicolons {
+                    self.output.write_char(';')?;
+                };
+                self.newline()?;
+                self.newline()?;
+            };
+            self.output.write_char('{')?;
+            write_word(&mut self.output, key)?;
+            self.output.write_str("}:")?;
+            self.newline()?;
+            if let ParsedValue::Dictionary(section, ..) = value {
+                semicolons = self.write_entries(&sorted_entries(section))?;
+            };
+        };
+        Ok(())
+    }
 
-impl Display for ParsedText {
+    fn write_list_document(&mut self, list: &ParsedList) -> fmt::Result {
+        let notation = list_notation(list, self.style.lists);
+        for (i, element) in list.elements.iter().enumerate() {
+            if i != 0 {
+                if notation == ListNotation::Delimited {
+                    self.output.write_char(';')?;
+                };
+                self.newline()?;
+            };
+            match notation {
+                ListNotation::Delimited => self.write_value(element, Context::Value)?,
+                notation => self.write_list_element(element, notation)?,
+            };
+        };
+        Ok(())
+    }
 
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let reserved = has_reserved(&self.str);
-        if reserved {
-            write!(f, "⟨{}⟩", self.str)
-        } else {
-            write!(f, "{}", self.str)
-        }
+    /// Write entries one per line, and return whether they are separated by
+    /// `;`.
+    fn write_entries(&mut self, entries: &[(&str, &ParsedValue)]) -> Result<bool, fmt::Error> {
+        let semicolons = needs_semicolons(entries.iter().map(|(_, value)| *value));
+        let width = self.key_width(entries);
+        for (i, (key, value)) in entries.iter().enumerate() {
+            if i != 0 {
+                if semicolons {
+                    self.output.write_char(';')?;
+                };
+                self.line()?;
+            };
+            self.write_entry(key, value, width)?;
+        };
+        Ok(semicolons)
     }
 
-}
+    /// Width of the widest key, to align entries to, or zero without
+    /// alignment.
+    fn key_width(&self, entries: &[(&str, &ParsedValue)]) -> usize {
+        if !self.style.align {
+            return 0;
+        };
+        entries.iter().map(|(key, _)| word_width(key)).max().unwrap_or(0)
+    }
 
-impl Display for ParsedTable {
+    fn write_value(&mut self, value: &ParsedValue, context: Context) -> fmt::Result {
+        match value {
+            ParsedValue::Nil(..) => match context {
+                Context::Term => self.output.write_str("{~}")?,
+                _ => self.output.write_char('~')?,
+            },
+            ParsedValue::Text(text, ..) => self.write_text(&text.str)?,
+            ParsedValue::Dictionary(dictionary, ..) => self.write_dictionary(dictionary)?,
+            ParsedValue::List(list, ..) => self.write_list(list)?,
+            ParsedValue::Compound(compound, ..) => {
+                if context == Context::Term {
+                    self.output.write_char('{')?;
+                };
+                for (i, component) in compound.components.iter().enumerate() {
+                    if i != 0 {
+                        self.output.write_char(if compound.whitespace[i - 1] { ' ' } else { '~' })?;
+                    };
+                    // Adjacent text would be joined, unless bracketed.
+                    if i != 0 && matches!(component, ParsedValue::Text(..)) && matches!(compound.components[i - 1], ParsedValue::Text(..)) {
+                        self.output.write_char('{')?;
+                        self.write_value(component, Context::Term)?;
+                        self.output.write_char('}')?;
+                    } else {
+                        self.write_value(component, Context::Term)?;
+                    };
+                };
+                if context == Context::Term {
+                    self.output.write_char('}')?;
+                };
+            }
+            ParsedValue::Tuple(tuple, ..) => self.write_tuple(tuple, context)?,
+            ParsedValue::Tagged(tagged, ..) => self.write_tagged(tagged)?,
+        }
+        Ok(())
+    }
 
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[")?;
-        for a in &self.elements {
-            a.fmt(f)?;
-            write!(f, "; ")?;
+    fn write_tuple(&mut self, tuple: &ParsedTuple, context: Context) -> fmt::Result {
+        match tuple {
+            ParsedTuple::Unit => self.output.write_str("<>")?,
+            ParsedTuple::Single(value) => {
+                if context != Context::Value {
+                    self.output.write_char('{')?;
+                };
+                self.output.write_str("<>: ")?;
+                self.write_value(value, Context::Value)?;
+                if context != Context::Value {
+                    self.output.write_char('}')?;
+                };
+            }
+            ParsedTuple::Multiple(elements) => {
+                if context != Context::Value {
+                    self.output.write_char('{')?;
+                };
+                for (i, element) in elements.iter().enumerate() {
+                    if i != 0 {
+                        self.output.write_str(" | ")?;
+                    };
+                    self.write_value(element, Context::Element)?;
+                };
+                if context != Context::Value {
+                    self.output.write_char('}')?;
+                };
+            }
         }
-        write!(f, "]")?;
         Ok(())
     }
 
-}
+    fn write_dictionary(&mut self, dictionary: &ParsedDictionary) -> fmt::Result {
+        let block = self.style.indent.is_some() && !dictionary.entries.is_empty();
+        let entries = sorted_entries(dictionary);
+        self.output.write_char('{')?;
+        self.depth += 1;
+        if block {
+            self.line()?;
+            self.write_entries(&entries)?;
+        } else if entries.iter().any(|(key, _)| !is_word(key)) {
+            // Keys that are not words read better as mapped keys, like
+            // `{a => 1 | \b c\ => 2}`.
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i != 0 {
+                    self.output.write_str(" | ")?;
+                };
+                write_word(&mut self.output, key)?;
+                self.output.write_str(" => ")?;
+                self.write_argument(value)?;
+            };
+        } else {
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i != 0 {
+                    self.output.write_str("; ")?;
+                };
+                self.write_entry(key, value, 0)?;
+            };
+        };
+        self.depth -= 1;
+        if block {
+            self.line()?;
+        };
+        self.output.write_char('}')?;
+        Ok(())
+    }
+
+    /// Write an entry, with the value aligned to a key width.
+    fn write_entry(&mut self, key: &str, value: &ParsedValue, width: usize) -> fmt::Result {
+        write_word(&mut self.output, key)?;
+        self.output.write_str(": ")?;
+        for _ in word_width(key)..width {
+            self.output.write_char(' ')?;
+        };
+        self.write_value(value, Context::Value)?;
+        Ok(())
+    }
 
-impl Display for ParsedEntry {
+    /// Write text, as a text block if the style allows it.
+    ///
+    /// The lines of a block are indented one level deeper than the line it
+    /// starts on, and the lexer removes that indentation again.
+    fn write_text(&mut self, text: &str) -> fmt::Result {
+        if self.style.text_blocks {
+            let newline = match self.style.newline {
+                Newline::Lf => "\n",
+                Newline::CrLf => "\r\n",
+            };
+            let indent = self.indentation(self.depth + 1);
+            let outer = self.indentation(self.depth);
+            if write_text_block(&mut self.output, text, newline, &indent, &outer)? {
+                return Ok(());
+            };
+        };
+        write_text(&mut self.output, text, self.style.quoting)
+    }
 
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.key.fmt(f)?;
-        write!(f, ":")?;
-        self.value.fmt(f)?;
+    fn write_list(&mut self, list: &ParsedList) -> fmt::Result {
+        match list_notation(list, self.style.lists) {
+            ListNotation::Delimited => self.write_delimited_list(list)?,
+            notation => {
+                let block = self.style.indent.is_some() && (notation == ListNotation::Aligned || list.elements.len() > 1);
+                self.output.write_char('[')?;
+                self.depth += 1;
+                for (i, element) in list.elements.iter().enumerate() {
+                    if block {
+                        self.line()?;
+                    } else if i != 0 {
+                        self.output.write_char(' ')?;
+                    };
+                    self.write_list_element(element, notation)?;
+                };
+                self.depth -= 1;
+                if block {
+                    self.line()?;
+                };
+                self.output.write_char(']')?;
+            }
+        };
         Ok(())
     }
 
-}
+    /// Write an element of a list in aligned or tabular notation.
+    fn write_list_element(&mut self, element: &ParsedValue, notation: ListNotation) -> fmt::Result {
+        match (notation, element) {
+            (ListNotation::Tabular, ParsedValue::Tuple(ParsedTuple::Multiple(cells), ..)) => {
+                self.output.write_char('|')?;
+                for cell in cells.iter() {
+                    self.write_value(cell, Context::Element)?;
+                    self.output.write_char('|')?;
+                };
+            }
+            _ => {
+                self.output.write_str("> ")?;
+                self.write_value(element, Context::Value)?;
+            }
+        };
+        Ok(())
+    }
 
+    fn write_delimited_list(&mut self, list: &ParsedList) -> fmt::Result {
+        let block = self.style.indent.is_some()
+            && list.elements.iter().any(|e| matches!(e, ParsedValue::Dictionary(..) | ParsedValue::List(..)));
+        self.output.write_char('[')?;
+        self.depth += 1;
+        for (i, element) in list.elements.iter().enumerate() {
+            if i != 0 {
+                self.output.write_char(';')?;
+                if !block {
+                    self.output.write_char(' ')?;
+                };
+            };
+            if block {
+                self.line()?;
+            };
+            self.write_value(element, Context::Value)?;
+        };
+        self.depth -= 1;
+        if block {
+            self.line()?;
+        };
+        self.output.write_char(']')?;
+        Ok(())
+    }
 
-impl Display for ParsedDictionary {
+    /// Write a tagged value in argument notation, like `<a>:{b}:{c}`.
+    fn write_tagged(&mut self, tagged: &ParsedTaggedValue) -> fmt::Result {
+        self.output.write_char('<')?;
+        write_word(&mut self.output, tagged.raw_name())?;
+        for attribute in &tagged.attributes {
+            self.output.write_char(' ')?;
+            write_word(&mut self.output, &attribute.0)?;
+            match &attribute.1 {
+                Some(ParsedAttributeValue::Text(value)) => {
+                    self.output.write_char(':')?;
+                    write_word(&mut self.output, value)?;
+                }
+                Some(ParsedAttributeValue::List(strings)) => {
+                    self.output.write_str(":[")?;
+                    for (i, string) in strings.iter().enumerate() {
+                        if i != 0 {
+                            self.output.write_str("; ")?;
+                        };
+                        write_word(&mut self.output, string)?;
+                    };
+                    self.output.write_char(']')?;
+                }
+                None => {}
+            };
+        };
+        self.output.write_char('>')?;
+        match tagged.value.as_ref() {
+            ParsedValue::Tuple(ParsedTuple::Unit, ..) => {}
+            ParsedValue::Tuple(ParsedTuple::Multiple(arguments), ..) => {
+                for argument in arguments.iter() {
+                    self.output.write_char(':')?;
+                    self.write_argument(argument)?;
+                };
+            }
+            ParsedValue::Tuple(ParsedTuple::Single(value), ..) => {
+                // A bracketed tuple argument is a single tuple.
+                self.output.write_str(":{")?;
+                self.write_value(value, Context::Value)?;
+                self.output.write_char('}')?;
+            }
+            value => {
+                self.output.write_char(':')?;
+                self.write_argument(value)?;
+            }
+        };
+        Ok(())
+    }
 
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{{")?;
-        for e in &self.entries {
-            e.fmt(f)?;
-        }
-        write!(f, "}}")?;
+    fn write_argument(&mut self, argument: &ParsedValue) -> fmt::Result {
+        match argument {
+            ParsedValue::Dictionary(..) | ParsedValue::List(..) => self.write_value(argument, Context::Term)?,
+            ParsedValue::Text(text, ..) if is_word(&text.str) && !is_quoted(&text.str, self.style.quoting) => self.output.write_str(&text.str)?,
+            argument => {
+                self.output.write_char('{')?;
+                self.write_value(argument, Context::Value)?;
+                self.output.write_char('}')?;
+            }
+        };
         Ok(())
     }
 
 }
 
+/// Number of characters of a key written as a word.
+fn word_width(key: &str) -> usize {
+    let mut written = String::new();
+    write_word(&mut written, key).unwrap();
+    written.chars().count()
+}
 
-impl Display for ParsedCompound {
-
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{{")?;
-        let mut iter = self.arguments.iter();
-        let mut last_whitespace = true;
-        loop {
-            if let Some((argument, whitespace)) = iter.next() {
-                if last_whitespace {
-                    write!(f, " ")?;
-                };
-                last_whitespace = *whitespace;
-                argument.fmt(f)?;
+/// Notation a list is written in. Empty lists, and lists with a value ending
+/// with `~`, are delimited. Tabular lists are lists of two or more tuples of
+/// the same length, with cells that are written on one line: without
+/// dictionaries, lists or text with newlines.
+fn list_notation(list: &ParsedList, notation: ListNotation) -> ListNotation {
+    if list.elements.is_empty() || needs_semicolons(list.elements.iter()) {
+        return ListNotation::Delimited;
+    };
+    match notation {
+        ListNotation::Tabular => {
+            let mut lengths = list.elements.iter().map(|element| match element {
+                ParsedValue::Tuple(ParsedTuple::Multiple(cells), ..) if cells.iter().all(is_cell) => Some(cells.len()),
+                _ => None,
+            });
+            let first = lengths.next().flatten();
+            if first.is_some() && list.elements.len() > 1 && lengths.all(|length| length == first) {
+                ListNotation::Tabular
             } else {
-                break;
-            };
+                ListNotation::Delimited
+            }
         }
-        write!(f, "}}")?;
-        Ok(())
+        notation => notation,
     }
-
 }
 
+/// Whether a value fits in a cell of a table.
+fn is_cell(value: &ParsedValue) -> bool {
+    match value {
+        ParsedValue::Text(text, ..) => !text.str.contains('\n'),
+        ParsedValue::Dictionary(..) | ParsedValue::List(..) => false,
+        ParsedValue::Compound(compound, ..) => compound.components.iter().all(is_cell),
+        ParsedValue::Tuple(ParsedTuple::Single(value), ..) => is_cell(value),
+        ParsedValue::Tuple(ParsedTuple::Multiple(elements), ..) => elements.iter().all(is_cell),
+        ParsedValue::Tagged(tagged, ..) => is_cell(&tagged.value),
+        _ => true,
+    }
+}
 
-impl Display for ParsedDirective {
-
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        // Command opening and name
-        write!(f, "<{}", &self.directive)?;
-        // Command attributes
-        let len = self.attributes.len();
-        if len > 0 {
-            write!(f, " ")?;
-            self.attributes.first().unwrap().fmt(f)?;
-        };
-        let mut i = 1;
-        while i < len {
-            let a = self.attributes.get(i).unwrap();
-            write!(f, " ")?;
-            a.fmt(f)?;
-            i += 1;
+/// Whether entries on separate lines must be separated by `;`.
+///
+/// A value ending with `~` joins the following newline, so it cannot end an
+/// entry separated by a newline alone. Separators are not mixed, so either
+/// every entry or no entry is followed by `;`.
+fn needs_semicolons<'a>(mut values: impl Iterator<Item=&'a ParsedValue>) -> bool {
+    values.any(|value| match value {
+        ParsedValue::Nil(..) => true,
+        ParsedValue::Tuple(ParsedTuple::Multiple(elements), ..) => matches!(elements.last(), Some(ParsedValue::Nil(..))),
+        ParsedValue::Tuple(ParsedTuple::Single(value), ..) => {
+            matches!(value.as_ref(), ParsedValue::Tuple(ParsedTuple::Multiple(elements), ..) if matches!(elements.last(), Some(ParsedValue::Nil(..))))
         }
-        // Command closing
-        write!(f, ">")?;
-        // Command arguments
-        let mut arguments = self.arguments.iter();
-        loop {
-            if let Some(argument) = arguments.next() {
-                write!(f, ":{{")?;
-                argument.fmt(f)?;
-                write!(f, "}}")?;
-            } else {
-                write!(f, " ")?;
-                break;
-            };
-        };
-        Ok(())
-    }
+        _ => false,
+    })
+}
 
+/// Entries with their keys as written, in source order.
+fn sorted_entries(dictionary: &ParsedDictionary) -> Vec<(&str, &ParsedValue)> {
+    dictionary.iter_sorted(KeyOrder::Source).map(|(key, value)| {
+        let key: &str = dictionary.raw_keys.get(key).map(|k| k.as_ref()).unwrap_or(key);
+        (key, value)
+    }).collect()
 }
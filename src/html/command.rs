@@ -2,12 +2,26 @@
 //!
 //! Test: cargo run --bin khi-html-cmd --features="html" -- examples/frontpage.html.khi
 //! Test: cargo run --bin khi-html-cmd --features="html" -- examples/fruits.xml.khi
+//!
+//! The flags `--pretty` and `--compact` before the source file choose the
+//! layout of the output, `--xml` writes XML with a declaration, and
+//! `--shorthand` expands class and id shorthand like `<div.card#main>`.
+//! `--document` writes a complete HTML document, and `--head <file>` writes
+//! a complete HTML document with the head metadata of a dictionary file.
+//! `--sanitize` drops the elements and attributes of untrusted documents
+//! that are not allowed, and `--strict` rejects them:
+//!
+//! Test: cargo run --bin khi-html-cmd --features="html" -- --pretty examples/frontpage.html.khi
+//! Test: cargo run --bin khi-html-cmd --features="html" -- --xml examples/fruits.xml.khi
+//! Test: cargo run --bin khi-html-cmd --features="html" -- --document examples/frontpage.html.khi
 
 use std::env;
 use std::fs::File;
 use std::io::Read;
-use khi::html::{PreprocessorError, write_html};
-use khi::parse::{parse_value_str};
+use std::path::Path;
+use std::rc::Rc;
+use khi::html::{Emission, HtmlLoader, HtmlOptions, OutputDialect, PreprocessorError, Sanitize, write_html_with};
+use khi::parse::{parse_dictionary_str, parse_value_str};
 use khi::parse::parser::error_to_string;
 
 fn main() {
@@ -20,7 +34,38 @@ fn main() {
 fn preprocess() -> Result<String, String> {
     let mut args = env::args();
     args.next(); // The first arg is the binary. Skip.
+    let mut options = HtmlOptions::default();
+    let mut args = args.peekable();
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        match flag.as_str() {
+            "--pretty" => options.pretty = true,
+            "--compact" => options.compact = true,
+            "--shorthand" => options.attribute_shorthand = true,
+            "--xml" => {
+                options.dialect = OutputDialect::Xml;
+                options.xml_declaration = true;
+            }
+            "--sanitize" => options.sanitize = Sanitize::Drop,
+            "--strict" => options.sanitize = Sanitize::Reject,
+            "--document" => options.emission = Emission::Document(parse_dictionary_str("").unwrap()),
+            "--head" => {
+                let path = args.next().ok_or_else(|| "Specify head file after --head.".to_string())?;
+                let source = std::fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+                let head = parse_dictionary_str(&source).map_err(|errors| {
+                    errors.iter().map(|e| format!("In {}: {}\n", path, error_to_string(e))).collect::<String>()
+                })?;
+                options.emission = Emission::Document(head);
+            }
+            flag => return Err(format!("Unknown flag {}.", flag)),
+        };
+    }
     if let Some(first) = args.next() {
+        // Included documents are relative to the directory of the document.
+        let directory = Path::new(&first).parent().map(Path::to_path_buf).unwrap_or_default();
+        let loader: HtmlLoader = Rc::new(move |include| {
+            std::fs::read_to_string(directory.join(include)).map_err(|e| e.to_string())
+        });
+        options.loader = Some(loader);
         let mut file = File::open(first).unwrap();
         let mut source = String::new();
         file.read_to_string(&mut source).unwrap();
@@ -36,24 +81,45 @@ fn preprocess() -> Result<String, String> {
                 return Err(errs);
             },
         };
-        match write_html(&document) {
-            Ok(output) => Ok(output),
-            Err(error) => {
-                Err(match error {
-                    PreprocessorError::IllegalTable(at) => {
-                        format!("Illegal table at {}:{}.", at.line, at.column)
-                    }
-                    PreprocessorError::MacroError(error) => error,
-                    PreprocessorError::TooManyArguments(at) => {
-                        format!("Tag at {}:{} has more than one argument.", at.line, at.column)
-                    }
-                    PreprocessorError::IllegalTuple(at) => {
-                        format!("Illegal tuple at {}:{}.", at.line, at.column)
-                    }
-                })
-            }
-        }
+        write_html_with(&document, &options).map_err(describe)
     } else {
         Err(format!("Specify source file as first argument."))
     }
 }
+
+fn describe(error: PreprocessorError) -> String {
+    match error {
+        PreprocessorError::IllegalTable(at) => {
+            format!("Illegal table at {}:{}.", at.line, at.column)
+        }
+        PreprocessorError::MacroError(error) => error,
+        PreprocessorError::TooManyArguments(at) => {
+            format!("Tag at {}:{} has more than one argument.", at.line, at.column)
+        }
+        PreprocessorError::IllegalTuple(at) => {
+            format!("Illegal tuple at {}:{}.", at.line, at.column)
+        }
+        PreprocessorError::DuplicateAttribute(at, key) => {
+            format!("Tag at {}:{} has more than one {} attribute.", at.line, at.column, key)
+        }
+        PreprocessorError::NotBooleanAttribute(at, key) => {
+            format!("Attribute {} of tag at {}:{} must have a value.", key, at.line, at.column)
+        }
+        PreprocessorError::HeadError(error) => error,
+        PreprocessorError::DisallowedElement(at, name) => {
+            format!("Element {} at {}:{} is not allowed.", name, at.line, at.column)
+        }
+        PreprocessorError::DisallowedAttribute(at, key) => {
+            format!("Attribute {} of tag at {}:{} is not allowed.", key, at.line, at.column)
+        }
+        PreprocessorError::ScriptUrl(at, key) => {
+            format!("Attribute {} of tag at {}:{} has a javascript: URL.", key, at.line, at.column)
+        }
+        PreprocessorError::UndeclaredPrefix(at, name) => {
+            format!("Namespace prefix of {} at {}:{} is not declared.", name, at.line, at.column)
+        }
+        PreprocessorError::Include(path, error) => {
+            format!("In {}: {}", path, describe(*error))
+        }
+    }
+}
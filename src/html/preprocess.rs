@@ -1,34 +1,392 @@
-use std::ops::Deref;
-use crate::{Dictionary, Tagged, Value, Text, Element, Attribute, Compound, Tuple};
-use crate::pdm::{ParsedDictionary, ParsedTaggedValue, ParsedTuple, ParsedValue, Position};
+use std::borrow::Cow;
+use std::ops::{Deref, Range};
+use std::rc::Rc;
+use crate::{Dictionary, Tagged, Value, Text, Element, Compound, Tuple};
+use crate::fmt::{preview, PREVIEW_LENGTH};
+use crate::parse::parse_value_str;
+use crate::parse::parser::error_to_string;
+use crate::pdm::{KeyOrder, ParsedAttribute, ParsedAttributeValue, ParsedDictionary, ParsedTaggedValue, ParsedTuple, ParsedValue, Position};
 
 pub fn write_html(value: &ParsedValue) -> Result<String, PreprocessorError> {
+    write_html_with(value, &HtmlOptions::default())
+}
+
+pub fn write_html_with(value: &ParsedValue, options: &HtmlOptions) -> Result<String, PreprocessorError> {
     let mut output = String::new();
-    let mut writer = XmlWriter { output: &mut output, column: 1, newline: 60, last: LastType::Whitespace, };
-    writer.write_xml_compound(value)?;
+    let mut writer = XmlWriter::new(&mut output, options);
+    writer.write_document(value)?;
     Ok(output)
 }
 
+/// Write a document, and map the ranges of the output to the source.
+pub fn write_html_mapped(value: &ParsedValue, options: &HtmlOptions) -> Result<(String, SourceMap), PreprocessorError> {
+    let mut output = String::new();
+    let mut writer = XmlWriter::new(&mut output, options);
+    writer.source_map = Some(SourceMap::default());
+    writer.write_document(value)?;
+    let source_map = writer.source_map.take().unwrap_or_default();
+    Ok((output, source_map))
+}
+
+/// Options for the preprocessor.
+#[derive(Clone)]
+pub struct HtmlOptions {
+    /// Expand attribute shorthand in tag headers.
+    ///
+    /// A flag `.name` adds `name` to the `class` attribute, and a flag `#name`
+    /// sets the `id` attribute. For example, `<div .card .wide #main>` is
+    /// written as `<div class="card wide" id="main">`. The shorthand may
+    /// also follow the tag name, like `<div.card.wide#main>`, and a tag name
+    /// of only shorthand, like `<.card>`, is a `div`.
+    pub attribute_shorthand: bool,
+    /// Writing of attributes without a value.
+    pub boolean_attributes: BooleanAttributes,
+    /// Write attribute values with double quotes and no single quotes in
+    /// single quotes, instead of writing the double quotes as `&quot;`.
+    pub single_quotes: bool,
+    /// Omit attributes with the value `false`, such as `checked:false`.
+    pub omit_false: bool,
+    /// Reject attributes without a value that are not in
+    /// [BOOLEAN_ATTRIBUTES].
+    pub validate_boolean_attributes: bool,
+    /// Write block elements on lines of their own, indented by nesting.
+    /// Elements with block elements in them end on a line of their own.
+    pub pretty: bool,
+    /// Spaces of indentation per nesting of block elements, with
+    /// [HtmlOptions::pretty]. 2 by default.
+    pub indent: usize,
+    /// The column after which whitespace is written as a newline. 60 by
+    /// default, and 0 for never.
+    pub wrap: usize,
+    /// Elements that are blocks, which [HtmlOptions::pretty] writes on lines
+    /// of their own. Other elements are inline. [BLOCK_ELEMENTS] by default.
+    pub block_elements: Vec<String>,
+    /// Write the document on one line, without the whitespace around block
+    /// elements. Takes the place of [HtmlOptions::pretty] and
+    /// [HtmlOptions::wrap].
+    pub compact: bool,
+    /// Elements in which text is written as it is, without collapsing
+    /// whitespace and breaking lines, and in which elements are inline.
+    /// [PRESERVE_ELEMENTS] by default.
+    pub preserve_elements: Vec<String>,
+    /// Loader of the documents included with `include!`. Without a loader,
+    /// `include!` is an error.
+    pub loader: Option<HtmlLoader>,
+    /// The dialect of the output.
+    pub dialect: OutputDialect,
+    /// Begin the output with the declaration
+    /// `<?xml version="1.0" encoding="UTF-8"?>`.
+    pub xml_declaration: bool,
+    /// Namespaces by prefix, like `svg` with `http://www.w3.org/2000/svg`,
+    /// which are declared on the outermost elements, unless they declare the
+    /// prefix themselves. The empty prefix is the default namespace.
+    pub namespaces: Vec<(String, String)>,
+    /// Writing of a fragment or of a complete document.
+    pub emission: Emission,
+    /// Sanitization of elements, macros and attributes, for documents that
+    /// are not trusted.
+    pub sanitize: Sanitize,
+    /// Elements and macros allowed by [HtmlOptions::sanitize].
+    /// [SAFE_ELEMENTS] by default.
+    pub allowed_elements: Vec<String>,
+    /// Attributes allowed by [HtmlOptions::sanitize]. [SAFE_ATTRIBUTES] by
+    /// default.
+    pub allowed_attributes: Vec<String>,
+    /// Reject element and attribute names with a prefix, like `svg:rect`,
+    /// that is not declared with `xmlns:prefix` in the element or an element
+    /// it is in.
+    pub validate_namespaces: bool,
+}
+
+/// Load the source of a document included with `<include!>:path` by path.
+pub type HtmlLoader = Rc<dyn Fn(&str) -> Result<String, String>>;
+
+impl Default for HtmlOptions {
+
+    fn default() -> Self {
+        HtmlOptions {
+            attribute_shorthand: false, boolean_attributes: BooleanAttributes::Minimized, single_quotes: false, omit_false: false, validate_boolean_attributes: false,
+            pretty: false, indent: 2, wrap: 60, block_elements: BLOCK_ELEMENTS.iter().map(|e| e.to_string()).collect(), compact: false,
+            preserve_elements: PRESERVE_ELEMENTS.iter().map(|e| e.to_string()).collect(), loader: None, dialect: OutputDialect::Html, xml_declaration: false,
+            namespaces: vec![], validate_namespaces: false, emission: Emission::Fragment,
+            sanitize: Sanitize::Off, allowed_elements: SAFE_ELEMENTS.iter().map(|e| e.to_string()).collect(),
+            allowed_attributes: SAFE_ATTRIBUTES.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+}
+
+/// Writing of attributes without a value.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum BooleanAttributes {
+    /// Write only the name, like `checked`.
+    #[default]
+    Minimized,
+    /// Write the name as the value, like `checked="checked"`, as required by
+    /// XHTML and XML.
+    Expanded,
+}
+
+/// Sanitization of elements, macros and attributes that are not allowed,
+/// and of `javascript:` URLs in [URL_ATTRIBUTES].
+///
+/// Names are allowed regardless of case, and macros like `raw!` and
+/// `include!` only if they are allowed elements. The head of
+/// [Emission::Document] is not sanitized.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Sanitize {
+    /// Write every element, macro and attribute.
+    #[default]
+    Off,
+    /// Drop elements and macros that are not allowed, with what is in them,
+    /// and attributes that are not allowed.
+    Drop,
+    /// Reject elements, macros and attributes that are not allowed.
+    Reject,
+}
+
+/// Writing of a fragment or of a complete document.
+#[derive(Clone, Default)]
+pub enum Emission {
+    /// Write the document as it is.
+    #[default]
+    Fragment,
+    /// Write the document as the body of a complete HTML document, after
+    /// `<!DOCTYPE html>` and a head with the metadata of a dictionary.
+    ///
+    /// The entries of the dictionary are `lang`, the language of the
+    /// document, `charset`, `utf-8` by default, `title`, `meta`, a
+    /// dictionary of `<meta>` names and contents, and `stylesheets` and
+    /// `scripts`, the paths of style sheets and scripts.
+    Document(ParsedDictionary),
+}
+
+/// Construct a complete HTML document with a head of the metadata of a
+/// dictionary, and a body.
+fn complete_document(head: &ParsedDictionary, body: &ParsedValue) -> Result<ParsedValue, PreprocessorError> {
+    let text = |key: &str| match head.get(key) {
+        None => Ok(None),
+        Some(value) => value.as_text().map(|text| Some(text.as_str())).ok_or_else(|| {
+            PreprocessorError::HeadError(format!("Head entry {} must be text, got {}.", key, preview(value, PREVIEW_LENGTH)))
+        }),
+    };
+    let paths = |key: &str| -> Result<Vec<&str>, PreprocessorError> {
+        let value = match head.get(key) {
+            Some(value) => value,
+            None => return Ok(vec![]),
+        };
+        let texts: Option<Vec<&str>> = match value.as_list() {
+            Some(list) => list.elements.iter().map(|element| element.as_text().map(|text| text.as_str())).collect(),
+            None => value.as_text().map(|text| vec![text.as_str()]),
+        };
+        texts.ok_or_else(|| PreprocessorError::HeadError(format!("Head entry {} must be paths, got {}.", key, preview(value, PREVIEW_LENGTH))))
+    };
+    for (key, _) in head.iter() {
+        if !["lang", "charset", "title", "meta", "stylesheets", "scripts"].contains(&key) {
+            return Err(PreprocessorError::HeadError(format!("Unknown head entry {}.", key)));
+        };
+    }
+    let mut elements = vec![];
+    let charset = text("charset")?.unwrap_or("utf-8");
+    elements.push(ParsedValue::tagged("meta", vec![("charset", Some(charset))], ParsedValue::Tuple(ParsedTuple::Unit, Position::ZERO, Position::ZERO)));
+    if let Some(title) = text("title")? {
+        elements.push(ParsedValue::tagged("title", vec![], ParsedValue::text(title)));
+    };
+    if let Some(meta) = head.get("meta") {
+        let entries = match meta.as_dictionary() {
+            Some(entries) => entries,
+            None => return Err(PreprocessorError::HeadError(format!("Head entry meta must be a dictionary, got {}.", preview(meta, PREVIEW_LENGTH)))),
+        };
+        for (name, content) in entries.iter_sorted(KeyOrder::Source) {
+            let content = match content.as_text() {
+                Some(content) => content.as_str(),
+                None => return Err(PreprocessorError::HeadError(format!("Meta {} must be text, got {}.", name, preview(content, PREVIEW_LENGTH)))),
+            };
+            elements.push(ParsedValue::tagged("meta", vec![("name", Some(name)), ("content", Some(content))], ParsedValue::Tuple(ParsedTuple::Unit, Position::ZERO, Position::ZERO)));
+        }
+    };
+    for path in paths("stylesheets")? {
+        elements.push(ParsedValue::tagged("link", vec![("rel", Some("stylesheet")), ("href", Some(path))], ParsedValue::Tuple(ParsedTuple::Unit, Position::ZERO, Position::ZERO)));
+    }
+    for path in paths("scripts")? {
+        elements.push(ParsedValue::tagged("script", vec![("src", Some(path))], ParsedValue::Nil(Position::ZERO, Position::ZERO)));
+    }
+    let whitespace = vec![false; elements.len() - 1];
+    let head_element = ParsedValue::tagged("head", vec![], ParsedValue::from_terms(Position::ZERO, Position::ZERO, elements, whitespace));
+    let body = if body.is_unit() { ParsedValue::Nil(body.from(), body.to()) } else { body.clone() };
+    let body_element = ParsedValue::tagged("body", vec![], body);
+    let html = ParsedValue::from_terms(Position::ZERO, Position::ZERO, vec![head_element, body_element], vec![false]);
+    let attributes = match text("lang")? {
+        Some(lang) => vec![("lang", Some(lang))],
+        None => vec![],
+    };
+    Ok(ParsedValue::tagged("html", attributes, html))
+}
+
+/// Positions in the source of the ranges of a written document.
+///
+/// Each element, text and macro maps the range of the output that it is
+/// written to, in bytes, to its position. The elements of an included
+/// document map to the `include!` tag.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// Ranges of the output, with the position of the value written to them,
+    /// in order of their ends.
+    ranges: Vec<(Range<usize>, Position)>,
+}
+
+impl SourceMap {
+
+    /// Get the position in the source of the innermost value written at an
+    /// offset of the output.
+    pub fn source(&self, offset: usize) -> Option<Position> {
+        self.ranges.iter()
+            .filter(|(range, _)| range.contains(&offset))
+            .min_by_key(|(range, _)| range.len())
+            .map(|(_, at)| *at)
+    }
+
+    /// Iterate over the ranges of the output, with the position of the value
+    /// written to them, in order of their ends.
+    pub fn iter(&self) -> impl Iterator<Item = (Range<usize>, Position)> + '_ {
+        self.ranges.iter().cloned()
+    }
+
+}
+
+/// The dialect of the output.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputDialect {
+    /// Elements without an argument, like `<br>`, have no closing tag, and
+    /// empty elements, like `<script>:<>`, have a closing tag.
+    #[default]
+    Html,
+    /// Elements without an argument and empty elements close themselves,
+    /// like `<br/>`, and attributes without a value are written with the
+    /// name as the value, like `checked="checked"`.
+    Xml,
+}
+
+/// The boolean attributes of HTML.
+pub const BOOLEAN_ATTRIBUTES: &[&str] = &[
+    "allowfullscreen", "async", "autofocus", "autoplay", "checked", "controls", "default", "defer",
+    "disabled", "formnovalidate", "hidden", "inert", "ismap", "itemscope", "loop", "multiple", "muted",
+    "nomodule", "novalidate", "open", "playsinline", "readonly", "required", "reversed", "selected",
+];
+
+/// The block elements of HTML.
+pub const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "base", "blockquote", "body", "caption", "dd", "details", "dialog", "div", "dl", "dt",
+    "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hgroup",
+    "hr", "html", "li", "link", "main", "meta", "nav", "noscript", "ol", "p", "pre", "script", "section", "style",
+    "summary", "table", "tbody", "td", "tfoot", "th", "thead", "title", "tr", "ul",
+];
+
+/// The elements of HTML allowed by [HtmlOptions::sanitize] by default,
+/// which cannot run scripts or change the page around them.
+pub const SAFE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "blockquote", "br", "caption", "cite", "code", "col", "colgroup", "dd", "del",
+    "dfn", "div", "dl", "dt", "em", "figcaption", "figure", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "i", "img",
+    "ins", "kbd", "li", "mark", "ol", "p", "pre", "q", "rp", "rt", "ruby", "s", "samp", "small", "span", "strong",
+    "sub", "sup", "table", "tbody", "td", "tfoot", "th", "thead", "time", "tr", "u", "ul", "var", "wbr",
+];
+
+/// The attributes of HTML allowed by [HtmlOptions::sanitize] by default.
+pub const SAFE_ATTRIBUTES: &[&str] = &[
+    "abbr", "alt", "cite", "class", "colspan", "datetime", "dir", "headers", "height", "href", "id", "lang",
+    "reversed", "rowspan", "scope", "span", "src", "start", "title", "width",
+];
+
+/// The attributes of HTML with a URL, in which [HtmlOptions::sanitize]
+/// rejects `javascript:` URLs.
+pub const URL_ATTRIBUTES: &[&str] = &[
+    "action", "background", "cite", "formaction", "href", "longdesc", "poster", "src", "xlink:href",
+];
+
+/// The elements of HTML in which whitespace is preserved.
+pub const PRESERVE_ELEMENTS: &[&str] = &["pre", "code", "textarea"];
+
 pub struct XmlWriter<'a> {
     output: &'a mut String,
     column: usize,
     newline: usize, // 0 for never newline.
     last: LastType,
+    /// Nesting of block elements.
+    depth: usize,
+    /// Whether a block element is written in the element being written.
+    blocks: bool,
+    /// Nesting of elements that preserve whitespace.
+    preserve: usize,
+    options: &'a HtmlOptions,
+    /// Paths of the documents being included, outermost first.
+    includes: Vec<String>,
+    /// The namespace prefixes declared in each element being written,
+    /// outermost first.
+    scopes: Vec<Vec<String>>,
+    source_map: Option<SourceMap>,
+    /// Whether the elements being written are of the options, like the head
+    /// of [Emission::Document], which are not sanitized.
+    trusted: bool,
 }
 
+type Attributes<'a> = Vec<(&'a str, Option<Cow<'a, str>>)>;
+
 #[derive(Eq, PartialEq)]
 enum LastType {
     Glyph,
     Whitespace,
+    /// The end of a block element, after which whitespace is skipped.
+    Block,
 }
 
-impl XmlWriter<'_> {
+impl<'a> XmlWriter<'a> {
+
+    fn new(output: &'a mut String, options: &'a HtmlOptions) -> Self {
+        let newline = if options.compact { 0 } else { options.wrap };
+        XmlWriter {
+            output, column: 1, newline, last: LastType::Whitespace, depth: 0, blocks: false, preserve: 0, options, includes: vec![], scopes: vec![],
+            source_map: None, trusted: false,
+        }
+    }
+
+    fn write_document(&mut self, value: &ParsedValue) -> Result<(), PreprocessorError> {
+        if self.options.xml_declaration {
+            self.push_str_non_breaking("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+            if !self.options.compact {
+                self.break_line();
+            };
+        };
+        match &self.options.emission {
+            Emission::Fragment => self.write_xml_compound(value),
+            Emission::Document(head) => {
+                let document = complete_document(head, value)?;
+                // The document is sanitized from its body.
+                self.trusted = true;
+                self.push_str_non_breaking("<!DOCTYPE html>");
+                if !self.options.compact {
+                    self.break_line();
+                };
+                self.write_xml_compound(&document)
+            }
+        }
+    }
+
+    /// Map the output from an offset to a position in the source, outside of
+    /// included documents.
+    fn map_range(&mut self, start: usize, at: Position) {
+        if let Some(source_map) = &mut self.source_map {
+            if self.includes.is_empty() {
+                source_map.ranges.push((start..self.output.len(), at));
+            };
+        };
+    }
 
     fn push_whitespace(&mut self) {
-        if self.last != LastType::Whitespace {
+        if self.preserve > 0 {
+            self.push_non_breaking(' ');
+        } else if self.last == LastType::Glyph {
             if self.newline != 0 && self.column > self.newline {
-                self.output.push('\n');
-                self.column = 1;
+                self.break_line();
             } else {
                 self.output.push(' ');
                 self.column += 1;
@@ -38,10 +396,15 @@ impl XmlWriter<'_> {
     }
 
     fn push_str(&mut self, str: &str) {
+        if self.preserve > 0 {
+            self.push_str_non_breaking(str);
+            return;
+        };
         for c in str.chars() {
             if c.is_whitespace() {
                 self.push_whitespace();
             } else {
+                self.end_block();
                 self.column += 1;
                 self.output.push(c);
                 self.last = LastType::Glyph;
@@ -50,7 +413,8 @@ impl XmlWriter<'_> {
     }
 
     fn push_non_breaking(&mut self, char: char) {
-        self.column += 1;
+        self.end_block();
+        self.column = if char == '\n' { 1 } else { self.column + 1 };
         self.output.push(char);
         if char.is_whitespace() {
             self.last = LastType::Whitespace;
@@ -65,6 +429,43 @@ impl XmlWriter<'_> {
         }
     }
 
+    /// Write a newline, indented by the nesting of block elements with
+    /// [HtmlOptions::pretty].
+    fn break_line(&mut self) {
+        self.output.push('\n');
+        self.column = 1;
+        if self.options.pretty && !self.options.compact {
+            let indent = self.depth * self.options.indent;
+            self.output.extend(std::iter::repeat(' ').take(indent));
+            self.column += indent;
+        };
+        self.last = LastType::Whitespace;
+    }
+
+    /// Start a block element, without the whitespace before it, on a line of
+    /// its own with [HtmlOptions::pretty].
+    fn start_block(&mut self) {
+        if self.last == LastType::Whitespace && self.output.ends_with(' ') {
+            self.output.pop();
+            self.column -= 1;
+        };
+        if self.options.pretty && !self.options.compact && !self.output.is_empty() && !self.output.ends_with('\n') {
+            self.break_line();
+        };
+        self.last = LastType::Whitespace;
+    }
+
+    /// Start a line after a block element, before what follows it, with
+    /// [HtmlOptions::pretty].
+    fn end_block(&mut self) {
+        if self.last == LastType::Block {
+            if self.options.pretty && !self.options.compact {
+                self.break_line();
+            };
+            self.last = LastType::Whitespace;
+        };
+    }
+
 }
 
 impl XmlWriter<'_> {
@@ -72,8 +473,11 @@ impl XmlWriter<'_> {
     fn write_xml_compound(&mut self, value: &ParsedValue) -> Result<(), PreprocessorError> {
         match value {
             ParsedValue::Nil(..) => {}
-            ParsedValue::Text(text, ..) => {
-                self.push_str(text.as_str());
+            ParsedValue::Text(text, from, _) => {
+                self.end_block();
+                let start = self.output.len();
+                self.push_str(&escape(text.as_str(), false));
+                self.map_range(start, *from);
             }
             ParsedValue::Dictionary(dictionary, from, to) => {
                 self.write_dictionary(dictionary, *from)?;
@@ -98,7 +502,12 @@ impl XmlWriter<'_> {
                 }
             }
             ParsedValue::Tagged(tag, from, to) => {
+                // Elements map themselves, after the whitespace before them.
+                let start = self.output.len();
                 self.write_tag(tag, *from)?;
+                if tag.name().ends_with('!') {
+                    self.map_range(start, *from);
+                };
             }
         }
         Ok(())
@@ -107,14 +516,16 @@ impl XmlWriter<'_> {
     fn write_tag(&mut self, tag: &ParsedTaggedValue, at: Position) -> Result<(), PreprocessorError> {
         let name = tag.name();
         let inner_value = tag.get();
+        if name.ends_with('!') && !self.allow_element(name, at)? {
+            return Ok(());
+        };
         if name.ends_with('!') {
             if name.deref() == "doctype!" {
                 if tag.has_attributes() {
                     return Err(PreprocessorError::MacroError(format!("doctype! macro cannot have attributes.")))
                 }
                 if !inner_value.is_text() {
-                    if inner_value.is_tuple() {eprintln!("{}", inner_value.as_tuple().unwrap().len())};
-                    return Err(PreprocessorError::MacroError(format!("doctype! must have 1 text argument.")))
+                    return Err(PreprocessorError::MacroError(format!("doctype! must have 1 text argument, got {}.", preview(inner_value, PREVIEW_LENGTH))))
                 }
                 let doctype = inner_value;
                 self.push_str_non_breaking("<!DOCTYPE ");
@@ -126,70 +537,337 @@ impl XmlWriter<'_> {
                     self.output.push_str(text.as_str());
                     Ok(())
                 } else {
-                    Err(PreprocessorError::MacroError(format!("raw! can only take a text argument.")))
+                    Err(PreprocessorError::MacroError(format!("raw! can only take a text argument, got {}.", preview(inner_value, PREVIEW_LENGTH))))
                 }
+            } else if name == "cdata!" {
+                if let Some(text) = inner_value.as_text() {
+                    // `]]>` ends a section, so it is split over two sections.
+                    self.output.push_str("<![CDATA[");
+                    self.output.push_str(&text.as_str().replace("]]>", "]]]]><![CDATA[>"));
+                    self.output.push_str("]]>");
+                    Ok(())
+                } else {
+                    Err(PreprocessorError::MacroError(format!("cdata! must take 1 text argument, got {}.", preview(inner_value, PREVIEW_LENGTH))))
+                }
+            } else if name == "include!" {
+                self.write_include(inner_value)
             } else {
                 Err(PreprocessorError::MacroError(format!("Unknown macro {}.", name)))
             }
         } else {
+            let (name, attributes) = self.expand_attributes(tag, at)?;
+            if !self.allow_element(name, at)? {
+                return Ok(());
+            };
+            let attributes = self.sanitize_attributes(attributes, at)?;
+            if name == "body" {
+                self.trusted = false;
+            };
+            let layout = self.options.pretty || self.options.compact;
+            let block = layout && self.preserve == 0 && self.options.block_elements.iter().any(|element| element == name);
+            let preserve = self.options.preserve_elements.iter().any(|element| element == name);
+            if block {
+                self.start_block();
+            };
+            self.end_block();
+            let start = self.output.len();
+            // The namespaces of the options are declared on the outermost elements.
+            let mut declarations = vec![];
+            if self.scopes.is_empty() {
+                for (prefix, uri) in &self.options.namespaces {
+                    let key = if prefix.is_empty() { String::from("xmlns") } else { format!("xmlns:{}", prefix) };
+                    if !attributes.iter().any(|(k, _)| *k == key) {
+                        declarations.push((key, uri.as_str()));
+                    };
+                }
+            };
+            let prefixes = attributes.iter().map(|(key, _)| *key).chain(declarations.iter().map(|(key, _)| key.as_str()))
+                .filter_map(|key| key.strip_prefix("xmlns:")).map(String::from).collect();
+            self.scopes.push(prefixes);
+            self.check_prefix(name, at)?;
+            for (key, _) in &attributes {
+                if !key.starts_with("xmlns:") {
+                    self.check_prefix(key, at)?;
+                };
+            }
             self.push_non_breaking('<');
-            self.push_str_non_breaking(&name);
-            for Attribute(key, value) in tag.iter_attributes() {
+            self.push_str_non_breaking(name);
+            for (key, value) in attributes {
                 match value {
+                    Some(value) if self.options.omit_false && value == "false" => {}
                     None => {
-                        self.push_non_breaking(' ');
-                        self.push_str_non_breaking(key);
-                    }
-                    Some(value) => {
-                        self.push_non_breaking(' ');
-                        self.push_str_non_breaking(key);
-                        self.push_str_non_breaking("=\"");
-                        self.push_str_non_breaking(value);
-                        self.push_non_breaking('"');
+                        if self.options.validate_boolean_attributes && !BOOLEAN_ATTRIBUTES.contains(&key) {
+                            return Err(PreprocessorError::NotBooleanAttribute(at, key.to_string()));
+                        };
+                        if self.options.boolean_attributes == BooleanAttributes::Expanded || self.options.dialect == OutputDialect::Xml {
+                            self.write_attribute(key, key);
+                        } else {
+                            self.push_non_breaking(' ');
+                            self.push_str_non_breaking(key);
+                        };
                     }
+                    Some(value) => self.write_attribute(key, &value),
                 };
             }
-            self.push_non_breaking('>');
-            if inner_value.is_tuple() {//todo
-                match inner_value.as_tuple().unwrap() {
-                    ParsedTuple::Unit => return Ok(()), // Self closing tag
-                    ParsedTuple::Single(s) => {
-                        if s.is_unit() {
-                            // Empty element
-                        } else {
-                            return Err(PreprocessorError::IllegalTuple(s.from()));
-                        }
-                    }
-                    ParsedTuple::Multiple(..) => return Err(PreprocessorError::TooManyArguments(at)),
-                }
-            } else {
-                self.write_xml_compound(inner_value)?
+            for (key, uri) in declarations {
+                self.write_attribute(&key, uri);
             }
-            self.push_str_non_breaking("</");
-            self.push_str_non_breaking(name);
-            self.push_non_breaking('>');
+            // An element without an argument has no closing tag in HTML, like
+            // `<br>`, and an element with an empty argument is empty, like
+            // `<script>:<>`. In XML, both close themselves.
+            let (empty, closed) = match inner_value.as_tuple() {
+                Some(ParsedTuple::Unit) => (true, false),
+                Some(ParsedTuple::Single(s)) if s.is_unit() => (true, true),
+                Some(ParsedTuple::Single(s)) => return Err(PreprocessorError::IllegalTuple(s.from())),
+                Some(ParsedTuple::Multiple(..)) => return Err(PreprocessorError::TooManyArguments(at)),
+                None => (false, true),
+            };
+            let outer = std::mem::replace(&mut self.blocks, false);
+            if empty && self.options.dialect == OutputDialect::Xml {
+                self.push_str_non_breaking("/>");
+            } else {
+                self.push_non_breaking('>');
+                if !empty {
+                    self.depth += usize::from(block);
+                    self.preserve += usize::from(preserve);
+                    self.write_xml_compound(inner_value)?;
+                    self.preserve -= usize::from(preserve);
+                    self.depth -= usize::from(block);
+                };
+                if closed {
+                    if self.blocks {
+                        self.start_block();
+                    };
+                    self.push_str_non_breaking("</");
+                    self.push_str_non_breaking(name);
+                    self.push_non_breaking('>');
+                };
+            };
+            self.scopes.pop();
+            self.map_range(start, at);
+            self.blocks = outer || block;
+            if block {
+                self.last = LastType::Block;
+            };
             Ok(())
         }
     }
 
+    /// Write an attribute with a value, like ` key="value"`.
+    fn write_attribute(&mut self, key: &str, value: &str) {
+        self.push_non_breaking(' ');
+        self.push_str_non_breaking(key);
+        let single = self.options.single_quotes && value.contains('"') && !value.contains('\'');
+        let quote = if single { '\'' } else { '"' };
+        self.push_non_breaking('=');
+        self.push_non_breaking(quote);
+        self.push_str_non_breaking(&escape(value, quote == '"'));
+        self.push_non_breaking(quote);
+    }
+
+    /// Whether an element or macro is written, with [HtmlOptions::sanitize].
+    fn allow_element(&self, name: &str, at: Position) -> Result<bool, PreprocessorError> {
+        let sanitize = if self.trusted { Sanitize::Off } else { self.options.sanitize };
+        if sanitize == Sanitize::Off || self.options.allowed_elements.iter().any(|e| e.eq_ignore_ascii_case(name)) {
+            Ok(true)
+        } else if sanitize == Sanitize::Drop {
+            Ok(false)
+        } else {
+            Err(PreprocessorError::DisallowedElement(at, name.to_string()))
+        }
+    }
+
+    /// Drop or reject the attributes that are not allowed, and those with a
+    /// `javascript:` URL, with [HtmlOptions::sanitize].
+    fn sanitize_attributes<'b>(&self, attributes: Attributes<'b>, at: Position) -> Result<Attributes<'b>, PreprocessorError> {
+        let sanitize = if self.trusted { Sanitize::Off } else { self.options.sanitize };
+        if sanitize == Sanitize::Off {
+            return Ok(attributes);
+        };
+        let mut allowed = vec![];
+        for (key, value) in attributes {
+            let error = if !self.options.allowed_attributes.iter().any(|a| a.eq_ignore_ascii_case(key)) {
+                PreprocessorError::DisallowedAttribute(at, key.to_string())
+            } else if URL_ATTRIBUTES.iter().any(|a| a.eq_ignore_ascii_case(key)) && value.as_deref().map_or(false, is_script_url) {
+                PreprocessorError::ScriptUrl(at, key.to_string())
+            } else {
+                allowed.push((key, value));
+                continue;
+            };
+            if sanitize == Sanitize::Reject {
+                return Err(error);
+            };
+        }
+        Ok(allowed)
+    }
+
+    /// Check that the prefix of a name, like `svg` of `svg:rect`, is declared
+    /// in an element being written, with [HtmlOptions::validate_namespaces].
+    fn check_prefix(&self, name: &str, at: Position) -> Result<(), PreprocessorError> {
+        let prefix = match name.split_once(':') {
+            Some((prefix, _)) => prefix,
+            None => return Ok(()),
+        };
+        if !self.options.validate_namespaces || prefix == "xml" || self.scopes.iter().flatten().any(|p| p == prefix) {
+            return Ok(());
+        };
+        Err(PreprocessorError::UndeclaredPrefix(at, name.to_string()))
+    }
+
+    /// Load, parse and write a document included with `<include!>:path`.
+    fn write_include(&mut self, argument: &ParsedValue) -> Result<(), PreprocessorError> {
+        let path = match argument.as_text() {
+            Some(path) => path.as_str(),
+            None => return Err(PreprocessorError::MacroError(format!("include! must take a path, got {}.", preview(argument, PREVIEW_LENGTH)))),
+        };
+        let loader = match &self.options.loader {
+            Some(loader) => loader,
+            None => return Err(PreprocessorError::MacroError(format!("include! of {} requires a loader.", path))),
+        };
+        if self.includes.iter().any(|include| include == path) {
+            let cycle: Vec<&str> = self.includes.iter().map(String::as_str).chain([path]).collect();
+            return Err(PreprocessorError::MacroError(format!("include! cycle: {}.", cycle.join(" -> "))));
+        };
+        let source = loader(path).map_err(|error| PreprocessorError::MacroError(format!("include! cannot load {}: {}", path, error)))?;
+        let document = parse_value_str(&source).map_err(|errors| {
+            let errors: Vec<String> = errors.iter().map(error_to_string).collect();
+            PreprocessorError::MacroError(format!("include! cannot parse {}: {}", path, errors.join(" ")))
+        })?;
+        self.includes.push(path.to_string());
+        let result = self.write_xml_compound(&document);
+        self.includes.pop();
+        result.map_err(|error| match error {
+            PreprocessorError::MacroError(message) if message.starts_with("include! cycle") => PreprocessorError::MacroError(message),
+            error => PreprocessorError::Include(path.to_string(), Box::new(error)),
+        })
+    }
+
+    /// Get the element name and the attributes of a tag, with shorthand
+    /// expanded if enabled.
+    fn expand_attributes<'b>(&self, tag: &'b ParsedTaggedValue, at: Position) -> Result<(&'b str, Attributes<'b>), PreprocessorError> {
+        let attributes = tag.attributes.iter().map(|ParsedAttribute(key, value)| {
+            let value = value.as_ref().map(|value| match value {
+                ParsedAttributeValue::Text(text) => Cow::Borrowed(text.deref()),
+                // A list of strings, like a list of classes, is joined by spaces.
+                ParsedAttributeValue::List(strings) => Cow::Owned(strings.join(" ")),
+            });
+            (key.deref(), value)
+        });
+        if !self.options.attribute_shorthand {
+            return Ok((tag.name(), attributes.collect()));
+        }
+        // The shorthand of the name, like `.card#main` of `div.card#main`,
+        // comes before the attributes.
+        let name = tag.name();
+        let (name, shorthand) = match name.find(['.', '#']) {
+            Some(0) => ("div", name),
+            Some(index) => name.split_at(index),
+            None => (name, ""),
+        };
+        let mut flags = vec![];
+        let mut start = 0;
+        for (index, c) in shorthand.char_indices().skip(1).chain([(shorthand.len(), '.')]) {
+            if c == '.' || c == '#' {
+                flags.push((&shorthand[start..index], None));
+                start = index;
+            };
+        }
+        let attributes = flags.into_iter().filter(|(flag, _): &(&str, _)| flag.len() > 1).chain(attributes);
+        let mut expanded: Attributes = vec![];
+        for (key, value) in attributes {
+            let (key, value) = match value {
+                None if key.len() > 1 && key.starts_with('.') => ("class", Some(Cow::Borrowed(&key[1..]))),
+                None if key.len() > 1 && key.starts_with('#') => ("id", Some(Cow::Borrowed(&key[1..]))),
+                value => (key, value),
+            };
+            match expanded.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, Some(classes))) if key == "class" && value.is_some() => {
+                    let classes = classes.to_mut();
+                    classes.push(' ');
+                    classes.push_str(&value.unwrap());
+                }
+                Some(..) if key == "class" || key == "id" => {
+                    return Err(PreprocessorError::DuplicateAttribute(at, key.to_string()));
+                }
+                _ => expanded.push((key, value)),
+            };
+        }
+        Ok((name, expanded))
+    }
+
     fn write_dictionary(&mut self, dictionary: &ParsedDictionary, at: Position) -> Result<(), PreprocessorError> {
         for (key, value) in dictionary.iter() {
+            if !self.allow_element(key, at)? {
+                continue;
+            };
+            self.check_prefix(key, at)?;
+            self.end_block();
+            let start = self.output.len();
             self.push_non_breaking('<');
             self.push_str_non_breaking(key);
+            if value.is_unit() && self.options.dialect == OutputDialect::Xml {
+                self.push_str_non_breaking("/>");
+                self.map_range(start, value.from());
+                continue;
+            };
             self.push_non_breaking('>');
+            self.scopes.push(vec![]);
             self.write_xml_compound(value)?;
+            self.scopes.pop();
             self.push_str_non_breaking("</");
             self.push_str_non_breaking(key);
             self.push_non_breaking('>');
+            self.map_range(start, value.from());
         }
         Ok(())
     }
 
 }
 
+/// Whether a URL runs a script, like `javascript:alert(1)`. Browsers ignore
+/// the case of the scheme, and whitespace and control characters in it.
+fn is_script_url(url: &str) -> bool {
+    let scheme: String = url.chars().filter(|c| !c.is_whitespace() && !c.is_control()).take(11).collect();
+    scheme.eq_ignore_ascii_case("javascript:")
+}
+
+/// Escape the characters of text that are markup, `<`, `>` and `&`, and in
+/// attribute values in double quotes also `"`, as character references.
+fn escape(text: &str, double_quoted: bool) -> Cow<'_, str> {
+    if !(text.contains(['<', '>', '&']) || double_quoted && text.contains('"')) {
+        return Cow::Borrowed(text);
+    };
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' if double_quoted => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        };
+    }
+    Cow::Owned(escaped)
+}
+
 pub enum PreprocessorError {
     IllegalTable(Position),
     MacroError(String),
     TooManyArguments(Position),
     IllegalTuple(Position),
+    DuplicateAttribute(Position, String),
+    NotBooleanAttribute(Position, String),
+    /// An entry of the head of [Emission::Document] that is not valid.
+    HeadError(String),
+    /// An element or macro that is not allowed by [HtmlOptions::sanitize].
+    DisallowedElement(Position, String),
+    /// An attribute that is not allowed by [HtmlOptions::sanitize].
+    DisallowedAttribute(Position, String),
+    /// An attribute with a `javascript:` URL, with [HtmlOptions::sanitize].
+    ScriptUrl(Position, String),
+    /// A name whose namespace prefix is not declared.
+    UndeclaredPrefix(Position, String),
+    /// An error in a document included with `include!` from a path, at a
+    /// position in that document.
+    Include(String, Box<PreprocessorError>),
 }
@@ -0,0 +1,38 @@
+//! Conversion between Khi and JSON documents.
+//!
+//! Documents are transcoded through serde without intermediate types. Khi
+//! text is untyped, so numbers and booleans in JSON become text in Khi, and
+//! all text in Khi becomes strings in JSON. Nil and the empty tuple are
+//! `null`, tuples are arrays, and tagged values are objects with one entry
+//! from the tag name to the value. JSON `null` is the empty tuple `<>`, as
+//! JSON does not tell it apart from unit.
+//!
+//! Entries of objects and dictionaries keep their order. Objects with
+//! duplicate keys are rejected.
+//!
+//! The serializer and deserializer compose with `serde_transcode` in the same
+//! way for other formats.
+
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use crate::de::{self, ValueDeserializer};
+use crate::fmt::format_value;
+use crate::parse::parse_value_str;
+use crate::ser::{self, ValueSerializer};
+
+/// Convert a Khi value document to a JSON document.
+pub fn khi_to_json_string(document: &str) -> de::Result<String> {
+    let value = parse_value_str(document).map_err(de::Error::Parse)?;
+    let mut output = Vec::new();
+    let mut serializer = serde_json::Serializer::new(&mut output);
+    serde_transcode::transcode(ValueDeserializer::new(&value), &mut serializer).map_err(de::Error::custom)?;
+    Ok(String::from_utf8(output).expect("JSON output is UTF-8"))
+}
+
+/// Convert a JSON document to a Khi value document.
+pub fn json_to_khi_string(document: &str) -> ser::Result<String> {
+    let mut deserializer = serde_json::Deserializer::from_str(document);
+    let value = serde_transcode::transcode(&mut deserializer, ValueSerializer::new())?;
+    deserializer.end().map_err(ser::Error::custom)?;
+    Ok(format_value(&value))
+}
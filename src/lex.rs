@@ -51,6 +51,17 @@ impl Token {
 
 }
 
+//// Comment
+
+/// A comment of the source, which is whitespace to the parser.
+#[derive(PartialEq, Eq, Clone)]
+pub struct Comment {
+    /// The position of the hash opening the comment.
+    pub at: Position,
+    /// The text after the hash, up to the end of the line.
+    pub text: String,
+}
+
 //// Char iterator
 
 pub struct CharIter<It: Iterator<Item = char>> {
@@ -61,12 +72,13 @@ pub struct CharIter<It: Iterator<Item = char>> {
     index: usize,
     line: usize,
     column: usize,
+    comments: Option<Vec<Comment>>,
 }
 
 impl <'a, It: Iterator<Item = char>> CharIter<It> {
 
     pub fn new(chars: It) -> Self {
-        let mut iter = CharIter { chars, c: None, d: None, e: None, index: 0, line: 1, column: 1 };
+        let mut iter = CharIter { chars, c: None, d: None, e: None, index: 0, line: 1, column: 1, comments: None };
         iter.next();
         iter.next();
         iter.next();
@@ -119,6 +131,31 @@ impl <'a, It: Iterator<Item = char>> CharIter<It> {
         }
     }
 
+    /// Skip a comment to the end of the line, and keep it if comments are
+    /// kept.
+    ///
+    /// Assumes that the current character is the hash opening the comment.
+    fn skip_comment(&mut self) {
+        if self.comments.is_none() {
+            self.skip_line();
+            return;
+        };
+        let at = self.position();
+        self.next();
+        let mut text = String::new();
+        while let Some(c) = self.c {
+            if c == '\n' {
+                break;
+            };
+            text.push(c);
+            self.next();
+        }
+        self.skip_line();
+        if let Some(comments) = &mut self.comments {
+            comments.push(Comment { at, text });
+        };
+    }
+
 }
 
 fn is_whitespace(c: char) -> bool {
@@ -129,16 +166,28 @@ fn is_whitespace(c: char) -> bool {
 
 /// Iterates over characters and produces tokens.
 pub fn lex<It: Iterator<Item = char>>(chars: It) -> Result<Vec<Token>, LexError> {
+    lex_tokens(&mut CharIter::new(chars))
+}
+
+/// Iterates over characters and produces tokens, and the comments that are
+/// part of the whitespace tokens.
+pub fn lex_with_comments<It: Iterator<Item = char>>(chars: It) -> Result<(Vec<Token>, Vec<Comment>), LexError> {
     let mut iter = CharIter::new(chars);
+    iter.comments = Some(vec![]);
+    let tokens = lex_tokens(&mut iter)?;
+    Ok((tokens, iter.comments.unwrap_or_default()))
+}
+
+fn lex_tokens<It: Iterator<Item = char>>(iter: &mut CharIter<It>) -> Result<Vec<Token>, LexError> {
     let mut tokens = vec![];
     loop {
         if let Some(c) = iter.c {
             if is_whitespace(c) { // Whitespace
-                let whitespace = lex_whitespace(&mut iter)?;
+                let whitespace = lex_whitespace(iter)?;
                 tokens.push(whitespace);
             } else if c == ':' {
                 if let Some(':') = iter.d { // Colon glyph
-                    let word = lex_word(&mut iter)?;
+                    let word = lex_word(iter)?;
                     tokens.push(word);
                 } else { // Colon
                     tokens.push(Token::Colon(iter.position()));
@@ -146,7 +195,7 @@ pub fn lex<It: Iterator<Item = char>>(chars: It) -> Result<Vec<Token>, LexError>
                 }
             } else if c == ';' { // Semicolon
                 if let Some(';') = iter.d { // Semicolon glyph
-                    let word = lex_word(&mut iter)?;
+                    let word = lex_word(iter)?;
                     tokens.push(word);
                 } else { // Semicolon
                     tokens.push(Token::Semicolon(iter.position()));
@@ -154,7 +203,7 @@ pub fn lex<It: Iterator<Item = char>>(chars: It) -> Result<Vec<Token>, LexError>
                 }
             } else if c == '|' {
                 if let Some('|') = iter.d { // Bar glyph
-                    let word = lex_word(&mut iter)?;
+                    let word = lex_word(iter)?;
                     tokens.push(word);
                 } else { // Bar
                     tokens.push(Token::Bar(iter.position()));
@@ -162,17 +211,17 @@ pub fn lex<It: Iterator<Item = char>>(chars: It) -> Result<Vec<Token>, LexError>
                 }
             } else if c == '~' {
                 if let Some('~') = iter.d { // Tilde glyph
-                    let word = lex_word(&mut iter)?;
+                    let word = lex_word(iter)?;
                     tokens.push(word);
                 } else { // Tilde
                     tokens.push(Token::Tilde(iter.position()));
                     iter.next();
                 }
             } else if c == '`' { // Illegal escape character
-                let word = lex_word(&mut iter)?;
+                let word = lex_word(iter)?;
                 tokens.push(word);
             } else if c == '\\' { // Transcription
-                let transcription = lex_transcription(&mut iter)?;
+                let transcription = lex_transcription(iter)?;
                 tokens.push(transcription);
             } else if c == '{' { // Left bracket
                 tokens.push(Token::LeftBracket(iter.position()));
@@ -189,10 +238,10 @@ pub fn lex<It: Iterator<Item = char>>(chars: It) -> Result<Vec<Token>, LexError>
             } else if c == '<' {
                 if let Some(d) = iter.d {
                     if d == '<' { // Left angle glyph
-                        let token = lex_word(&mut iter)?;
+                        let token = lex_word(iter)?;
                         tokens.push(token);
                     } else if d == '#' { // Text block
-                        let text_block = lex_text_block(&mut iter)?;
+                        let text_block = lex_text_block(iter)?;
                         tokens.push(text_block);
                     } else { // Left angle
                         tokens.push(Token::LeftAngle(iter.position()));
@@ -204,7 +253,7 @@ pub fn lex<It: Iterator<Item = char>>(chars: It) -> Result<Vec<Token>, LexError>
                 }
             } else if c == '>' {
                 if let Some('>') = iter.d { // Right angle glyph
-                    let token = lex_word(&mut iter)?;
+                    let token = lex_word(iter)?;
                     tokens.push(token);
                 } else { // Right angle
                     tokens.push(Token::RightAngle(iter.position()));
@@ -213,21 +262,21 @@ pub fn lex<It: Iterator<Item = char>>(chars: It) -> Result<Vec<Token>, LexError>
             } else if c == '#' {
                 if let Some(d) = iter.d {
                     if d == '#' || is_whitespace(d) { // Comment
-                        let whitespace = lex_whitespace(&mut iter)?;
+                        let whitespace = lex_whitespace(iter)?;
                         tokens.push(whitespace);
                     } else { // Hash glyph: handle illegal cases in word
-                        let word = lex_word(&mut iter)?;
+                        let word = lex_word(iter)?;
                         tokens.push(word);
                     }
                 } else { // Comment before end
-                    let whitespace = lex_whitespace(&mut iter)?;
+                    let whitespace = lex_whitespace(iter)?;
                     tokens.push(whitespace);
                 }
             } else if c == '=' && iter.d == Some('>') && iter.e != Some('>') {
                 tokens.push(Token::DoubleArrow(iter.position()));
                 iter.next(); iter.next();
             } else { // Text glyph
-                let word = lex_word(&mut iter)?;
+                let word = lex_word(iter)?;
                 tokens.push(word);
             }
         } else {
@@ -250,7 +299,7 @@ fn lex_whitespace<It: Iterator<Item = char>>(iter: &mut CharIter<It>) -> Result<
             } else if c == '#' {
                 if let Some(d) = iter.d {
                     if is_whitespace(d) || d == '#' {
-                        iter.skip_line();
+                        iter.skip_comment();
                     } else {
                         break;
                     };
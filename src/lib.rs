@@ -5,8 +5,7 @@ pub mod lex;
 #[cfg(feature = "parse")]
 pub mod parse;
 
-//#[cfg(feature = "enc")]
-//pub mod enc;
+pub mod enc;
 
 #[cfg(feature = "html")]
 pub mod html;
@@ -14,13 +13,28 @@ pub mod html;
 #[cfg(feature = "tex")]
 pub mod tex;
 pub mod pdm;
-//#[cfg(feature = "serde")]
-//pub mod ser;
-//#[cfg(feature = "serde")]
-//pub mod de;
+pub mod number;
+pub mod boolean;
+mod macros;
+#[cfg(feature = "parse")]
+mod testing;
+#[cfg(feature = "sync")]
+pub mod store;
+#[cfg(feature = "watch")]
+pub mod config;
+#[cfg(feature = "serde")]
+pub mod ser;
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "serde")]
+pub mod serde_helpers;
+#[cfg(feature = "json")]
+pub mod json;
+
+pub mod fmt;
+pub mod model;
 
-//mod fmt;
-//mod model;
+use std::borrow::Cow;
 
 /// A value.
 ///
@@ -133,11 +147,13 @@ pub trait Tagged<
     fn get(&self) -> &Vl;
 }
 
-/// An attribute of a tag.
-pub struct Attribute<'a>(&'a str, Option<&'a str>);
+/// An attribute of a tag. A value that is a list of strings is given as the
+/// strings joined by spaces.
+pub struct Attribute<'a>(&'a str, Option<Cow<'a, str>>);
 
-/// An attribute value of a tag.
-pub struct AttributeValue<'a>(Option<&'a str>);
+/// An attribute value of a tag. A list of strings is given as the strings
+/// joined by spaces.
+pub struct AttributeValue<'a>(Option<Cow<'a, str>>);
 
 /// A tuple.
 pub trait Tuple<
@@ -217,12 +233,20 @@ pub trait Compound<
 > {
     /// Iterator over the elements in a compound.
     type ElementIterator<'a>: Iterator<Item=Element<&'a Vl>> where Self: 'a, Vl: 'a;
-    /// Number of elements in this compound.
+    /// Iterator over the components in a compound, with the whitespace around them.
+    type ComponentIterator<'a>: Iterator<Item=Component<&'a Vl>> where Self: 'a, Vl: 'a;
+    /// Number of elements in this compound, including whitespace.
     fn len(&self) -> usize;
-    /// Get the element at an index.
-    fn get(&self, index: usize) -> Option<Element<&Vl>>;
+    /// Number of components in this compound, not including whitespace.
+    fn element_count(&self) -> usize;
+    /// Get the component at an index, not counting whitespace, with the
+    /// whitespace around it.
+    fn get(&self, index: usize) -> Option<Component<&Vl>>;
     /// Iterate over the elements in this compound.
     fn iter(&self) -> Self::ElementIterator<'_>;
+    /// Iterate over the components in this compound, with the whitespace
+    /// around them.
+    fn iter_with_separators(&self) -> Self::ComponentIterator<'_>;
 }
 
 /// An element in a compound.
@@ -231,6 +255,20 @@ pub enum Element<T> {
     Whitespace,
 }
 
+/// A component in a compound, with the whitespace around it.
+///
+/// A component without whitespace before it is glued to the previous
+/// component, like `b` in `a~b`. The first component has no whitespace
+/// before it, and the last component has no whitespace after it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Component<T> {
+    pub value: T,
+    /// Whether whitespace separates this component from the previous one.
+    pub whitespace_before: bool,
+    /// Whether whitespace separates this component from the next one.
+    pub whitespace_after: bool,
+}
+
 /// Get the character corresponding to an escaped character sequence.
 pub fn translate_escape_character(char: char) -> Result<char, ()> {
     match char {
@@ -0,0 +1,75 @@
+//! Construction macro.
+
+/// Construct a [ParsedValue](crate::pdm::ParsedValue) from Khi-like notation.
+///
+/// Entries and list elements are separated by semicolons. A value is one of:
+/// - `~` for nil
+/// - a literal or words, which become text
+/// - `[a; b; c]` for a list
+/// - `{key: value; ...}` for a dictionary
+/// - `<tag attr:value flag>: value` for a tagged value
+/// - `(expression)` for a Rust expression converted into a value
+///
+/// At the top level, entries are a dictionary. All positions are zero.
+///
+/// ```
+/// # use khi::khi;
+/// # use khi::{Dictionary, Value};
+/// let document = khi!{ name: Khi; list: [1; 2; 3]; tag: <tag attr:x>: body };
+/// let dictionary = document.as_dictionary().unwrap();
+/// assert!(dictionary.get("list").unwrap().is_list());
+/// ```
+#[macro_export]
+macro_rules! khi {
+    // Values.
+    (@value ~) => { $crate::pdm::ParsedValue::nil($crate::pdm::Position::ZERO, $crate::pdm::Position::ZERO) };
+    (@value [$($elements:tt)*]) => { $crate::khi!(@list [] [] $($elements)*) };
+    (@value {$($entries:tt)*}) => { $crate::khi!(@dictionary [] [] $($entries)*) };
+    (@value ($value:expr)) => { <$crate::pdm::ParsedValue as ::core::convert::From<_>>::from($value) };
+    (@value < $name:ident $($rest:tt)*) => { $crate::khi!(@tag $name [] $($rest)*) };
+    (@value $text:literal) => { $crate::pdm::ParsedValue::text(&*$text.to_string()) };
+    (@value $($words:tt)+) => { $crate::pdm::ParsedValue::text(stringify!($($words)+)) };
+    // Tags.
+    (@tag $name:ident [$($attributes:tt)*] > : $($value:tt)+) => {
+        $crate::pdm::ParsedValue::tagged(stringify!($name), vec![$($attributes)*], $crate::khi!(@value $($value)+))
+    };
+    (@tag $name:ident [$($attributes:tt)*] >) => {
+        $crate::pdm::ParsedValue::tagged(stringify!($name), vec![$($attributes)*], $crate::khi!(@value ~))
+    };
+    (@tag $name:ident [$($attributes:tt)*] $key:ident : $value:tt $($rest:tt)*) => {
+        $crate::khi!(@tag $name [$($attributes)* (stringify!($key), Some($crate::khi!(@attribute $value))),] $($rest)*)
+    };
+    (@tag $name:ident [$($attributes:tt)*] $key:ident $($rest:tt)*) => {
+        $crate::khi!(@tag $name [$($attributes)* (stringify!($key), None),] $($rest)*)
+    };
+    (@attribute $value:literal) => { &*$value.to_string() };
+    (@attribute $value:tt) => { stringify!($value) };
+    // Lists, split at semicolons.
+    (@list [$($elements:expr,)*] []) => { $crate::pdm::ParsedValue::list(vec![$($elements,)*]) };
+    (@list [$($elements:expr,)*] [$($element:tt)+]) => {
+        $crate::khi!(@list [$($elements,)* $crate::khi!(@value $($element)+),] [])
+    };
+    (@list [$($elements:expr,)*] [$($element:tt)+] ; $($rest:tt)*) => {
+        $crate::khi!(@list [$($elements,)* $crate::khi!(@value $($element)+),] [] $($rest)*)
+    };
+    (@list [$($elements:expr,)*] [$($element:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::khi!(@list [$($elements,)*] [$($element)* $next] $($rest)*)
+    };
+    // Dictionaries, split at semicolons.
+    (@dictionary [$($entries:expr,)*] []) => { $crate::pdm::ParsedValue::dictionary(vec![$($entries,)*]) };
+    (@dictionary [$($entries:expr,)*] [$key:tt : $($value:tt)+]) => {
+        $crate::khi!(@dictionary [$($entries,)* ($crate::khi!(@key $key), $crate::khi!(@value $($value)+)),] [])
+    };
+    (@dictionary [$($entries:expr,)*] [$key:tt : $($value:tt)+] ; $($rest:tt)*) => {
+        $crate::khi!(@dictionary [$($entries,)* ($crate::khi!(@key $key), $crate::khi!(@value $($value)+)),] [] $($rest)*)
+    };
+    (@dictionary [$($entries:expr,)*] [$($entry:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::khi!(@dictionary [$($entries,)*] [$($entry)* $next] $($rest)*)
+    };
+    (@key $key:literal) => { $key };
+    (@key $key:ident) => { stringify!($key) };
+    // Entry.
+    () => { $crate::khi!(@dictionary [] []) };
+    ($key:tt : $($rest:tt)*) => { $crate::khi!(@dictionary [] [] $key : $($rest)*) };
+    ($($value:tt)+) => { $crate::khi!(@value $($value)+) };
+}
@@ -1,115 +1,322 @@
 //! Plain in-memory representation of Khi data structures.
+//!
+//! A [SimpleValue] is an owned value without source positions, which is
+//! convenient to construct, compare and store. With the `serde` feature, it
+//! implements `Serialize` and `Deserialize`, and so does
+//! [ParsedValue](crate::pdm::ParsedValue), optionally with positions. The
+//! [Khi deserializer](crate::de) reads a simple value from the value as it
+//! is written, so that `from_str::<SimpleValue>` parses a document. Other
+//! formats read the variants as they are serialized.
 
-use crate::{Dictionary, Pattern, Structure, Table};
+use crate::pdm::{KeyOrder, ParsedAttribute, ParsedAttributeValue, ParsedCompound, ParsedTaggedValue, ParsedTuple, ParsedValue, Position, SharedStr};
 
+/// A value.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum SimpleValue {
-    Nil,
-    Text(SimpleText),
-    Dictionary(SimpleDictionary),
-    List(SimpleList),
-    Compound(Vec<SimpleValue>),
+    Text(String),
     Tagged(SimpleTagged),
+    /// A tuple. A tuple with one element is a tuple wrapping another tuple.
+    Tuple(Vec<SimpleValue>),
+    Dictionary(SimpleDictionary),
+    List(Vec<SimpleValue>),
+    Compound(SimpleCompound),
+    Nil,
 }
 
-impl Value<SimpleText, SimpleDictionary, SimpleList, SimpleComponent, SimpleTagged> for SimpleValue {
+/// A tagged value.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SimpleTagged {
+    pub name: String,
+    pub attributes: Vec<(String, Option<SimpleAttributeValue>)>,
+    pub value: Box<SimpleValue>,
+}
 
-    type StructureIterator = ();
+/// The value of an attribute of a tag.
+///
+/// With the `serde` feature, a value is serialized as an enum, and is also
+/// read from text or a sequence of text, like an attribute read by the Khi
+/// deserializer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum SimpleAttributeValue {
+    Text(String),
+    List(Vec<String>),
+}
 
-    fn length(&self) -> usize {
-        match self {
-            SimpleValue::Text(..) => 1,
-            SimpleValue::List(..) => 1,
-            SimpleValue::Dictionary(..) => 1,
-            SimpleValue::Tagged(..) => 1,
-            SimpleValue::Compound(v) => v.len(),
-            SimpleValue::Nil => 0,
+impl From<&ParsedAttributeValue> for SimpleAttributeValue {
+    fn from(value: &ParsedAttributeValue) -> Self {
+        match value {
+            ParsedAttributeValue::Text(text) => SimpleAttributeValue::Text(text.to_string()),
+            ParsedAttributeValue::List(strings) => SimpleAttributeValue::List(strings.iter().map(|s| s.to_string()).collect()),
         }
     }
+}
 
-    fn is_empty(&self) -> bool {
-        matches!(self, SimpleStructure::Empty(..))
+impl From<&SimpleAttributeValue> for ParsedAttributeValue {
+    fn from(value: &SimpleAttributeValue) -> Self {
+        match value {
+            SimpleAttributeValue::Text(text) => ParsedAttributeValue::Text(SharedStr::from(text.as_str())),
+            SimpleAttributeValue::List(strings) => ParsedAttributeValue::List(strings.iter().map(|s| SharedStr::from(s.as_str())).collect()),
+        }
     }
+}
 
-    fn is_unary(&self) -> bool {
-        todo!()
-    }
+/// A dictionary.
+///
+/// Entries are kept in order. With the `serde` feature, a dictionary is
+/// serialized as a map.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SimpleDictionary {
+    pub entries: Vec<(String, SimpleValue)>,
+}
 
-    fn is_compound(&self) -> bool {
-        todo!()
+impl SimpleDictionary {
+    /// Get the value at a key.
+    pub fn get(&self, key: &str) -> Option<&SimpleValue> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
     }
+}
+
+/// A compound.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SimpleCompound {
+    pub components: Vec<SimpleValue>,
+    /// Whether there is whitespace between each pair of adjacent components.
+    pub whitespace: Vec<bool>,
+}
 
-    fn get(&self, index: usize) -> Option<&Component> {
-        todo!()
+impl From<&ParsedValue> for SimpleValue {
+    /// Convert a parsed value. Dictionary entries are ordered as in the source.
+    fn from(value: &ParsedValue) -> Self {
+        match value {
+            ParsedValue::Text(text, ..) => SimpleValue::Text(text.str.to_string()),
+            ParsedValue::Tagged(tagged, ..) => SimpleValue::Tagged(SimpleTagged {
+                name: tagged.name.to_string(),
+                attributes: tagged.attributes.iter().map(|a| (a.0.to_string(), a.1.as_ref().map(SimpleAttributeValue::from))).collect(),
+                value: Box::new(SimpleValue::from(tagged.value.as_ref())),
+            }),
+            ParsedValue::Tuple(tuple, ..) => SimpleValue::Tuple(match tuple {
+                ParsedTuple::Unit => vec![],
+                ParsedTuple::Single(value) => vec![SimpleValue::from(value.as_ref())],
+                ParsedTuple::Multiple(elements) => elements.iter().map(SimpleValue::from).collect(),
+            }),
+            ParsedValue::Dictionary(dictionary, ..) => {
+                let entries = dictionary.iter_sorted(KeyOrder::Source).map(|(k, v)| (k.to_string(), SimpleValue::from(v))).collect();
+                SimpleValue::Dictionary(SimpleDictionary { entries })
+            }
+            ParsedValue::List(list, ..) => SimpleValue::List(list.elements.iter().map(SimpleValue::from).collect()),
+            ParsedValue::Compound(compound, ..) => SimpleValue::Compound(SimpleCompound {
+                components: compound.components.iter().map(SimpleValue::from).collect(),
+                whitespace: compound.whitespace.clone(),
+            }),
+            ParsedValue::Nil(..) => SimpleValue::Nil,
+        }
     }
+}
 
-    fn iter(&self) -> Self::StructureIterator {
-        todo!()
+impl ParsedValue {
+    /// Copy this value without positions.
+    ///
+    /// A parsed value keeps the start and end position of every node, which
+    /// long-lived stores of many documents rarely need. A simple value has no
+    /// positions, and a node is less than half the size of a parsed node.
+    pub fn strip_spans(&self) -> SimpleValue {
+        SimpleValue::from(self)
     }
+}
 
-    fn conform_text(&self) -> Option<&Text<Self, Table, Dictionary, Directive, Component>> {
-        todo!()
+impl From<&SimpleValue> for ParsedValue {
+    /// Convert a simple value. Positions are zero.
+    fn from(value: &SimpleValue) -> Self {
+        parsed(value)
     }
+}
 
-    fn conform_table(&self) -> Option<&Table> {
-        todo!()
+// `ParsedValue::from` is the inherent position getter, so conversion recurses
+// through this function.
+fn parsed(value: &SimpleValue) -> ParsedValue {
+    let at = Position::ZERO;
+    match value {
+        SimpleValue::Text(text) => ParsedValue::text(text),
+        SimpleValue::Tagged(tagged) => {
+            let attributes = tagged.attributes.iter().map(|(k, v)| ParsedAttribute(SharedStr::from(k.as_str()), v.as_ref().map(ParsedAttributeValue::from))).collect();
+            let tagged = ParsedTaggedValue { name: SharedStr::from(tagged.name.as_str()), raw_name: None, attributes, value: Box::new(parsed(&tagged.value)) };
+            ParsedValue::Tagged(tagged, at, at)
+        }
+        SimpleValue::Tuple(elements) => {
+            let tuple = match elements.len() {
+                0 => ParsedTuple::Unit,
+                1 => ParsedTuple::Single(Box::new(parsed(&elements[0]))),
+                _ => ParsedTuple::Multiple(elements.iter().map(parsed).collect()),
+            };
+            ParsedValue::Tuple(tuple, at, at)
+        }
+        SimpleValue::Dictionary(dictionary) => {
+            ParsedValue::dictionary(dictionary.entries.iter().map(|(k, v)| (k.as_str(), parsed(v))).collect())
+        }
+        SimpleValue::List(elements) => ParsedValue::list(elements.iter().map(parsed).collect()),
+        SimpleValue::Compound(compound) => {
+            let components = compound.components.iter().map(parsed).collect();
+            ParsedValue::Compound(ParsedCompound { components, whitespace: compound.whitespace.clone() }, at, at)
+        }
+        SimpleValue::Nil => ParsedValue::Nil(at, at),
     }
+}
 
-    fn conform_dictionary(&self) -> Option<&Dictionary> {
-        todo!()
+#[cfg(feature = "serde")]
+impl serde::Serialize for SimpleDictionary {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&Entries(self.entries.iter().map(|(k, v)| (k, v)).collect()), serializer)
     }
+}
 
-    fn conform_directive(&self) -> Option<&Directive> {
-        todo!()
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SimpleDictionary {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <Entries<String, SimpleValue> as serde::Deserialize>::deserialize(deserializer).map(|entries| SimpleDictionary { entries: entries.0 })
     }
+}
 
-    fn is_text(&self) -> bool {
-        todo!()
+/// The name of the newtype struct under which the Khi deserializer exposes a
+/// value as it is written. Other formats read the variants of a
+/// [SimpleValue] as they are serialized.
+#[cfg(feature = "serde")]
+pub(crate) const SIMPLE_VALUE: &str = "$khi::SimpleValue";
+
+#[cfg(feature = "serde")]
+const SIMPLE_VALUE_VARIANTS: &[&str] = &["Text", "Tagged", "Tuple", "Dictionary", "List", "Compound", "Nil"];
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SimpleValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(SIMPLE_VALUE, SimpleValueVisitor)
     }
+}
 
-    fn is_dictionary(&self) -> bool {
-        todo!()
+#[cfg(feature = "serde")]
+struct SimpleValueVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for SimpleValueVisitor {
+    type Value = SimpleValue;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a simple value")
     }
 
-    fn is_table(&self) -> bool {
-        todo!()
+    fn visit_newtype_struct<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_enum("SimpleValue", SIMPLE_VALUE_VARIANTS, SimpleValueVisitor)
     }
 
-    fn is_pattern(&self) -> bool {
-        todo!()
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        use serde::de::VariantAccess;
+        let (kind, access) = data.variant()?;
+        match kind {
+            SimpleKind::Text => access.newtype_variant().map(SimpleValue::Text),
+            SimpleKind::Tagged => access.newtype_variant().map(SimpleValue::Tagged),
+            SimpleKind::Tuple => access.newtype_variant().map(SimpleValue::Tuple),
+            SimpleKind::Dictionary => access.newtype_variant().map(SimpleValue::Dictionary),
+            SimpleKind::List => access.newtype_variant().map(SimpleValue::List),
+            SimpleKind::Compound => access.newtype_variant().map(SimpleValue::Compound),
+            SimpleKind::Nil => access.unit_variant().map(|()| SimpleValue::Nil),
+        }
     }
+}
 
+/// The variant of a [SimpleValue].
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(variant_identifier)]
+enum SimpleKind {
+    Text,
+    Tagged,
+    Tuple,
+    Dictionary,
+    List,
+    Compound,
+    Nil,
 }
 
-/// A simple text implementation.
-pub struct SimpleText {
-    string: String,
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SimpleAttributeValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_enum("SimpleAttributeValue", &["Text", "List"], SimpleAttributeValueVisitor)
+    }
 }
 
-impl SimpleText {
+#[cfg(feature = "serde")]
+struct SimpleAttributeValueVisitor;
 
-    fn new(string: String) -> Self {
-        Self { string }
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for SimpleAttributeValueVisitor {
+    type Value = SimpleAttributeValue;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an attribute value")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Self::Value, E> {
+        Ok(SimpleAttributeValue::Text(text.to_string()))
     }
 
-    fn from_str(str: &str) -> Self {
-        Self { string: String::from(str) }
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut strings = vec![];
+        while let Some(string) = seq.next_element()? {
+            strings.push(string);
+        };
+        Ok(SimpleAttributeValue::List(strings))
     }
 
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        use serde::de::VariantAccess;
+        let (kind, access) = data.variant::<AttributeKind>()?;
+        match kind {
+            AttributeKind::Text => access.newtype_variant().map(SimpleAttributeValue::Text),
+            AttributeKind::List => access.newtype_variant().map(SimpleAttributeValue::List),
+        }
+    }
 }
 
-/// A simple table implementation.
-pub struct SimpleList {
-    entries: Vec<SimpleValue>,
+/// The variant of a [SimpleAttributeValue].
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(variant_identifier)]
+enum AttributeKind {
+    Text,
+    List,
 }
 
-/// A simple dictionary implementation.
-pub struct SimpleDictionary {
-    entries: Vec<(SimpleText, SimpleValue)>,
+/// Dictionary entries, serialized as a map in order.
+#[cfg(feature = "serde")]
+pub(crate) struct Entries<K, V>(pub Vec<(K, V)>);
+
+#[cfg(feature = "serde")]
+impl<K: serde::Serialize, V: serde::Serialize> serde::Serialize for Entries<K, V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, v)))
+    }
 }
 
-/// A simple tag implementation.
-pub struct SimpleTagged {
-    header: String,
-    attributes: Vec<(SimpleText, SimpleValue)>,
-    arguments: Vec<SimpleComponent>
+#[cfg(feature = "serde")]
+impl<'de, K: serde::Deserialize<'de>, V: serde::Deserialize<'de>> serde::Deserialize<'de> for Entries<K, V> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor<K, V>(std::marker::PhantomData<(K, V)>);
+        impl<'de, K: serde::Deserialize<'de>, V: serde::Deserialize<'de>> serde::de::Visitor<'de> for EntriesVisitor<K, V> {
+            type Value = Entries<K, V>;
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a map")
+            }
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                };
+                Ok(Entries(entries))
+            }
+        }
+        deserializer.deserialize_map(EntriesVisitor(std::marker::PhantomData))
+    }
 }
@@ -0,0 +1,282 @@
+//! Recognition of numbers in bare words.
+//!
+//! Khi has no number type: `1e5` and `0x1F` are text. A [NumberFormat]
+//! decides which words are numbers, and is shared by everything in the crate
+//! that converts text to numbers, so that a word is a number in one place if
+//! and only if it is a number in the others. The [TryFrom] conversions of
+//! [ParsedValue] use the default format. With the `decimal` feature,
+//! `Decimal` of `rust_decimal` is a [Number], for amounts that should not be
+//! rounded to floating point numbers. Arbitrary precision decimals, like
+//! those of `bigdecimal`, are not supported.
+
+use crate::pdm::{ConversionError, ParsedValue};
+
+/// Syntax of numbers.
+///
+/// A number is an optional sign followed by decimal digits, optionally with
+/// a fractional part for floating point numbers. The options allow more. The
+/// default format accepts what the standard library parses, that is, a `+`
+/// sign, and exponents and special values in floating point numbers.
+///
+/// Precisely, in every format:
+///
+/// - A `-` sign, and a `+` sign if allowed, comes first, and only once.
+/// - A fractional part is a `.` with digits on at least one side, like `1.`
+///   or `.5`, and only in floating point numbers.
+/// - An exponent is `e` or `E`, an optional sign, and digits, like `1e-5`.
+///   Integer exponents may not be negative, and at most `40`.
+/// - Separators are between digits, and never first or last in a run of
+///   digits, so `1_000` is allowed but `_1`, `1_` and `1_.5` are not.
+/// - Radix prefixes are for integers only, so `0x1F` is an integer but not a
+///   floating point number.
+/// - Special values are for floating point numbers only, in any case and
+///   with a sign, like `-inf` and `NaN`.
+/// - Nothing else is allowed, including whitespace and `,`. Floating point
+///   numbers that are too large are infinite, like in the standard library.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NumberFormat {
+    /// Allow a leading `+`.
+    pub plus_sign: bool,
+    /// Allow decimal exponents in floating point numbers, like `2.5E-3`.
+    pub exponents: bool,
+    /// Allow non-negative decimal exponents in integers, like `1e5`.
+    pub integer_exponents: bool,
+    /// Allow hexadecimal `0x1F`, octal `0o17` and binary `0b101` integers.
+    pub radix_prefixes: bool,
+    /// Allow `_` between digits, like `1_000_000`.
+    pub separators: bool,
+    /// Allow `inf`, `infinity` and `NaN` in any case as floating point numbers.
+    pub special_values: bool,
+}
+
+impl NumberFormat {
+    /// Plain decimal numbers only.
+    pub const STRICT: NumberFormat = NumberFormat {
+        plus_sign: false, exponents: false, integer_exponents: false, radix_prefixes: false, separators: false, special_values: false,
+    };
+
+    /// Everything that can be a number.
+    pub const LENIENT: NumberFormat = NumberFormat {
+        plus_sign: true, exponents: true, integer_exponents: true, radix_prefixes: true, separators: true, special_values: true,
+    };
+
+    /// Check if text is a number, without converting it. Every number is an
+    /// integer, a floating point number, or both.
+    pub fn is_number(&self, text: &str) -> bool {
+        self.normalize(text, false).is_some() || self.normalize(text, true).is_some()
+    }
+
+    /// Check if text is an integer.
+    pub fn is_integer(&self, text: &str) -> bool {
+        self.normalize(text, true).is_some()
+    }
+
+    /// Check if text is a floating point number.
+    pub fn is_float(&self, text: &str) -> bool {
+        self.normalize(text, false).is_some()
+    }
+
+    /// Parse text as a number.
+    pub fn parse<T: Number>(&self, text: &str) -> Option<T> {
+        let (digits, radix) = self.normalize(text, T::INTEGER)?;
+        T::from_digits(&digits, radix)
+    }
+
+    /// Rewrite a number in the syntax of the standard library, with its radix.
+    fn normalize(&self, text: &str, integer: bool) -> Option<(String, u32)> {
+        let (sign, unsigned) = match text.as_bytes().first() {
+            Some(b'-') => ("-", &text[1..]),
+            Some(b'+') if self.plus_sign => ("", &text[1..]),
+            _ => ("", text),
+        };
+        if unsigned.is_empty() {
+            return None;
+        };
+        if !integer && self.special_values {
+            let lower = unsigned.to_ascii_lowercase();
+            if lower == "inf" || lower == "infinity" || lower == "nan" {
+                return Some((format!("{}{}", sign, lower), 10));
+            };
+        };
+        if self.radix_prefixes && integer && unsigned.len() > 2 {
+            let radix = match &unsigned.as_bytes()[..2] {
+                b"0x" | b"0X" => 16,
+                b"0o" | b"0O" => 8,
+                b"0b" | b"0B" => 2,
+                _ => 0,
+            };
+            if radix != 0 {
+                let digits = self.digits(&unsigned[2..], |c| c.is_digit(radix))?;
+                return Some((format!("{}{}", sign, digits), radix));
+            };
+        };
+        let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+            Some(i) if (integer && self.integer_exponents) || (!integer && self.exponents) => (&unsigned[..i], Some(&unsigned[i + 1..])),
+            _ => (unsigned, None),
+        };
+        let (whole, fraction) = match mantissa.split_once('.') {
+            Some(_) if integer => return None,
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (mantissa, None),
+        };
+        let whole = if whole.is_empty() && fraction.is_some() { String::new() } else { self.digits(whole, |c| c.is_ascii_digit())? };
+        let fraction = match fraction {
+            Some("") if !whole.is_empty() => Some(String::new()),
+            Some(fraction) => Some(self.digits(fraction, |c| c.is_ascii_digit())?),
+            None => None,
+        };
+        let exponent = match exponent {
+            Some(exponent) => {
+                let (exponent_sign, digits) = match exponent.as_bytes().first() {
+                    Some(b'-') => ("-", &exponent[1..]),
+                    Some(b'+') => ("", &exponent[1..]),
+                    _ => ("", exponent),
+                };
+                Some((exponent_sign, self.digits(digits, |c| c.is_ascii_digit())?))
+            }
+            None => None,
+        };
+        if integer {
+            let mut digits = format!("{}{}", sign, whole);
+            if let Some((exponent_sign, exponent)) = exponent {
+                let exponent: usize = exponent.parse().ok()?;
+                if exponent_sign == "-" || exponent > 40 {
+                    return None;
+                };
+                digits.extend(std::iter::repeat('0').take(exponent));
+            };
+            return Some((digits, 10));
+        };
+        let mut digits = format!("{}{}", sign, whole);
+        if let Some(fraction) = fraction {
+            digits.push('.');
+            digits.push_str(&fraction);
+        };
+        if let Some((exponent_sign, exponent)) = exponent {
+            digits.push('e');
+            digits.push_str(exponent_sign);
+            digits.push_str(&exponent);
+        };
+        Some((digits, 10))
+    }
+
+    /// Check a run of digits, removing separators.
+    fn digits<F: Fn(char) -> bool>(&self, text: &str, is_digit: F) -> Option<String> {
+        if text.is_empty() || text.starts_with('_') || text.ends_with('_') {
+            return None;
+        };
+        let mut digits = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c == '_' && self.separators {
+                continue;
+            } else if is_digit(c) {
+                digits.push(c);
+            } else {
+                return None;
+            };
+        };
+        Some(digits)
+    }
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat { plus_sign: true, exponents: true, integer_exponents: false, radix_prefixes: false, separators: false, special_values: true }
+    }
+}
+
+/// A number type that text can be converted to.
+pub trait Number: Sized {
+    /// Name of the type in errors.
+    const NAME: &'static str;
+    /// Whether the type is an integer type.
+    const INTEGER: bool;
+    /// Parse digits in the syntax of the standard library.
+    fn from_digits(digits: &str, radix: u32) -> Option<Self>;
+}
+
+macro_rules! impl_integer {
+    ($($ty:ident),*) => {
+        $(
+            impl Number for $ty {
+                const NAME: &'static str = stringify!($ty);
+                const INTEGER: bool = true;
+
+                fn from_digits(digits: &str, radix: u32) -> Option<Self> {
+                    $ty::from_str_radix(digits, radix).ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+macro_rules! impl_nonzero {
+    ($($ty:ident => $int:ident),*) => {
+        $(
+            /// Integers other than zero, so that `0` is not a number of the type.
+            impl Number for std::num::$ty {
+                const NAME: &'static str = stringify!($ty);
+                const INTEGER: bool = true;
+
+                fn from_digits(digits: &str, radix: u32) -> Option<Self> {
+                    std::num::$ty::new($int::from_str_radix(digits, radix).ok()?)
+                }
+            }
+        )*
+    };
+}
+
+impl_nonzero!(
+    NonZeroI8 => i8, NonZeroI16 => i16, NonZeroI32 => i32, NonZeroI64 => i64, NonZeroI128 => i128, NonZeroIsize => isize,
+    NonZeroU8 => u8, NonZeroU16 => u16, NonZeroU32 => u32, NonZeroU64 => u64, NonZeroU128 => u128, NonZeroUsize => usize
+);
+
+macro_rules! impl_float {
+    ($($ty:ident),*) => {
+        $(
+            impl Number for $ty {
+                const NAME: &'static str = stringify!($ty);
+                const INTEGER: bool = false;
+
+                fn from_digits(digits: &str, _radix: u32) -> Option<Self> {
+                    digits.parse().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32, f64);
+
+/// Decimal numbers, read without rounding, so that text with more digits
+/// than a decimal holds is not a decimal. Special values are not decimals.
+#[cfg(feature = "decimal")]
+impl Number for rust_decimal::Decimal {
+    const NAME: &'static str = "decimal";
+    const INTEGER: bool = false;
+
+    fn from_digits(digits: &str, _radix: u32) -> Option<Self> {
+        if digits.contains('e') {
+            rust_decimal::Decimal::from_scientific(digits).ok()
+        } else {
+            rust_decimal::Decimal::from_str_exact(digits).ok()
+        }
+    }
+}
+
+impl ParsedValue {
+    /// Convert text to a number in a format.
+    pub fn to_number_with<T: Number>(&self, format: &NumberFormat) -> Result<T, ConversionError> {
+        match self {
+            ParsedValue::Text(text, ..) => format.parse(&text.str).ok_or_else(|| ConversionError::Invalid(T::NAME, self.from(), text.str.to_string())),
+            _ => Err(ConversionError::Expected("text", self.from())),
+        }
+    }
+
+    /// Check if this value is text that is a number in a format.
+    pub fn is_number_with(&self, format: &NumberFormat) -> bool {
+        matches!(self, ParsedValue::Text(text, ..) if format.is_number(&text.str))
+    }
+}
@@ -11,20 +11,44 @@
 // tokens. In the final stage the parser operates on these reduced tokens to
 // create a parsed document model (AST).
 
-use std::collections::{HashSet};
-use crate::lex::{lex, LexError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::fmt::{format_dictionary_with, format_list_with, format_value_with, Style};
+use crate::lex::{lex, lex_with_comments, Comment, LexError};
 use crate::parse::parser::{ParseError, Parser};
 use crate::parse::reducer::{Reduced, ReduceError, Reducer};
-use crate::pdm::{ParsedDictionary, ParsedList, ParsedValue, Position};
+use crate::pdm::{Interner, KeyComparison, KeyNormalization, ParsedDictionary, ParsedList, ParsedValue, Position};
 
 const MAX_DEPTH: usize = 256; //TODO
 
+/// A message about a document, like an error of the parser or of a
+/// preprocessor, for reporting errors of different kinds alike.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// A stable code identifying the kind of error, like `expected`.
+    pub code: &'static str,
+    pub message: String,
+    /// The position in the document, if any.
+    pub at: Option<Position>,
+}
+
 /// Parse a value document string.
 pub fn parse_value_str(document: &str) -> Result<ParsedValue, Vec<ParseError>> {
+    parse_value_str_with(document, &ParseOptions::default())
+}
+
+/// Parse a value document string with options.
+pub fn parse_value_str_with(document: &str, options: &ParseOptions) -> Result<ParsedValue, Vec<ParseError>> {
+    measure(options, DocumentKind::Value, || parse_value_document(document, options))
+}
+
+fn parse_value_document(document: &str, options: &ParseOptions) -> Result<ParsedValue, Vec<ParseError>> {
     let tokens = unwrap_or_throw(tokenize(document))?;
-    let mut strings = HashSet::new();
+    let mut strings = Interner::new();
     let mut errors = Vec::new();
-    let mut parser = Parser::new(&tokens, &mut strings, &mut errors, false, Position { index: 0, line: 0, column: 0 });
+    let mut parser = Parser::new(&tokens, &mut strings, &mut errors, options, false, Position { index: 0, line: 0, column: 0 });
     let parse = parser.parse_value_document();
     if parse.is_ok() && !parser.is_end() {
         let error = ParseError::ExpectedEnd(parser.t0.to_type(), parser.at());
@@ -35,10 +59,19 @@ pub fn parse_value_str(document: &str) -> Result<ParsedValue, Vec<ParseError>> {
 
 /// Parse a dictionary document string.
 pub fn parse_dictionary_str(document: &str) -> Result<ParsedDictionary, Vec<ParseError>> {
+    parse_dictionary_str_with(document, &ParseOptions::default())
+}
+
+/// Parse a dictionary document string with options.
+pub fn parse_dictionary_str_with(document: &str, options: &ParseOptions) -> Result<ParsedDictionary, Vec<ParseError>> {
+    measure(options, DocumentKind::Dictionary, || parse_dictionary_document(document, options))
+}
+
+fn parse_dictionary_document(document: &str, options: &ParseOptions) -> Result<ParsedDictionary, Vec<ParseError>> {
     let tokens = unwrap_or_throw(tokenize(document))?;
-    let mut strings = HashSet::new();
+    let mut strings = Interner::new();
     let mut errors = Vec::new();
-    let mut parser = Parser::new(&tokens, &mut strings, &mut errors, false, Position { index: 0, line: 0, column: 0 });
+    let mut parser = Parser::new(&tokens, &mut strings, &mut errors, options, false, Position { index: 0, line: 0, column: 0 });
     let parse = parser.parse_dictionary_document();
     if parse.is_ok() && !parser.is_end() {
         let error = ParseError::ExpectedEnd(parser.t0.to_type(), parser.at());
@@ -49,10 +82,19 @@ pub fn parse_dictionary_str(document: &str) -> Result<ParsedDictionary, Vec<Pars
 
 /// Parse a list document string.
 pub fn parse_list_str(document: &str) -> Result<ParsedList, Vec<ParseError>> {
+    parse_list_str_with(document, &ParseOptions::default())
+}
+
+/// Parse a list document string with options.
+pub fn parse_list_str_with(document: &str, options: &ParseOptions) -> Result<ParsedList, Vec<ParseError>> {
+    measure(options, DocumentKind::List, || parse_list_document(document, options))
+}
+
+fn parse_list_document(document: &str, options: &ParseOptions) -> Result<ParsedList, Vec<ParseError>> {
     let tokens = unwrap_or_throw(tokenize(document))?;
-    let mut strings = HashSet::new();
+    let mut strings = Interner::new();
     let mut errors = Vec::new();
-    let mut parser = Parser::new(&tokens, &mut strings, &mut errors, false, Position { index: 0, line: 0, column: 0 });
+    let mut parser = Parser::new(&tokens, &mut strings, &mut errors, options, false, Position { index: 0, line: 0, column: 0 });
     let parse = parser.parse_list_document();
     if parse.is_ok() && !parser.is_end() {
         let error = ParseError::ExpectedEnd(parser.t0.to_type(), parser.at());
@@ -61,6 +103,183 @@ pub fn parse_list_str(document: &str) -> Result<ParsedList, Vec<ParseError>> {
     present_parse(parse, errors)
 }
 
+/// A parsed document with the style of its source.
+pub struct StyledDocument<T> {
+    pub document: T,
+    /// The prevailing indentation and newlines of the source.
+    pub style: Style,
+    /// The comments of the source in order, if kept with
+    /// [ParseOptions::comments].
+    pub comments: Vec<Comment>,
+}
+
+/// Parse a value document string with options, and detect its style.
+pub fn parse_value_str_styled(document: &str, options: &ParseOptions) -> Result<StyledDocument<ParsedValue>, Vec<ParseError>> {
+    let style = Style::detect(document);
+    let comments = collect_comments(document, options);
+    parse_value_str_with(document, options).map(|document| StyledDocument { document, style, comments })
+}
+
+/// Parse a dictionary document string with options, and detect its style.
+pub fn parse_dictionary_str_styled(document: &str, options: &ParseOptions) -> Result<StyledDocument<ParsedDictionary>, Vec<ParseError>> {
+    let style = Style::detect(document);
+    let comments = collect_comments(document, options);
+    parse_dictionary_str_with(document, options).map(|document| StyledDocument { document, style, comments })
+}
+
+/// Parse a list document string with options, and detect its style.
+pub fn parse_list_str_styled(document: &str, options: &ParseOptions) -> Result<StyledDocument<ParsedList>, Vec<ParseError>> {
+    let style = Style::detect(document);
+    let comments = collect_comments(document, options);
+    parse_list_str_with(document, options).map(|document| StyledDocument { document, style, comments })
+}
+
+/// Collect the comments of a document if they are kept.
+fn collect_comments(document: &str, options: &ParseOptions) -> Vec<Comment> {
+    if !options.comments {
+        return vec![];
+    };
+    match lex_with_comments(document.chars()) {
+        Ok((_, comments)) => comments,
+        Err(..) => vec![],
+    }
+}
+
+impl StyledDocument<ParsedValue> {
+    /// Format the document in the style of its source.
+    pub fn format(&self) -> String {
+        format_value_with(&self.document, &self.style)
+    }
+}
+
+impl StyledDocument<ParsedDictionary> {
+    /// Format the document in the style of its source.
+    pub fn format(&self) -> String {
+        format_dictionary_with(&self.document, &self.style)
+    }
+}
+
+impl StyledDocument<ParsedList> {
+    /// Format the document in the style of its source.
+    pub fn format(&self) -> String {
+        format_list_with(&self.document, &self.style)
+    }
+}
+
+/// Parser options.
+///
+/// The default options parse a document exactly as written.
+#[derive(Clone, Default)]
+pub struct ParseOptions {
+    /// Normalization applied to dictionary keys and tag names.
+    ///
+    /// The form written in the source remains available through
+    /// [ParsedDictionary::raw_key] and [ParsedTaggedValue::raw_name].
+    ///
+    /// [ParsedTaggedValue::raw_name]: crate::pdm::ParsedTaggedValue::raw_name
+    pub key_normalization: KeyNormalization,
+    /// Comparison of dictionary keys. Keys that compare equal are rejected as
+    /// duplicates.
+    pub key_comparison: KeyComparison,
+    /// Receiver of metrics, such as parse failures and durations.
+    pub metrics: Option<Arc<dyn ParseMetrics>>,
+    /// Keep the comments of the source in [StyledDocument::comments]. Off by
+    /// default.
+    pub comments: bool,
+}
+
+//// Metrics
+
+/// The kind of document being parsed.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum DocumentKind {
+    Value,
+    Dictionary,
+    List,
+}
+
+/// Receives metrics of parsing.
+///
+/// Set in [ParseOptions::metrics] to observe documents parsed with those
+/// options, for example to export them to a monitoring system.
+pub trait ParseMetrics: Send + Sync {
+    /// Called after a document was parsed.
+    fn parsed(&self, _kind: DocumentKind, _duration: Duration) {}
+    /// Called after a document failed to parse.
+    fn failed(&self, _kind: DocumentKind, _errors: &[ParseError], _duration: Duration) {}
+}
+
+/// Counters of parse attempts, failures, errors by [code](ParseError::code)
+/// and total duration.
+#[derive(Default)]
+pub struct ParseCounters {
+    attempts: AtomicU64,
+    failures: AtomicU64,
+    nanos: AtomicU64,
+    errors: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl ParseCounters {
+    pub fn new() -> Self {
+        ParseCounters::default()
+    }
+
+    /// Number of documents parsed, including failures.
+    pub fn attempts(&self) -> u64 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    /// Number of documents that failed to parse.
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent parsing.
+    pub fn duration(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    /// Number of errors by code.
+    pub fn errors(&self) -> HashMap<&'static str, u64> {
+        self.errors.lock().unwrap().clone()
+    }
+
+    fn count(&self, duration: Duration) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        self.nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl ParseMetrics for ParseCounters {
+    fn parsed(&self, _kind: DocumentKind, duration: Duration) {
+        self.count(duration);
+    }
+
+    fn failed(&self, _kind: DocumentKind, errors: &[ParseError], duration: Duration) {
+        self.count(duration);
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let mut counts = self.errors.lock().unwrap();
+        for error in errors {
+            *counts.entry(error.code()).or_insert(0) += 1;
+        };
+    }
+}
+
+fn measure<T, F: FnOnce() -> Result<T, Vec<ParseError>>>(options: &ParseOptions, kind: DocumentKind, parse: F) -> Result<T, Vec<ParseError>> {
+    let metrics = match &options.metrics {
+        Some(metrics) => metrics,
+        None => return parse(),
+    };
+    let start = Instant::now();
+    let result = parse();
+    let duration = start.elapsed();
+    match &result {
+        Ok(_) => metrics.parsed(kind, duration),
+        Err(errors) => metrics.failed(kind, errors, duration),
+    };
+    result
+}
+
 fn unwrap_or_throw<T>(t: Result<T, ParseError>) -> Result<T, Vec<ParseError>> {
     match t {
         Ok(o) => Ok(o),
@@ -114,22 +333,23 @@ fn tokenize(document: &str) -> Result<Vec<Reduced>, ParseError> {
 /// Parser
 pub mod parser {
 
-    use std::collections::{HashMap, HashSet};
+    use std::borrow::Cow;
     use std::fmt::{Debug, Formatter};
     use std::ops::Deref;
-    use std::rc::Rc;
     use std::slice::Iter;
     use std::vec;
     use crate::{Dictionary, Value};
+    use crate::parse::{Diagnostic, ParseOptions};
     use crate::parse::reducer::{Reduced, ScopeType, StringType};
-    use crate::pdm::{ParsedAttribute, ParsedDictionary, ParsedList, ParsedTaggedValue, ParsedText, ParsedTuple, ParsedValue, Position};
+    use crate::pdm::{Interner, ParsedAttribute, ParsedAttributeValue, ParsedDictionary, ParsedList, ParsedTaggedValue, ParsedText, ParsedTuple, ParsedValue, Position, SharedStr};
 
     pub struct Parser<'a> {
         stream: Iter<'a, Reduced>,
         pub t0: &'a Reduced,
         t1: &'a Reduced,
-        strings: &'a mut HashSet<Rc<str>>,
+        strings: &'a mut Interner,
         errors: &'a mut Vec<ParseError>,
+        options: &'a ParseOptions,
         whitespace_before: bool,
         last_position: Position,
     }
@@ -137,8 +357,9 @@ pub mod parser {
     impl<'a> Parser<'a> {
         pub fn new(
             tokens: &'a Vec<Reduced>,
-            strings: &'a mut HashSet<Rc<str>>,
+            strings: &'a mut Interner,
             errors: &'a mut Vec<ParseError>,
+            options: &'a ParseOptions,
             whitespace_before: bool,
             open_position: Position,
         ) -> Self {
@@ -146,7 +367,7 @@ pub mod parser {
             let mut iter = Parser {
                 stream: tokens.iter(),
                 t0: &DEFAULT, t1: &DEFAULT,
-                strings, errors, whitespace_before,
+                strings, errors, options, whitespace_before,
                 last_position: open_position,
             };
             iter.shift();
@@ -175,15 +396,8 @@ pub mod parser {
             matches!(self.t0, Reduced::End(..))
         }
 
-        fn store_str(&mut self, string: &str) -> Rc<str> {
-            if let Some(str) = self.strings.get(string) {
-                str.clone()
-            } else {
-                let count = Rc::from(string);
-                let str = Rc::clone(&count);
-                self.strings.insert(count);
-                str
-            }
+        fn store_str(&mut self, string: &str) -> SharedStr {
+            self.strings.intern(string)
         }
 
     }
@@ -196,6 +410,13 @@ pub mod parser {
         /// <value-document> → *
         ///                  | *<value>*
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "");
+        /// assert_parses!(value, "a b");
+        /// assert_rejects!(value, "a ]");
+        /// ```
         pub(crate) fn parse_value_document(&mut self) -> Result<ParsedValue, ParseError> {
             let value = if matches!(self.t0, Reduced::String(..) | Reduced::CurlyBracket(..) | Reduced::SquareBracket(..) | Reduced::AngleBracket(..) | Reduced::Tilde(..) | Reduced::Bar(..) | Reduced::TaggedValueHeader(..)) {
                 self.parse_value()?
@@ -211,6 +432,13 @@ pub mod parser {
         /// <dictionary-document> → *
         ///                       | *<dictionary>*
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(dictionary, "");
+        /// assert_parses!(dictionary, "a: 1\nb: 2");
+        /// assert_rejects!(dictionary, "a");
+        /// ```
         pub(crate) fn parse_dictionary_document(&mut self) -> Result<ParsedDictionary, ParseError> {
             let dictionary = if matches!(self.t0, Reduced::AssignmentHeader(..) | Reduced::CurlyHeader(..) | Reduced::SquareHeader(..)) {
                 self.parse_dictionary()?
@@ -226,6 +454,13 @@ pub mod parser {
         /// <list-document> → *
         ///                 | *<list>*
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(list, "");
+        /// assert_parses!(list, "a; b");
+        /// assert_rejects!(list, "a; ;");
+        /// ```
         pub(crate) fn parse_list_document(&mut self) -> Result<ParsedList, ParseError> {
             let list = if matches!(self.t0, Reduced::String(..) | Reduced::CurlyBracket(..) | Reduced::SquareBracket(..) | Reduced::AngleBracket(..) | Reduced::Tilde(..) | Reduced::Bar(..) | Reduced::Bullet(..) | Reduced::TaggedValueHeader(..)) {
                 self.parse_list()?
@@ -242,6 +477,14 @@ pub mod parser {
         ///         | "|" <inner-value>
         ///         | <tagged-value>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "a | b");
+        /// assert_parses!(value, "| a");
+        /// assert_parses!(value, "<a>: b");
+        /// assert_rejects!(value, "a |");
+        /// ```
         fn parse_value(&mut self) -> Result<ParsedValue, ParseError> {
             match self.t0 {
                 Reduced::String(..) | Reduced::CurlyBracket(..) | Reduced::SquareBracket(..) | Reduced::AngleBracket(..) | Reduced::Tilde(..) => self.parse_inner_value(),
@@ -268,6 +511,14 @@ pub mod parser {
         ///        | <bracketed-list>
         ///        | <tagged-arguments>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "a {b} [c] <d>");
+        /// assert_parses!(value, "a ~ b");
+        /// assert_parses!(value, "~");
+        /// assert_rejects!(value, "a ~ ;");
+        /// ```
         fn parse_block(&mut self) -> Result<ParsedValue, ParseError> {
             let mut terms: Vec<ParsedValue> = vec![];
             let mut whitespace = vec![];
@@ -322,6 +573,13 @@ pub mod parser {
         ///         | <string> <text'>
         ///         | "~" <text'>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "Hello world!");
+        /// assert_parses!(value, "a~b~c");
+        /// assert_rejects!(value, "a:");
+        /// ```
         fn parse_text(&mut self) -> Result<ParsedValue, ParseError> {
             let mut text = String::new();
             let mut space_before = false;
@@ -367,6 +625,13 @@ pub mod parser {
         /// <tuple-element> → <block>
         ///                 | <mapped-key>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "a | b | c");
+        /// assert_parses!(value, "a => b | c => d");
+        /// assert_rejects!(value, "| |");
+        /// ```
         fn parse_inner_value(&mut self) -> Result<ParsedValue, ParseError> {
             let mut elements = vec![];
             let mut mapped_keys = vec![];
@@ -386,7 +651,7 @@ pub mod parser {
             }
             let to = self.at_last();
             if !mapped_keys.is_empty() {
-                let dictionary = create_dictionary(vec![(vec![], mapped_keys)], &mut self.errors, from, to);
+                let dictionary = create_dictionary(vec![(vec![], from, mapped_keys)], self.errors, self.options, from, to);
                 elements.insert(0, ParsedValue::Dictionary(dictionary, from, to));
             }
             let inner_value = if elements.len() == 1 {
@@ -402,6 +667,12 @@ pub mod parser {
         /// ```text
         /// <mapped-key> → <key> "=>" <block>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "a => b");
+        /// assert_rejects!(value, "=> b");
+        /// ```
         fn parse_mapped_key(&mut self) -> Result<(ParsedKey, ParsedValue), ParseError> {
             let from = self.t0.at();
             if !matches!(self.t0, Reduced::AssignmentHeader(..) | Reduced::String(..)) {
@@ -421,6 +692,13 @@ pub mod parser {
         /// ```text
         /// <tagged-value> → <tag>":"_<value>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "<a>: b");
+        /// assert_parses!(value, "<a b:c>: d e");
+        /// assert_rejects!(value, "<a>: ");
+        /// ```
         fn parse_tagged_value(&mut self) -> Result<ParsedValue, ParseError> {
             let from = self.at();
             let tag = self.parse_tag()?;
@@ -432,9 +710,9 @@ pub mod parser {
             let value = self.parse_value()?;
             let to = self.at();
             let tagged_value = match tag {
-                Some((name, attributes)) => {
+                Some((name, raw_name, attributes)) => {
                     ParsedValue::Tagged(ParsedTaggedValue {
-                        name: self.store_str(&name), attributes, value: Box::new(value),
+                        name, raw_name, attributes, value: Box::new(value),
                     }, from, to)
                 }
                 None => {
@@ -466,6 +744,14 @@ pub mod parser {
         ///           | <curly-header>":"_<inner-dictionary>
         ///           | <curly-header>":"_<value>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(dictionary, "a: 1; b: 2");
+        /// assert_parses!(dictionary, "x: 0\n{s}: a: 1\n{t}: b: 2");
+        /// assert_parses!(dictionary, "[l]: a; b");
+        /// assert_rejects!(dictionary, "{s} a: 1");
+        /// ```
         fn parse_dictionary(&mut self) -> Result<ParsedDictionary, ParseError> {
             let mut dictionary_sections = vec![];
             let mut direct_entries = vec![];
@@ -477,6 +763,7 @@ pub mod parser {
             loop {
                 match self.t0 {
                     Reduced::CurlyHeader(..) => {
+                        let header_at = self.at();
                         let header = self.parse_header()?;
                         if !matches!(self.t0, Reduced::Colon(..)) {
                             return ParseError::token_expectation_error(&[Rule::Colon], self.t0, Rule::AbsoluteDictionary, from);
@@ -492,7 +779,7 @@ pub mod parser {
                             }
                             Reduced::AssignmentHeader(..) => {
                                 let entries = self.parse_inner_dictionary()?;
-                                dictionary_sections.push((header, entries))
+                                dictionary_sections.push((header, header_at, entries))
                             }
                             _ => direct_entries.push((header, ParsedValue::Dictionary(ParsedDictionary::empty(), content_from, content_from))),
                         }
@@ -518,8 +805,8 @@ pub mod parser {
                 }
             }
             let to = self.at_last();
-            dictionary_sections.push((vec![], direct_entries));
-            let dictionary = create_dictionary(dictionary_sections, self.errors, from, to);
+            dictionary_sections.push((vec![], from, direct_entries));
+            let dictionary = create_dictionary(dictionary_sections, self.errors, self.options, from, to);
             Ok(dictionary)
         }
 
@@ -530,13 +817,21 @@ pub mod parser {
         ///
         /// <square-header> → "["<key>"]"
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(dictionary, "{s}: x");
+        /// assert_parses!(dictionary, "[l]: a");
+        /// assert_rejects!(dictionary, "{s: a: 1");
+        /// assert_rejects!(dictionary, "[l] a");
+        /// ```
         fn parse_header(&mut self) -> Result<ParsedKey, ParseError> {
             let mut key = vec![];
             let at = self.at();
             match self.t0 {
                 Reduced::CurlyHeader(_, ht, fw, scope) | Reduced::SquareHeader(_, ht, fw, scope) => {
                     self.shift();
-                    let mut parser = Parser::new(&scope, self.strings, self.errors, *fw, *ht);
+                    let mut parser = Parser::new(&scope, self.strings, self.errors, self.options, *fw, *ht);
                     parser.require_no_whitespace_before();
                     key = parser.parse_key()?;
                     parser.require_end();
@@ -552,6 +847,13 @@ pub mod parser {
         /// <inner-dictionary> → <delimited-dictionary>
         ///                    | <aligned-dictionary>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "{a: 1; b: 2}");
+        /// assert_parses!(value, "{a: 1\nb: 2}");
+        /// assert_rejects!(value, "{a: 1; b: 2");
+        /// ```
         fn parse_inner_dictionary(&mut self) -> Result<Vec<ParsedEntry>, ParseError> {
             let entry = self.parse_entry()?;
             let entries = match self.t0 {
@@ -578,6 +880,13 @@ pub mod parser {
         ///                        | <entry> ";"
         ///                        | <entry> ";" <delimited-dictionary>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(dictionary, "a: 1;");
+        /// assert_parses!(dictionary, "a: 1; b: 2;");
+        /// assert_rejects!(dictionary, "; a: 1");
+        /// ```
         fn parse_delimited_dictionary(&mut self, entries: Vec<ParsedEntry>) -> Result<Vec<ParsedEntry>, ParseError> {
             let mut entries = entries;
             loop {
@@ -600,6 +909,13 @@ pub mod parser {
         /// <aligned-dictionary> → <entry>
         ///                      | <entry>_<aligned-dictionary>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(dictionary, "a: 1\nb: 2");
+        /// assert_parses!(dictionary, "a: 1 b: 2");
+        /// assert_rejects!(dictionary, "a: 1\n: 2");
+        /// ```
         fn parse_aligned_dictionary(&mut self, entries: Vec<ParsedEntry>) -> Result<Vec<ParsedEntry>, ParseError> {
             let mut entries = entries;
             loop {
@@ -621,6 +937,14 @@ pub mod parser {
         /// <key> → <string>
         ///       | <string>":"<key>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(dictionary, "a: 1");
+        /// assert_parses!(dictionary, "a:b: 1");
+        /// assert_rejects!(dictionary, ": 1");
+        /// assert_rejects!(dictionary, "a 1");
+        /// ```
         fn parse_entry(&mut self) -> Result<ParsedEntry, ParseError> {
             let at = self.at();
             if let Reduced::AssignmentHeader(.., s) | Reduced::String(.., s) = self.t0 {
@@ -637,7 +961,7 @@ pub mod parser {
         }
 
         /// Parse a key and a colon.
-        fn parse_entry_key(&mut self) -> Result<Vec<Rc<str>>, ParseError> {
+        fn parse_entry_key(&mut self) -> Result<Vec<SharedStr>, ParseError> {
             let mut key = vec![];
             loop {
                 let s = match self.t0 {
@@ -667,7 +991,13 @@ pub mod parser {
         /// <key> → <string>
         ///       | <string>":"<key>
         /// ```
-        fn parse_key(&mut self) -> Result<Vec<Rc<str>>, ParseError> {
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(dictionary, "a:b:c: 1");
+        /// assert_rejects!(dictionary, "a:: 1");
+        /// ```
+        fn parse_key(&mut self) -> Result<Vec<SharedStr>, ParseError> {
             let mut key = vec![];
             loop {
                 match self.t0 {
@@ -696,6 +1026,15 @@ pub mod parser {
         ///        | <tabular-list>
         ///        | <tagged-list>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(list, "a; b");
+        /// assert_parses!(list, "> a\n> b");
+        /// assert_parses!(list, "|a|b|\n|c|d|");
+        /// assert_parses!(list, "<a>: 1\n<b>: 2");
+        /// assert_rejects!(list, "> a; b");
+        /// ```
         fn parse_list(&mut self) -> Result<ParsedList, ParseError> {
             match self.t0 {
                 Reduced::Bullet(..) => self.parse_aligned_list(vec![]),
@@ -738,6 +1077,13 @@ pub mod parser {
         ///                  | <value> ";"
         ///                  | <value> ";" <delimited-list>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(list, "a; b");
+        /// assert_parses!(list, "a; b;");
+        /// assert_rejects!(list, ";");
+        /// ```
         fn parse_delimited_list(&mut self, elements: Vec<ParsedValue>) -> Result<ParsedList, ParseError> {
             let mut elements = elements;
             loop {
@@ -761,6 +1107,13 @@ pub mod parser {
         /// <aligned-list> → ">"_<value>
         ///                | ">"_<value>_<aligned-list>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(list, "> a");
+        /// assert_parses!(list, "> a\n> b");
+        /// assert_rejects!(list, ">a");
+        /// ```
         fn parse_aligned_list(&mut self, elements: Vec<ParsedValue>) -> Result<ParsedList, ParseError> {
             let mut elements = elements;
             let at = self.at(); // TODO: Might be earlier.
@@ -786,6 +1139,13 @@ pub mod parser {
         /// <tabular-list> → "|" <inner-value> "|"
         ///                | "|" <inner-value> "|"_<tabular-list>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(list, "|a|b|\n|c|d|");
+        /// assert_parses!(list, "|a| |b|");
+        /// assert_rejects!(list, "|a|b|\n|c|d");
+        /// ```
         fn parse_tabular_list(&mut self, elements: Vec<ParsedValue>) -> Result<ParsedList, ParseError> {
             let mut elements = elements;
             if !matches!(self.t0, Reduced::Bar(..)) {
@@ -812,6 +1172,12 @@ pub mod parser {
         /// <tagged-list> → <tagged-value>
         ///               | <tagged-value>_<tagged-list>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(list, "<a>: 1\n<b>: 2");
+        /// assert_rejects!(list, "<a>: 1\n<b>:");
+        /// ```
         fn parse_tagged_list(&mut self, elements: Vec<ParsedValue>) -> Result<ParsedList, ParseError> {
             let mut elements = elements;
             loop {
@@ -830,6 +1196,13 @@ pub mod parser {
         /// <tagged-arguments> → <tag>
         ///                    | <tag><arguments>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "<a>");
+        /// assert_parses!(value, "<a>:b:c");
+        /// assert_rejects!(value, "<a>:");
+        /// ```
         fn parse_tagged_arguments(&mut self) -> Result<ParsedValue, ParseError> {
             let from = self.at();
             let tag = self.parse_tag()?;
@@ -852,9 +1225,8 @@ pub mod parser {
                 ParsedValue::Tuple(ParsedTuple::Multiple(arguments.into_boxed_slice()), from, to)
             };
             let tagged_arguments = match tag {
-                Some((name, attributes)) => {
-                    let name = self.store_str(&name);
-                    ParsedValue::Tagged(ParsedTaggedValue { name, attributes, value: Box::new(value) }, from, to)
+                Some((name, raw_name, attributes)) => {
+                    ParsedValue::Tagged(ParsedTaggedValue { name, raw_name, attributes, value: Box::new(value) }, from, to)
                 }
                 None => {
                     value
@@ -875,6 +1247,13 @@ pub mod parser {
         ///            | <bracketed-list>
         ///            | <tagged-arguments>
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "<a>:b:c");
+        /// assert_parses!(value, "<a>:{b}:[c]:<d>");
+        /// assert_rejects!(value, "<a>: :b");
+        /// ```
         fn parse_arguments(&mut self) -> Result<Vec<ParsedValue>, ParseError> {
             let mut arguments = vec![];
             if !matches!(self.t0, Reduced::Colon(..)) {
@@ -921,10 +1300,18 @@ pub mod parser {
         ///       | "<"<word>_<attributes> ">"
         ///       | "<"">"
         /// ```
-        fn parse_tag(&mut self) -> Result<Option<(Rc<str>, Vec<ParsedAttribute>)>, ParseError> {
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "<>");
+        /// assert_parses!(value, "<a b c:d>");
+        /// assert_rejects!(value, "< a>");
+        /// assert_rejects!(value, "<{a}>");
+        /// ```
+        fn parse_tag(&mut self) -> Result<Option<(SharedStr, Option<SharedStr>, Vec<ParsedAttribute>)>, ParseError> {
             if let Reduced::AngleBracket(from, _, fw, _, scope) | Reduced::TaggedValueHeader(from, _, fw, scope) = self.t0 {
                 self.shift();
-                let mut parser = Parser::new(scope, self.strings, self.errors, *fw, *from);
+                let mut parser = Parser::new(scope, self.strings, self.errors, self.options, *fw, *from);
                 parser.require_no_whitespace_before();
                 if parser.is_end() {
                     return Ok(None);
@@ -945,7 +1332,11 @@ pub mod parser {
                     vec![]
                 };
                 parser.require_end();
-                Ok(Some((name, attributes)))
+                let (name, raw_name) = match parser.options.key_normalization.apply(&name) {
+                    Cow::Borrowed(_) => (name, None),
+                    Cow::Owned(normalized) => (parser.store_str(&normalized), Some(name)),
+                };
+                Ok(Some((name, raw_name, attributes)))
             } else {
                 return ParseError::token_expectation_error(&[Rule::AngularBracket], self.t0, Rule::Tag, self.at());
             }
@@ -958,7 +1349,15 @@ pub mod parser {
         ///              | <attribute>_<attributes>
         ///
         /// <attribute> → <word>
-        ///             | <word>":"<string>
+        ///             | <word>":"<attribute-value>
+        /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "<a b c:d>");
+        /// assert_parses!(value, "<a b:\\c d\\>");
+        /// assert_parses!(value, "<a b:[c; d]>");
+        /// assert_rejects!(value, "<a b:>");
         /// ```
         fn parse_attributes(&mut self) -> Result<Vec<ParsedAttribute>, ParseError> {
             let mut attributes = vec![];
@@ -978,7 +1377,7 @@ pub mod parser {
                 self.shift();
                 if matches!(self.t0, Reduced::Colon(..)) {
                     self.shift();
-                    let value = self.parse_string()?;
+                    let value = self.parse_attribute_value()?;
                     attributes.push(ParsedAttribute(key, Some(value)));
                 } else {
                     attributes.push(ParsedAttribute(key, None));
@@ -987,6 +1386,45 @@ pub mod parser {
             Ok(attributes)
         }
 
+        /// Parse an attribute value, a string or a list of strings, like a
+        /// list of classes.
+        ///
+        /// ```text
+        /// <attribute-value> → <string>
+        ///                   | "[" "]"
+        ///                   | "[" <strings> "]"
+        ///
+        /// <strings> → <string>
+        ///           | <string>";"<strings>
+        /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "<a b:[c; \\d e\\]>");
+        /// assert_parses!(value, "<a b:[]>");
+        /// assert_rejects!(value, "<a b:[c; {d}]>");
+        /// assert_rejects!(value, "<a b:[c | d]>");
+        /// ```
+        fn parse_attribute_value(&mut self) -> Result<ParsedAttributeValue, ParseError> {
+            if let Reduced::SquareBracket(_, to, fw, _, scope) = &self.t0 {
+                self.shift();
+                let mut parser = Parser::new(scope, self.strings, self.errors, self.options, *fw, *to);
+                let mut strings = vec![];
+                while !parser.is_end() {
+                    strings.push(parser.parse_string()?);
+                    if matches!(parser.t0, Reduced::Semicolon(..)) {
+                        parser.shift();
+                    } else if !parser.is_end() {
+                        return ParseError::token_expectation_error(&[Rule::Semicolon, Rule::Close], parser.t0, Rule::Attribute, parser.at());
+                    };
+                }
+                parser.require_end();
+                Ok(ParsedAttributeValue::List(strings))
+            } else {
+                self.parse_string().map(ParsedAttributeValue::Text)
+            }
+        }
+
         /// Parse bracketed construct.
         ///
         /// ```text
@@ -995,10 +1433,19 @@ pub mod parser {
         /// <bracketed-dictionary> → "{" "}"
         ///                        | "{" <dictionary> "}"
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "{a}");
+        /// assert_parses!(value, "{}");
+        /// assert_parses!(value, "{a: 1}");
+        /// assert_rejects!(value, "{a: 1");
+        /// assert_rejects!(value, "{a]");
+        /// ```
         fn parse_bracketed_construct(&mut self) -> Result<ParsedValue, ParseError> {
             if let Reduced::CurlyBracket(from, to, wi, _, scope) = self.t0 {
                 self.shift();
-                let mut parser = Parser::new(scope, self.strings, self.errors, *wi, *to);
+                let mut parser = Parser::new(scope, self.strings, self.errors, self.options, *wi, *to);
                 let value = match parser.t0 {
                     Reduced::AssignmentHeader(..) | Reduced::CurlyHeader(..) | Reduced::SquareHeader(..) => {
                         let dictionary = parser.parse_dictionary()?;
@@ -1026,10 +1473,18 @@ pub mod parser {
         /// <bracketed-list> → "[" "]"
         ///                  | "[" <list> "]"
         /// ```
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "[]");
+        /// assert_parses!(value, "[a; b]");
+        /// assert_rejects!(value, "[a; b");
+        /// assert_rejects!(value, "[a}");
+        /// ```
         fn parse_bracketed_list(&mut self) -> Result<ParsedValue, ParseError> {
             if let Reduced::SquareBracket(from, to, fw, _, scope) = &self.t0 {
                 self.shift();
-                let mut parser = Parser::new(scope, self.strings, self.errors, *fw, *to);
+                let mut parser = Parser::new(scope, self.strings, self.errors, self.options, *fw, *to);
                 let list = if !parser.is_end() {
                     parser.parse_list()?
                 } else {
@@ -1049,7 +1504,16 @@ pub mod parser {
         ///          | <transcription>
         ///          | <text-block>
         /// ```
-        fn parse_string(&mut self) -> Result<Rc<str>, ParseError> {
+        ///
+        /// ```
+        /// # use khi::{assert_parses, assert_rejects};
+        /// assert_parses!(value, "word");
+        /// assert_parses!(value, "\\transcription\\");
+        /// assert_parses!(value, "<#>\n  text block\n<#>");
+        /// assert_rejects!(value, "`a");
+        /// assert_rejects!(value, "<#>\n  unclosed");
+        /// ```
+        fn parse_string(&mut self) -> Result<SharedStr, ParseError> {
             match self.t0 {
                 Reduced::String(.., text) => {
                     self.shift();
@@ -1061,11 +1525,15 @@ pub mod parser {
 
     }
 
-    /// Construct a dictionary from entries and sections.
-    fn create_dictionary(sections: Vec<(Vec<Rc<str>>, Vec<ParsedEntry>)>, errors: &mut Vec<ParseError>, from: Position, to: Position) -> ParsedDictionary {
-        let mut dictionary = ParsedDictionary { entries: HashMap::new() };
-        for (section_key, entries) in sections {
-            let dictionary_reference = match resolve_dictionary(&mut dictionary, &section_key, from) {
+    /// Construct a dictionary from entries and sections. Each section carries the
+    /// position of its header, which is used for the section dictionary and for
+    /// errors about the section key.
+    fn create_dictionary(sections: Vec<(Vec<SharedStr>, Position, Vec<ParsedEntry>)>, errors: &mut Vec<ParseError>, options: &ParseOptions, from: Position, to: Position) -> ParsedDictionary {
+        let mut dictionary = ParsedDictionary::empty();
+        dictionary.normalization = options.key_normalization;
+        dictionary.comparison = options.key_comparison;
+        for (section_key, section_at, entries) in sections {
+            let dictionary_reference = match resolve_dictionary(&mut dictionary, &section_key, section_at) {
                 Ok(r) => r,
                 Err(e) => {
                     errors.push(e);
@@ -1080,21 +1548,30 @@ pub mod parser {
                         continue;
                     }
                 };
-                let k = &entry_key[entry_key.len() - 1];
-                if dictionary_reference.entries.contains_key(k) {
+                let k = normalize_key(dictionary_reference, &entry_key[entry_key.len() - 1]);
+                if dictionary_reference.find_key(&k).is_some() {
                     errors.push(ParseError::KeyAlreadyAssigned(from)); //TODO from
                     continue;
                 } else {
-                    dictionary_reference.entries.insert(k.clone(), value);
+                    dictionary_reference.order.push(k.clone());
+                    dictionary_reference.entries.insert(k, value);
                 }
             }
         }
         dictionary
     }
 
-    fn resolve_dictionary<'a>(root: &'a mut ParsedDictionary, key: &[Rc<str>], at: Position) -> Result<&'a mut ParsedDictionary, ParseError> {
+    fn resolve_dictionary<'a>(root: &'a mut ParsedDictionary, key: &[SharedStr], at: Position) -> Result<&'a mut ParsedDictionary, ParseError> {
         let mut dictionary_reference = root;
         for k in key {
+            let d = dictionary_reference.empty_like();
+            let k = match dictionary_reference.find_key(k) {
+                Some(existing) => existing.clone(),
+                None => normalize_key(dictionary_reference, k),
+            };
+            if !dictionary_reference.entries.contains_key(k.deref()) {
+                dictionary_reference.order.push(k.clone());
+            };
             let entries = &mut dictionary_reference.entries;
             if entries.contains_key(k.deref()) {
                 if let Some(ParsedValue::Dictionary(ref mut d, ..)) = entries.get_mut(k.deref()) {
@@ -1103,7 +1580,6 @@ pub mod parser {
                     return Err(ParseError::KeyNotDictionary(at)); // TODO
                 }
             } else {
-                let d = ParsedDictionary::empty();
                 entries.insert(k.clone(), ParsedValue::Dictionary(d, at, at));
                 let d = entries.get_mut(k.deref()).unwrap();
                 dictionary_reference = d.as_mut_dictionary().unwrap();
@@ -1112,6 +1588,19 @@ pub mod parser {
         Ok(dictionary_reference)
     }
 
+    /// Normalize a key for a dictionary, remembering the key as written if it
+    /// was changed.
+    fn normalize_key(dictionary: &mut ParsedDictionary, key: &SharedStr) -> SharedStr {
+        match dictionary.normalization.apply(key) {
+            Cow::Borrowed(_) => key.clone(),
+            Cow::Owned(normalized) => {
+                let normalized: SharedStr = SharedStr::from(normalized);
+                dictionary.raw_keys.entry(normalized.clone()).or_insert_with(|| key.clone());
+                normalized
+            }
+        }
+    }
+
     //// Errors
 
     impl Parser<'_> {
@@ -1156,7 +1645,7 @@ pub mod parser {
 
     }
 
-    type ParsedKey = Vec<Rc<str>>;
+    type ParsedKey = Vec<SharedStr>;
 
     type ParsedEntry = (ParsedKey, ParsedValue);
 
@@ -1286,6 +1775,52 @@ pub mod parser {
         }
     }
 
+    impl ParseError {
+        /// A stable code identifying the kind of error, such as for counting
+        /// errors by kind.
+        pub fn code(&self) -> &'static str {
+            match self {
+                ParseError::EscapingEndOfStream => "escaping_end_of_stream",
+                ParseError::InvalidEscapeSequence(..) => "invalid_escape_sequence",
+                ParseError::IllegalHashSequence(..) => "illegal_hash_sequence",
+                ParseError::UnclosedTextBlock(..) => "unclosed_text_block",
+                ParseError::InvalidTextBlockConfiguration(..) => "invalid_text_block_configuration",
+                ParseError::MismatchedClose(..) => "mismatched_close",
+                ParseError::Expected(..) => "expected",
+                ParseError::ExpectedColumns(..) => "expected_columns",
+                ParseError::KeyNotDictionary(..) => "key_not_dictionary",
+                ParseError::KeyAlreadyAssigned(..) => "key_already_assigned",
+                ParseError::ExpectedWhitespace(..) => "expected_whitespace",
+                ParseError::UnexpectedWhitespace(..) => "unexpected_whitespace",
+                ParseError::AttributeMustBeWord(..) => "attribute_must_be_word",
+                ParseError::TagNameMustBeWord(..) => "tag_name_must_be_word",
+                ParseError::ExpectedEnd(..) => "expected_end",
+            }
+        }
+
+        /// Get a diagnostic of the error.
+        pub fn to_diagnostic(&self) -> Diagnostic {
+            let at = match self {
+                ParseError::EscapingEndOfStream => None,
+                ParseError::InvalidEscapeSequence(at)
+                | ParseError::IllegalHashSequence(at)
+                | ParseError::UnclosedTextBlock(at)
+                | ParseError::InvalidTextBlockConfiguration(at)
+                | ParseError::MismatchedClose(at, ..)
+                | ParseError::Expected(_, _, at, ..)
+                | ParseError::ExpectedColumns(at, ..)
+                | ParseError::KeyNotDictionary(at)
+                | ParseError::KeyAlreadyAssigned(at)
+                | ParseError::ExpectedWhitespace(at)
+                | ParseError::UnexpectedWhitespace(at)
+                | ParseError::AttributeMustBeWord(at, _)
+                | ParseError::TagNameMustBeWord(at, _)
+                | ParseError::ExpectedEnd(_, at) => Some(*at),
+            };
+            Diagnostic { code: self.code(), message: error_to_string(self), at }
+        }
+    }
+
     pub fn error_to_string(error: &ParseError) -> String {
         match error {
             ParseError::EscapingEndOfStream => {
@@ -1,10 +1,133 @@
 //! Parsed document model (AST) reference implementation.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
+#[cfg(not(feature = "sync"))]
 use std::rc::Rc;
+#[cfg(feature = "sync")]
+use std::sync::Arc;
 use std::slice::Iter;
-use crate::{Attribute, AttributeValue, Compound, Dictionary, Element, List, Tagged, Text, Tuple, Value};
+use crate::{Attribute, AttributeValue, Component, Compound, Dictionary, Element, List, Tagged, Text, Tuple, Value};
+use crate::boolean::BoolFormat;
+use crate::number::NumberFormat;
+#[cfg(feature = "decimal")]
+use rust_decimal::Decimal;
+
+//// Shared string
+
+/// A reference counted string, shared between the nodes of parsed documents.
+///
+/// This is an `Rc<str>`, or an `Arc<str>` with the `sync` feature, which makes
+/// parsed documents [Send] and [Sync].
+#[cfg(not(feature = "sync"))]
+pub type SharedStr = Rc<str>;
+
+/// A reference counted string, shared between the nodes of parsed documents.
+///
+/// This is an `Rc<str>`, or an `Arc<str>` with the `sync` feature, which makes
+/// parsed documents [Send] and [Sync].
+#[cfg(feature = "sync")]
+pub type SharedStr = Arc<str>;
+
+/// A set of shared strings, which gives equal strings the same allocation.
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<SharedStr>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    /// Get the shared string equal to a string, adding it if there is none.
+    pub fn intern(&mut self, string: &str) -> SharedStr {
+        match self.strings.get(string) {
+            Some(shared) => shared.clone(),
+            None => {
+                let shared = SharedStr::from(string);
+                self.strings.insert(shared.clone());
+                shared
+            }
+        }
+    }
+
+    /// Number of distinct strings.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+impl ParsedValue {
+    /// Copy this value with its strings in a new interner.
+    ///
+    /// The copy holds no references to the strings of the document this value
+    /// is part of, so keeping the copy does not keep the rest of the document
+    /// in memory. Positions are kept.
+    pub fn compact(&self) -> ParsedValue {
+        self.compact_into(&mut Interner::new())
+    }
+
+    /// Copy this value with its strings in an interner, which may be shared
+    /// with other compacted values.
+    pub fn compact_into(&self, interner: &mut Interner) -> ParsedValue {
+        match self {
+            ParsedValue::Nil(from, to) => ParsedValue::Nil(*from, *to),
+            ParsedValue::Text(text, from, to) => ParsedValue::Text(ParsedText { str: interner.intern(&text.str) }, *from, *to),
+            ParsedValue::Dictionary(dictionary, from, to) => {
+                let mut compacted = dictionary.empty_like();
+                for (key, value) in &dictionary.entries {
+                    compacted.entries.insert(interner.intern(key), value.compact_into(interner));
+                };
+                compacted.order = dictionary.order.iter().map(|key| interner.intern(key)).collect();
+                for (key, raw_key) in &dictionary.raw_keys {
+                    compacted.raw_keys.insert(interner.intern(key), interner.intern(raw_key));
+                };
+                ParsedValue::Dictionary(compacted, *from, *to)
+            }
+            ParsedValue::List(list, from, to) => {
+                let elements = list.elements.iter().map(|e| e.compact_into(interner)).collect();
+                ParsedValue::List(ParsedList { elements }, *from, *to)
+            }
+            ParsedValue::Compound(compound, from, to) => {
+                let components = compound.components.iter().map(|c| c.compact_into(interner)).collect();
+                ParsedValue::Compound(ParsedCompound { components, whitespace: compound.whitespace.clone() }, *from, *to)
+            }
+            ParsedValue::Tuple(tuple, from, to) => {
+                let tuple = match tuple {
+                    ParsedTuple::Unit => ParsedTuple::Unit,
+                    ParsedTuple::Single(value) => ParsedTuple::Single(Box::new(value.compact_into(interner))),
+                    ParsedTuple::Multiple(values) => ParsedTuple::Multiple(values.iter().map(|v| v.compact_into(interner)).collect()),
+                };
+                ParsedValue::Tuple(tuple, *from, *to)
+            }
+            ParsedValue::Tagged(tagged, from, to) => {
+                let attributes = tagged.attributes.iter().map(|a| {
+                    let value = a.1.as_ref().map(|value| match value {
+                        ParsedAttributeValue::Text(text) => ParsedAttributeValue::Text(interner.intern(text)),
+                        ParsedAttributeValue::List(strings) => ParsedAttributeValue::List(strings.iter().map(|s| interner.intern(s)).collect()),
+                    });
+                    ParsedAttribute(interner.intern(&a.0), value)
+                }).collect();
+                let tagged = ParsedTaggedValue {
+                    name: interner.intern(&tagged.name),
+                    raw_name: tagged.raw_name.as_ref().map(|n| interner.intern(n)),
+                    attributes,
+                    value: Box::new(tagged.value.compact_into(interner)),
+                };
+                ParsedValue::Tagged(tagged, *from, *to)
+            }
+        }
+    }
+}
 
 //// Position
 
@@ -13,8 +136,147 @@ use crate::{Attribute, AttributeValue, Compound, Dictionary, Element, List, Tagg
 /// Contains a line number and a column number, corresponding to a character in
 /// a document.
 #[derive(PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position { pub index: usize, pub line: usize, pub column: usize }
 
+impl Position {
+    /// Position of values that were not parsed from a document.
+    pub const ZERO: Position = Position { index: 0, line: 0, column: 0 };
+}
+
+//// Key normalization
+
+/// Normalization of dictionary keys and tag names.
+#[derive(PartialEq, Eq, Copy, Clone, Default)]
+pub enum KeyNormalization {
+    /// Keys are used as written.
+    #[default]
+    None,
+    /// Keys are converted to lowercase with [str::to_lowercase]. This is not
+    /// full Unicode case folding: `ß` stays `ß` while `SS` becomes `ss`.
+    Lowercase,
+    /// Keys are converted to Unicode normalization form C.
+    #[cfg(feature = "unicode")]
+    Nfc,
+    /// Keys are converted to Unicode normalization form C and lowercase.
+    #[cfg(feature = "unicode")]
+    NfcLowercase,
+}
+
+impl KeyNormalization {
+    /// Normalize a key.
+    ///
+    /// Borrows the key if it is already normalized.
+    pub fn apply<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        match self {
+            KeyNormalization::None => Cow::Borrowed(key),
+            KeyNormalization::Lowercase => to_lowercase(key),
+            #[cfg(feature = "unicode")]
+            KeyNormalization::Nfc => to_nfc(key),
+            #[cfg(feature = "unicode")]
+            KeyNormalization::NfcLowercase => match to_nfc(key) {
+                Cow::Borrowed(key) => to_lowercase(key),
+                Cow::Owned(key) => Cow::Owned(to_lowercase(&key).into_owned()),
+            },
+        }
+    }
+}
+
+/// Convert to lowercase.
+fn to_lowercase(key: &str) -> Cow<'_, str> {
+    if key.chars().all(|c| c.to_lowercase().eq(std::iter::once(c))) {
+        Cow::Borrowed(key)
+    } else {
+        Cow::Owned(key.to_lowercase())
+    }
+}
+
+#[cfg(feature = "unicode")]
+fn to_nfc(key: &str) -> Cow<'_, str> {
+    use unicode_normalization::{is_nfc, UnicodeNormalization};
+    if is_nfc(key) {
+        Cow::Borrowed(key)
+    } else {
+        Cow::Owned(key.nfc().collect())
+    }
+}
+
+/// Comparison of dictionary keys on lookup.
+#[derive(PartialEq, Eq, Copy, Clone, Default)]
+pub enum KeyComparison {
+    /// Keys must match exactly.
+    #[default]
+    Exact,
+    /// Keys match regardless of case. Keys keep the casing they were written
+    /// with.
+    CaseInsensitive,
+}
+
+impl KeyComparison {
+    /// Check if two keys are equal under this comparison.
+    pub fn eq(&self, a: &str, b: &str) -> bool {
+        match self {
+            KeyComparison::Exact => a == b,
+            KeyComparison::CaseInsensitive => a.chars().flat_map(char::to_lowercase).eq(b.chars().flat_map(char::to_lowercase)),
+        }
+    }
+}
+
+/// Order of dictionary entries in [ParsedDictionary::iter_sorted].
+#[derive(PartialEq, Eq, Copy, Clone, Default, Debug)]
+pub enum KeyOrder {
+    /// Keys in lexicographic order.
+    #[default]
+    Lexicographic,
+    /// Keys in lexicographic order, except that runs of digits compare as
+    /// numbers, so that `item2` comes before `item10`.
+    Natural,
+    /// Entries in the order they were written or
+    /// [inserted](ParsedDictionary::insert), as recorded in
+    /// [ParsedDictionary::order]. Entries missing from the order come last,
+    /// in lexicographic order.
+    Source,
+}
+
+impl KeyOrder {
+    /// Compare two keys in this order. Keys compare lexicographically in
+    /// source order, since the position belongs to the value.
+    pub fn cmp(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            KeyOrder::Natural => natural_cmp(a, b).then_with(|| a.cmp(b)),
+            KeyOrder::Lexicographic | KeyOrder::Source => a.cmp(b),
+        }
+    }
+}
+
+/// Compare strings with runs of digits as numbers.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (x, y) = match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (x, y),
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let (x, rest_a) = a.split_at(a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len()));
+            let (y, rest_b) = b.split_at(b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len()));
+            let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+            let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+            if ordering != Ordering::Equal {
+                return ordering;
+            };
+            (a, b) = (rest_a, rest_b);
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            };
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        };
+    }
+}
+
 //// Value
 
 /// A parsed value.
@@ -69,6 +331,40 @@ impl ParsedValue {
         }.clone()
     }
 
+    /// Get the original text of this value in the source it was parsed from.
+    ///
+    /// Trailing whitespace is excluded. Carriage returns are not counted by
+    /// positions, so they are skipped when locating the span. Returns an empty
+    /// string if the span does not lie within the source.
+    pub fn source_slice<'a>(&self, source: &'a str) -> &'a str {
+        let from = self.from().index;
+        let to = self.to().index.max(from);
+        let mut start = None;
+        let mut end = None;
+        let mut index = 0;
+        for (offset, c) in source.char_indices() {
+            if c == '\r' {
+                continue;
+            };
+            if index == from {
+                start = Some(offset);
+            };
+            if index == to {
+                end = Some(offset);
+                break;
+            };
+            index += 1;
+        };
+        if index == to {
+            end = end.or(Some(source.len()));
+            start = start.or(end);
+        };
+        match (start, end) {
+            (Some(start), Some(end)) => source[start..end].trim_end(),
+            _ => "",
+        }
+    }
+
     pub fn from_tuple(mut values: Vec<ParsedValue>, from: Position, to: Position) -> Self {
         let len = values.len();
         if len == 0 {
@@ -85,6 +381,35 @@ impl ParsedValue {
         }
     }
 
+    /// Construct text. Positions are zero.
+    pub fn text(text: &str) -> Self {
+        ParsedValue::Text(ParsedText { str: SharedStr::from(text) }, Position::ZERO, Position::ZERO)
+    }
+
+    /// Construct a list. Positions are zero.
+    pub fn list(elements: Vec<ParsedValue>) -> Self {
+        ParsedValue::List(ParsedList { elements }, Position::ZERO, Position::ZERO)
+    }
+
+    /// Construct a dictionary. Positions are zero, and the entries are in
+    /// order.
+    ///
+    /// A later entry replaces an earlier entry with the same key.
+    pub fn dictionary(entries: Vec<(&str, ParsedValue)>) -> Self {
+        let mut dictionary = ParsedDictionary::empty();
+        for (key, value) in entries {
+            dictionary.insert(key, value);
+        };
+        ParsedValue::Dictionary(dictionary, Position::ZERO, Position::ZERO)
+    }
+
+    /// Construct a tagged value. Positions are zero.
+    pub fn tagged(name: &str, attributes: Vec<(&str, Option<&str>)>, value: ParsedValue) -> Self {
+        let attributes = attributes.into_iter().map(|(k, v)| ParsedAttribute(SharedStr::from(k), v.map(ParsedAttributeValue::from))).collect();
+        let tagged = ParsedTaggedValue { name: SharedStr::from(name), raw_name: None, attributes, value: Box::new(value) };
+        ParsedValue::Tagged(tagged, Position::ZERO, Position::ZERO)
+    }
+
     pub fn is_unit(&self) -> bool {
         matches!(self, ParsedValue::Tuple(ParsedTuple::Unit, ..))
     }
@@ -110,6 +435,18 @@ impl ParsedValue {
 
 }
 
+impl From<&str> for ParsedValue {
+    fn from(text: &str) -> Self {
+        ParsedValue::text(text)
+    }
+}
+
+impl From<String> for ParsedValue {
+    fn from(text: String) -> Self {
+        ParsedValue::text(&text)
+    }
+}
+
 impl Value<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompound, ParsedTuple, ParsedTaggedValue> for ParsedValue {
     fn is_text(&self) -> bool {
         matches!(self, ParsedValue::Text(..))
@@ -256,7 +593,7 @@ impl Value<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompound
 
 #[derive(PartialEq, Eq, Clone)]
 pub struct ParsedText {
-    pub str: Rc<str>
+    pub str: SharedStr
 }
 
 impl Text<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompound, ParsedTuple, ParsedTaggedValue> for ParsedText {
@@ -270,11 +607,23 @@ impl Text<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompound,
 /// A parsed tagged value.
 #[derive(Clone)]
 pub struct ParsedTaggedValue {
-    pub name: Rc<str>,
+    pub name: SharedStr,
+    /// The name as written in the source, if it was changed by normalization.
+    pub raw_name: Option<SharedStr>,
     pub attributes: Vec<ParsedAttribute>,
     pub value: Box<ParsedValue>,
 }
 
+impl ParsedTaggedValue {
+    /// Get the name as written in the source.
+    pub fn raw_name(&self) -> &str {
+        match &self.raw_name {
+            Some(raw_name) => raw_name,
+            None => &self.name,
+        }
+    }
+}
+
 impl Tagged<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompound, ParsedTuple, Self> for ParsedTaggedValue {
     type AttributeIterator<'b> = AttributeIterator<'b>;
 
@@ -289,24 +638,15 @@ impl Tagged<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompoun
     fn get_attribute_by(&self, key: &str) -> Option<AttributeValue<'_>> {
         for attribute in &self.attributes {
             if key.eq(attribute.key().deref()) {
-                return match attribute {
-                    ParsedAttribute(_, Some(v)) => Some(AttributeValue(Some(&v))),
-                    ParsedAttribute(_, None) => Some(AttributeValue(None)),
-                };
+                return Some(AttributeValue(attribute.1.as_ref().map(ParsedAttributeValue::joined)));
             }
         }
         None
     }
 
     fn get_attribute_at(&self, index: usize) -> Option<Attribute<'_>> {
-        if let Some(attribute) = self.attributes.get(index) {
-            match attribute {
-                ParsedAttribute(k, Some(v)) => Some(Attribute(&k, Some(&v))),
-                ParsedAttribute(k, None) => Some(Attribute(&k, None)),
-            }
-        } else {
-            None
-        }
+        let ParsedAttribute(key, value) = self.attributes.get(index)?;
+        Some(Attribute(key, value.as_ref().map(ParsedAttributeValue::joined)))
     }
 
     fn iter_attributes(&self) -> Self::AttributeIterator<'_> {
@@ -319,10 +659,61 @@ impl Tagged<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompoun
 }
 
 #[derive(Clone)]
-pub struct ParsedAttribute(pub Rc<str>, pub Option<Rc<str>>);
+pub struct ParsedAttribute(pub SharedStr, pub Option<ParsedAttributeValue>);
+
+/// The value of an attribute of a tag.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ParsedAttributeValue {
+    /// A string, like `c` of `<a b:c>`.
+    Text(SharedStr),
+    /// A list of strings, like `[c; d]` of `<a b:[c; d]>`.
+    List(Vec<SharedStr>),
+}
+
+impl ParsedAttributeValue {
+    /// Get the string of a value that is not a list.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            ParsedAttributeValue::Text(text) => Some(text),
+            ParsedAttributeValue::List(..) => None,
+        }
+    }
+
+    /// Get the strings of the value: the string, or the strings of a list.
+    pub fn strings(&self) -> &[SharedStr] {
+        match self {
+            ParsedAttributeValue::Text(text) => std::slice::from_ref(text),
+            ParsedAttributeValue::List(strings) => strings,
+        }
+    }
+
+    /// Get the value as one string, with the strings of a list joined by
+    /// spaces, like a list of classes.
+    pub fn joined(&self) -> Cow<'_, str> {
+        match self {
+            ParsedAttributeValue::Text(text) => Cow::Borrowed(text),
+            ParsedAttributeValue::List(strings) => Cow::Owned(strings.join(" ")),
+        }
+    }
+}
+
+impl Debug for ParsedAttributeValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParsedAttributeValue::Text(text) => Debug::fmt(text, f),
+            ParsedAttributeValue::List(strings) => f.debug_list().entries(strings).finish(),
+        }
+    }
+}
+
+impl From<&str> for ParsedAttributeValue {
+    fn from(text: &str) -> Self {
+        ParsedAttributeValue::Text(SharedStr::from(text))
+    }
+}
 
 impl ParsedAttribute {
-    fn key(&self) -> Rc<str> {
+    fn key(&self) -> SharedStr {
         self.0.clone()
     }
 }
@@ -335,15 +726,8 @@ impl<'a> Iterator for AttributeIterator<'a> {
     type Item = Attribute<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(ParsedAttribute(key, value)) = self.iter.next() {
-            if let Some(value) = value {
-                Some(Attribute(key, Some(value)))
-            } else {
-                Some(Attribute(key, None))
-            }
-        } else {
-            None
-        }
+        let ParsedAttribute(key, value) = self.iter.next()?;
+        Some(Attribute(key, value.as_ref().map(ParsedAttributeValue::joined)))
     }
 }
 
@@ -393,6 +777,26 @@ impl Tuple<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompound
     }
 }
 
+impl ParsedTuple {
+    /// Move the elements out of this tuple.
+    pub fn into_elements(self) -> Vec<ParsedValue> {
+        match self {
+            ParsedTuple::Unit => vec![],
+            ParsedTuple::Single(v) => vec![*v],
+            ParsedTuple::Multiple(m) => m.into_vec(),
+        }
+    }
+}
+
+impl IntoIterator for ParsedTuple {
+    type Item = ParsedValue;
+    type IntoIter = std::vec::IntoIter<ParsedValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_elements().into_iter()
+    }
+}
+
 pub enum TupleIterator<'a> {
     Unit,
     Single(bool, &'a ParsedValue),
@@ -427,15 +831,243 @@ impl<'a> Iterator for TupleIterator<'a> {
 /// A parsed dictionary.
 #[derive(Clone)]
 pub struct ParsedDictionary {
-    pub entries: HashMap<Rc<str>, ParsedValue>,
+    pub entries: HashMap<SharedStr, ParsedValue>,
+    /// Keys as written in the source, for the keys changed by normalization.
+    pub raw_keys: HashMap<SharedStr, SharedStr>,
+    /// Normalization applied to keys, both when parsing and on lookup.
+    pub normalization: KeyNormalization,
+    /// Comparison of keys on lookup.
+    pub comparison: KeyComparison,
+    /// Keys in the order they were parsed or inserted with
+    /// [ParsedDictionary::insert], see [KeyOrder::Source].
+    pub order: Vec<SharedStr>,
 }
 
 impl ParsedDictionary {
     pub fn empty() -> Self {
-        ParsedDictionary { entries: HashMap::new() }
+        ParsedDictionary {
+            entries: HashMap::new(), raw_keys: HashMap::new(),
+            normalization: KeyNormalization::None, comparison: KeyComparison::Exact,
+            order: vec![],
+        }
+    }
+
+    /// Insert an entry, replacing the entry at a matching key.
+    ///
+    /// The key is normalized, and a new key is ordered after the other keys.
+    /// Returns the replaced value.
+    pub fn insert(&mut self, key: &str, value: ParsedValue) -> Option<ParsedValue> {
+        if let Some(key) = self.find_key(key).cloned() {
+            return self.entries.insert(key, value);
+        };
+        let normalized = SharedStr::from(self.normalization.apply(key).as_ref());
+        if normalized.as_ref() != key {
+            self.raw_keys.insert(normalized.clone(), SharedStr::from(key));
+        };
+        self.order.push(normalized.clone());
+        self.entries.insert(normalized, value)
+    }
+
+    /// Remove the entry at a key.
+    pub fn remove(&mut self, key: &str) -> Option<ParsedValue> {
+        let key = self.find_key(key)?.clone();
+        self.raw_keys.remove(&key);
+        self.order.retain(|k| *k != key);
+        self.entries.remove(&key)
+    }
+
+    /// Empty dictionary with the same key normalization and comparison as
+    /// this dictionary.
+    pub fn empty_like(&self) -> Self {
+        ParsedDictionary { normalization: self.normalization, comparison: self.comparison, ..ParsedDictionary::empty() }
+    }
+
+    /// Find the stored key matching a key.
+    pub fn find_key(&self, key: &str) -> Option<&SharedStr> {
+        let key = self.normalization.apply(key);
+        if let Some((key, _)) = self.entries.get_key_value(key.as_ref()) {
+            return Some(key);
+        }
+        match self.comparison {
+            KeyComparison::Exact => None,
+            comparison => self.first_key(|k| comparison.eq(k, &key)),
+        }
+    }
+
+    /// Find the first key in source order that matches a predicate.
+    fn first_key<P: Fn(&str) -> bool>(&self, predicate: P) -> Option<&SharedStr> {
+        self.iter_sorted(KeyOrder::Source)
+            .find(|(key, _)| predicate(key))
+            .and_then(|(key, _)| self.entries.get_key_value(key))
+            .map(|(key, _)| key)
+    }
+
+    /// Get the entry at a key, ignoring case.
+    ///
+    /// An exact match is preferred. Otherwise, if several keys differ from the
+    /// key only in case, the entry of the first of them in source order is
+    /// returned. Case is ignored by comparing keys converted to lowercase, as
+    /// in [KeyComparison::CaseInsensitive], which is not full Unicode case
+    /// folding: `straße` does not match `STRASSE`.
+    pub fn get_ci(&self, key: &str) -> Option<&ParsedValue> {
+        self.get(key).or_else(|| {
+            let key = self.first_key(|k| KeyComparison::CaseInsensitive.eq(k, key))?;
+            self.entries.get(key)
+        })
+    }
+
+    /// Get a key as it was written in the source.
+    ///
+    /// Returns `None` if there is no entry at the key.
+    pub fn raw_key(&self, key: &str) -> Option<&str> {
+        let key = self.find_key(key)?;
+        match self.raw_keys.get(key) {
+            Some(raw_key) => Some(raw_key),
+            None => Some(key),
+        }
+    }
+
+    /// Merge another dictionary into this dictionary.
+    ///
+    /// Entries of the other dictionary are added in its source order, and
+    /// their keys are normalized as in [ParsedDictionary::insert]. Where both
+    /// dictionaries have a dictionary at a key, they are merged recursively.
+    /// Lists are appended or replaced as configured. Other values at the same
+    /// key are conflicts, resolved by the strategy.
+    pub fn merge(&mut self, other: &ParsedDictionary, strategy: &MergeStrategy) {
+        self.merge_at(&Path::root(), other, strategy);
+    }
+
+    fn merge_at(&mut self, path: &Path, other: &ParsedDictionary, strategy: &MergeStrategy) {
+        for (key, theirs) in other.iter_sorted(KeyOrder::Source) {
+            let key = other.raw_keys.get(key).map_or(key, |raw_key| raw_key);
+            let key = match self.find_key(key) {
+                Some(key) => key.clone(),
+                None => {
+                    self.insert(key, theirs.clone());
+                    continue;
+                }
+            };
+            let path = path.join(PathSegment::Key(key.to_string()));
+            let ours = self.entries.get_mut(&key).unwrap();
+            match (ours, theirs) {
+                (ParsedValue::Dictionary(ours, ..), ParsedValue::Dictionary(theirs, ..)) => {
+                    ours.merge_at(&path, theirs, strategy);
+                }
+                (ParsedValue::List(ours, ..), ParsedValue::List(theirs, ..)) if strategy.lists == ListMerge::Append => {
+                    ours.elements.extend(theirs.elements.iter().cloned());
+                }
+                (ours, theirs) => {
+                    let resolution = match &strategy.on_conflict {
+                        Some(on_conflict) => on_conflict(&path, ours, theirs),
+                        None => Resolution::Replace,
+                    };
+                    match resolution {
+                        Resolution::Keep => {}
+                        Resolution::Replace => *ours = theirs.clone(),
+                        Resolution::Value(value) => *ours = value,
+                    };
+                }
+            };
+        };
+    }
+
+    /// Keep the entries for which a predicate returns `true`.
+    ///
+    /// The predicate takes the stored key. Kept entries are unchanged,
+    /// including their positions.
+    pub fn retain<F: FnMut(&str, &mut ParsedValue) -> bool>(&mut self, mut f: F) {
+        self.entries.retain(|key, value| f(key, value));
+        let entries = &self.entries;
+        self.raw_keys.retain(|key, _| entries.contains_key(key));
+        self.order.retain(|key| entries.contains_key(key));
+    }
+
+    /// Map the entries to new values, dropping those for which the function
+    /// returns `None`.
+    ///
+    /// The result has the same key normalization, comparison and raw keys.
+    pub fn filter_map<F: FnMut(&str, &ParsedValue) -> Option<ParsedValue>>(&self, mut f: F) -> ParsedDictionary {
+        let mut dictionary = self.empty_like();
+        for (key, value) in &self.entries {
+            if let Some(value) = f(key, value) {
+                dictionary.entries.insert(key.clone(), value);
+                if let Some(raw_key) = self.raw_keys.get(key) {
+                    dictionary.raw_keys.insert(key.clone(), raw_key.clone());
+                };
+            };
+        };
+        dictionary.order = self.order.iter().filter(|key| dictionary.entries.contains_key(*key)).cloned().collect();
+        dictionary
+    }
+
+    /// Iterate over the entries in an order.
+    ///
+    /// Yields the stored keys, see [ParsedDictionary::raw_key] for the keys
+    /// as written.
+    pub fn iter_sorted(&self, order: KeyOrder) -> SortedEntryIterator<'_> {
+        let mut entries: Vec<(&str, &ParsedValue)> = self.entries.iter().map(|(key, value)| (key.as_ref(), value)).collect();
+        match order {
+            KeyOrder::Source => {
+                let ranks: HashMap<&str, usize> = self.order.iter().enumerate().map(|(i, key)| (key.as_ref(), i)).collect();
+                let rank = |key: &str| ranks.get(key).copied().unwrap_or(usize::MAX);
+                entries.sort_by(|(a, _), (b, _)| rank(a).cmp(&rank(b)).then_with(|| a.cmp(b)))
+            }
+            order => entries.sort_by(|(a, _), (b, _)| order.cmp(a, b)),
+        };
+        SortedEntryIterator(entries.into_iter())
+    }
+
+    /// Move the entries out of this dictionary.
+    pub fn into_entries(self) -> HashMap<SharedStr, ParsedValue> {
+        self.entries
+    }
+}
+
+impl IntoIterator for ParsedDictionary {
+    type Item = (SharedStr, ParsedValue);
+    type IntoIter = std::collections::hash_map::IntoIter<SharedStr, ParsedValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
     }
 }
 
+/// How to merge dictionaries with [ParsedDictionary::merge].
+#[derive(Default)]
+pub struct MergeStrategy {
+    /// How to merge lists at the same key.
+    pub lists: ListMerge,
+    /// Resolves conflicting values at the same key. The arguments are the path
+    /// of the conflict, the value in the dictionary merged into and the value
+    /// in the dictionary merged from. The value merged from replaces the
+    /// other value by default.
+    pub on_conflict: Option<Box<ConflictResolver>>,
+}
+
+/// Resolves a merge conflict, see [MergeStrategy::on_conflict].
+pub type ConflictResolver = dyn Fn(&Path, &ParsedValue, &ParsedValue) -> Resolution;
+
+/// How to merge lists at the same key.
+#[derive(PartialEq, Eq, Copy, Clone, Default)]
+pub enum ListMerge {
+    /// The list merged from replaces the list merged into.
+    #[default]
+    Replace,
+    /// The elements of the list merged from are appended.
+    Append,
+}
+
+/// Resolution of a merge conflict.
+pub enum Resolution {
+    /// Keep the value merged into.
+    Keep,
+    /// Replace with the value merged from.
+    Replace,
+    /// Replace with another value.
+    Value(ParsedValue),
+}
+
 impl Dictionary<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCompound, ParsedTuple, ParsedTaggedValue> for ParsedDictionary {
     type EntryIterator<'b> = EntryIterator<'b>;
 
@@ -448,6 +1080,7 @@ impl Dictionary<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCom
     }
 
     fn get(&self, key: &str) -> Option<&ParsedValue> {
+        let key = self.find_key(key)?;
         if let Some(entry) = self.entries.get(key) {
             Some(entry)
         } else {
@@ -456,7 +1089,8 @@ impl Dictionary<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCom
     }
 
     fn get_mut(&mut self, key: &str) -> Option<&mut ParsedValue> {
-        if let Some(entry) = self.entries.get_mut(key) {
+        let key = self.find_key(key)?.clone();
+        if let Some(entry) = self.entries.get_mut(&key) {
             Some(entry)
         } else {
             None
@@ -468,7 +1102,7 @@ impl Dictionary<ParsedValue, ParsedText, ParsedDictionary, ParsedList, ParsedCom
     }
 }
 
-pub struct EntryIterator<'a>(std::collections::hash_map::Iter<'a, Rc<str>, ParsedValue>);
+pub struct EntryIterator<'a>(std::collections::hash_map::Iter<'a, SharedStr, ParsedValue>);
 
 impl<'a> Iterator for EntryIterator<'a> {
     type Item = (&'a str, &'a ParsedValue);
@@ -482,6 +1116,16 @@ impl<'a> Iterator for EntryIterator<'a> {
     }
 }
 
+pub struct SortedEntryIterator<'a>(std::vec::IntoIter<(&'a str, &'a ParsedValue)>);
+
+impl<'a> Iterator for SortedEntryIterator<'a> {
+    type Item = (&'a str, &'a ParsedValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
 //// List
 
 /// A parsed list.
@@ -495,6 +1139,31 @@ impl ParsedList {
     pub fn empty() -> Self {
         ParsedList { elements: vec![] }
     }
+
+    /// Move the elements out of this list.
+    pub fn into_elements(self) -> Vec<ParsedValue> {
+        self.elements
+    }
+
+    /// Keep the elements for which a predicate returns `true`, in order.
+    pub fn retain<F: FnMut(&mut ParsedValue) -> bool>(&mut self, f: F) {
+        self.elements.retain_mut(f);
+    }
+
+    /// Map the elements to new values, dropping those for which the function
+    /// returns `None`.
+    pub fn filter_map<F: FnMut(&ParsedValue) -> Option<ParsedValue>>(&self, f: F) -> ParsedList {
+        ParsedList { elements: self.elements.iter().filter_map(f).collect() }
+    }
+}
+
+impl IntoIterator for ParsedList {
+    type Item = ParsedValue;
+    type IntoIter = std::vec::IntoIter<ParsedValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.into_iter()
+    }
 }
 
 impl List<ParsedValue, ParsedText, ParsedDictionary, Self, ParsedCompound, ParsedTuple, ParsedTaggedValue> for ParsedList {
@@ -525,17 +1194,72 @@ pub struct ParsedCompound {
     pub whitespace: Vec<bool>,
 }
 
-impl ParsedCompound {}
+impl ParsedCompound {
+    /// Keep the components for which a predicate returns `true`, in order.
+    ///
+    /// Components that were separated by whitespace on either side of a
+    /// removed component remain separated by whitespace. Like
+    /// [ParsedValue::from_terms], the result is nil if no component is kept,
+    /// the component if one is kept, and otherwise a compound.
+    pub fn retain<F: FnMut(&mut ParsedValue) -> bool>(self, mut f: F) -> ParsedValue {
+        let (from, to) = self.span();
+        let components = self.components.into_iter().map(|mut c| if f(&mut c) { Some(c) } else { None });
+        ParsedCompound::from_kept(components, &self.whitespace, from, to)
+    }
+
+    /// Map the components to new values, dropping those for which the
+    /// function returns `None`.
+    ///
+    /// Whitespace and the result are as with [ParsedCompound::retain].
+    pub fn filter_map<F: FnMut(&ParsedValue) -> Option<ParsedValue>>(&self, f: F) -> ParsedValue {
+        let (from, to) = self.span();
+        ParsedCompound::from_kept(self.components.iter().map(f), &self.whitespace, from, to)
+    }
+
+    /// The positions of the first and the last component.
+    fn span(&self) -> (Position, Position) {
+        let from = self.components.first().map_or(Position::ZERO, ParsedValue::from);
+        let to = self.components.last().map_or(Position::ZERO, ParsedValue::to);
+        (from, to)
+    }
+
+    fn from_kept<I: Iterator<Item=Option<ParsedValue>>>(kept: I, whitespace: &[bool], from: Position, to: Position) -> ParsedValue {
+        let mut components = vec![];
+        let mut spaces = vec![];
+        let mut space = false;
+        for (i, component) in kept.enumerate() {
+            if i != 0 {
+                space |= whitespace[i - 1];
+            };
+            if let Some(component) = component {
+                if !components.is_empty() {
+                    spaces.push(space);
+                };
+                components.push(component);
+                space = false;
+            };
+        };
+        ParsedValue::from_terms(from, to, components, spaces)
+    }
+}
 
 impl Compound<ParsedValue, ParsedText, ParsedDictionary, ParsedList, Self, ParsedTuple, ParsedTaggedValue> for ParsedCompound {
     type ElementIterator<'b> = ElementIterator<'b>;
+    type ComponentIterator<'b> = ComponentIterator<'b>;
 
     fn len(&self) -> usize {
-        self.components.len() + self.whitespace.len()
+        self.components.len() + self.whitespace.iter().filter(|w| **w).count()
+    }
+
+    fn element_count(&self) -> usize {
+        self.components.len()
     }
 
-    fn get(&self, _index: usize) -> Option<Element<&ParsedValue>> {
-        todo!()
+    fn get(&self, index: usize) -> Option<Component<&ParsedValue>> {
+        let value = self.components.get(index)?;
+        let whitespace_before = index != 0 && self.whitespace[index - 1];
+        let whitespace_after = self.whitespace.get(index).copied().unwrap_or(false);
+        Some(Component { value, whitespace_before, whitespace_after })
     }
 
     fn iter(&self) -> Self::ElementIterator<'_> {
@@ -546,6 +1270,25 @@ impl Compound<ParsedValue, ParsedText, ParsedDictionary, ParsedList, Self, Parse
             after_component: false,
         }
     }
+
+    fn iter_with_separators(&self) -> Self::ComponentIterator<'_> {
+        ComponentIterator { compound: self, index: 0 }
+    }
+}
+
+pub struct ComponentIterator<'b> {
+    compound: &'b ParsedCompound,
+    index: usize,
+}
+
+impl<'b> Iterator for ComponentIterator<'b> {
+    type Item = Component<&'b ParsedValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let component = self.compound.get(self.index)?;
+        self.index += 1;
+        Some(component)
+    }
 }
 
 pub struct ElementIterator<'b> {
@@ -559,6 +1302,9 @@ impl<'b> Iterator for ElementIterator<'b> {
     type Item = Element<&'b ParsedValue>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.components.is_empty() {
+            return None;
+        };
         if self.index != self.components.len() - 1 {
             if self.after_component {
                 let whitespace = self.whitespace[self.index];
@@ -577,3 +1323,1001 @@ impl<'b> Iterator for ElementIterator<'b> {
         Some(Element::Element(&self.components[self.index]))
     }
 }
+
+//// Conversion
+
+/// An error converting a parsed value to a Rust type.
+#[derive(PartialEq, Eq, Clone)]
+pub enum ConversionError {
+    /// Expected a kind of value, such as text or a list, at a position.
+    Expected(&'static str, Position),
+    /// Text at a position is not a valid value of a type.
+    Invalid(&'static str, Position, String),
+}
+
+impl ConversionError {
+    /// Position of the value that could not be converted.
+    pub fn at(&self) -> Position {
+        match self {
+            ConversionError::Expected(_, at) => *at,
+            ConversionError::Invalid(_, at, _) => *at,
+        }
+    }
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversionError::Expected(expected, at) => write!(f, "Expected {} at {}:{}.", expected, at.line, at.column),
+            ConversionError::Invalid(ty, at, text) => write!(f, "Invalid {} ⟨{}⟩ at {}:{}.", ty, text, at.line, at.column),
+        }
+    }
+}
+
+impl Debug for ConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl ParsedValue {
+    /// Get the text of a text value, or an error at this value.
+    fn expect_text(&self) -> Result<&str, ConversionError> {
+        match self {
+            ParsedValue::Text(text, ..) => Ok(&text.str),
+            _ => Err(ConversionError::Expected("text", self.from())),
+        }
+    }
+}
+
+impl TryFrom<&ParsedValue> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: &ParsedValue) -> Result<Self, Self::Error> {
+        value.expect_text().map(String::from)
+    }
+}
+
+impl TryFrom<&ParsedValue> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: &ParsedValue) -> Result<Self, Self::Error> {
+        value.to_bool_with(&BoolFormat::default())
+    }
+}
+
+macro_rules! impl_try_from_str {
+    ($($ty:ident),*) => {
+        $(
+            impl TryFrom<&ParsedValue> for $ty {
+                type Error = ConversionError;
+
+                /// Convert text in the default [NumberFormat].
+                fn try_from(value: &ParsedValue) -> Result<Self, Self::Error> {
+                    value.to_number_with(&NumberFormat::default())
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from_str!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+#[cfg(feature = "decimal")]
+impl_try_from_str!(Decimal);
+
+impl<T> TryFrom<&ParsedValue> for Vec<T> where for<'a> T: TryFrom<&'a ParsedValue, Error = ConversionError> {
+    type Error = ConversionError;
+
+    /// Convert the elements of a list or tuple.
+    fn try_from(value: &ParsedValue) -> Result<Self, Self::Error> {
+        match value {
+            ParsedValue::List(list, ..) => list.elements.iter().map(T::try_from).collect(),
+            ParsedValue::Tuple(tuple, ..) => tuple.iter().map(T::try_from).collect(),
+            _ => Err(ConversionError::Expected("list", value.from())),
+        }
+    }
+}
+
+//// Canonical form
+
+impl ParsedValue {
+    /// Hash this value, ignoring positions and the order of dictionary entries
+    /// and tag attributes.
+    ///
+    /// Values that are [canonically equal](ParsedValue::canonical_eq) have the
+    /// same hash. The hash is 64-bit FNV-1a, so it is the same in every
+    /// process and on every platform, and can be stored.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut hasher = FnvHasher::default();
+        Canonical(self).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Check if two values are equal, ignoring positions and the order of
+    /// dictionary entries and tag attributes.
+    pub fn canonical_eq(&self, other: &ParsedValue) -> bool {
+        match (self, other) {
+            (ParsedValue::Nil(..), ParsedValue::Nil(..)) => true,
+            (ParsedValue::Text(a, ..), ParsedValue::Text(b, ..)) => a.str == b.str,
+            (ParsedValue::Tagged(a, ..), ParsedValue::Tagged(b, ..)) => {
+                a.name == b.name && sorted_attributes(a) == sorted_attributes(b) && a.value.canonical_eq(&b.value)
+            }
+            (ParsedValue::Tuple(a, ..), ParsedValue::Tuple(b, ..)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.canonical_eq(b))
+            }
+            (ParsedValue::Dictionary(a, ..), ParsedValue::Dictionary(b, ..)) => {
+                a.entries.len() == b.entries.len() && a.entries.iter().all(|(k, a)| b.entries.get(k).map_or(false, |b| a.canonical_eq(b)))
+            }
+            (ParsedValue::List(a, ..), ParsedValue::List(b, ..)) => {
+                a.elements.len() == b.elements.len() && a.elements.iter().zip(&b.elements).all(|(a, b)| a.canonical_eq(b))
+            }
+            (ParsedValue::Compound(a, ..), ParsedValue::Compound(b, ..)) => {
+                a.whitespace == b.whitespace && a.components.len() == b.components.len()
+                    && a.components.iter().zip(&b.components).all(|(a, b)| a.canonical_eq(b))
+            }
+            _ => false,
+        }
+    }
+}
+
+fn sorted_attributes(tagged: &ParsedTaggedValue) -> Vec<(&str, Option<&ParsedAttributeValue>)> {
+    let mut attributes: Vec<(&str, Option<&ParsedAttributeValue>)> = tagged.attributes.iter().map(|a| (a.0.as_ref(), a.1.as_ref())).collect();
+    attributes.sort();
+    attributes
+}
+
+/// A value compared and hashed in canonical form.
+///
+/// Ignores positions and the order of dictionary entries and tag attributes,
+/// so it can be used as a key in hash maps and sets.
+#[derive(Clone, Copy)]
+pub struct Canonical<'a>(pub &'a ParsedValue);
+
+impl PartialEq for Canonical<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.canonical_eq(other.0)
+    }
+}
+
+impl Eq for Canonical<'_> {}
+
+impl Hash for Canonical<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0 {
+            ParsedValue::Nil(..) => state.write_u8(0),
+            ParsedValue::Text(text, ..) => {
+                state.write_u8(1);
+                hash_str(&text.str, state);
+            }
+            ParsedValue::Tagged(tagged, ..) => {
+                state.write_u8(2);
+                hash_str(&tagged.name, state);
+                let attributes = sorted_attributes(tagged);
+                state.write_usize(attributes.len());
+                for (name, value) in attributes {
+                    hash_str(name, state);
+                    match value {
+                        None => state.write_u8(0),
+                        Some(ParsedAttributeValue::Text(text)) => {
+                            state.write_u8(1);
+                            hash_str(text, state);
+                        }
+                        Some(ParsedAttributeValue::List(strings)) => {
+                            state.write_u8(2);
+                            state.write_usize(strings.len());
+                            for string in strings {
+                                hash_str(string, state);
+                            };
+                        }
+                    };
+                };
+                Canonical(&tagged.value).hash(state);
+            }
+            ParsedValue::Tuple(tuple, ..) => {
+                state.write_u8(3);
+                state.write_usize(tuple.len());
+                for value in tuple.iter() {
+                    Canonical(value).hash(state);
+                };
+            }
+            ParsedValue::Dictionary(dictionary, ..) => {
+                state.write_u8(4);
+                state.write_usize(dictionary.entries.len());
+                for (key, value) in dictionary.iter_sorted(KeyOrder::Lexicographic) {
+                    hash_str(key, state);
+                    Canonical(value).hash(state);
+                };
+            }
+            ParsedValue::List(list, ..) => {
+                state.write_u8(5);
+                state.write_usize(list.elements.len());
+                for value in &list.elements {
+                    Canonical(value).hash(state);
+                };
+            }
+            ParsedValue::Compound(compound, ..) => {
+                state.write_u8(6);
+                state.write_usize(compound.components.len());
+                for whitespace in &compound.whitespace {
+                    state.write_u8(*whitespace as u8);
+                };
+                for value in &compound.components {
+                    Canonical(value).hash(state);
+                };
+            }
+        }
+    }
+}
+
+/// Hash a string with its length, independently of how the standard library
+/// hashes strings.
+fn hash_str<H: Hasher>(string: &str, state: &mut H) {
+    state.write_usize(string.len());
+    state.write(string.as_bytes());
+}
+
+/// 64-bit FNV-1a, with `usize` written as 64 bits on every platform.
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        };
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write(&(i as u64).to_le_bytes());
+    }
+}
+
+//// Path
+
+/// A path to a value within a document.
+///
+/// Displayed with keys separated by colons, indices in square brackets, tagged
+/// value contents as `<>` and attributes prefixed with `@`, like
+/// `servers[2]:port`.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct Path(pub Vec<PathSegment>);
+
+/// A segment of a [Path].
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum PathSegment {
+    /// Entry of a dictionary.
+    Key(String),
+    /// Element of a list or tuple.
+    Index(usize),
+    /// Content of a tagged value.
+    Content,
+    /// Attribute of a tagged value.
+    Attribute(String),
+}
+
+impl Path {
+    /// Path to the root of a document.
+    pub fn root() -> Self {
+        Path(vec![])
+    }
+
+    /// Path extended with a segment.
+    pub fn join(&self, segment: PathSegment) -> Self {
+        let mut path = self.clone();
+        path.0.push(segment);
+        path
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                PathSegment::Key(key) => {
+                    if i != 0 {
+                        write!(f, ":")?;
+                    };
+                    write!(f, "{}", key)?;
+                }
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+                PathSegment::Content => write!(f, "<>")?,
+                PathSegment::Attribute(name) => write!(f, "@{}", name)?,
+            };
+        };
+        Ok(())
+    }
+}
+
+//// Diff
+
+/// A difference between two values.
+///
+/// Refers to the values in the compared documents, which carry their spans.
+pub enum DiffOp<'a> {
+    /// A value at a path only exists in the new document.
+    Added(Path, &'a ParsedValue),
+    /// A value at a path only exists in the old document.
+    Removed(Path, &'a ParsedValue),
+    /// A value at a path was changed from the old to the new value.
+    Changed(Path, &'a ParsedValue, &'a ParsedValue),
+    /// The tagged value at a path was renamed.
+    TagRenamed(Path, &'a ParsedValue, &'a ParsedValue),
+    /// An attribute of the tagged value at a path changed. The attribute value
+    /// is `None` if the attribute does not exist, and `Some(None)` if it has
+    /// no value.
+    AttributeChanged(Path, &'a str, Option<Option<&'a ParsedAttributeValue>>, Option<Option<&'a ParsedAttributeValue>>),
+}
+
+impl DiffOp<'_> {
+    /// Path of the changed value.
+    pub fn path(&self) -> &Path {
+        match self {
+            DiffOp::Added(path, ..) => path,
+            DiffOp::Removed(path, ..) => path,
+            DiffOp::Changed(path, ..) => path,
+            DiffOp::TagRenamed(path, ..) => path,
+            DiffOp::AttributeChanged(path, ..) => path,
+        }
+    }
+}
+
+/// Compute the structural differences from an old value to a new value.
+///
+/// Positions, whitespace and the order of dictionary entries and attributes
+/// are ignored. Lists and tuples are compared element by element.
+pub fn diff<'a>(old: &'a ParsedValue, new: &'a ParsedValue) -> Vec<DiffOp<'a>> {
+    let mut ops = vec![];
+    diff_at(&Path::root(), old, new, &mut ops);
+    ops
+}
+
+fn diff_at<'a>(path: &Path, old: &'a ParsedValue, new: &'a ParsedValue, ops: &mut Vec<DiffOp<'a>>) {
+    match (old, new) {
+        (ParsedValue::Dictionary(a, ..), ParsedValue::Dictionary(b, ..)) => {
+            let mut keys: Vec<&SharedStr> = a.entries.keys().chain(b.entries.keys().filter(|k| !a.entries.contains_key(*k))).collect();
+            keys.sort();
+            for key in keys {
+                let path = path.join(PathSegment::Key(key.to_string()));
+                match (a.entries.get(key), b.entries.get(key)) {
+                    (Some(a), Some(b)) => diff_at(&path, a, b, ops),
+                    (Some(a), None) => ops.push(DiffOp::Removed(path, a)),
+                    (None, Some(b)) => ops.push(DiffOp::Added(path, b)),
+                    (None, None) => {}
+                };
+            };
+        }
+        (ParsedValue::List(a, ..), ParsedValue::List(b, ..)) => {
+            diff_elements(path, a.elements.iter(), b.elements.iter(), ops);
+        }
+        (ParsedValue::Tuple(a, ..), ParsedValue::Tuple(b, ..)) => {
+            diff_elements(path, a.iter(), b.iter(), ops);
+        }
+        (ParsedValue::Tagged(a, ..), ParsedValue::Tagged(b, ..)) => {
+            if a.name != b.name {
+                ops.push(DiffOp::TagRenamed(path.clone(), old, new));
+            };
+            let mut names: Vec<&str> = a.attributes.iter().chain(b.attributes.iter()).map(|a| a.0.as_ref()).collect();
+            names.sort();
+            names.dedup();
+            for name in names {
+                let find = |tagged: &'a ParsedTaggedValue| tagged.attributes.iter().find(|a| a.0.as_ref() == name).map(|a| a.1.as_ref());
+                let (x, y) = (find(a), find(b));
+                if x != y {
+                    ops.push(DiffOp::AttributeChanged(path.clone(), name, x, y));
+                };
+            };
+            diff_at(&path.join(PathSegment::Content), &a.value, &b.value, ops);
+        }
+        (ParsedValue::Nil(..), ParsedValue::Nil(..)) => {}
+        _ => {
+            if !old.canonical_eq(new) {
+                ops.push(DiffOp::Changed(path.clone(), old, new));
+            };
+        }
+    };
+}
+
+fn diff_elements<'a>(path: &Path, mut a: impl Iterator<Item = &'a ParsedValue>, mut b: impl Iterator<Item = &'a ParsedValue>, ops: &mut Vec<DiffOp<'a>>) {
+    let mut index = 0;
+    loop {
+        let path = path.join(PathSegment::Index(index));
+        match (a.next(), b.next()) {
+            (Some(a), Some(b)) => diff_at(&path, a, b, ops),
+            (Some(a), None) => ops.push(DiffOp::Removed(path, a)),
+            (None, Some(b)) => ops.push(DiffOp::Added(path, b)),
+            (None, None) => break,
+        };
+        index += 1;
+    };
+}
+
+//// Patch
+
+/// An operation of a patch, see [ParsedValue::patch].
+#[derive(Clone)]
+pub enum PatchOp {
+    /// Set the value at a path. A missing key of a dictionary is added.
+    Set(Path, ParsedValue),
+    /// Remove an entry of a dictionary or an element of a list.
+    Remove(Path),
+    /// Insert an element into a list before an index, or at the end if the
+    /// index is the length of the list.
+    Insert(Path, ParsedValue),
+}
+
+/// An error applying a patch.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum PatchError {
+    /// There is no value at a path.
+    NotFound(Path),
+    /// An operation does not apply to a path, like removing the content of a
+    /// tagged value or inserting into a dictionary.
+    Invalid(Path),
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::NotFound(path) => write!(f, "No value at ⟨{}⟩.", path),
+            PatchError::Invalid(path) => write!(f, "Invalid patch at ⟨{}⟩.", path),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl ParsedValue {
+    /// Get the value at a path.
+    pub fn get_at(&self, path: &Path) -> Option<&ParsedValue> {
+        path.0.iter().try_fold(self, |value, segment| match (value, segment) {
+            (ParsedValue::Dictionary(dictionary, ..), PathSegment::Key(key)) => dictionary.get(key),
+            (ParsedValue::List(list, ..), PathSegment::Index(index)) => list.elements.get(*index),
+            (ParsedValue::Tuple(tuple, ..), PathSegment::Index(index)) => tuple.get(*index),
+            (ParsedValue::Tagged(tagged, ..), PathSegment::Content) => Some(tagged.value.as_ref()),
+            _ => None,
+        })
+    }
+
+    /// Get the value at a path for modification.
+    pub fn get_at_mut(&mut self, path: &Path) -> Option<&mut ParsedValue> {
+        path.0.iter().try_fold(self, |value, segment| match (value, segment) {
+            (ParsedValue::Dictionary(dictionary, ..), PathSegment::Key(key)) => dictionary.get_mut(key),
+            (ParsedValue::List(list, ..), PathSegment::Index(index)) => list.elements.get_mut(*index),
+            (ParsedValue::Tuple(ParsedTuple::Single(value), ..), PathSegment::Index(0)) => Some(value.as_mut()),
+            (ParsedValue::Tuple(ParsedTuple::Multiple(values), ..), PathSegment::Index(index)) => values.get_mut(*index),
+            (ParsedValue::Tagged(tagged, ..), PathSegment::Content) => Some(tagged.value.as_mut()),
+            _ => None,
+        })
+    }
+
+    /// Set the value at a path.
+    ///
+    /// A missing key of a dictionary is added. Returns the replaced value.
+    pub fn set_at(&mut self, path: &Path, value: ParsedValue) -> Result<Option<ParsedValue>, PatchError> {
+        let (parent, last) = match path.0.split_last() {
+            Some((last, parent)) => (Path(parent.to_vec()), last),
+            None => return Ok(Some(std::mem::replace(self, value))),
+        };
+        let target = self.get_at_mut(&parent).ok_or_else(|| PatchError::NotFound(parent.clone()))?;
+        if let (ParsedValue::Dictionary(dictionary, ..), PathSegment::Key(key)) = (&mut *target, last) {
+            return Ok(dictionary.insert(key, value));
+        };
+        match target.get_at_mut(&Path(vec![last.clone()])) {
+            Some(target) => Ok(Some(std::mem::replace(target, value))),
+            None => Err(PatchError::NotFound(path.clone())),
+        }
+    }
+
+    /// Remove an entry of a dictionary or an element of a list at a path.
+    ///
+    /// Returns the removed value.
+    pub fn remove_at(&mut self, path: &Path) -> Result<ParsedValue, PatchError> {
+        let (last, parent) = path.0.split_last().ok_or_else(|| PatchError::Invalid(path.clone()))?;
+        let parent = Path(parent.to_vec());
+        match (self.get_at_mut(&parent), last) {
+            (Some(ParsedValue::Dictionary(dictionary, ..)), PathSegment::Key(key)) => {
+                dictionary.remove(key).ok_or_else(|| PatchError::NotFound(path.clone()))
+            }
+            (Some(ParsedValue::List(list, ..)), PathSegment::Index(index)) if *index < list.elements.len() => {
+                Ok(list.elements.remove(*index))
+            }
+            (Some(ParsedValue::List(..)), PathSegment::Index(_)) => Err(PatchError::NotFound(path.clone())),
+            (Some(_), _) => Err(PatchError::Invalid(path.clone())),
+            (None, _) => Err(PatchError::NotFound(parent)),
+        }
+    }
+
+    /// Insert an element into a list at a path that ends in an index.
+    ///
+    /// The index may be the length of the list, to append.
+    pub fn insert_at(&mut self, path: &Path, value: ParsedValue) -> Result<(), PatchError> {
+        let (last, parent) = path.0.split_last().ok_or_else(|| PatchError::Invalid(path.clone()))?;
+        let parent = Path(parent.to_vec());
+        match (self.get_at_mut(&parent), last) {
+            (Some(ParsedValue::List(list, ..)), PathSegment::Index(index)) if *index <= list.elements.len() => {
+                list.elements.insert(*index, value);
+                Ok(())
+            }
+            (Some(ParsedValue::List(..)), PathSegment::Index(_)) => Err(PatchError::NotFound(path.clone())),
+            (Some(_), _) => Err(PatchError::Invalid(path.clone())),
+            (None, _) => Err(PatchError::NotFound(parent)),
+        }
+    }
+
+    /// Apply the operations of a patch in order.
+    ///
+    /// Either all operations are applied, or none if one of them fails.
+    pub fn patch(&mut self, patch: &[PatchOp]) -> Result<(), PatchError> {
+        let mut patched = self.clone();
+        for op in patch {
+            match op {
+                PatchOp::Set(path, value) => patched.set_at(path, value.clone()).map(|_| ())?,
+                PatchOp::Remove(path) => patched.remove_at(path).map(|_| ())?,
+                PatchOp::Insert(path, value) => patched.insert_at(path, value.clone())?,
+            };
+        };
+        *self = patched;
+        Ok(())
+    }
+}
+
+impl ParsedDictionary {
+    /// Apply the operations of a patch in order, with paths from this
+    /// dictionary.
+    ///
+    /// Either all operations are applied, or none if one of them fails.
+    pub fn patch(&mut self, patch: &[PatchOp]) -> Result<(), PatchError> {
+        let mut value = ParsedValue::Dictionary(self.clone(), Position::ZERO, Position::ZERO);
+        value.patch(patch)?;
+        match value {
+            ParsedValue::Dictionary(dictionary, ..) => {
+                *self = dictionary;
+                Ok(())
+            }
+            _ => Err(PatchError::Invalid(Path::root())),
+        }
+    }
+}
+
+//// Cursor
+
+/// A cursor for navigating a document.
+///
+/// Keeps track of the path from the root to the current value. Siblings in
+/// dictionaries are ordered as they appear in the source.
+#[derive(Clone)]
+pub struct Cursor<'a> {
+    root: &'a ParsedValue,
+    stack: Vec<(PathSegment, &'a ParsedValue)>,
+}
+
+impl<'a> Cursor<'a> {
+    /// Cursor at the root of a document.
+    pub fn new(root: &'a ParsedValue) -> Self {
+        Cursor { root, stack: vec![] }
+    }
+
+    /// The current value.
+    pub fn value(&self) -> &'a ParsedValue {
+        match self.stack.last() {
+            Some((_, value)) => value,
+            None => self.root,
+        }
+    }
+
+    /// Path from the root to the current value.
+    pub fn path(&self) -> Path {
+        Path(self.stack.iter().map(|(segment, _)| segment.clone()).collect())
+    }
+
+    /// Span of the current value.
+    pub fn span(&self) -> (Position, Position) {
+        let value = self.value();
+        (value.from(), value.to())
+    }
+
+    /// Number of values entered from the root.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Enter the entry at a key of the current dictionary.
+    ///
+    /// Returns `false` and stays in place if there is no such entry.
+    pub fn enter_key(&mut self, key: &str) -> bool {
+        let ParsedValue::Dictionary(dictionary, ..) = self.value() else { return false };
+        match dictionary.find_key(key) {
+            Some(key) => {
+                let value = &dictionary.entries[key];
+                self.stack.push((PathSegment::Key(key.to_string()), value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enter the element at an index of the current list or tuple.
+    ///
+    /// Returns `false` and stays in place if there is no such element.
+    pub fn enter_index(&mut self, index: usize) -> bool {
+        let element = match self.value() {
+            ParsedValue::List(list, ..) => list.elements.get(index),
+            ParsedValue::Tuple(tuple, ..) => tuple.get(index),
+            _ => None,
+        };
+        match element {
+            Some(element) => {
+                self.stack.push((PathSegment::Index(index), element));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enter the content of the current tagged value.
+    ///
+    /// Returns `false` and stays in place if the value is not tagged.
+    pub fn enter_content(&mut self) -> bool {
+        match self.value() {
+            ParsedValue::Tagged(tagged, ..) => {
+                self.stack.push((PathSegment::Content, &tagged.value));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Move to the parent of the current value.
+    ///
+    /// Returns `false` at the root.
+    pub fn parent(&mut self) -> bool {
+        self.stack.pop().is_some()
+    }
+
+    /// Move to the next sibling of the current value.
+    ///
+    /// Returns `false` and stays in place if there is no next sibling.
+    pub fn next_sibling(&mut self) -> bool {
+        self.move_sibling(true)
+    }
+
+    /// Move to the previous sibling of the current value.
+    ///
+    /// Returns `false` and stays in place if there is no previous sibling.
+    pub fn previous_sibling(&mut self) -> bool {
+        self.move_sibling(false)
+    }
+
+    fn move_sibling(&mut self, forward: bool) -> bool {
+        let Some((segment, _)) = self.stack.last() else { return false };
+        let parent = match self.stack.len() {
+            1 => self.root,
+            len => self.stack[len - 2].1,
+        };
+        let sibling = match (segment, parent) {
+            (PathSegment::Index(index), _) => {
+                let index = if forward { Some(index + 1) } else { index.checked_sub(1) };
+                let element = match parent {
+                    ParsedValue::List(list, ..) => index.and_then(|i| list.elements.get(i)),
+                    ParsedValue::Tuple(tuple, ..) => index.and_then(|i| tuple.get(i)),
+                    _ => None,
+                };
+                index.zip(element).map(|(i, element)| (PathSegment::Index(i), element))
+            }
+            (PathSegment::Key(key), ParsedValue::Dictionary(dictionary, ..)) => {
+                let entries: Vec<(&str, &ParsedValue)> = dictionary.iter_sorted(KeyOrder::Source).collect();
+                let position = entries.iter().position(|(k, _)| *k == key.as_str());
+                let position = position.and_then(|p| if forward { Some(p + 1) } else { p.checked_sub(1) });
+                position.and_then(|p| entries.get(p)).map(|(k, v)| (PathSegment::Key(k.to_string()), *v))
+            }
+            _ => None,
+        };
+        match sibling {
+            Some(sibling) => {
+                *self.stack.last_mut().unwrap() = sibling;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+//// Serde
+
+/// Options for serializing a [ParsedValue] with serde.
+#[cfg(feature = "serde")]
+#[derive(PartialEq, Eq, Copy, Clone, Default, Debug)]
+pub struct SerializeOptions {
+    /// Include the `from` and `to` positions of every value. Off by default.
+    pub positions: bool,
+}
+
+/// A [ParsedValue] to serialize with options, see [ParsedValue::serializable].
+#[cfg(feature = "serde")]
+#[derive(Copy, Clone)]
+pub struct SerializableValue<'a> {
+    value: &'a ParsedValue,
+    options: SerializeOptions,
+}
+
+#[cfg(feature = "serde")]
+impl ParsedValue {
+    /// Serialize this value with options instead of the default options.
+    pub fn serializable(&self, options: SerializeOptions) -> SerializableValue<'_> {
+        SerializableValue { value: self, options }
+    }
+}
+
+/// Serialized with the default [SerializeOptions], see [SerializableValue].
+#[cfg(feature = "serde")]
+impl serde::Serialize for ParsedValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serializable(SerializeOptions::default()).serialize(serializer)
+    }
+}
+
+/// Serialized as a struct variant per kind of value, with the fields of the
+/// value and a `span` field. The span is `None`, or the `from` and `to`
+/// positions of the value if [SerializeOptions::positions] is set.
+/// Dictionaries are serialized as maps, with the entries in source order.
+#[cfg(feature = "serde")]
+impl serde::Serialize for SerializableValue<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStructVariant;
+        use crate::model::{Entries, SimpleAttributeValue};
+        let value = self.value;
+        let options = self.options;
+        let (index, variant, len) = match value {
+            ParsedValue::Text(..) => (0, "Text", 2),
+            ParsedValue::Tagged(..) => (1, "Tagged", 4),
+            ParsedValue::Tuple(..) => (2, "Tuple", 2),
+            ParsedValue::Dictionary(..) => (3, "Dictionary", 2),
+            ParsedValue::List(..) => (4, "List", 2),
+            ParsedValue::Compound(..) => (5, "Compound", 3),
+            ParsedValue::Nil(..) => (6, "Nil", 1),
+        };
+        let mut s = serializer.serialize_struct_variant("ParsedValue", index, variant, len)?;
+        match value {
+            ParsedValue::Text(text, ..) => s.serialize_field("text", &*text.str)?,
+            ParsedValue::Tagged(tagged, ..) => {
+                let attributes: Vec<(&str, Option<SimpleAttributeValue>)> = tagged.attributes.iter().map(|a| (&*a.0, a.1.as_ref().map(SimpleAttributeValue::from))).collect();
+                s.serialize_field("name", &*tagged.name)?;
+                s.serialize_field("attributes", &attributes)?;
+                s.serialize_field("value", &tagged.value.serializable(options))?;
+            }
+            ParsedValue::Tuple(tuple, ..) => s.serialize_field("elements", &tuple.iter().map(|v| v.serializable(options)).collect::<Vec<_>>())?,
+            ParsedValue::Dictionary(dictionary, ..) => {
+                let entries: Vec<(&str, SerializableValue)> = dictionary.iter_sorted(KeyOrder::Source).map(|(k, v)| (k, v.serializable(options))).collect();
+                s.serialize_field("entries", &Entries(entries))?;
+            }
+            ParsedValue::List(list, ..) => s.serialize_field("elements", &list.elements.iter().map(|v| v.serializable(options)).collect::<Vec<_>>())?,
+            ParsedValue::Compound(compound, ..) => {
+                s.serialize_field("components", &compound.components.iter().map(|v| v.serializable(options)).collect::<Vec<_>>())?;
+                s.serialize_field("whitespace", &compound.whitespace)?;
+            }
+            ParsedValue::Nil(..) => {}
+        };
+        let span = if self.options.positions { Some((value.from(), value.to())) } else { None };
+        s.serialize_field("span", &span)?;
+        s.end()
+    }
+}
+
+/// Deserialized from the form it is serialized as. Values without a span,
+/// which may also be omitted in self-describing formats, are at
+/// [Position::ZERO].
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ParsedValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        SerializedValue::deserialize(deserializer).map(SerializedValue::into_value)
+    }
+}
+
+#[cfg(feature = "serde")]
+type Span = Option<(Position, Position)>;
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(rename = "ParsedValue")]
+enum SerializedValue {
+    Text {
+        text: String,
+        #[serde(default)] span: Span,
+    },
+    Tagged {
+        name: String,
+        attributes: Vec<(String, Option<crate::model::SimpleAttributeValue>)>,
+        value: Box<SerializedValue>,
+        #[serde(default)] span: Span,
+    },
+    Tuple {
+        elements: Vec<SerializedValue>,
+        #[serde(default)] span: Span,
+    },
+    Dictionary {
+        entries: crate::model::Entries<String, SerializedValue>,
+        #[serde(default)] span: Span,
+    },
+    List {
+        elements: Vec<SerializedValue>,
+        #[serde(default)] span: Span,
+    },
+    Compound {
+        components: Vec<SerializedValue>,
+        whitespace: Vec<bool>,
+        #[serde(default)] span: Span,
+    },
+    Nil {
+        #[serde(default)] span: Span,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl SerializedValue {
+    fn span(&self) -> Span {
+        match self {
+            SerializedValue::Text { span, .. } => *span,
+            SerializedValue::Tagged { span, .. } => *span,
+            SerializedValue::Tuple { span, .. } => *span,
+            SerializedValue::Dictionary { span, .. } => *span,
+            SerializedValue::List { span, .. } => *span,
+            SerializedValue::Compound { span, .. } => *span,
+            SerializedValue::Nil { span } => *span,
+        }
+    }
+
+    fn into_value(self) -> ParsedValue {
+        let (from, to) = self.span().unwrap_or((Position::ZERO, Position::ZERO));
+        match self {
+            SerializedValue::Text { text, .. } => ParsedValue::Text(ParsedText { str: SharedStr::from(text) }, from, to),
+            SerializedValue::Tagged { name, attributes, value, .. } => {
+                let attributes = attributes.into_iter().map(|(k, v)| ParsedAttribute(SharedStr::from(k), v.as_ref().map(ParsedAttributeValue::from))).collect();
+                let value = Box::new(value.into_value());
+                ParsedValue::Tagged(ParsedTaggedValue { name: SharedStr::from(name), raw_name: None, attributes, value }, from, to)
+            }
+            SerializedValue::Tuple { elements, .. } => {
+                let mut elements: Vec<ParsedValue> = elements.into_iter().map(SerializedValue::into_value).collect();
+                let tuple = match elements.len() {
+                    0 => ParsedTuple::Unit,
+                    1 => ParsedTuple::Single(Box::new(elements.pop().unwrap())),
+                    _ => ParsedTuple::Multiple(elements.into_boxed_slice()),
+                };
+                ParsedValue::Tuple(tuple, from, to)
+            }
+            SerializedValue::Dictionary { entries, .. } => {
+                let mut dictionary = ParsedDictionary::empty();
+                for (key, value) in entries.0 {
+                    dictionary.insert(&key, value.into_value());
+                };
+                ParsedValue::Dictionary(dictionary, from, to)
+            }
+            SerializedValue::List { elements, .. } => {
+                ParsedValue::List(ParsedList { elements: elements.into_iter().map(SerializedValue::into_value).collect() }, from, to)
+            }
+            SerializedValue::Compound { components, whitespace, .. } => {
+                let components = components.into_iter().map(SerializedValue::into_value).collect();
+                ParsedValue::Compound(ParsedCompound { components, whitespace }, from, to)
+            }
+            SerializedValue::Nil { .. } => ParsedValue::Nil(from, to),
+        }
+    }
+}
+
+//// Arbitrary
+
+#[cfg(feature = "fuzzing")]
+impl<'a> arbitrary::Arbitrary<'a> for ParsedValue {
+    /// Generate a value of depth at most 4.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        ParsedValue::arbitrary_with_depth(u, 4)
+    }
+}
+
+#[cfg(feature = "fuzzing")]
+impl ParsedValue {
+    /// Generate a value of at most a depth for fuzzing.
+    ///
+    /// The value is one the parser can produce from some document, with zero
+    /// positions. This includes compounds with nil components, like `a{~}`,
+    /// and dictionaries with empty keys, like `{\\: 1}`. Text has no carriage
+    /// returns, and tag names and attributes are alphanumeric words. Fewer
+    /// input bytes give smaller values, and exhausted input gives empty text,
+    /// so shrinking the input shrinks the value.
+    pub fn arbitrary_with_depth(u: &mut arbitrary::Unstructured<'_>, depth: usize) -> arbitrary::Result<Self> {
+        arbitrary_value(u, depth, true)
+    }
+}
+
+#[cfg(feature = "fuzzing")]
+fn arbitrary_value(u: &mut arbitrary::Unstructured<'_>, depth: usize, compound: bool) -> arbitrary::Result<ParsedValue> {
+    let at = Position::ZERO;
+    let kinds = match (depth, compound) {
+        (0, _) => 2,
+        (_, false) => 6,
+        (_, true) => 7,
+    };
+    let value = match u.choose_index(kinds)? {
+        0 => ParsedValue::Text(ParsedText { str: arbitrary_text(u)? }, at, at),
+        1 => ParsedValue::Nil(at, at),
+        2 => {
+            let elements = |u: &mut arbitrary::Unstructured<'_>, n| (0..n).map(|_| arbitrary_value(u, depth - 1, true)).collect::<arbitrary::Result<_>>();
+            let tuple = match u.int_in_range(0..=3)? {
+                0 => ParsedTuple::Unit,
+                // A single tuple is a bracketed tuple, like `<>: a | b`.
+                1 => {
+                    let n = u.int_in_range(2..=3)?;
+                    ParsedTuple::Single(Box::new(ParsedValue::Tuple(ParsedTuple::Multiple(elements(u, n)?), at, at)))
+                }
+                n => ParsedTuple::Multiple(elements(u, n)?),
+            };
+            ParsedValue::Tuple(tuple, at, at)
+        }
+        3 => {
+            let mut dictionary = ParsedDictionary::empty();
+            for _ in 0..u.int_in_range(0..=3)? {
+                dictionary.insert(&arbitrary_text(u)?, arbitrary_value(u, depth - 1, true)?);
+            };
+            ParsedValue::Dictionary(dictionary, at, at)
+        }
+        4 => {
+            let elements = (0..u.int_in_range(0..=3)?).map(|_| arbitrary_value(u, depth - 1, true)).collect::<arbitrary::Result<_>>()?;
+            ParsedValue::List(ParsedList { elements }, at, at)
+        }
+        5 => {
+            let mut attributes = vec![];
+            for _ in 0..u.int_in_range(0..=2)? {
+                let value = match u.int_in_range(0..=2)? {
+                    0 => None,
+                    1 => Some(ParsedAttributeValue::Text(arbitrary_word(u)?)),
+                    _ => Some(ParsedAttributeValue::List((0..u.int_in_range(0..=2)?).map(|_| arbitrary_word(u)).collect::<arbitrary::Result<_>>()?)),
+                };
+                attributes.push(ParsedAttribute(arbitrary_word(u)?, value));
+            };
+            let value = Box::new(arbitrary_value(u, depth - 1, true)?);
+            ParsedValue::Tagged(ParsedTaggedValue { name: arbitrary_word(u)?, raw_name: None, attributes, value }, at, at)
+        }
+        _ => {
+            // Compounds are flat, so components are not compounds.
+            let n = u.int_in_range(2..=3)?;
+            let components = (0..n).map(|_| arbitrary_value(u, depth - 1, false)).collect::<arbitrary::Result<_>>()?;
+            let whitespace = (1..n).map(|_| u.arbitrary()).collect::<arbitrary::Result<_>>()?;
+            ParsedValue::Compound(ParsedCompound { components, whitespace }, at, at)
+        }
+    };
+    Ok(value)
+}
+
+/// Text without carriage returns, which no document can contain.
+#[cfg(feature = "fuzzing")]
+fn arbitrary_text(u: &mut arbitrary::Unstructured<'_>) -> arbitrary::Result<SharedStr> {
+    let text: &str = u.arbitrary()?;
+    Ok(SharedStr::from(text.replace('\r', "")))
+}
+
+/// A non-empty alphanumeric word.
+#[cfg(feature = "fuzzing")]
+fn arbitrary_word(u: &mut arbitrary::Unstructured<'_>) -> arbitrary::Result<SharedStr> {
+    let text: &str = u.arbitrary()?;
+    let word: String = text.chars().filter(|c| c.is_alphanumeric()).collect();
+    Ok(SharedStr::from(if word.is_empty() { "a" } else { &word }))
+}
@@ -1,269 +1,782 @@
 //! Serde serializer for the Khi data format.
-
-use std::str::FromStr;
-use hex::ToHex;
+//!
+//! Data is serialized to a [ParsedValue], which is then written by the
+//! formatter. The data model maps to Khi as follows:
+//!
+//! - Booleans, numbers, characters and strings are text. Integers are
+//!   decimal, up to 128 bits wide, and `NonZero` integers are the integer
+//!   they hold. Byte arrays are text in hexadecimal, or another
+//!   [ByteRepresentation].
+//! - `None` is nil `~`, and `Some` is the value it contains. Other
+//!   representations can be selected with [OptionRepresentation].
+//! - Unit and unit structs are the empty tuple `<>`, or another
+//!   [UnitRepresentation]. Newtype structs are the value they wrap, or another
+//!   [NewtypeRepresentation].
+//! - Sequences are lists. Tuples and tuple structs are tuples.
+//! - Maps and structs are dictionaries, with the entries in serialization
+//!   order. Structs may be tagged with their name, see
+//!   [StructRepresentation], and fields of tagged structs may be attributes,
+//!   see [SerializerOptions::attributes]. Map keys must serialize to text,
+//!   like strings, numbers and unit variants. Dictionaries with keys that are
+//!   not words are written with mapped keys, like `{a => 1 | \b c\ => 2}`.
+//! - Enum variants are tags: `<Variant>` for unit variants, and
+//!   `<Variant>:value` for other variants. Other representations can be
+//!   selected with [EnumRepresentation].
+//!
+//! Text is written as bare words where it reads as the same text, so the
+//! string `1.5` is written like the number. With [Quoting::Canonical], text
+//! that is a number or more than one word is transcribed, like `\1.5\`, so
+//! that documents read the same to programs that look for numbers.
+
+use std::fmt::{self, Display, Formatter, Write};
+use std::io;
+use base64::Engine;
 use numtoa::NumToA;
-use serde::de::{DeserializeSeed, MapAccess, SeqAccess, Visitor};
-use serde::ser::{SerializeMap, SerializeTuple};
-use serde::{Deserialize, Serialize};
-use crate::parse::ParsedExpression;
-use crate::{Component, Dictionary, Directive, Expression, Table, Text};
-use crate::model::{SimpleStructure as MStructure};
-use crate::fmt::FastFormatter;
+use serde::ser::{self, Serialize};
+use crate::enc::encode::encode_bool;
+use crate::fmt::{format_dictionary_with, format_value_with, write_value_with, ListNotation, Style};
+use crate::pdm::{KeyOrder, ParsedAttribute, ParsedAttributeValue, ParsedDictionary, ParsedTaggedValue, ParsedTuple, ParsedValue, Position};
+
+pub use crate::fmt::Quoting;
+
+/// Serialize data to a Khi value.
+pub fn to_value<T: ?Sized + Serialize>(value: &T) -> Result<ParsedValue> {
+    to_value_with(value, &SerializerOptions::default())
+}
 
-/// Serialize a data type to a Khi structure.
-pub fn to_expression<T: Serialize>(t: T) -> Result<impl Expression<_, _, _, _, _>> {
-    let serializer = StructureSerializer::new();
-    t.serialize(serializer)?;
-    serializer.done()
+/// Serialize data to a Khi value with options.
+pub fn to_value_with<T: ?Sized + Serialize>(value: &T, options: &SerializerOptions) -> Result<ParsedValue> {
+    value.serialize(ValueSerializer::with_options(*options))
 }
 
-/// Serialize a data type to a Khi structure string.
-pub fn to_string<T: Serialize>(t: T) -> Result<String> {
-    let structure = to_expression(t);
-    let out = structure.format_compact();
-    Ok(out)
+/// Serialize data to a Khi value document.
+pub fn to_string<T: ?Sized + Serialize>(value: &T) -> Result<String> {
+    to_string_with(value, &SerializerOptions::default())
 }
 
-trait SerializeStructure: Serialize {
+/// Serialize data to a Khi value document with options.
+pub fn to_string_with<T: ?Sized + Serialize>(value: &T, options: &SerializerOptions) -> Result<String> {
+    Ok(format_value_with(&to_value_with(value, options)?, &options.style()))
+}
+
+/// Serialize data to a Khi value document written to a writer.
+///
+/// The document is written as it is formatted, in many small writes, so a
+/// file or socket should be wrapped in a [BufWriter](io::BufWriter).
+pub fn to_writer<W: io::Write, T: ?Sized + Serialize>(writer: W, value: &T) -> Result<()> {
+    to_writer_with(writer, value, &SerializerOptions::default())
+}
 
-    fn serialize_as_structure(&self) -> impl Expression<_, _, _, _, _>;
+/// Serialize data to a Khi value document written to a writer with options.
+pub fn to_writer_with<W: io::Write, T: ?Sized + Serialize>(writer: W, value: &T, options: &SerializerOptions) -> Result<()> {
+    let value = to_value_with(value, options)?;
+    let mut adapter = IoWriter { writer, error: None };
+    write_value_with(&mut adapter, &value, &options.style()).map_err(|_| match adapter.error.take() {
+        Some(error) => Error::Io(error),
+        None => Error::Custom(String::from("Formatter returned an error")),
+    })
+}
+
+/// Adapter that writes formatted text to a writer, and keeps the IO error
+/// that [fmt::Error] cannot carry.
+struct IoWriter<W: io::Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.writer.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
 
-    fn serialize_to_string(&self) -> String;
+/// Serialize data to a Khi value document in the [pretty](Style::pretty)
+/// style, with indented blocks, aligned entries, text blocks, and sequences of
+/// tuples as tables.
+///
+/// Other styles, like pretty documents with delimited sequences, combine
+/// with [to_value_with] and [format_value_with].
+pub fn to_string_pretty<T: ?Sized + Serialize>(value: &T) -> Result<String> {
+    Ok(format_value_with(&to_value(value)?, &Style::pretty()))
+}
 
+/// Serialize data to a Khi dictionary document.
+///
+/// The data must serialize to a dictionary, like a struct or a map.
+pub fn to_dictionary_string<T: ?Sized + Serialize>(value: &T) -> Result<String> {
+    to_dictionary_string_with(value, &SerializerOptions::default())
 }
 
-impl <S: Serialize> SerializeStructure for S {
+/// Serialize data to a Khi dictionary document with options.
+pub fn to_dictionary_string_with<T: ?Sized + Serialize>(value: &T, options: &SerializerOptions) -> Result<String> {
+    match to_value_with(value, options)? {
+        ParsedValue::Dictionary(dictionary, ..) => Ok(format_dictionary_with(&dictionary, &options.style())),
+        _ => Err(Error::NotDictionary),
+    }
+}
 
-    fn serialize_as_structure(&self) -> impl Expression<_, _, _, _, _> {
-        input.serialize(StructureSerializer::new())
+/// Serialize data to a Khi dictionary document in the
+/// [pretty](Style::pretty) style, where nested dictionaries are sections.
+///
+/// The data must serialize to a dictionary, like a struct or a map.
+pub fn to_dictionary_string_pretty<T: ?Sized + Serialize>(value: &T) -> Result<String> {
+    match to_value(value)? {
+        ParsedValue::Dictionary(dictionary, ..) => Ok(format_dictionary_with(&dictionary, &Style::pretty())),
+        _ => Err(Error::NotDictionary),
     }
+}
 
-    fn serialize_to_string(&self) -> String {
-        self.serialize_as_structure().encode()
+/// Serializer options.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SerializerOptions {
+    /// Representation of enum variants.
+    pub enums: EnumRepresentation,
+    /// Representation of optional values.
+    pub optionals: OptionRepresentation,
+    /// Representation of byte arrays.
+    pub bytes: ByteRepresentation,
+    /// Representation of unit and unit structs.
+    pub units: UnitRepresentation,
+    /// Representation of newtype structs.
+    pub newtypes: NewtypeRepresentation,
+    /// Representation of structs.
+    pub structs: StructRepresentation,
+    /// Quoting of text in documents written by the serializer. Values built
+    /// with [to_value_with] are not affected.
+    pub quoting: Quoting,
+    /// Fields written as attributes of the tag of tagged structs and struct
+    /// variants, like `<Link id:home>:{…}`, rather than as entries. The
+    /// fields must serialize to text, or to nil, which omits the attribute, or
+    /// to the empty tuple, which is an attribute without a value.
+    pub attributes: &'static [&'static str],
+    /// Notation of sequences in documents written by the serializer.
+    pub sequences: SequenceStyle,
+}
+
+impl SerializerOptions {
+    /// Style of documents written by the serializer.
+    fn style(&self) -> Style {
+        let lists = match self.sequences {
+            SequenceStyle::Delimited => ListNotation::Delimited,
+            SequenceStyle::Aligned => ListNotation::Aligned,
+            SequenceStyle::Tabular => ListNotation::Tabular,
+        };
+        Style { quoting: self.quoting, lists, ..Style::default() }
     }
+}
+
+/// Notation of sequences. Sequences that cannot be written in a notation are
+/// delimited.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum SequenceStyle {
+    /// Elements separated by `;`, like `[a; b]`.
+    #[default]
+    Delimited,
+    /// Elements after bullets, like `[> a > b]`.
+    Aligned,
+    /// Sequences of two or more tuples of the same length as rows of a table,
+    /// like `[|1|a| |2|b|]`.
+    Tabular,
+}
+
+/// Representation of enum variants, for documents that model variants
+/// differently.
+///
+/// The deserializer reads a tag as the variant in every representation, so
+/// that `<Empty>` and `<Circle>:2` can be mixed with variants written in the
+/// selected representation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum EnumRepresentation {
+    /// The variant is a tag around the data, like `<Circle>:2` or
+    /// `<Polygon>:{sides: 5}`, and `<Empty>` for unit variants.
+    #[default]
+    External,
+    /// The variant is an entry in the dictionary of the data, like
+    /// `{type: Polygon; sides: 5}`. Tuple variants, and newtype variants that
+    /// do not contain a dictionary, cannot be represented.
+    Internal {
+        /// Key of the variant entry.
+        tag: &'static str,
+    },
+    /// The variant is not written, and the data is written as is. Unit
+    /// variants are written as text, like `Empty`.
+    Untagged,
+}
+
+/// Representation of optional values.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum OptionRepresentation {
+    /// `None` is nil `~`, and `Some` is the value it contains.
+    #[default]
+    Nil,
+    /// `None` is the empty tuple `<>`, and `Some` is the value it contains.
+    /// Nil is also read as `None`.
+    Empty,
+    /// `None` is the tag `<?>`, and `Some` is the value tagged with `?`, like
+    /// `<?>:value`.
+    Tagged,
+}
+
+/// Representation of byte arrays, which are serialized by `serde_bytes` and
+/// types that call `serialize_bytes`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ByteRepresentation {
+    /// Text in hexadecimal, like `000fff`.
+    #[default]
+    Hex,
+    /// Text in standard base64 with padding, like `AA//`.
+    Base64,
+    /// A list of numbers, like `[0; 15; 255]`.
+    List,
+}
+
+/// Representation of unit and unit structs. The deserializer reads every
+/// representation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum UnitRepresentation {
+    /// The empty tuple `<>`.
+    #[default]
+    Empty,
+    /// Nil `~`. Optional units are then indistinguishable from `None` in the
+    /// default [OptionRepresentation].
+    Nil,
+    /// The name of a unit struct as text, like `Marker`. Unit is the empty
+    /// tuple.
+    Name,
+}
+
+/// Representation of structs. The deserializer reads every representation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum StructRepresentation {
+    /// A dictionary of the fields, like `{name: Apple; amount: 30}`.
+    #[default]
+    Dictionary,
+    /// A dictionary of the fields, tagged with the name of the struct, like
+    /// `<Fruit>:{name: Apple; amount: 30}`.
+    Tagged,
+}
 
+/// Representation of newtype structs. The deserializer reads every
+/// representation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum NewtypeRepresentation {
+    /// The value the struct wraps.
+    #[default]
+    Transparent,
+    /// A tag named after the struct, like `<Meters>:1.5`.
+    Tagged,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Khi serialization error.
+#[derive(Debug)]
 pub enum Error {
-    /// Unable to convert key structure to text.
+    /// A map key does not serialize to text.
     NonTextKey,
+    /// A map or struct has two entries with the same key.
+    DuplicateKey(String),
+    /// A dictionary document was requested for data that is not a
+    /// dictionary.
+    NotDictionary,
+    /// An enum variant cannot be represented internally tagged.
+    NotTaggable(&'static str),
+    /// A field written as an attribute does not serialize to text or a list
+    /// of text.
+    NonTextAttribute(&'static str),
+    /// Error raised by a `Serialize` implementation.
+    Custom(String),
+    /// The document could not be written.
+    Io(io::Error),
 }
 
-pub struct StructureSerializer {
-    result: MStructure,
-    buffer: [u8; 20],
+/// I/O errors are equal if they are of the same kind.
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Error::NonTextKey, Error::NonTextKey) => true,
+            (Error::DuplicateKey(a), Error::DuplicateKey(b)) => a == b,
+            (Error::NotDictionary, Error::NotDictionary) => true,
+            (Error::NotTaggable(a), Error::NotTaggable(b)) => a == b,
+            (Error::NonTextAttribute(a), Error::NonTextAttribute(b)) => a == b,
+            (Error::Custom(a), Error::Custom(b)) => a == b,
+            (Error::Io(a), Error::Io(b)) => a.kind() == b.kind(),
+            _ => false,
+        }
+    }
 }
 
-impl StructureSerializer {
+impl Eq for Error {}
 
-    pub fn new() -> Self {
-        Self { result: MStructure::Empty, buffer: [0u8; 20] }
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NonTextKey => write!(f, "Map key must serialize to text."),
+            Error::DuplicateKey(key) => write!(f, "Duplicate key {}.", key),
+            Error::NotDictionary => write!(f, "Value must serialize to a dictionary."),
+            Error::NotTaggable(variant) => write!(f, "Variant {} must contain a dictionary to be internally tagged.", variant),
+            Error::NonTextAttribute(field) => write!(f, "Attribute {} must serialize to text, a list of text, nil or the empty tuple.", field),
+            Error::Custom(message) => write!(f, "{}", message),
+            Error::Io(error) => write!(f, "{}", error),
+        }
     }
+}
 
-    pub fn serialize_num<T: NumToA<T>>(&mut self, v: T) -> () {
-        let str = v.numtoa_str(10, &mut self.buffer);
-        self.result.push_str(str);
-
-        ()
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
     }
+}
 
-    pub fn done() -> impl Expression<_, _, _, _, _> {
-
+impl ser::Error for Error {
+    fn custom<T: Display>(message: T) -> Self {
+        Error::Custom(message.to_string())
     }
+}
 
+/// Serializer producing a [ParsedValue]. Positions are zero, and dictionary
+/// entries are in the order they were serialized.
+#[derive(Clone, Copy, Default)]
+pub struct ValueSerializer {
+    options: SerializerOptions,
 }
 
-impl serde::Serializer for &mut StructureSerializer {
+impl ValueSerializer {
+    pub fn new() -> Self {
+        ValueSerializer::default()
+    }
 
-    type Ok = ();
+    pub fn with_options(options: SerializerOptions) -> Self {
+        ValueSerializer { options }
+    }
 
-    type Error = Error;
+    fn dictionary(self) -> DictionarySerializer {
+        DictionarySerializer { serializer: self, variant: None, dictionary: ParsedDictionary::empty(), attributes: vec![], key: None }
+    }
 
-    type SerializeSeq = ();
+    /// Start a dictionary with the variant entry of an internally tagged
+    /// variant.
+    fn tagged_dictionary(self, tag: &'static str, variant: &'static str) -> Result<DictionarySerializer> {
+        let mut dictionary = self.dictionary();
+        dictionary.insert(tag, ParsedValue::text(variant))?;
+        Ok(dictionary)
+    }
+}
 
-    type SerializeTuple = ();
+macro_rules! serialize_integer {
+    ($($method:ident: $type:ty,)*) => {
+        $(
+            fn $method(self, v: $type) -> Result<ParsedValue> {
+                // Wide enough for i128::MIN.
+                let mut buffer = [0u8; 40];
+                Ok(ParsedValue::text(v.numtoa_str(10, &mut buffer)))
+            }
+        )*
+    };
+}
 
-    type SerializeTupleStruct = ();
+impl ser::Serializer for ValueSerializer {
+    type Ok = ParsedValue;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = TupleSerializer;
+    type SerializeTupleStruct = TupleSerializer;
+    type SerializeTupleVariant = TupleSerializer;
+    type SerializeMap = DictionarySerializer;
+    type SerializeStruct = DictionarySerializer;
+    type SerializeStructVariant = DictionarySerializer;
 
-    type SerializeTupleVariant = ();
+    fn serialize_bool(self, v: bool) -> Result<ParsedValue> {
+        Ok(ParsedValue::text(encode_bool(v)))
+    }
 
-    type SerializeMap = MapSerializer;
+    serialize_integer! {
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+    }
 
-    type SerializeStruct = ();
+    fn serialize_f32(self, v: f32) -> Result<ParsedValue> {
+        self.collect_str(&v)
+    }
 
-    type SerializeStructVariant = ();
+    fn serialize_f64(self, v: f64) -> Result<ParsedValue> {
+        self.collect_str(&v)
+    }
 
-    fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
-        let v = if v {
-            "true"
-        } else {
-            "false"
-        };
-        self.result = MText(v);
+    fn serialize_char(self, v: char) -> Result<ParsedValue> {
+        Ok(ParsedValue::text(v.encode_utf8(&mut [0; 4])))
     }
 
-    fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_str(self, v: &str) -> Result<ParsedValue> {
+        Ok(ParsedValue::text(v))
     }
 
-    fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
-        todo!()
+    /// Text is written into a buffer on the stack, unless it is long.
+    fn collect_str<T: ?Sized + Display>(self, value: &T) -> Result<ParsedValue> {
+        let mut buffer = DisplayBuffer::new();
+        write!(buffer, "{}", value).map_err(|_| Error::Custom(String::from("Display implementation returned an error")))?;
+        Ok(ParsedValue::text(buffer.as_str()))
     }
 
-    fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_bytes(self, v: &[u8]) -> Result<ParsedValue> {
+        match self.options.bytes {
+            ByteRepresentation::Hex => Ok(ParsedValue::text(&hex::encode(v))),
+            ByteRepresentation::Base64 => Ok(ParsedValue::text(&base64::engine::general_purpose::STANDARD.encode(v))),
+            ByteRepresentation::List => Ok(ParsedValue::list(v.iter().map(|b| ParsedValue::text(&b.to_string())).collect())),
+        }
     }
 
-    fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_none(self) -> Result<ParsedValue> {
+        match self.options.optionals {
+            OptionRepresentation::Nil => Ok(ParsedValue::nil(Position::ZERO, Position::ZERO)),
+            OptionRepresentation::Empty => Ok(unit()),
+            OptionRepresentation::Tagged => Ok(ParsedValue::tagged("?", vec![], unit())),
+        }
     }
 
-    fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<ParsedValue> {
+        match self.options.optionals {
+            OptionRepresentation::Nil | OptionRepresentation::Empty => value.serialize(self),
+            OptionRepresentation::Tagged => Ok(ParsedValue::tagged("?", vec![], value.serialize(self)?)),
+        }
     }
 
-    fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_unit(self) -> Result<ParsedValue> {
+        match self.options.units {
+            UnitRepresentation::Empty | UnitRepresentation::Name => Ok(unit()),
+            UnitRepresentation::Nil => Ok(ParsedValue::nil(Position::ZERO, Position::ZERO)),
+        }
     }
 
-    fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_unit_struct(self, name: &'static str) -> Result<ParsedValue> {
+        match self.options.units {
+            UnitRepresentation::Name => Ok(ParsedValue::text(name)),
+            _ => self.serialize_unit(),
+        }
     }
 
-    fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str) -> Result<ParsedValue> {
+        match self.options.enums {
+            EnumRepresentation::External => Ok(ParsedValue::tagged(variant, vec![], unit())),
+            EnumRepresentation::Internal { tag } => self.tagged_dictionary(tag, variant)?.finish(),
+            EnumRepresentation::Untagged => Ok(ParsedValue::text(variant)),
+        }
     }
 
-    fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, name: &'static str, value: &T) -> Result<ParsedValue> {
+        match self.options.newtypes {
+            NewtypeRepresentation::Transparent => value.serialize(self),
+            NewtypeRepresentation::Tagged => Ok(ParsedValue::tagged(name, vec![], value.serialize(self)?)),
+        }
     }
 
-    fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _variant_index: u32, variant: &'static str, value: &T) -> Result<ParsedValue> {
+        let value = value.serialize(self)?;
+        match self.options.enums {
+            EnumRepresentation::External => Ok(ParsedValue::tagged(variant, vec![], value)),
+            EnumRepresentation::Internal { tag } => match value {
+                ParsedValue::Dictionary(entries, ..) => {
+                    let mut dictionary = self.tagged_dictionary(tag, variant)?;
+                    for (key, value) in entries.iter_sorted(KeyOrder::Source) {
+                        dictionary.insert(key, value.clone())?;
+                    };
+                    dictionary.finish()
+                }
+                _ => Err(Error::NotTaggable(variant)),
+            },
+            EnumRepresentation::Untagged => Ok(value),
+        }
     }
 
-    fn serialize_char(self, v: char) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer> {
+        Ok(SeqSerializer { serializer: self, elements: Vec::with_capacity(len.unwrap_or(0)) })
     }
 
-    fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_tuple(self, len: usize) -> Result<TupleSerializer> {
+        Ok(TupleSerializer { serializer: self, variant: None, elements: Vec::with_capacity(len) })
     }
 
-    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<TupleSerializer> {
+        self.serialize_tuple(len)
     }
 
-    fn serialize_none(self) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str, len: usize) -> Result<TupleSerializer> {
+        let variant = match self.options.enums {
+            EnumRepresentation::External => Some(variant),
+            EnumRepresentation::Internal { .. } => return Err(Error::NotTaggable(variant)),
+            EnumRepresentation::Untagged => None,
+        };
+        Ok(TupleSerializer { serializer: self, variant, elements: Vec::with_capacity(len) })
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok> where T: Serialize {
-        todo!()
+    fn serialize_map(self, _len: Option<usize>) -> Result<DictionarySerializer> {
+        Ok(self.dictionary())
     }
 
-    fn serialize_unit(self) -> std::result::Result<Self::Ok, Self::Error> {
-        todo!()
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<DictionarySerializer> {
+        match self.options.structs {
+            StructRepresentation::Dictionary => Ok(self.dictionary()),
+            StructRepresentation::Tagged => Ok(DictionarySerializer { variant: Some(name), ..self.dictionary() }),
+        }
     }
 
-    fn serialize_unit_struct(self, name: &'static str) -> std::result::Result<Self::Ok, Self::Error> {
-        todo!()
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str, _len: usize) -> Result<DictionarySerializer> {
+        match self.options.enums {
+            EnumRepresentation::External => Ok(DictionarySerializer { variant: Some(variant), ..self.dictionary() }),
+            EnumRepresentation::Internal { tag } => self.tagged_dictionary(tag, variant),
+            EnumRepresentation::Untagged => Ok(self.dictionary()),
+        }
     }
+}
+
+/// Serializes a sequence as a list.
+pub struct SeqSerializer {
+    serializer: ValueSerializer,
+    elements: Vec<ParsedValue>,
+}
 
-    fn serialize_unit_variant(self, name: &'static str, variant_index: u32, variant: &'static str) -> std::result::Result<Self::Ok, Self::Error> {
-        todo!()
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = ParsedValue;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.elements.push(value.serialize(self.serializer)?);
+        Ok(())
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, name: &'static str, value: &T) -> std::result::Result<Self::Ok, Self::Error> where T: Serialize {
-        todo!()
+    fn end(self) -> Result<ParsedValue> {
+        Ok(ParsedValue::list(self.elements))
     }
+}
+
+/// Serializes a tuple as a tuple, tagged with the variant for tuple variants.
+pub struct TupleSerializer {
+    serializer: ValueSerializer,
+    variant: Option<&'static str>,
+    elements: Vec<ParsedValue>,
+}
 
-    fn serialize_newtype_variant<T: ?Sized>(self, name: &'static str, variant_index: u32, variant: &'static str, value: &T) -> std::result::Result<Self::Ok, Self::Error> where T: Serialize {
-        todo!()
+impl TupleSerializer {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.elements.push(value.serialize(self.serializer)?);
+        Ok(())
     }
 
-    fn serialize_seq(self, len: Option<usize>) -> std::result::Result<Self::SerializeSeq, Self::Error> {
-        todo!()
+    fn finish(self) -> Result<ParsedValue> {
+        let tuple = ParsedValue::from_tuple(self.elements, Position::ZERO, Position::ZERO);
+        Ok(tag(self.variant, tuple))
     }
+}
+
+impl ser::SerializeTuple for TupleSerializer {
+    type Ok = ParsedValue;
+    type Error = Error;
 
-    fn serialize_tuple(self, len: usize) -> std::result::Result<Self::SerializeTuple, Self::Error> {
-        todo!()
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
     }
 
-    fn serialize_tuple_struct(self, name: &'static str, len: usize) -> std::result::Result<Self::SerializeTupleStruct, Self::Error> {
-        todo!()
+    fn end(self) -> Result<ParsedValue> {
+        self.finish()
     }
+}
 
-    fn serialize_tuple_variant(self, name: &'static str, variant_index: u32, variant: &'static str, len: usize) -> std::result::Result<Self::SerializeTupleVariant, Self::Error> {
-        todo!()
+impl ser::SerializeTupleStruct for TupleSerializer {
+    type Ok = ParsedValue;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
     }
 
-    fn serialize_map(self, len: Option<usize>) -> std::result::Result<Self::SerializeMap, Self::Error> {
-        todo!()
+    fn end(self) -> Result<ParsedValue> {
+        self.finish()
     }
+}
 
-    fn serialize_struct(self, name: &'static str, len: usize) -> std::result::Result<Self::SerializeStruct, Self::Error> {
-        todo!()
+impl ser::SerializeTupleVariant for TupleSerializer {
+    type Ok = ParsedValue;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
     }
 
-    fn serialize_struct_variant(self, name: &'static str, variant_index: u32, variant: &'static str, len: usize) -> std::result::Result<Self::SerializeStructVariant, Self::Error> {
-        todo!()
+    fn end(self) -> Result<ParsedValue> {
+        self.finish()
     }
 }
 
-pub struct TupleSerializer {
-
+/// Serializes a map or a struct as a dictionary, tagged with the variant for
+/// struct variants.
+pub struct DictionarySerializer {
+    serializer: ValueSerializer,
+    variant: Option<&'static str>,
+    dictionary: ParsedDictionary,
+    /// Attributes of the tag, when the dictionary is tagged.
+    attributes: Vec<ParsedAttribute>,
+    /// Key of the entry being serialized.
+    key: Option<String>,
 }
 
-impl SerializeTuple for TupleSerializer {
+impl DictionarySerializer {
+    /// Insert an entry after the other entries.
+    fn insert(&mut self, key: &str, value: ParsedValue) -> Result<()> {
+        if self.dictionary.find_key(key).is_some() {
+            return Err(Error::DuplicateKey(key.to_string()));
+        };
+        self.dictionary.insert(key, value);
+        Ok(())
+    }
 
-    type Ok = ();
+    /// Serialize a field of a struct or struct variant, as an attribute if
+    /// the dictionary is tagged and the field is one of the attributes.
+    fn field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        let value = value.serialize(self.serializer)?;
+        if self.variant.is_none() || !self.serializer.options.attributes.contains(&key) {
+            return self.insert(key, value);
+        };
+        match value {
+            ParsedValue::Text(text, ..) => self.attributes.push(ParsedAttribute(key.into(), Some(ParsedAttributeValue::Text(text.str)))),
+            ParsedValue::List(list, ..) => {
+                let strings = list.elements.iter().map(|element| match element {
+                    ParsedValue::Text(text, ..) => Ok(text.str.clone()),
+                    _ => Err(Error::NonTextAttribute(key)),
+                }).collect::<Result<_>>()?;
+                self.attributes.push(ParsedAttribute(key.into(), Some(ParsedAttributeValue::List(strings))));
+            }
+            ParsedValue::Tuple(ParsedTuple::Unit, ..) => self.attributes.push(ParsedAttribute(key.into(), None)),
+            ParsedValue::Nil(..) => {}
+            _ => return Err(Error::NonTextAttribute(key)),
+        };
+        Ok(())
+    }
 
-    type Error = ();
+    fn finish(self) -> Result<ParsedValue> {
+        let dictionary = ParsedValue::Dictionary(self.dictionary, Position::ZERO, Position::ZERO);
+        match self.variant {
+            Some(variant) => {
+                let tagged = ParsedTaggedValue { name: variant.into(), raw_name: None, attributes: self.attributes, value: Box::new(dictionary) };
+                Ok(ParsedValue::Tagged(tagged, Position::ZERO, Position::ZERO))
+            }
+            None => Ok(dictionary),
+        }
+    }
+}
+
+impl ser::SerializeMap for DictionarySerializer {
+    type Ok = ParsedValue;
+    type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> where T: Serialize {
-        todo!()
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        let key = match key.serialize(ValueSerializer::new())? {
+            ParsedValue::Text(text, ..) => text.str.to_string(),
+            ParsedValue::Tagged(tagged, ..) if tagged.value.is_unit() => tagged.name.to_string(),
+            _ => return Err(Error::NonTextKey),
+        };
+        self.key = Some(key);
+        Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        todo!()
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take().ok_or_else(|| Error::Custom(String::from("Map value serialized before key.")))?;
+        let value = value.serialize(self.serializer)?;
+        self.insert(&key, value)
     }
 
+    fn end(self) -> Result<ParsedValue> {
+        self.finish()
+    }
 }
 
-pub struct MapSerializer<'a> {
-    serializer: &'a mut StructureSerializer,
-}
+impl ser::SerializeStruct for DictionarySerializer {
+    type Ok = ParsedValue;
+    type Error = Error;
 
-impl SerializeMap for MapSerializer {
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.field(key, value)
+    }
 
-    type Ok = ();
+    fn end(self) -> Result<ParsedValue> {
+        self.finish()
+    }
+}
 
+impl ser::SerializeStructVariant for DictionarySerializer {
+    type Ok = ParsedValue;
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> where T: Serialize {
-
-        self.serializer.result.push(key)
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.field(key, value)
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> where T: Serialize {
-        value.serialize(self.serializer)?;
-        self.
+    fn end(self) -> Result<ParsedValue> {
+        self.finish()
     }
+}
 
-    fn serialize_entry<K: ?Sized, V: ?Sized>(&mut self, key: &K, value: &V) -> Result<(), Self::Error> where K: Serialize, V: Serialize {
-        todo!()
+/// Text written by [Display], kept on the stack while it is short.
+struct DisplayBuffer {
+    stack: [u8; 64],
+    len: usize,
+    /// The text, once it is too long for the stack.
+    heap: Option<String>,
+}
+
+impl DisplayBuffer {
+    fn new() -> Self {
+        DisplayBuffer { stack: [0; 64], len: 0, heap: None }
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.serializer.result.push('}');
-        Ok(Self::Ok)
+    fn as_str(&self) -> &str {
+        match &self.heap {
+            Some(heap) => heap,
+            // Only whole strings are copied to the stack.
+            None => std::str::from_utf8(&self.stack[..self.len]).expect("Buffer holds UTF-8"),
+        }
     }
+}
 
+impl std::fmt::Write for DisplayBuffer {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        if let Some(heap) = &mut self.heap {
+            heap.push_str(s);
+        } else if self.len + s.len() <= self.stack.len() {
+            self.stack[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+        } else {
+            let mut heap = String::with_capacity(2 * (self.len + s.len()));
+            heap.push_str(self.as_str());
+            heap.push_str(s);
+            self.heap = Some(heap);
+        };
+        Ok(())
+    }
 }
 
+/// The empty tuple `<>`.
+fn unit() -> ParsedValue {
+    ParsedValue::from_tuple(vec![], Position::ZERO, Position::ZERO)
+}
 
+/// Tag a value with a variant name.
+fn tag(variant: Option<&'static str>, value: ParsedValue) -> ParsedValue {
+    match variant {
+        Some(variant) => ParsedValue::tagged(variant, vec![], value),
+        None => value,
+    }
+}
@@ -0,0 +1,143 @@
+//! Adapters for `#[serde(with = "…")]`, for fields written in the forms that
+//! people use in hand-written documents.
+//!
+//! ```
+//! # use std::time::{Duration, SystemTime};
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Serialize, Deserialize)]
+//! struct Upload {
+//!     #[serde(with = "khi::serde_helpers::base64")]
+//!     data: Vec<u8>,
+//!     #[serde(with = "khi::serde_helpers::rfc3339")]
+//!     created: SystemTime,
+//!     #[serde(with = "khi::serde_helpers::duration")]
+//!     timeout: Duration,
+//! }
+//!
+//! let upload: Upload = khi::de::from_str(r"{data: AAE=; created: \2024-05-01T12:00:00Z\; timeout: 5m 30s}").unwrap();
+//! assert_eq!(upload.data, vec![0, 1]);
+//! assert_eq!(upload.timeout, Duration::from_secs(330));
+//! ```
+//!
+//! The adapters are not specific to Khi, and work with any format that has
+//! strings.
+
+use std::fmt::Formatter;
+use serde::de::{self, Unexpected, Visitor};
+use serde::Deserializer;
+
+/// Deserialize a string with a parser.
+fn deserialize_text<'de, D: Deserializer<'de>, T>(deserializer: D, expecting: &'static str, parse: fn(&str) -> Option<T>) -> Result<T, D::Error> {
+    deserializer.deserialize_str(TextVisitor { expecting, parse })
+}
+
+struct TextVisitor<T> {
+    expecting: &'static str,
+    parse: fn(&str) -> Option<T>,
+}
+
+impl<T> Visitor<'_> for TextVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str(self.expecting)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+        (self.parse)(v).ok_or_else(|| E::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+/// Byte arrays as hexadecimal text, like `00ff`.
+pub mod hex {
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<T: ?Sized + AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&::hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        super::deserialize_text(deserializer, "hexadecimal bytes", |text| ::hex::decode(text).ok())
+    }
+}
+
+/// Byte arrays as standard base64 text with padding, like `AP8=`.
+pub mod base64 {
+    use ::base64::engine::general_purpose::STANDARD;
+    use ::base64::Engine;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<T: ?Sized + AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        super::deserialize_text(deserializer, "base64 bytes", |text| STANDARD.decode(text).ok())
+    }
+}
+
+/// Timestamps as RFC 3339 text, like `2024-05-01T12:00:00Z`.
+///
+/// Timestamps are written in UTC, with as many fractional digits as needed.
+/// They are read like [decode_datetime](crate::enc::DecodeTextExt::decode_datetime),
+/// but must have an offset, as a [SystemTime] is a point in time.
+///
+/// [SystemTime]: std::time::SystemTime
+pub mod rfc3339 {
+    use std::time::SystemTime;
+    use serde::{Deserializer, Serializer};
+    use crate::enc::datetime::{format_datetime, parse_datetime, DateTime};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_datetime(time.to_parts()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        super::deserialize_text(deserializer, "an RFC 3339 timestamp", |text| {
+            parse_datetime(text).filter(|parts| parts.offset.is_some()).and_then(SystemTime::from_parts)
+        })
+    }
+}
+
+/// Durations as amounts of units separated by spaces, like `5m 30s`.
+///
+/// The units are `d`, `h`, `m`, `s`, `ms`, `us` and `ns`. Durations are
+/// written with the largest units first, and zero is `0s`. They are read
+/// like [decode_duration](crate::enc::DecodeTextExt::decode_duration), so
+/// units may be repeated and in any order, and ISO 8601 and clocks are also
+/// read.
+pub mod duration {
+    use std::time::Duration;
+    use serde::{Deserializer, Serializer};
+    use crate::enc::datetime::{self, format_duration, parse_duration};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_duration(datetime::Duration::to_nanos(duration)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        super::deserialize_text(deserializer, "a duration like 5m 30s", |text| parse_duration(text).and_then(datetime::Duration::from_nanos))
+    }
+}
+
+/// Floating point numbers with a decimal comma, like `3,5`.
+///
+/// A decimal point is also read, and other than the separator, numbers are
+/// read in the default [NumberFormat](crate::number::NumberFormat).
+pub mod comma_decimal {
+    use serde::{Deserializer, Serializer};
+    use crate::number::NumberFormat;
+
+    pub fn serialize<S: Serializer>(number: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&number.to_string().replacen('.', ",", 1))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        super::deserialize_text(deserializer, "a number with a decimal comma", |text| {
+            if text.contains('.') && text.contains(',') {
+                return None;
+            };
+            NumberFormat::default().parse(&text.replacen(',', ".", 1))
+        })
+    }
+}
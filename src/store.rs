@@ -0,0 +1,199 @@
+//! Concurrent document store.
+//!
+//! A [DocumentStore] manages named documents that can be read and replaced from
+//! multiple threads, for example by a server that hot-reloads its
+//! configuration. Documents are shared as `Arc<ParsedValue>`, so readers keep a
+//! consistent snapshot while a document is replaced.
+//!
+//! A panic in an update function or a subscriber does not make the store
+//! unusable. Documents are only replaced after the update function returns, so
+//! the store is never left half-updated.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::pdm::{MergeStrategy, ParsedValue};
+
+/// A thread-safe store of named documents.
+///
+/// Cloning a store gives another handle to the same documents.
+#[derive(Clone, Default)]
+pub struct DocumentStore {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    documents: RwLock<Documents>,
+    subscribers: RwLock<Vec<(usize, Arc<Subscriber>)>>,
+    /// Held while notifying, so that changes are notified in order.
+    notifying: Mutex<()>,
+    next_subscription: AtomicUsize,
+}
+
+#[derive(Default)]
+struct Documents {
+    documents: HashMap<String, Arc<ParsedValue>>,
+    version: u64,
+}
+
+type Subscriber = dyn Fn(&Change) + Send + Sync;
+
+impl DocumentStore {
+    /// Empty store.
+    pub fn new() -> Self {
+        DocumentStore::default()
+    }
+
+    /// Get a snapshot of a document.
+    pub fn get(&self, name: &str) -> Option<Arc<ParsedValue>> {
+        read(&self.inner.documents).documents.get(name).cloned()
+    }
+
+    /// Check if there is a document with a name.
+    pub fn contains(&self, name: &str) -> bool {
+        read(&self.inner.documents).documents.contains_key(name)
+    }
+
+    /// Number of changes made to the store.
+    pub fn version(&self) -> u64 {
+        read(&self.inner.documents).version
+    }
+
+    /// Names of the stored documents.
+    pub fn names(&self) -> Vec<String> {
+        read(&self.inner.documents).documents.keys().cloned().collect()
+    }
+
+    /// Insert or replace a document.
+    ///
+    /// Returns the replaced document.
+    pub fn insert(&self, name: &str, document: ParsedValue) -> Option<Arc<ParsedValue>> {
+        self.update(name, |_| Some(document))
+    }
+
+    /// Remove a document.
+    ///
+    /// Returns the removed document.
+    pub fn remove(&self, name: &str) -> Option<Arc<ParsedValue>> {
+        self.update(name, |_| None)
+    }
+
+    /// Atomically update a document.
+    ///
+    /// The function receives the current document and returns the new document,
+    /// or `None` to remove it. No other write to the store happens in between.
+    /// The function must not access the store. If it panics, the document is
+    /// left as it was.
+    ///
+    /// Returns the previous document.
+    pub fn update<F: FnOnce(Option<&ParsedValue>) -> Option<ParsedValue>>(&self, name: &str, f: F) -> Option<Arc<ParsedValue>> {
+        let mut documents = write(&self.inner.documents);
+        let old = documents.documents.get(name).cloned();
+        let new = f(old.as_deref()).map(Arc::new);
+        match &new {
+            Some(new) => documents.documents.insert(name.to_string(), new.clone()),
+            None => documents.documents.remove(name),
+        };
+        if old.is_none() && new.is_none() {
+            return old;
+        };
+        documents.version += 1;
+        let change = Change { name: name.to_string(), old: old.clone(), new, version: documents.version };
+        // Start notifying before the next write can begin, so that subscribers
+        // see changes in the order they were made.
+        let notifying = lock(&self.inner.notifying);
+        drop(documents);
+        self.notify(&change);
+        drop(notifying);
+        old
+    }
+
+    /// Atomically merge a document into a stored document.
+    ///
+    /// If both are dictionaries, they are merged recursively with the default
+    /// [MergeStrategy], so the given document overrides the stored document.
+    /// Otherwise the stored document is replaced.
+    ///
+    /// Returns the previous document.
+    pub fn merge(&self, name: &str, document: ParsedValue) -> Option<Arc<ParsedValue>> {
+        self.update(name, |old| Some(match old {
+            Some(old) => merge(old, document),
+            None => document,
+        }))
+    }
+
+    /// Subscribe to changes.
+    ///
+    /// The callback is called after each change, on the thread that made it.
+    /// Changes are notified one at a time, in the order they were made. The
+    /// callback may read the store, but must not change it. The subscription
+    /// ends when the returned handle is dropped.
+    pub fn subscribe<F: Fn(&Change) + Send + Sync + 'static>(&self, callback: F) -> Subscription {
+        let id = self.inner.next_subscription.fetch_add(1, Ordering::Relaxed);
+        write(&self.inner.subscribers).push((id, Arc::new(callback)));
+        Subscription { store: Arc::downgrade(&self.inner), id }
+    }
+
+    fn notify(&self, change: &Change) {
+        let subscribers: Vec<Arc<Subscriber>> = read(&self.inner.subscribers).iter().map(|(_, s)| s.clone()).collect();
+        for subscriber in subscribers {
+            subscriber(change);
+        };
+    }
+}
+
+fn merge(old: &ParsedValue, new: ParsedValue) -> ParsedValue {
+    match (old, new) {
+        (ParsedValue::Dictionary(old, ..), ParsedValue::Dictionary(new, from, to)) => {
+            let mut merged = old.clone();
+            merged.merge(&new, &MergeStrategy::default());
+            ParsedValue::Dictionary(merged, from, to)
+        }
+        (_, new) => new,
+    }
+}
+
+/// A change to a document in a [DocumentStore].
+#[derive(Clone)]
+pub struct Change {
+    /// Name of the document.
+    pub name: String,
+    /// The document before the change, if it existed.
+    pub old: Option<Arc<ParsedValue>>,
+    /// The document after the change, if it still exists.
+    pub new: Option<Arc<ParsedValue>>,
+    /// [Version](DocumentStore::version) of the store after the change.
+    pub version: u64,
+}
+
+/// A subscription to changes in a [DocumentStore].
+///
+/// Unsubscribes when dropped.
+pub struct Subscription {
+    store: std::sync::Weak<Inner>,
+    id: usize,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(store) = self.store.upgrade() {
+            write(&store.subscribers).retain(|(id, _)| *id != self.id);
+        };
+    }
+}
+
+// A panic while a lock is held cannot leave the store inconsistent, so
+// poisoned locks are recovered.
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn lock<T>(lock: &Mutex<T>) -> MutexGuard<'_, T> {
+    lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
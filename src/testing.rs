@@ -0,0 +1,52 @@
+//! Assertions for testing documents against the grammar.
+//!
+//! The macros are exported with the `parse` feature, and check the examples of
+//! the grammar rules in the parser.
+
+/// Assert that a document parses.
+///
+/// The document is parsed as a `value` document unless `dictionary` or `list`
+/// is given.
+///
+/// ```
+/// # use khi::assert_parses;
+/// assert_parses!("<Tag>:arg");
+/// assert_parses!(dictionary, "key: value");
+/// assert_parses!(list, "a; b");
+/// ```
+#[macro_export]
+macro_rules! assert_parses {
+    (value, $source:expr) => { $crate::assert_parses!(@parse parse_value_str, "value", $source) };
+    (dictionary, $source:expr) => { $crate::assert_parses!(@parse parse_dictionary_str, "dictionary", $source) };
+    (list, $source:expr) => { $crate::assert_parses!(@parse parse_list_str, "list", $source) };
+    (@parse $parse:ident, $kind:literal, $source:expr) => {
+        if let Err(errors) = $crate::parse::$parse($source) {
+            panic!("expected {:?} to parse as a {} document, but got: {:?}", $source, $kind, errors);
+        }
+    };
+    ($source:expr) => { $crate::assert_parses!(value, $source) };
+}
+
+/// Assert that a document does not parse.
+///
+/// The document is parsed as a `value` document unless `dictionary` or `list`
+/// is given.
+///
+/// ```
+/// # use khi::assert_rejects;
+/// assert_rejects!("{");
+/// assert_rejects!(dictionary, "a: 1; a: 2");
+/// assert_rejects!(list, "a; ]");
+/// ```
+#[macro_export]
+macro_rules! assert_rejects {
+    (value, $source:expr) => { $crate::assert_rejects!(@parse parse_value_str, "value", $source) };
+    (dictionary, $source:expr) => { $crate::assert_rejects!(@parse parse_dictionary_str, "dictionary", $source) };
+    (list, $source:expr) => { $crate::assert_rejects!(@parse parse_list_str, "list", $source) };
+    (@parse $parse:ident, $kind:literal, $source:expr) => {
+        if $crate::parse::$parse($source).is_ok() {
+            panic!("expected {:?} to be rejected as a {} document, but it parsed", $source, $kind);
+        }
+    };
+    ($source:expr) => { $crate::assert_rejects!(value, $source) };
+}
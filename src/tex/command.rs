@@ -1,75 +1,213 @@
 //! Command binary for the TeX-preprocessor.
 //!
 //! Test: cargo run --bin khi-tex-cmd --features="tex" -- examples/equations.tex.khi
+//!
+//! Project mode preprocesses a main document and its chapters into a build
+//! directory, and optionally builds the main document with `latexmk`:
+//!
+//! Test: cargo run --bin khi-tex-cmd --features="tex" -- --project build --latexmk examples/equations.tex.khi
+//!
+//! Notation mode writes the notation index of a document:
+//!
+//! Test: cargo run --bin khi-tex-cmd --features="tex" -- --notation examples/equations.tex.khi
+//!
+//! Report mode writes the statistics of the output of a document:
+//!
+//! Test: cargo run --bin khi-tex-cmd --features="tex" -- --report examples/equations.tex.khi
 
 use std::env;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
 use khi::parse::{parse_value_str};
 use khi::parse::parser::error_to_string;
-use khi::tex::{PreprocessorError, write_tex};
+use khi::pdm::ParsedValue;
+use khi::tex::{SourceMap, TexLoader, TexOptions, collect_notation_with, lint_commands, lint_references, write_tex_mapped, write_tex_report};
 
 fn main() {
-    match preprocess() {
+    let mut args = env::args();
+    args.next(); // The first arg is the binary. Skip.
+    let args: Vec<String> = args.collect();
+    let result = match args.first().map(String::as_str) {
+        Some("--project") => project(&args[1..]),
+        Some("--notation") => notation(&args[1..]),
+        Some("--report") => report(&args[1..]),
+        _ => preprocess(&args),
+    };
+    match result {
         Ok(output) => print!("{}\n\n", output),
         Err(error) => eprint!("{}\n\n", error),
     };
 }
 
-fn preprocess() -> Result<String, String> {
-    let mut args = env::args();
-    args.next(); // The first arg is the binary. Skip.
-    if let Some(first) = args.next() {
-        let mut file = File::open(&first).unwrap();
-        let mut source = String::new();
-        file.read_to_string(&mut source).unwrap();
-        eprint!("Preprocessing document of size: {}\n\n", source.len());
-        let document = match parse_value_str(&source) {
-            Ok(document) => document,
-            Err(errors) => {
-                let mut errs = String::new();
-                for e in errors {
-                    errs.push_str(&error_to_string(&e));
-                    errs.push('\n');
-                }
-                return Err(errs);
-            },
-        };
-        match write_tex(&document) {
-            Ok(output) => {
-                if let Some(second) = args.next() {
-                    if first.eq(&second) {
-                        return Err(format!("Trying to overwrite source!"));
-                    }
-                    let mut out = File::create(&second).unwrap();
-                    out.write_all(output.as_bytes()).unwrap();
-                    Ok(format!("Successfully generated document."))
-                } else {
-                    Ok(output)
-                }
-            },
-            Err(error) => match error {
-                PreprocessorError::IllegalTable(at) => {
-                    Err(format!("Illegal table at {}:{}.", at.line, at.column))
-                }
-                PreprocessorError::IllegalDictionary(at) => {
-                    Err(format!("Illegal dictionary at {}:{}.", at.line, at.column))
-                }
-                PreprocessorError::ZeroTable(at) => {
-                    Err(format!("Table cannot be empty at {}:{}.", at.line, at.column))
-                }
-                PreprocessorError::MacroError(at, directive) => {
-                    Err(format!("Unknown macro {} at {}:{}.", &directive, at.line, at.column))
-                }
-                PreprocessorError::MissingOptionalArgument(at) => {
-                    Err(format!("Missing optional argument at {}:{}.", at.line, at.column))
-                }
-                PreprocessorError::IllegalTuple(at) => {
-                    Err(format!("Illegal tuple at {}:{}.", at.line, at.column))
-                }
-            },
+fn preprocess(args: &[String]) -> Result<String, String> {
+    if let Some(first) = args.first() {
+        let output = preprocess_file(first)?;
+        if let Some(second) = args.get(1) {
+            if first.eq(second) {
+                return Err(format!("Trying to overwrite source!"));
+            }
+            let mut out = File::create(second).unwrap();
+            out.write_all(output.as_bytes()).unwrap();
+            Ok(format!("Successfully generated document."))
+        } else {
+            Ok(output)
         }
     } else {
         Err(format!("Specify source file as first argument."))
     }
 }
+
+fn notation(args: &[String]) -> Result<String, String> {
+    if let Some(first) = args.first() {
+        let document = parse_file(first)?;
+        Ok(collect_notation_with(&document, &options_for(first)).to_khi())
+    } else {
+        Err(String::from("Specify source file after --notation."))
+    }
+}
+
+fn report(args: &[String]) -> Result<String, String> {
+    if let Some(first) = args.first() {
+        let document = parse_file(first)?;
+        let (_, report) = write_tex_report(&document, &options_for(first)).map_err(|error| error.to_string())?;
+        Ok(report.to_khi())
+    } else {
+        Err(String::from("Specify source file after --report."))
+    }
+}
+
+fn parse_file(path: &str) -> Result<ParsedValue, String> {
+    let mut file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path, e))?;
+    let mut source = String::new();
+    file.read_to_string(&mut source).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    eprint!("Preprocessing document of size: {}\n\n", source.len());
+    parse_value_str(&source).map_err(|errors| {
+        let mut errs = String::new();
+        for e in errors {
+            errs.push_str(&error_to_string(&e));
+            errs.push('\n');
+        }
+        errs
+    })
+}
+
+fn preprocess_file(path: &str) -> Result<String, String> {
+    preprocess_mapped(path).map(|(output, _)| output)
+}
+
+/// Preprocess a file, and map the lines of the output to the source.
+fn preprocess_mapped(path: &str) -> Result<(String, SourceMap), String> {
+    let document = parse_file(path)?;
+    let options = options_for(path);
+    for warning in lint_commands(&document, &options).into_iter().chain(lint_references(&document, &options)) {
+        let at = warning.at.map(|at| format!(":{}:{}", at.line, at.column)).unwrap_or_default();
+        eprintln!("{}{}: Warning: {}", path, at, warning.message);
+    };
+    write_tex_mapped(&document, &options).map_err(|error| error.to_string())
+}
+
+/// Options for a document, which includes documents relative to its directory.
+fn options_for(path: &str) -> TexOptions {
+    let directory = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+    let loader: TexLoader = Rc::new(move |include| {
+        std::fs::read_to_string(directory.join(include)).map_err(|e| e.to_string())
+    });
+    TexOptions { loader: Some(loader), ..TexOptions::default() }
+}
+
+/// Project mode: `--project <build directory> [--latexmk] <main> [chapters...]`.
+///
+/// Each source `name.tex.khi` is written to `<build directory>/name.tex`, so
+/// the main document can include chapters with `<input>:name`. With
+/// `--latexmk`, the main document is built in the build directory, and errors
+/// in the LaTeX log are reported at the Khi source lines they came from.
+fn project(args: &[String]) -> Result<String, String> {
+    let mut args = args.iter();
+    let build = match args.next() {
+        Some(build) => PathBuf::from(build),
+        None => return Err(String::from("Specify build directory after --project.")),
+    };
+    let mut latexmk = false;
+    let mut sources = vec![];
+    for arg in args {
+        if arg == "--latexmk" {
+            latexmk = true;
+        } else {
+            sources.push(PathBuf::from(arg));
+        }
+    }
+    if sources.is_empty() {
+        return Err(String::from("Specify main source file."));
+    }
+    std::fs::create_dir_all(&build).map_err(|e| format!("Cannot create {}: {}", build.display(), e))?;
+    let mut outputs = vec![];
+    let mut source_maps = vec![];
+    let mut errors = String::new();
+    for source in &sources {
+        let output = build.join(output_name(source));
+        match preprocess_mapped(&source.to_string_lossy()) {
+            Ok((tex, source_map)) => {
+                std::fs::write(&output, tex).map_err(|e| format!("Cannot write {}: {}", output.display(), e))?;
+                source_maps.push(source_map);
+            }
+            Err(error) => {
+                errors.push_str(&format!("{}:\n{}\n", source.display(), error));
+            }
+        }
+        outputs.push(output);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    if !latexmk {
+        return Ok(format!("Successfully generated {} documents in {}.", outputs.len(), build.display()));
+    }
+    let main = output_name(&sources[0]);
+    let result = Command::new("latexmk")
+        .args(["-pdf", "-interaction=nonstopmode", "-file-line-error"])
+        .arg(&main)
+        .current_dir(&build)
+        .output()
+        .map_err(|e| format!("Cannot run latexmk: {}", e))?;
+    if result.status.success() {
+        return Ok(format!("Successfully built {}.", build.join(main).display()));
+    }
+    let log = String::from_utf8_lossy(&result.stdout);
+    let mut errors = String::new();
+    for line in log.lines() {
+        if let Some(error) = map_log_line(line, &sources, &source_maps) {
+            errors.push_str(&error);
+            errors.push('\n');
+        }
+    }
+    if errors.is_empty() {
+        errors.push_str(&log);
+    }
+    Err(errors)
+}
+
+/// The name of the generated document: `name.tex.khi` becomes `name.tex`.
+fn output_name(source: &Path) -> String {
+    let name = source.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = name.strip_suffix(".khi").unwrap_or(&name);
+    if name.ends_with(".tex") {
+        name.to_string()
+    } else {
+        format!("{}.tex", name)
+    }
+}
+
+/// Map an error line of the form `file.tex:line: message` to the Khi source,
+/// with the source map of the generated document.
+fn map_log_line(line: &str, sources: &[PathBuf], source_maps: &[SourceMap]) -> Option<String> {
+    let (file, rest) = line.split_once(".tex:")?;
+    let (number, message) = rest.split_once(':')?;
+    let number: usize = number.parse().ok()?;
+    let name = format!("{}.tex", Path::new(file).file_name()?.to_string_lossy());
+    let index = sources.iter().position(|source| output_name(source) == name)?;
+    let at = source_maps[index].source(number)?;
+    Some(format!("{}:{}:{}:{}", sources[index].display(), at.line, at.column, message))
+}
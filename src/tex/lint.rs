@@ -0,0 +1,232 @@
+//! Lint of the commands and references of a TeX document.
+//!
+//! Checks the commands of a document against the known commands, given with
+//! [TexOptions::commands] and declared with `<preamble!>`, and the commands
+//! defined with `def!`, to catch typos like `<frak>` for `<mathfrak>` before
+//! LaTeX runs. Checks the keys of `cite!` and `ref!` against the citation
+//! keys and the labels, to catch broken references.
+
+use crate::parse::{parse_value_str, Diagnostic};
+use crate::pdm::{KeyOrder, ParsedTaggedValue, ParsedTuple, ParsedValue, Position};
+use crate::tex::preprocess::words;
+use crate::tex::{TexLoader, TexOptions};
+use crate::{Tagged, Text, Value};
+
+/// Commands that the preprocessor handles itself, which are always known.
+const KERNEL_COMMANDS: &[&str] = &["begin", "end", "documentclass", "usepackage"];
+
+/// Check that the commands of a document are known, and warn about each use
+/// of an unknown command, with the known command it is likely a typo of.
+///
+/// Without known commands, in the options or declared with `<preamble!>`,
+/// nothing is checked. The positions of the commands of an included document
+/// are positions in that document, and the message names the document.
+pub fn lint_commands(document: &ParsedValue, options: &TexOptions) -> Vec<Diagnostic> {
+    let linter = Linter::walk(document, options);
+    if linter.known.is_empty() && !linter.declared {
+        return vec![];
+    };
+    let known = |name: &str| KERNEL_COMMANDS.contains(&name) || linter.known.iter().any(|k| k == name) || options.macros.get(name).is_some();
+    linter.uses.iter().filter(|(name, ..)| !known(name)).map(|usage| warn("unknown_command", "command", usage, &linter.known)).collect()
+}
+
+/// Check that the keys of `cite!` and `ref!` are known, and warn about each
+/// unknown key, with the known key it is likely a typo of.
+///
+/// Citation keys are known from [TexOptions::citations] and from the
+/// bibliography files of `bib!`, loaded with the loader of the options.
+/// Without known citation keys, citations are not checked. Labels are known
+/// from `<label>` in the document.
+pub fn lint_references(document: &ParsedValue, options: &TexOptions) -> Vec<Diagnostic> {
+    let linter = Linter::walk(document, options);
+    let mut warnings = vec![];
+    if !linter.keys.is_empty() {
+        let citations = linter.citations.iter().filter(|(key, ..)| !linter.keys.contains(key));
+        warnings.extend(citations.map(|usage| warn("unknown_citation", "citation key", usage, &linter.keys)));
+    };
+    let references = linter.references.iter().filter(|(label, ..)| !linter.labels.contains(label));
+    warnings.extend(references.map(|usage| warn("unknown_label", "label", usage, &linter.labels)));
+    warnings.sort_by_key(|warning| warning.at.map(|at| at.index));
+    warnings
+}
+
+/// A warning about a use of an unknown name of some kind.
+fn warn(code: &'static str, kind: &str, (name, at, include): &Use, known: &[String]) -> Diagnostic {
+    let mut message = match include {
+        Some(path) => format!("In {}: Unknown {} {}.", path, kind, name),
+        None => format!("Unknown {} {}.", kind, name),
+    };
+    if let Some(suggestion) = suggest(name, known) {
+        message.push_str(&format!(" Did you mean {}?", suggestion));
+    };
+    Diagnostic { code, message, at: Some(*at) }
+}
+
+/// The keys of the entries of a BibTeX file, like `knuth84` of
+/// `@book{knuth84, ...}`.
+fn bibliography_keys(source: &str) -> Vec<String> {
+    source.split('@').skip(1).filter_map(|entry| {
+        let (kind, rest) = entry.split_once(['{', '('])?;
+        let kind = kind.trim().to_lowercase();
+        if kind.is_empty() || kind == "comment" || kind == "string" || kind == "preamble" || !kind.chars().all(char::is_alphanumeric) {
+            return None;
+        };
+        let key = rest.split(',').next()?.trim();
+        if key.is_empty() { None } else { Some(key.to_string()) }
+    }).collect()
+}
+
+/// The known command that a name is likely a typo of, if any: the closest
+/// within two edits, or one that ends with the name, like `mathfrak` for
+/// `frak`.
+fn suggest<'a>(name: &str, known: &'a [String]) -> Option<&'a str> {
+    known.iter()
+        .map(|k| (k, distance(name, k)))
+        .filter(|(k, d)| *d <= 2 || (name.len() >= 3 && k.ends_with(name)))
+        .min_by_key(|(_, d)| *d)
+        .map(|(k, _)| k.as_str())
+}
+
+/// The edit distance of two names.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous + usize::from(x != *y);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// A use of a name, with its position and the document it is in, if
+/// included.
+type Use = (String, Position, Option<String>);
+
+struct Linter {
+    /// Known commands, with those declared and defined in the document.
+    known: Vec<String>,
+    /// Whether the document declares commands with `<preamble!>`.
+    declared: bool,
+    /// Commands used.
+    uses: Vec<Use>,
+    /// Known citation keys, with those of the bibliography files.
+    keys: Vec<String>,
+    /// Keys of `cite!`.
+    citations: Vec<Use>,
+    /// Labels of `<label>`.
+    labels: Vec<String>,
+    /// Labels of `ref!`.
+    references: Vec<Use>,
+    /// Prefix of the built-in macros.
+    prefix: String,
+    loader: Option<TexLoader>,
+    /// Paths of the documents being included.
+    includes: Vec<String>,
+}
+
+impl Linter {
+
+    fn walk(document: &ParsedValue, options: &TexOptions) -> Self {
+        let mut linter = Linter {
+            known: options.commands.clone(), declared: false, uses: vec![], keys: options.citations.clone(), citations: vec![],
+            labels: vec![], references: vec![], prefix: options.macro_prefix.clone(), loader: options.loader.clone(), includes: vec![],
+        };
+        linter.lint(document);
+        linter
+    }
+
+    fn lint(&mut self, value: &ParsedValue) {
+        match value {
+            ParsedValue::Nil(..) | ParsedValue::Text(..) => {}
+            ParsedValue::Tagged(tag, at, _) => self.lint_tag(tag, *at),
+            ParsedValue::Tuple(tuple, ..) => match tuple {
+                ParsedTuple::Unit => {}
+                ParsedTuple::Single(value) => self.lint(value),
+                ParsedTuple::Multiple(values) => values.iter().for_each(|v| self.lint(v)),
+            },
+            ParsedValue::Dictionary(dictionary, ..) => dictionary.iter_sorted(KeyOrder::Source).for_each(|(_, v)| self.lint(v)),
+            ParsedValue::List(list, ..) => list.elements.iter().for_each(|v| self.lint(v)),
+            ParsedValue::Compound(compound, ..) => compound.components.iter().for_each(|v| self.lint(v)),
+        }
+    }
+
+    fn lint_include(&mut self, argument: &ParsedValue) {
+        let path = match argument.as_text() {
+            Some(path) => path.as_str().to_string(),
+            None => return,
+        };
+        if self.includes.contains(&path) {
+            return;
+        };
+        let source = match &self.loader {
+            Some(loader) => loader(&path),
+            None => return,
+        };
+        if let Some(document) = source.ok().and_then(|source| parse_value_str(&source).ok()) {
+            self.includes.push(path);
+            self.lint(&document);
+            self.includes.pop();
+        };
+    }
+
+    /// Load the keys of a bibliography file of `<bib!>:file`, which is
+    /// `file.bib` if it has no extension.
+    fn lint_bibliography(&mut self, argument: &ParsedValue) {
+        let file = match argument.as_text() {
+            Some(file) => file.as_str(),
+            None => return,
+        };
+        let path = if file.ends_with(".bib") { file.to_string() } else { format!("{}.bib", file) };
+        if let Some(Ok(source)) = self.loader.as_ref().map(|loader| loader(&path)) {
+            self.keys.extend(bibliography_keys(&source));
+        };
+    }
+
+    fn lint_tag(&mut self, tag: &ParsedTaggedValue, at: Position) {
+        let name = tag.name();
+        let inner_value = tag.get();
+        let builtin = if name.ends_with('!') { name.strip_prefix(self.prefix.as_str()).unwrap_or_default() } else { "" };
+        if builtin == "preamble!" {
+            if let Some(names) = words(inner_value) {
+                self.known.extend(names.into_iter().map(String::from));
+                self.declared = true;
+            };
+        } else if builtin == "def!" {
+            let arguments: Vec<&ParsedValue> = inner_value.iter_as_tuple().collect();
+            if let Some((command, arguments)) = arguments.split_first() {
+                // The command is written as text or as a tag, like `<Log>`.
+                match command {
+                    ParsedValue::Text(text, ..) => self.known.push(text.as_str().to_string()),
+                    ParsedValue::Tagged(tag, ..) if tag.get().is_unit() => self.known.push(tag.name().to_string()),
+                    _ => {}
+                };
+                arguments.iter().for_each(|v| self.lint(v));
+            };
+        } else if builtin == "include!" {
+            self.lint_include(inner_value);
+        } else if builtin == "cite!" || builtin == "ref!" {
+            let include = self.includes.last().cloned();
+            let keys = words(inner_value).unwrap_or_default().into_iter().map(|key| (key.to_string(), at, include.clone()));
+            if builtin == "cite!" { self.citations.extend(keys) } else { self.references.extend(keys) };
+        } else if builtin == "bib!" {
+            self.lint_bibliography(inner_value);
+        } else if name.ends_with('!') || name == "$" || name == "n" || name == "p" {
+            self.lint(inner_value);
+        } else {
+            let command = name.trim_end_matches('\'');
+            if command == "label" {
+                if let Some(label) = inner_value.iter_as_tuple().last().and_then(|label| label.as_text()) {
+                    self.labels.push(label.as_str().to_string());
+                };
+            };
+            self.uses.push((command.to_string(), at, self.includes.last().cloned()));
+            self.lint(inner_value);
+        }
+    }
+
+}
@@ -1,5 +1,11 @@
 //! LaTeX preprocessor.
 
+mod lint;
+mod node;
+mod notation;
 mod preprocess;
 
+pub use lint::*;
+pub use node::*;
+pub use notation::*;
 pub use preprocess::*;
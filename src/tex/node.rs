@@ -0,0 +1,142 @@
+//! TeX node tree.
+//!
+//! The preprocessor writes a document as a tree of TeX nodes, which is then
+//! rendered. [build_tex_with] returns the tree, so that it can be changed
+//! before it is rendered with [render_tex], like to rewrite labels or to
+//! collect citations:
+//!
+//! ```
+//! # use khi::parse::parse_value_str;
+//! # use khi::tex::{build_tex_with, render_tex, BreakMode, TexNode, TexOptions};
+//! let document = parse_value_str("See <cite>:knuth84 and <cite>:lamport94 too.").unwrap();
+//! let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+//! let mut nodes = build_tex_with(&document, &options).ok().unwrap();
+//! let mut citations = vec![];
+//! for node in &nodes {
+//!     node.visit(&mut |node| if let TexNode::Command { name, arguments } = node {
+//!         if name == "cite" {
+//!             citations.push(render_tex(arguments));
+//!         };
+//!     });
+//! };
+//! assert_eq!(citations, vec!["{knuth84}", "{lamport94}"]);
+//! for node in &mut nodes {
+//!     node.visit_mut(&mut |node| if let TexNode::Command { name, .. } = node {
+//!         if name == "cite" {
+//!             *name = String::from("citep");
+//!         };
+//!     });
+//! };
+//! assert_eq!(render_tex(&nodes), "See \\citep{knuth84} and \\citep{lamport94} too.");
+//! ```
+
+/// A node of TeX.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TexNode {
+    /// TeX as it is, like text, spaces, newlines and symbols.
+    Text(String),
+    /// A command like `\frac{a}{b}`. The arguments are groups, or commands
+    /// written without braces, with the text between them, like newlines.
+    Command { name: String, arguments: Vec<TexNode> },
+    /// A group in `{}`, or an optional argument in `[]`.
+    Group { optional: bool, content: Vec<TexNode> },
+    /// An environment like `\begin{figure}[h]...\end{figure}` written by
+    /// `env!`, with its arguments.
+    Environment { name: String, arguments: Vec<TexNode>, content: Vec<TexNode> },
+    /// A table like `\begin{tabular}{ll}...\end{tabular}` written by
+    /// `tabular!` and the matrix macros. The content is rows and the text
+    /// between them, like `\\` and rules.
+    Tabular { environment: String, arguments: Vec<TexNode>, content: Vec<TexNode> },
+    /// A row of a table, with its cells separated by `&`.
+    Row(Vec<Vec<TexNode>>),
+}
+
+impl TexNode {
+
+    /// Render the node as TeX.
+    pub fn render(&self, output: &mut String) {
+        match self {
+            TexNode::Text(text) => output.push_str(text),
+            TexNode::Command { name, arguments } => {
+                output.push('\\');
+                output.push_str(name);
+                render_into(arguments, output);
+            }
+            TexNode::Group { optional, content } => {
+                output.push(if *optional { '[' } else { '{' });
+                render_into(content, output);
+                output.push(if *optional { ']' } else { '}' });
+            }
+            TexNode::Environment { name: environment, arguments, content } | TexNode::Tabular { environment, arguments, content } => {
+                output.push_str("\\begin{");
+                output.push_str(environment);
+                output.push('}');
+                render_into(arguments, output);
+                render_into(content, output);
+                output.push_str("\\end{");
+                output.push_str(environment);
+                output.push('}');
+            }
+            TexNode::Row(cells) => {
+                for (i, cell) in cells.iter().enumerate() {
+                    if i != 0 {
+                        output.push('&');
+                    };
+                    render_into(cell, output);
+                };
+            }
+        }
+    }
+
+    /// Call a function with this node and then with each node in it, in
+    /// order.
+    pub fn visit<F: FnMut(&TexNode)>(&self, f: &mut F) {
+        f(self);
+        for nodes in self.children() {
+            nodes.iter().for_each(|node| node.visit(f));
+        };
+    }
+
+    /// Call a function with this node and then with each node in it, in
+    /// order, to change them.
+    pub fn visit_mut<F: FnMut(&mut TexNode)>(&mut self, f: &mut F) {
+        f(self);
+        for nodes in self.children_mut() {
+            nodes.iter_mut().for_each(|node| node.visit_mut(f));
+        };
+    }
+
+    /// The sequences of nodes in this node.
+    pub fn children(&self) -> Vec<&Vec<TexNode>> {
+        match self {
+            TexNode::Text(..) => vec![],
+            TexNode::Command { arguments, .. } => vec![arguments],
+            TexNode::Group { content, .. } => vec![content],
+            TexNode::Environment { arguments, content, .. } | TexNode::Tabular { arguments, content, .. } => vec![arguments, content],
+            TexNode::Row(cells) => cells.iter().collect(),
+        }
+    }
+
+    /// The sequences of nodes in this node, to change them.
+    pub fn children_mut(&mut self) -> Vec<&mut Vec<TexNode>> {
+        match self {
+            TexNode::Text(..) => vec![],
+            TexNode::Command { arguments, .. } => vec![arguments],
+            TexNode::Group { content, .. } => vec![content],
+            TexNode::Environment { arguments, content, .. } | TexNode::Tabular { arguments, content, .. } => vec![arguments, content],
+            TexNode::Row(cells) => cells.iter_mut().collect(),
+        }
+    }
+
+}
+
+/// Render a tree of TeX nodes.
+pub fn render_tex(nodes: &[TexNode]) -> String {
+    let mut output = String::new();
+    render_into(nodes, &mut output);
+    output
+}
+
+fn render_into(nodes: &[TexNode], output: &mut String) {
+    nodes.iter().for_each(|node| node.render(output));
+}
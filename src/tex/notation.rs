@@ -0,0 +1,247 @@
+//! Notation index of a TeX document.
+//!
+//! Collects the commands defined with `def!` and the commands used in math
+//! mode, and exports them as a Khi document that serves as a notation index.
+
+use std::collections::HashMap;
+use crate::fmt::{format_dictionary, format_value};
+use crate::pdm::{KeyOrder, ParsedTaggedValue, ParsedTuple, ParsedValue, Position};
+use crate::parse::parse_value_str;
+use crate::tex::preprocess::{conditional, MATRIX_MACROS};
+use crate::tex::{TexLoader, TexOptions};
+use crate::{Tagged, Text, Value};
+
+/// Environments in which the content is in math mode.
+pub const MATH_ENVIRONMENTS: &[&str] = &[
+    "math", "displaymath", "equation", "equation*", "align", "align*", "alignat", "alignat*",
+    "gather", "gather*", "multline", "multline*", "flalign", "flalign*", "eqnarray", "eqnarray*",
+];
+
+/// Commands and symbols of a document.
+pub struct NotationIndex {
+    /// Commands defined with `def!`, in source order.
+    pub definitions: Vec<Definition>,
+    /// Commands used in math mode, in order of first use.
+    pub symbols: Vec<Symbol>,
+}
+
+/// A command defined with `def!`.
+pub struct Definition {
+    pub name: String,
+    pub arity: String,
+    /// The substitute, formatted as Khi.
+    pub body: String,
+    pub at: Position,
+}
+
+/// A command used in math mode.
+pub struct Symbol {
+    pub name: String,
+    /// Number of uses.
+    pub count: usize,
+    /// First use.
+    pub at: Position,
+}
+
+/// Collect the notation of a document.
+pub fn collect_notation(document: &ParsedValue) -> NotationIndex {
+    collect_notation_with(document, &TexOptions::default())
+}
+
+/// Collect the notation of a document written with options, which give the
+/// prefix of the built-in macros and the loader of included documents.
+///
+/// The positions of the notation of an included document are positions in
+/// that document. Documents that cannot be loaded or parsed are skipped.
+pub fn collect_notation_with(document: &ParsedValue, options: &TexOptions) -> NotationIndex {
+    let mut collector = Collector {
+        index: NotationIndex { definitions: vec![], symbols: vec![] }, environments: vec![], math: 0, prefix: options.macro_prefix.clone(),
+        loader: options.loader.clone(), includes: vec![], variables: options.variables.clone(),
+    };
+    collector.collect(document);
+    collector.index
+}
+
+impl NotationIndex {
+
+    /// Get a symbol by command name.
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// Get a definition by command name.
+    pub fn definition(&self, name: &str) -> Option<&Definition> {
+        self.definitions.iter().find(|d| d.name == name)
+    }
+
+    /// Export the index as a Khi dictionary document with the entries
+    /// `definitions` and `symbols`.
+    ///
+    /// Symbols defined in the document refer to their definition with a
+    /// `defined` entry.
+    pub fn to_khi(&self) -> String {
+        let definitions = self.definitions.iter().map(|d| {
+            ParsedValue::dictionary(vec![
+                ("name", ParsedValue::text(&d.name)),
+                ("arguments", ParsedValue::text(&d.arity)),
+                ("body", ParsedValue::text(&d.body)),
+                ("line", ParsedValue::text(&d.at.line.to_string())),
+            ])
+        }).collect();
+        let symbols = self.symbols.iter().map(|s| {
+            let mut entries = vec![
+                ("name", ParsedValue::text(&s.name)),
+                ("count", ParsedValue::text(&s.count.to_string())),
+                ("line", ParsedValue::text(&s.at.line.to_string())),
+            ];
+            if let Some(definition) = self.definition(&s.name) {
+                entries.push(("defined", ParsedValue::text(&definition.at.line.to_string())));
+            };
+            ParsedValue::dictionary(entries)
+        }).collect();
+        let document = ParsedValue::dictionary(vec![
+            ("definitions", ParsedValue::list(definitions)),
+            ("symbols", ParsedValue::list(symbols)),
+        ]);
+        format_dictionary(document.as_dictionary().unwrap())
+    }
+
+}
+
+struct Collector {
+    index: NotationIndex,
+    /// Open environments.
+    environments: Vec<String>,
+    /// Math mode nesting.
+    math: usize,
+    /// Prefix of the built-in macros.
+    prefix: String,
+    loader: Option<TexLoader>,
+    /// Paths of the documents being included.
+    includes: Vec<String>,
+    /// Variables of the document, which choose the content of `if!`.
+    variables: HashMap<String, String>,
+}
+
+impl Collector {
+
+    fn collect(&mut self, value: &ParsedValue) {
+        match value {
+            ParsedValue::Nil(..) | ParsedValue::Text(..) => {}
+            ParsedValue::Tagged(tag, at, _) => self.collect_tag(tag, *at),
+            ParsedValue::Tuple(tuple, ..) => match tuple {
+                ParsedTuple::Unit => {}
+                ParsedTuple::Single(value) => self.collect(value),
+                ParsedTuple::Multiple(values) => values.iter().for_each(|v| self.collect(v)),
+            },
+            ParsedValue::Dictionary(dictionary, ..) => dictionary.iter_sorted(KeyOrder::Source).for_each(|(_, v)| self.collect(v)),
+            ParsedValue::List(list, ..) => list.elements.iter().for_each(|v| self.collect(v)),
+            ParsedValue::Compound(compound, ..) => compound.components.iter().for_each(|v| self.collect(v)),
+        }
+    }
+
+    fn collect_include(&mut self, argument: &ParsedValue) {
+        let path = match argument.as_text() {
+            Some(path) => path.as_str().to_string(),
+            None => return,
+        };
+        if self.includes.contains(&path) {
+            return;
+        };
+        let source = match &self.loader {
+            Some(loader) => loader(&path),
+            None => return,
+        };
+        if let Some(document) = source.ok().and_then(|source| parse_value_str(&source).ok()) {
+            self.includes.push(path);
+            self.collect(&document);
+            self.includes.pop();
+        };
+    }
+
+    fn collect_tag(&mut self, tag: &ParsedTaggedValue, at: Position) {
+        let name = tag.name();
+        let inner_value = tag.get();
+        let builtin = if name.ends_with('!') { name.strip_prefix(self.prefix.as_str()).unwrap_or_default() } else { "" };
+        if builtin == "def!" {
+            let arguments: Vec<&ParsedValue> = inner_value.iter_as_tuple().collect();
+            // A default of the first argument may come before the substitute.
+            if let [tag, arity, substitute] | [tag, arity, _, substitute] = arguments[..] {
+                // The command is written as text or as a tag, like `<Log>`.
+                let command = match tag {
+                    ParsedValue::Text(text, ..) => Some(text.as_str()),
+                    ParsedValue::Tagged(tag, ..) if tag.get().is_unit() => Some(tag.name()),
+                    _ => None,
+                };
+                if let Some(command) = command {
+                    self.index.definitions.push(Definition {
+                        name: command.to_string(),
+                        arity: arity.as_text().map(|a| a.as_str().to_string()).unwrap_or_default(),
+                        body: format_value(substitute),
+                        at,
+                    });
+                };
+            };
+        } else if builtin == "env!" {
+            let arguments: Vec<&ParsedValue> = inner_value.iter_as_tuple().collect();
+            if let [environment, arguments @ .., content] = &arguments[..] {
+                let environment = environment.as_text().map(|e| e.as_str().trim_end_matches('\''));
+                let math = environment.map_or(false, |e| MATH_ENVIRONMENTS.contains(&e));
+                for argument in arguments {
+                    self.collect(argument);
+                };
+                if math {
+                    self.math += 1;
+                };
+                self.collect(content);
+                if math {
+                    self.math -= 1;
+                };
+            };
+        } else if builtin == "tabular!" || builtin == "sup!" || builtin == "sub!" {
+            self.collect(inner_value);
+        } else if MATRIX_MACROS.iter().any(|(name, _)| *name == builtin) {
+            self.math += 1;
+            self.collect(inner_value);
+            self.math -= 1;
+        } else if builtin == "include!" {
+            self.collect_include(inner_value);
+        } else if builtin == "if!" || builtin == "ifdef!" {
+            if let Ok(Some(branch)) = conditional(builtin, inner_value, &self.variables) {
+                self.collect(branch);
+            };
+        } else if name.ends_with('!') {
+            // Other macros write raw text.
+        } else if name == "$" {
+            self.math += 1;
+            self.collect(inner_value);
+            self.math -= 1;
+        } else if name == "begin" || name == "end" {
+            let environment = inner_value.iter_as_tuple().next().and_then(|e| e.as_text()).map(|e| e.as_str().to_string());
+            if let Some(environment) = environment {
+                let math = MATH_ENVIRONMENTS.contains(&environment.as_str());
+                if name == "begin" {
+                    if math {
+                        self.math += 1;
+                    };
+                    self.environments.push(environment);
+                } else if self.environments.last() == Some(&environment) {
+                    if math {
+                        self.math -= 1;
+                    };
+                    self.environments.pop();
+                };
+            };
+        } else {
+            if self.math > 0 && name != "n" {
+                let name = name.trim_end_matches('\'');
+                match self.index.symbols.iter_mut().find(|s| s.name == name) {
+                    Some(symbol) => symbol.count += 1,
+                    None => self.index.symbols.push(Symbol { name: name.to_string(), count: 1, at }),
+                };
+            };
+            self.collect(inner_value);
+        }
+    }
+
+}
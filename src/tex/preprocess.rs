@@ -1,43 +1,505 @@
 // '%' must be inserted as "\%". % indicates a TeX comment.
 // '$' => '\$'
-// '^' must be inserted as "\^". ^ is the superscript operator in math mode, and reserved in text mode.
-// '_' must be inserted as "\_". _ is the subscript operator in math mode, and reserved in text mode.
+// '^' must be inserted as "\^{}" in text mode. ^ is the superscript operator in math mode, and reserved in text mode.
+// '_' must be inserted as "\_" in text mode. _ is the subscript operator in math mode, and reserved in text mode.
 // '&' must be inserted as "\&". & is the tabulation operator.
 // '#' must be inserted as "\#". # is the argument substitution operator.
 // '\' must be inserted as "\textbackslash" in text and "\backslash" or "\setminus" in math. "\\" indicates a line break.
+//
+// The writer tracks math mode through `<$>`, math environments and text
+// commands. In the substitute of `def!`, the mode is not known, and `#`, `^`,
+// `_` and `\` are written as they are, like in arguments that name things,
+// such as labels and files. `raw!` writes text without escaping.
 
-use std::fmt::Write;
-use crate::pdm::{ParsedList, ParsedTaggedValue, ParsedValue, Position};
-use crate::{Compound, Element, List, Tagged, Text, Tuple, Value};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter, Write};
+use std::rc::Rc;
+use unicode_width::UnicodeWidthChar;
+use crate::fmt::{format_dictionary, preview, PREVIEW_LENGTH};
+use crate::lex::{lex_with_comments, Comment};
+use crate::parse::{parse_value_str, Diagnostic};
+use crate::parse::parser::error_to_string;
+use crate::pdm::{KeyOrder, ParsedDictionary, ParsedList, ParsedTaggedValue, ParsedValue, Position};
+use crate::tex::{render_tex, TexNode, MATH_ENVIRONMENTS};
+use crate::{Attribute, Compound, Element, List, Tagged, Text, Tuple, Value};
+
+/// Commands whose arguments are in text mode, also in math mode.
+const TEXT_COMMANDS: &[&str] = &[
+    "text", "textrm", "textit", "textbf", "textsf", "texttt", "textup", "textsl", "textsc", "textnormal", "mbox", "intertext",
+];
+
+/// Tags whose text is written as a verbatim environment, with the name of
+/// the environment.
+const VERBATIM_TAGS: &[(&str, &str)] = &[("verbatim", "verbatim"), ("code", "lstlisting")];
+
+/// Macros that write a table as a math environment, with the name of the
+/// environment.
+pub(crate) const MATRIX_MACROS: &[(&str, &str)] = &[
+    ("matrix!", "matrix"), ("pmatrix!", "pmatrix"), ("bmatrix!", "bmatrix"), ("vmatrix!", "vmatrix"), ("cases!", "cases"),
+];
+
+/// Commands whose arguments name things, and are written without escaping
+/// `#`, `^`, `_` and `\`.
+const NAMING_COMMANDS: &[&str] = &[
+    "begin", "end", "label", "ref", "eqref", "pageref", "cite", "url", "href", "includegraphics", "input", "include",
+    "documentclass", "usepackage", "bibliography", "bibliographystyle",
+];
+
+/// The content of `<if!>:variable:value:then:else` and
+/// `<ifdef!>:variable:then:else` to write with the variables, if any. The
+/// content otherwise is optional.
+pub(crate) fn conditional<'v>(name: &str, value: &'v ParsedValue, variables: &HashMap<String, String>) -> Result<Option<&'v ParsedValue>, String> {
+    let arguments: Vec<&ParsedValue> = value.iter_as_tuple().collect();
+    let (variable, expected, branches) = match (name, &arguments[..]) {
+        ("if!", [variable, expected, branches @ ..]) if (1..=2).contains(&branches.len()) => (*variable, Some(*expected), branches),
+        ("ifdef!", [variable, branches @ ..]) if (1..=2).contains(&branches.len()) => (*variable, None, branches),
+        ("if!", _) => return Err(format!("if! must take a variable, a value and content, got {}.", preview(value, PREVIEW_LENGTH))),
+        _ => return Err(format!("ifdef! must take a variable and content, got {}.", preview(value, PREVIEW_LENGTH))),
+    };
+    let variable = match variable.as_text() {
+        Some(variable) => variables.get(variable.as_str()),
+        None => return Err(format!("{} must take a variable name, got {}.", name, preview(variable, PREVIEW_LENGTH))),
+    };
+    let holds = match expected {
+        Some(expected) => match expected.as_text() {
+            Some(expected) => variable.map_or(false, |variable| variable == expected.as_str()),
+            None => return Err(format!("if! must take a value, got {}.", preview(expected, PREVIEW_LENGTH))),
+        },
+        None => variable.is_some(),
+    };
+    Ok(if holds { branches.first().copied() } else { branches.get(1).copied() })
+}
+
+/// The words of a list like `[a; b]` or of a text like `{a b}`, like the
+/// commands of `preamble!` and the keys of `cite!`.
+pub(crate) fn words(value: &ParsedValue) -> Option<Vec<&str>> {
+    let mut names = vec![];
+    match value {
+        ParsedValue::Text(text, ..) => names.extend(text.as_str().split_whitespace()),
+        ParsedValue::List(list, ..) => for element in &list.elements {
+            names.extend(element.as_text()?.as_str().split_whitespace());
+        },
+        value if value.is_unit() => {}
+        _ => return None,
+    };
+    Some(names)
+}
 
 pub fn write_tex(structure: &ParsedValue) -> Result<String, PreprocessorError> {
-    write_tex_with(structure, BreakMode::Mirror)
+    write_tex_with(structure, &TexOptions::default())
+}
+
+pub fn write_tex_with(structure: &ParsedValue, options: &TexOptions) -> Result<String, PreprocessorError> {
+    build_tex_with(structure, options).map(|nodes| render_tex(&nodes))
 }
 
-pub fn write_tex_with(structure: &ParsedValue, mode: BreakMode) -> Result<String, PreprocessorError> {
+/// Write a document as a tree of TeX nodes, to be rendered with
+/// [render_tex].
+pub fn build_tex_with(structure: &ParsedValue, options: &TexOptions) -> Result<Vec<TexNode>, PreprocessorError> {
     let mut output = String::new();
-    let mut writer = Writer { output: &mut output, column: 1, break_mode: mode, last_type: LastType::Whitespace, line: 1 };
+    let mut writer = Writer::new(&mut output, options);
     writer.write_inner(structure)?;
-    Ok(output)
+    Ok(writer.finish())
+}
+
+/// Write a document, and map the lines of the output to the source.
+///
+/// With [TexOptions::annotate], each line that the writer breaks ends in a
+/// comment `%&khi:<line>` with the line in the source. The comment follows a
+/// space, so that it does not change the document.
+pub fn write_tex_mapped(structure: &ParsedValue, options: &TexOptions) -> Result<(String, SourceMap), PreprocessorError> {
+    let mut output = String::new();
+    let mut writer = Writer::new(&mut output, options);
+    writer.source_map = Some(SourceMap::default());
+    writer.write_inner(structure)?;
+    let source_map = writer.source_map.take().unwrap_or_default();
+    Ok((render_tex(&writer.finish()), source_map))
+}
+
+/// Write a document with the source it was parsed from, and map the lines of
+/// the output to the source.
+///
+/// The source is required to find the empty lines that end paragraphs with
+/// [TexOptions::paragraphs], and the comments written with
+/// [TexOptions::comments].
+pub fn write_tex_source(source: &str, structure: &ParsedValue, options: &TexOptions) -> Result<(String, SourceMap), PreprocessorError> {
+    let mut output = String::new();
+    let mut writer = Writer::new(&mut output, options);
+    writer.source = Some(Source::new(source, options.comments));
+    writer.source_map = Some(SourceMap::default());
+    writer.write_inner(structure)?;
+    writer.write_trailing_comments();
+    let source_map = writer.source_map.take().unwrap_or_default();
+    Ok((render_tex(&writer.finish()), source_map))
+}
+
+/// Write a document, and report the lines of the output and the macros and
+/// commands written, to find the values that dominate a document.
+///
+/// The uses of macros and commands in an included document are at the
+/// `include!` of the document.
+pub fn write_tex_report(structure: &ParsedValue, options: &TexOptions) -> Result<(String, TexReport), PreprocessorError> {
+    let mut output = String::new();
+    let mut writer = Writer::new(&mut output, options);
+    writer.source_map = Some(SourceMap::default());
+    writer.expansions = Some(vec![]);
+    writer.write_inner(structure)?;
+    let source_map = writer.source_map.take().unwrap_or_default();
+    let expansions = writer.expansions.take().unwrap_or_default();
+    let output = render_tex(&writer.finish());
+    let lines = if output.is_empty() { 0 } else { output.matches('\n').count() + 1 };
+    Ok((output, TexReport { lines, source_map, expansions }))
+}
+
+/// Options for the preprocessor.
+#[derive(Clone)]
+pub struct TexOptions {
+    /// Insertion of newlines.
+    pub break_mode: BreakMode,
+    /// The column after which [BreakMode::Margin] converts spaces to
+    /// newlines. Columns count the display width of characters, so that a
+    /// wide character, like CJK, takes 2 columns.
+    pub margin: usize,
+    /// Break lines in math mode with [BreakMode::Margin]. Otherwise, lines
+    /// are only broken outside of math. On by default.
+    pub break_math: bool,
+    /// Writing of the lines of the source without values, with
+    /// [BreakMode::Mirror].
+    pub newlines: Newlines,
+    /// Prefix of the names of the built-in macros, like `k.` for `<k.env!>`.
+    /// Empty by default.
+    pub macro_prefix: String,
+    /// Reject unknown macros. Otherwise, the tag of an unknown macro is
+    /// ignored, and its value is written. On by default.
+    pub strict: bool,
+    /// Escaping of text.
+    pub escaping: Escaping,
+    /// Writing of superscripts and subscripts in the text of math mode.
+    pub scripts: Scripts,
+    /// Macros of the caller, which are consulted before the built-in macros
+    /// and commands.
+    pub macros: TexMacroRegistry,
+    /// End lines with the line in the source, with [write_tex_mapped].
+    pub annotate: bool,
+    /// Write `\par` at the empty lines of the source, in text mode outside
+    /// of arguments and of the preamble, with [write_tex_source].
+    pub paragraphs: bool,
+    /// Write the comments of the source as `%` comments on the same lines,
+    /// with [write_tex_source], [BreakMode::Mirror] and
+    /// [Newlines::Commented].
+    pub comments: bool,
+    /// Loader of the documents included with `include!`. Without a loader,
+    /// `include!` is an error.
+    pub loader: Option<TexLoader>,
+    /// Variables of the document, which `if!` and `ifdef!` test, like
+    /// `mode` with the value `beamer`.
+    pub variables: HashMap<String, String>,
+    /// Known citation keys, against which [lint_references] checks the keys
+    /// of `cite!`, with those in the bibliography files of `bib!`.
+    ///
+    /// [lint_references]: crate::tex::lint_references
+    pub citations: Vec<String>,
+    /// Known commands, against which [lint_commands] checks the commands of
+    /// a document, with those declared with `<preamble!>`.
+    ///
+    /// [lint_commands]: crate::tex::lint_commands
+    pub commands: Vec<String>,
+}
+
+/// Load the source of a document included with `<include!>:path` by path.
+pub type TexLoader = Rc<dyn Fn(&str) -> Result<String, String>>;
+
+impl Default for TexOptions {
+
+    fn default() -> Self {
+        TexOptions {
+            break_mode: BreakMode::Mirror, margin: 80, break_math: true, newlines: Newlines::Commented, macro_prefix: String::new(), strict: true,
+            escaping: Escaping::Mode, scripts: Scripts::Glued, macros: TexMacroRegistry::new(), annotate: false, paragraphs: false,
+            comments: false, loader: None, variables: HashMap::new(), citations: vec![], commands: vec![],
+        }
+    }
+
+}
+
+/// Positions in the source of the lines of a written document.
+///
+/// A line of the output maps to the first value written on it, and the
+/// lines of text written by macros like `raw!` map to the macro.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// Lines of the output, counting from 1, with the position of the first
+    /// value on them, in order.
+    lines: Vec<(usize, Position)>,
+}
+
+impl SourceMap {
+
+    /// Get the position in the source of a line of the output, counting
+    /// from 1.
+    pub fn source(&self, line: usize) -> Option<Position> {
+        let index = self.lines.partition_point(|(l, _)| *l <= line);
+        index.checked_sub(1).map(|index| self.lines[index].1)
+    }
+
+    /// Iterate over the lines of the output that start a value, with the
+    /// position of the value.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Position)> + '_ {
+        self.lines.iter().copied()
+    }
+
+}
+
+/// Statistics of a written document.
+pub struct TexReport {
+    /// Number of lines of the output.
+    pub lines: usize,
+    /// Positions in the source of the lines of the output.
+    pub source_map: SourceMap,
+    /// Macros and commands written, in order of first use.
+    pub expansions: Vec<Expansion>,
+}
+
+/// A macro or command written.
+pub struct Expansion {
+    /// Tag name, like `env!` or `frac`.
+    pub name: String,
+    /// Positions of the uses, in order of writing.
+    pub uses: Vec<Position>,
+}
+
+impl TexReport {
+
+    /// Get an expansion by tag name.
+    pub fn expansion(&self, name: &str) -> Option<&Expansion> {
+        self.expansions.iter().find(|e| e.name == name)
+    }
+
+    /// Number of uses of a macro or command by tag name.
+    pub fn count(&self, name: &str) -> usize {
+        self.expansion(name).map_or(0, |e| e.uses.len())
+    }
+
+    /// Export the report as a Khi dictionary document with the entries
+    /// `lines`, `expansions` and `map`.
+    ///
+    /// Expansions are listed by number of uses, most used first, with the
+    /// lines of the uses. The map lists the lines of the output that start a
+    /// value, with the line of the value in the source.
+    pub fn to_khi(&self) -> String {
+        let mut expansions: Vec<&Expansion> = self.expansions.iter().collect();
+        expansions.sort_by_key(|e| std::cmp::Reverse(e.uses.len()));
+        let expansions = expansions.into_iter().map(|e| {
+            let mut lines: Vec<usize> = e.uses.iter().map(|at| at.line).collect();
+            lines.dedup();
+            ParsedValue::dictionary(vec![
+                ("name", ParsedValue::text(&e.name)),
+                ("count", ParsedValue::text(&e.uses.len().to_string())),
+                ("lines", ParsedValue::list(lines.iter().map(|line| ParsedValue::text(&line.to_string())).collect())),
+            ])
+        }).collect();
+        let map = self.source_map.iter().map(|(line, at)| {
+            ParsedValue::dictionary(vec![
+                ("line", ParsedValue::text(&line.to_string())),
+                ("source", ParsedValue::text(&at.line.to_string())),
+            ])
+        }).collect();
+        let document = ParsedValue::dictionary(vec![
+            ("lines", ParsedValue::text(&self.lines.to_string())),
+            ("expansions", ParsedValue::list(expansions)),
+            ("map", ParsedValue::list(map)),
+        ]);
+        format_dictionary(document.as_dictionary().unwrap())
+    }
+
+}
+
+/// A macro of the caller, which writes a tagged value.
+pub type TexMacro = fn(&ParsedTaggedValue, &mut Writer) -> Result<(), PreprocessorError>;
+
+/// Macros of the caller by tag name.
+///
+/// A registered name takes the place of a built-in macro or command of the
+/// same name, so `raw!` may be replaced, and `abs!` may be added:
+///
+/// ```
+/// # use khi::parse::parse_value_str;
+/// # use khi::tex::{write_tex_with, BreakMode, TexMacroRegistry, TexOptions};
+/// let mut macros = TexMacroRegistry::new();
+/// macros.register("abs!", |tag, writer| {
+///     writer.write_raw("\\left|");
+///     writer.write_value(&tag.value)?;
+///     writer.write_raw("\\right|");
+///     Ok(())
+/// });
+/// let document = parse_value_str("<$>:<abs!>:{x_1}").unwrap();
+/// let options = TexOptions { break_mode: BreakMode::Never, macros, ..TexOptions::default() };
+/// let tex = write_tex_with(&document, &options).ok().unwrap();
+/// assert_eq!(tex, "$\\left|x_1\\right|$");
+/// ```
+#[derive(Clone, Default)]
+pub struct TexMacroRegistry {
+    macros: HashMap<String, TexMacro>,
+}
+
+impl TexMacroRegistry {
+
+    pub fn new() -> Self {
+        TexMacroRegistry { macros: HashMap::new() }
+    }
+
+    /// Register a macro, replacing any macro of the same name.
+    pub fn register(&mut self, name: &str, handler: TexMacro) {
+        self.macros.insert(name.to_string(), handler);
+    }
+
+    /// Get a macro by name.
+    pub fn get(&self, name: &str) -> Option<TexMacro> {
+        self.macros.get(name).copied()
+    }
+
 }
 
 pub struct Writer<'a> {
     output: &'a mut String,
     column: usize,
-    break_mode: BreakMode,
     last_type: LastType,
     line: usize, // Last line read in the source file
+    /// Last line of the source with a value.
+    source_line: usize,
+    /// Math mode nesting.
+    math: usize,
+    /// Open environments.
+    environments: Vec<String>,
+    /// Nesting of `def!` substitutes and naming arguments.
+    verbatim: usize,
+    source_map: Option<SourceMap>,
+    /// Macros and commands written, with [write_tex_report].
+    expansions: Option<Vec<Expansion>>,
+    /// The position of the value being written.
+    position: Position,
+    options: &'a TexOptions,
+    /// Paths of the documents being included, outermost first.
+    includes: Vec<String>,
+    /// Write `\par` before the next value.
+    paragraph: bool,
+    /// Nesting of arguments.
+    arguments: usize,
+    /// Between `\documentclass` and `\begin{document}`.
+    preamble: bool,
+    source: Option<Source<'a>>,
+    /// The nodes being written, innermost last. The first is the document.
+    tree: Vec<Frame>,
+}
+
+/// A node being written, with the nodes written in it.
+struct Frame {
+    kind: FrameKind,
+    nodes: Vec<TexNode>,
+    /// The arguments of an environment or the cells of a row that are
+    /// written, before the nodes.
+    parts: Vec<Vec<TexNode>>,
+    /// The offset in the output after the nodes.
+    last: usize,
+}
+
+enum FrameKind {
+    Document,
+    Command(String),
+    Group(bool),
+    Environment(String),
+    Tabular(String),
+    Row,
+}
+
+/// The source of a document.
+struct Source<'a> {
+    text: &'a str,
+    /// Byte offsets of the characters counted by positions, which skip
+    /// carriage returns.
+    offsets: Vec<usize>,
+    /// The comments of the source, if written, and the index of the first
+    /// that is not written yet.
+    comments: Vec<Comment>,
+    comment: usize,
+}
+
+impl<'a> Source<'a> {
+
+    fn new(text: &'a str, comments: bool) -> Self {
+        let offsets = text.char_indices().filter(|(_, c)| *c != '\r').map(|(offset, _)| offset).collect();
+        let comments = match comments {
+            true => lex_with_comments(text.chars()).map(|(_, comments)| comments).unwrap_or_default(),
+            false => vec![],
+        };
+        Source { text, offsets, comments, comment: 0 }
+    }
+
+    /// Get the source from a position to another.
+    fn slice(&self, from: Position, to: Position) -> &'a str {
+        let offset = |position: Position| self.offsets.get(position.index).copied().unwrap_or(self.text.len());
+        let (from, to) = (offset(from), offset(to));
+        &self.text[from.min(to)..to]
+    }
+
+}
+
+/// The number of columns that a character takes in a terminal or an editor,
+/// which is 2 for wide characters, like CJK, and 0 for combining characters.
+fn width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Whether a trimmed line of the source is only a comment.
+fn is_comment(line: &str) -> bool {
+    line == "#" || line.starts_with("# ")
+}
+
+/// Writing of superscripts and subscripts in the text of math mode.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scripts {
+    /// Write `^` and `_` as they are, so that `x^ab` is `x` to the power of
+    /// `a`, followed by `b`, as in TeX.
+    #[default]
+    Glued,
+    /// Group a script of several letters or digits, with an optional `-`,
+    /// so that `x^ab` is written as `x^{ab}` and `x^-1` as `x^{-1}`.
+    Grouped,
 }
 
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 pub enum BreakMode {
     /// Do not insert newlines.
     Never,
-    /// Convert spaces to newlines after reaching a margin.
-    Margin(usize),
+    /// Convert spaces to newlines after reaching the margin.
+    Margin,
     /// Convert spaces to newlines to mirror the input Khi document.
+    #[default]
     Mirror
 }
 
+/// Writing of the lines of the source without values.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Newlines {
+    /// Write a comment line `%` for each of them, so that the lines of the
+    /// output are the lines of the source. An empty line would end a
+    /// paragraph.
+    #[default]
+    Commented,
+    /// Skip them, and write a single newline before the next value.
+    Collapsed,
+}
+
+/// Escaping of text.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Escaping {
+    /// Escape text for the mode it is in.
+    #[default]
+    Mode,
+    /// Escape only `$`, `%` and `&`, and write `#`, `^`, `_` and `\` as they
+    /// are, like in arguments that name things.
+    Minimal,
+}
+
 #[derive(Eq, PartialEq)]
 enum LastType {
     Newline,
@@ -48,7 +510,89 @@ enum LastType {
     Command,
 }
 
-impl Writer<'_> {
+impl<'a> Writer<'a> {
+
+    fn new(output: &'a mut String, options: &'a TexOptions) -> Self {
+        Writer {
+            output, column: 1, last_type: LastType::Whitespace, line: 1, source_line: 1, math: 0, environments: vec![], verbatim: 0,
+            source_map: None, expansions: None, position: Position::ZERO, options, includes: vec![],
+            paragraph: false, arguments: 0, preamble: false, source: None,
+            tree: vec![Frame { kind: FrameKind::Document, nodes: vec![], parts: vec![], last: 0 }],
+        }
+    }
+
+    /// Add the output up to an offset to the node being written as text.
+    fn flush_text(&mut self, to: usize) {
+        let frame = self.tree.last_mut().unwrap();
+        let to = to.min(self.output.len());
+        if frame.last < to {
+            let text = &self.output[frame.last..to];
+            match frame.nodes.last_mut() {
+                Some(TexNode::Text(last)) => last.push_str(text),
+                _ => frame.nodes.push(TexNode::Text(text.to_string())),
+            };
+            frame.last = to;
+        };
+    }
+
+    /// Start a node, which begins at an offset of the output. The output
+    /// after the offset, like `\begin{name}`, is implied by the node.
+    fn open_node(&mut self, kind: FrameKind, start: usize) {
+        self.flush_text(start);
+        self.tree.push(Frame { kind, nodes: vec![], parts: vec![], last: self.output.len() });
+    }
+
+    /// End the arguments of an environment, or a cell of a row, at an offset
+    /// of the output. The output after the offset, like `&`, is implied.
+    fn split_node(&mut self, end: usize) {
+        self.flush_text(end);
+        let frame = self.tree.last_mut().unwrap();
+        frame.parts.push(std::mem::take(&mut frame.nodes));
+        frame.last = self.output.len();
+    }
+
+    /// End a node, whose content ends at an offset of the output. The output
+    /// after the offset, like `\end{name}`, is implied by the node.
+    fn close_node(&mut self, end: usize) {
+        self.flush_text(end);
+        let Frame { kind, nodes, mut parts, .. } = match self.tree.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let node = match kind {
+            FrameKind::Document => {
+                self.tree.push(Frame { kind, nodes, parts, last: end });
+                return;
+            }
+            FrameKind::Command(name) => TexNode::Command { name, arguments: nodes },
+            FrameKind::Group(optional) => TexNode::Group { optional, content: nodes },
+            FrameKind::Environment(name) => TexNode::Environment { name, arguments: parts.pop().unwrap_or_default(), content: nodes },
+            FrameKind::Tabular(environment) => TexNode::Tabular { environment, arguments: parts.pop().unwrap_or_default(), content: nodes },
+            FrameKind::Row => {
+                parts.push(nodes);
+                TexNode::Row(parts)
+            }
+        };
+        let frame = self.tree.last_mut().unwrap();
+        frame.nodes.push(node);
+        frame.last = self.output.len();
+    }
+
+    /// Add the nodes of a document written on its own, whose output begins
+    /// at an offset of the output.
+    fn splice_nodes(&mut self, nodes: Vec<TexNode>, start: usize) {
+        self.flush_text(start);
+        let frame = self.tree.last_mut().unwrap();
+        frame.nodes.extend(nodes);
+        frame.last = self.output.len();
+    }
+
+    /// The nodes of the document.
+    fn finish(mut self) -> Vec<TexNode> {
+        self.flush_text(self.output.len());
+        self.tree.truncate(1);
+        self.tree.pop().map(|frame| frame.nodes).unwrap_or_default()
+    }
 
     fn push(&mut self, char: char) {
         if char.is_whitespace() {
@@ -79,48 +623,79 @@ impl Writer<'_> {
         } else {
             self.output.push(char);
             self.last_type = LastType::Glyph;
-            self.column += 1;
+            self.column += width(char);
         };
     }
 
     fn contract_opportunity(&mut self) {
-        while self.output.ends_with(' ') {
+        // The output of the nodes already written is kept.
+        let last = self.tree.last().map_or(0, |frame| frame.last);
+        while self.output.len() > last && self.output.ends_with(' ') {
             self.output.pop();
         }
     }
 
     fn normalize_and_push_str(&mut self, str: &str) {
-        for c in str.chars() {
-            if c == '$' {
-                self.output.push('\\');
-                self.output.push('$');
-                self.last_type = LastType::Glyph;
-                self.column += 2;
-            } else if c == '%' {
-                self.output.push('\\');
-                self.output.push('%');
-                self.last_type = LastType::Glyph;
-                self.column += 2;
-            } else if c == '&' {
-                self.output.push('\\');
-                self.output.push('&');
-                self.last_type = LastType::Glyph;
-                self.column += 2;
-            } else {
-                self.push(c);
+        let scripts = self.verbatim == 0 && self.math > 0 && self.options.scripts == Scripts::Grouped;
+        let verbatim = self.verbatim > 0 || self.options.escaping == Escaping::Minimal;
+        let mut chars = str.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '^' | '_' if scripts => {
+                    self.push(c);
+                    // A script of several characters is grouped, like `x^{-1}`.
+                    let mut script = String::new();
+                    if let Some(sign) = chars.next_if_eq(&'-') {
+                        script.push(sign);
+                    };
+                    while let Some(c) = chars.next_if(|c| c.is_alphanumeric()) {
+                        script.push(c);
+                    };
+                    if script.chars().count() > 1 {
+                        self.push('{');
+                        self.push_str(&script);
+                        self.push('}');
+                    } else {
+                        self.push_str(&script);
+                    };
+                }
+                '$' => self.push_escaped("\\$"),
+                '%' => self.push_escaped("\\%"),
+                '&' => self.push_escaped("\\&"),
+                '#' if !verbatim => self.push_escaped("\\#"),
+                '_' if !verbatim && self.math == 0 => self.push_escaped("\\_"),
+                '^' if !verbatim && self.math == 0 => self.push_escaped("\\^{}"),
+                '\\' if !verbatim && self.math == 0 => self.push_escaped("\\textbackslash{}"),
+                '\\' if !verbatim => self.push_escaped("\\backslash{}"),
+                c => self.push(c),
             }
         }
     }
 
+    /// Push an escape sequence of a character.
+    fn push_escaped(&mut self, escaped: &str) {
+        self.output.push_str(escaped);
+        self.last_type = LastType::Glyph;
+        self.column += escaped.len();
+    }
+
+    /// Push characters without escaping, like command names.
+    fn push_str(&mut self, str: &str) {
+        for c in str.chars() {
+            self.push(c);
+        }
+    }
+
     /// React to the position of a value.
     fn break_opportunity(&mut self, position: Position) {
         let at_line = position.line;
-        match self.break_mode {
+        match self.options.break_mode {
             BreakMode::Never => {}
-            BreakMode::Margin(margin) => {
-                if margin < self.column {
+            BreakMode::Margin => {
+                if self.options.margin < self.column && (self.options.break_math || self.math == 0) {
                     if !matches!(self.last_type, LastType::Newline) {
                         self.contract_opportunity();
+                        self.annotate_line();
                         self.output.push('\n');
                         self.line += 1;
                         self.last_type = LastType::Newline;
@@ -128,24 +703,209 @@ impl Writer<'_> {
                     }
                 }
             }
+            BreakMode::Mirror if self.options.newlines == Newlines::Collapsed => {
+                if self.source_line < at_line && !matches!(self.last_type, LastType::Newline) {
+                    self.contract_opportunity();
+                    self.annotate_line();
+                    self.output.push('\n');
+                    self.line += 1;
+                    self.last_type = LastType::Newline;
+                    self.column = 1;
+                }
+            }
             BreakMode::Mirror => {
                 if self.line < at_line {
+                    let comment = self.take_comment(self.line);
                     if matches!(self.last_type, LastType::Newline) {
-                        self.output.push_str("%\n");
+                        self.output.push_str(comment.as_deref().unwrap_or("%"));
+                        self.output.push('\n');
                     } else {
                         self.contract_opportunity();
+                        if let Some(comment) = comment {
+                            if !self.output.is_empty() {
+                                self.output.push(' ');
+                            };
+                            self.output.push_str(&comment);
+                        };
+                        self.annotate_line();
                         self.output.push('\n');
                     }
                     self.line += 1;
                     self.last_type = LastType::Newline;
                     self.column = 1;
                     while self.line < at_line {
-                        self.output.push_str("%\n");
+                        let comment = self.take_comment(self.line);
+                        if self.paragraph && self.line + 1 == at_line {
+                            // The empty line of the source ends the paragraph.
+                            self.output.push_str("\\par");
+                            if let Some(comment) = comment {
+                                self.output.push(' ');
+                                self.output.push_str(&comment);
+                            };
+                            self.paragraph = false;
+                        } else {
+                            self.output.push_str(comment.as_deref().unwrap_or("%"));
+                        };
+                        self.output.push('\n');
                         self.line += 1;
                     }
                 }
             }
         }
+        if self.paragraph {
+            self.paragraph = false;
+            self.push_str("\\par");
+            self.last_type = LastType::Command;
+        };
+        self.source_line = self.source_line.max(at_line);
+        self.map_line(position);
+    }
+
+    /// Take the first comment of the source not written yet, if it is on a
+    /// line up to the given one, as a TeX comment.
+    fn take_comment(&mut self, line: usize) -> Option<String> {
+        if self.verbatim > 0 || self.options.break_mode != BreakMode::Mirror || self.options.newlines != Newlines::Commented {
+            return None;
+        };
+        let source = self.source.as_mut()?;
+        let comment = source.comments.get(source.comment).filter(|comment| comment.at.line <= line)?;
+        let comment = format!("%{}", comment.text);
+        source.comment += 1;
+        Some(comment)
+    }
+
+    /// Write the comments of the source after the last value on their lines.
+    fn write_trailing_comments(&mut self) {
+        let lines = match &self.source {
+            Some(source) => source.comments[source.comment..].iter().map(|comment| comment.at.line).collect(),
+            None => vec![],
+        };
+        for line in lines {
+            let comment = match self.take_comment(line) {
+                Some(comment) => comment,
+                None => return,
+            };
+            if !matches!(self.last_type, LastType::Newline) {
+                self.contract_opportunity();
+                self.annotate_line();
+                self.output.push('\n');
+                self.line += 1;
+                self.last_type = LastType::Newline;
+                self.column = 1;
+            };
+            while self.line < line {
+                self.output.push_str("%\n");
+                self.line += 1;
+            }
+            self.output.push_str(&comment);
+            self.output.push('\n');
+            self.line += 1;
+        }
+    }
+
+    /// Whether empty lines end paragraphs where the writer is.
+    fn in_paragraphs(&self) -> bool {
+        self.options.paragraphs && self.source.is_some() && self.math == 0 && self.verbatim == 0 && self.arguments == 0 && !self.preamble
+    }
+
+    /// Write `\par` before a value if an empty line separates it from the
+    /// previous value in the source. Lines with only a comment are skipped.
+    fn paragraph_opportunity(&mut self, previous: &ParsedValue, next: &ParsedValue) {
+        if !self.in_paragraphs() {
+            return;
+        };
+        let source = match &self.source {
+            Some(source) => source.slice(previous.from(), next.from()),
+            None => return,
+        };
+        // The lines between the line of the previous value and that of the
+        // next, from the last. The previous value may end on the line of the
+        // next.
+        let lines: Vec<&str> = source.split('\n').collect();
+        let (last, lines) = lines.split_last().unwrap_or((&"", &[]));
+        let last = last.trim();
+        if !last.is_empty() && !is_comment(last) {
+            self.paragraph = false;
+            return;
+        };
+        let mut empty = false;
+        for line in lines.iter().skip(1).rev() {
+            let line = line.trim();
+            if line.is_empty() {
+                empty = true;
+            } else if !is_comment(line) {
+                break;
+            };
+        };
+        self.paragraph = empty;
+    }
+
+    /// Split text at the empty lines of its source, into paragraphs with the
+    /// line they start on. Gives `None` for text of one paragraph, or text
+    /// whose words are not those of its source, like quoted text.
+    fn split_paragraphs(&self, text: &str, from: Position, to: Position) -> Option<Vec<(usize, String)>> {
+        let source = self.source.as_ref()?.slice(from, to);
+        let mut paragraphs: Vec<(usize, Vec<&str>)> = vec![];
+        let mut empty = true;
+        for (i, line) in source.split('\n').enumerate() {
+            let line = line.split(" # ").next().unwrap_or_default().trim();
+            if line.is_empty() {
+                empty = true;
+            } else if !is_comment(line) {
+                if empty {
+                    paragraphs.push((from.line + i, vec![]));
+                    empty = false;
+                };
+                paragraphs.last_mut()?.1.extend(line.split_whitespace());
+            };
+        };
+        let paragraphs: Vec<(usize, String)> = paragraphs.into_iter().map(|(line, words)| (line, words.join(" "))).collect();
+        let words: Vec<&str> = paragraphs.iter().map(|(_, paragraph)| paragraph.as_str()).collect();
+        if paragraphs.len() < 2 || words.join(" ") != text {
+            return None;
+        };
+        Some(paragraphs)
+    }
+
+    /// Write text, with `\par` at the empty lines of its source.
+    fn write_paragraphs(&mut self, text: &str, from: Position, to: Position) {
+        let paragraphs = if self.in_paragraphs() { self.split_paragraphs(text, from, to) } else { None };
+        let paragraphs = match paragraphs {
+            Some(paragraphs) => paragraphs,
+            None => return self.normalize_and_push_str(text),
+        };
+        for (i, (line, paragraph)) in paragraphs.iter().enumerate() {
+            if i > 0 {
+                self.push(' ');
+                self.paragraph = true;
+                self.break_opportunity(Position { index: from.index, line: *line, column: 1 });
+                self.separate_command_opportunity();
+            };
+            self.normalize_and_push_str(paragraph);
+        };
+    }
+
+    /// Map the current line to a position, if it is the first value on it.
+    fn map_line(&mut self, position: Position) {
+        if position.line == 0 {
+            return;
+        };
+        self.position = position;
+        if let Some(source_map) = &mut self.source_map {
+            if source_map.lines.last().map_or(true, |(line, _)| *line < self.line) {
+                source_map.lines.push((self.line, position));
+            };
+        };
+    }
+
+    /// End the current line with the line in the source, if enabled.
+    fn annotate_line(&mut self) {
+        if !self.options.annotate {
+            return;
+        };
+        if let Some(source) = self.source_map.as_ref().and_then(|source_map| source_map.source(self.line)) {
+            self.output.push_str(&format!(" %&khi:{}", source.line));
+        };
     }
 
     /// If an empty command was last written, insert a space.
@@ -157,11 +917,18 @@ impl Writer<'_> {
         }
     }
 
-    fn write_raw(&mut self, raw: &str) {
+    /// Write text without escaping.
+    pub fn write_raw(&mut self, raw: &str) {
         for c in raw.chars() {
             self.output.push(c);
             if c == '\n' {
                 self.line += 1;
+                self.map_line(self.position);
+                self.column = 1;
+                self.last_type = LastType::Newline;
+            } else {
+                self.column += width(c);
+                self.last_type = LastType::Glyph;
             }
         }
     }
@@ -170,6 +937,16 @@ impl Writer<'_> {
 
 impl Writer<'_> {
 
+    /// Write a value, like the content of a tag.
+    pub fn write_value(&mut self, value: &ParsedValue) -> Result<(), PreprocessorError> {
+        self.write_inner(value)
+    }
+
+    /// Write text, escaped for the mode it is in.
+    pub fn write_text(&mut self, text: &str) {
+        self.normalize_and_push_str(text);
+    }
+
     fn write_inner(&mut self, value: &ParsedValue) -> Result<(), PreprocessorError> {
         match value {
             ParsedValue::Nil(at, _) => {
@@ -177,9 +954,9 @@ impl Writer<'_> {
                 self.push('{');
                 self.push('}');
             }
-            ParsedValue::Text(text, at, _) => {
+            ParsedValue::Text(text, at, to) => {
                 self.break_opportunity(*at);
-                self.normalize_and_push_str(text.as_str());
+                self.write_paragraphs(text.as_str(), *at, *to);
             }
             ParsedValue::Dictionary(_, at, _) => {
                 return Err(PreprocessorError::IllegalDictionary(*at));
@@ -190,16 +967,21 @@ impl Writer<'_> {
             }
             ParsedValue::Compound(compound, at, _) => {
                 self.break_opportunity(*at);
+                let mut previous: Option<&ParsedValue> = None;
                 for element in compound.iter() {
                     match element {
                         Element::Element(solid) => {
+                            if let Some(previous) = previous {
+                                self.paragraph_opportunity(previous, solid);
+                            };
+                            previous = Some(solid);
                             match solid {
                                 ParsedValue::Nil(at, _) => {
                                     self.break_opportunity(*at);
                                     self.push('{');
                                     self.push('}');
                                 }
-                                ParsedValue::Text(text, at, _) => {
+                                ParsedValue::Text(text, at, to) => {
                                     self.break_opportunity(*at);
                                     if self.last_type == LastType::Caret || self.last_type == LastType::Underscore {
                                         self.push('{');
@@ -207,7 +989,7 @@ impl Writer<'_> {
                                         self.push('}');
                                     } else {
                                         self.separate_command_opportunity();
-                                        self.normalize_and_push_str(text.as_str());
+                                        self.write_paragraphs(text.as_str(), *at, *to);
                                     }
                                 }
                                 ParsedValue::Dictionary(_, at, _) => {
@@ -217,11 +999,11 @@ impl Writer<'_> {
                                     self.break_opportunity(*at);
                                     self.write_tabulation(&table, *at)?;
                                 }
-                                ParsedValue::Compound(compound, at, _) => {
+                                ParsedValue::Compound(_, at, _) => {
                                     self.break_opportunity(*at);
-                                    self.push('{');
+                                    self.open_group(false);
                                     self.write_inner(solid)?;
-                                    self.push('}');
+                                    self.close_group(false);
                                 }
                                 ParsedValue::Tuple(_, at, _) => {
                                     return Err(PreprocessorError::IllegalTuple(*at));
@@ -258,163 +1040,613 @@ impl Writer<'_> {
 
     fn write_tabulation(&mut self, list: &ParsedList, at: Position) -> Result<(), PreprocessorError> {
         for element in list.iter() {
-            let mut columns = element.iter_as_tuple();
-            if let Some(c) = columns.next() {
-                self.write_inner(&c)?;
+            self.write_row(element)?;
+        };
+        Ok(())
+    }
+
+    /// Write a row of a table, like `a&b\\`.
+    fn write_row(&mut self, row: &ParsedValue) -> Result<(), PreprocessorError> {
+        self.write_cells(row)?;
+        self.push('\\');
+        self.push('\\');
+        Ok(())
+    }
+
+    /// Write the cells of a row, like `a&b`.
+    fn write_cells(&mut self, row: &ParsedValue) -> Result<(), PreprocessorError> {
+        self.open_node(FrameKind::Row, self.output.len());
+        let mut columns = row.iter_as_tuple();
+        if let Some(c) = columns.next() {
+            self.write_inner(&c)?;
+        };
+        while let Some(c) = columns.next() {
+            let end = self.output.len();
+            self.push('&');
+            self.split_node(end);
+            self.write_inner(&c)?;
+        };
+        self.close_node(self.output.len());
+        Ok(())
+    }
+
+    /// Write `<pmatrix!>:[a|b; c|d]` as `\begin{pmatrix}a&b\\c&d\end{pmatrix}`,
+    /// in math mode.
+    fn write_matrix(&mut self, name: &str, environment: &str, value: &ParsedValue, at: Position) -> Result<(), PreprocessorError> {
+        let list = match value {
+            ParsedValue::List(list, ..) => list,
+            value => return Err(PreprocessorError::MacroError(at, format!("{} must take a table, got {}.", name, preview(value, PREVIEW_LENGTH)))),
+        };
+        let start = self.output.len();
+        self.write_raw(&format!("\\begin{{{}}}", environment));
+        self.open_node(FrameKind::Tabular(environment.to_string()), start);
+        self.split_node(self.output.len());
+        let math = self.math;
+        self.math += 1;
+        let mut result = Ok(());
+        for (i, row) in list.elements.iter().enumerate() {
+            if i > 0 {
+                self.push('\\');
+                self.push('\\');
             };
-            while let Some(c) = columns.next() {
-                self.push('&');
-                self.write_inner(&c)?;
+            result = self.write_cells(row);
+            if result.is_err() {
+                break;
             };
-            self.push('\\');
-            self.push('\\');
         };
+        self.math = math;
+        result?;
+        let end = self.output.len();
+        self.write_raw(&format!("\\end{{{}}}", environment));
+        self.close_node(end);
         Ok(())
     }
 
-    fn write_macro(&mut self, tag: &ParsedTaggedValue, at: Position) -> Result<(), PreprocessorError> {
-        let mut name = tag.name();
+    /// Write `<def!>:Log:1:{...}` as `\newcommand\Log[1]{...}`, with a default
+    /// of the first argument like `<def!>:Log:2:e:{...}` as
+    /// `\newcommand\Log[2][e]{...}`. With `star`, write a starred form like
+    /// `\NewDocumentCommand\Log{s O{e} m}{...}`, in which `#1` is whether the
+    /// star is given.
+    fn write_definition(&mut self, tag: &ParsedTaggedValue, at: Position) -> Result<(), PreprocessorError> {
         let inner_value = tag.get();
-        if name.ends_with("!") {
-            if name.eq("def!") {
-                if !inner_value.is_tuple() {
-                    return Err(PreprocessorError::MacroError(at, format!("def! must take 3 arguments.")));
-                }
-                let inner_value = inner_value.as_tuple().unwrap();
-                if inner_value.len() != 3 {
-                    return Err(PreprocessorError::MacroError(at, format!("def! must take 3 arguments.")));
-                }
-                let tag = inner_value.get(0).unwrap().as_text().unwrap();
-                let arity = inner_value.get(1).unwrap();
-                let substitute = inner_value.get(2).unwrap();
-                self.output.push_str("\\newcommand");
-                self.output.push('\\');
-                self.output.push_str(tag.as_str());
+        let arguments: Vec<&ParsedValue> = inner_value.iter_as_tuple().collect();
+        let (name, arity, default, substitute) = match arguments[..] {
+            [name, arity, substitute] => (name, arity, None, substitute),
+            [name, arity, default, substitute] => (name, arity, Some(default), substitute),
+            _ => return Err(PreprocessorError::MacroError(at, format!("def! must take 3 or 4 arguments, got {}.", preview(inner_value, PREVIEW_LENGTH)))),
+        };
+        let name = match name.as_text() {
+            Some(name) => name,
+            None => return Err(PreprocessorError::MacroError(at, format!("def! must take a command name, got {}.", preview(name, PREVIEW_LENGTH)))),
+        };
+        let star = tag.get_attribute_by("star").is_some();
+        if star {
+            let count = match arity.as_text().and_then(|arity| arity.as_str().parse::<usize>().ok()) {
+                Some(count) if default.is_none() || count > 0 => count,
+                _ => return Err(PreprocessorError::MacroError(at, format!("def! star must take a number of arguments, got {}.", preview(arity, PREVIEW_LENGTH)))),
+            };
+            self.output.push_str("\\NewDocumentCommand\\");
+            self.output.push_str(name.as_str());
+            self.output.push_str("{s");
+            let mut mandatory = count;
+            if let Some(default) = default {
+                self.output.push_str(" O{");
+                self.write_substitute(default)?;
+                self.output.push('}');
+                mandatory -= 1;
+            };
+            self.output.push_str(&" m".repeat(mandatory));
+            self.output.push('}');
+        } else {
+            self.output.push_str("\\newcommand\\");
+            self.output.push_str(name.as_str());
+            self.output.push('[');
+            self.write_inner(arity)?;
+            self.output.push(']');
+            if let Some(default) = default {
                 self.output.push('[');
-                self.write_inner(arity)?;
+                self.write_substitute(default)?;
                 self.output.push(']');
-                self.output.push('{');
-                self.write_inner(substitute)?;
-                self.output.push('}');
-                self.last_type = LastType::Glyph;
-            } else if name.eq("lines!") {
-                if inner_value.is_tuple() {
-                    return Err(PreprocessorError::MacroError(at, format!("lines! takes 1 text argument.")));
+            };
+        };
+        self.output.push('{');
+        self.write_substitute(substitute)?;
+        self.output.push('}');
+        self.last_type = LastType::Glyph;
+        Ok(())
+    }
+
+    /// Write a value as in the substitute of `def!`, where the mode is not
+    /// known.
+    fn write_substitute(&mut self, value: &ParsedValue) -> Result<(), PreprocessorError> {
+        self.verbatim += 1;
+        let result = self.write_inner(value);
+        self.verbatim -= 1;
+        result
+    }
+
+    /// Write `<tabular! columns:lr header rules:booktabs long>:[a|b; c|d]` as
+    /// a complete `tabular` environment, or `longtable` with `long`.
+    fn write_tabular(&mut self, tag: &ParsedTaggedValue, at: Position) -> Result<(), PreprocessorError> {
+        let list = match tag.get() {
+            ParsedValue::List(list, ..) => list,
+            value => return Err(PreprocessorError::MacroError(at, format!("tabular! must take a table, got {}.", preview(value, PREVIEW_LENGTH)))),
+        };
+        let attribute = |key: &str| tag.get_attribute_by(key).map(|value| value.0);
+        let (top, middle, bottom) = match attribute("rules").as_ref().map(|rules| rules.as_deref()) {
+            None => ("", "", ""),
+            Some(Some("lines")) => ("\\hline ", "\\hline ", "\\hline "),
+            Some(Some("booktabs")) => ("\\toprule ", "\\midrule ", "\\bottomrule "),
+            Some(rules) => return Err(PreprocessorError::MacroError(at, format!("tabular! rules must be lines or booktabs, got {}.", rules.unwrap_or("no value")))),
+        };
+        let columns = match attribute("columns") {
+            Some(Some(columns)) => columns.to_string(),
+            Some(None) => return Err(PreprocessorError::MacroError(at, String::from("tabular! columns must have a value, like columns:lcr."))),
+            None => "l".repeat(list.elements.iter().map(|row| row.iter_as_tuple().count()).max().unwrap_or(1)),
+        };
+        let (header, long) = (attribute("header").is_some(), attribute("long").is_some());
+        let environment = if long { "longtable" } else { "tabular" };
+        let start = self.output.len();
+        self.write_raw(&format!("\\begin{{{}}}", environment));
+        self.open_node(FrameKind::Tabular(environment.to_string()), start);
+        let start = self.output.len();
+        self.write_raw("{");
+        self.open_node(FrameKind::Group(false), start);
+        self.write_raw(&columns);
+        let end = self.output.len();
+        self.write_raw("}");
+        self.close_node(end);
+        self.split_node(self.output.len());
+        self.write_raw(top);
+        for (i, row) in list.elements.iter().enumerate() {
+            self.write_row(row)?;
+            if i == 0 && header {
+                self.write_raw(middle);
+                if long {
+                    // The header is repeated on every page.
+                    self.write_raw("\\endhead ");
+                };
+            };
+        };
+        self.write_raw(bottom);
+        let end = self.output.len();
+        self.write_raw(&format!("\\end{{{}}}", environment));
+        self.close_node(end);
+        Ok(())
+    }
+
+    fn write_macro(&mut self, tag: &ParsedTaggedValue, at: Position) -> Result<(), PreprocessorError> {
+        let name = tag.name();
+        let inner_value = tag.get();
+        self.record_expansion(name.trim_end_matches('\''), at, 1);
+        if let Some(handler) = self.options.macros.get(name) {
+            handler(tag, self)?;
+        } else if name.ends_with("!") {
+            let builtin = name.strip_prefix(self.options.macro_prefix.as_str()).unwrap_or_default();
+            if builtin.eq("def!") {
+                self.write_definition(tag, at)?;
+            } else if builtin.eq("lines!") {
+                if !inner_value.is_text() {
+                    return Err(PreprocessorError::MacroError(at, format!("lines! takes 1 text argument, got {}.", preview(inner_value, PREVIEW_LENGTH))));
                 }
                 let text = inner_value.as_text().unwrap();
-                self.output.write_char('\n').or(Err(PreprocessorError::MacroError(at, format!("Error on writing to output in macro at {}:{}.", at.line, at.column))))?;
+                self.output.write_char('\n').or(Err(PreprocessorError::MacroError(at, String::from("lines! cannot write to output."))))?;
                 self.line += 1;
+                self.map_line(self.position);
                 self.write_raw(text.as_str());
-            } else if name.eq("raw!") {
+            } else if builtin.eq("env!") {
+                self.write_environment(inner_value, at)?;
+            } else if builtin.eq("include!") {
+                self.write_include(inner_value, at)?;
+            } else if builtin.eq("if!") || builtin.eq("ifdef!") {
+                let branch = conditional(builtin, inner_value, &self.options.variables).map_err(|message| PreprocessorError::MacroError(at, message))?;
+                if let Some(branch) = branch {
+                    self.write_inner(branch)?;
+                };
+            } else if let Some((_, environment)) = MATRIX_MACROS.iter().find(|(name, _)| *name == builtin) {
+                self.write_matrix(builtin, environment, inner_value, at)?;
+            } else if builtin.eq("tabular!") {
+                self.write_tabular(tag, at)?;
+            } else if builtin.eq("sup!") || builtin.eq("sub!") {
+                self.write_script(builtin == "sup!", inner_value)?;
+            } else if builtin.eq("cite!") || builtin.eq("ref!") {
+                let keys = match words(inner_value) {
+                    Some(keys) if !keys.is_empty() => keys,
+                    _ => return Err(PreprocessorError::MacroError(at, format!("{} must take keys, got {}.", builtin, preview(inner_value, PREVIEW_LENGTH)))),
+                };
+                self.write_keys(if builtin == "cite!" { "cite" } else { "ref" }, &keys.join(","));
+            } else if builtin.eq("bib!") {
+                let file = match inner_value.as_text() {
+                    Some(file) => file.as_str(),
+                    None => return Err(PreprocessorError::MacroError(at, format!("bib! must take a bibliography file, got {}.", preview(inner_value, PREVIEW_LENGTH)))),
+                };
+                match tag.get_attribute_by("style").map(|value| value.0) {
+                    Some(Some(style)) => self.write_keys("bibliographystyle", &style),
+                    Some(None) => return Err(PreprocessorError::MacroError(at, String::from("bib! style must have a value, like style:plain."))),
+                    None => {}
+                };
+                self.write_keys("bibliography", file);
+            } else if builtin.eq("preamble!") {
+                // The declaration of the known commands is for the lint.
+                if words(inner_value).is_none() {
+                    return Err(PreprocessorError::MacroError(at, format!("preamble! must take command names, got {}.", preview(inner_value, PREVIEW_LENGTH))));
+                };
+            } else if builtin.eq("raw!") {
                 if !inner_value.is_text() {
-                    return Err(PreprocessorError::MacroError(at, format!("raw! must take 1 text argument.")));
+                    return Err(PreprocessorError::MacroError(at, format!("raw! must take 1 text argument, got {}.", preview(inner_value, PREVIEW_LENGTH))));
                 }
                 let text = inner_value.as_text().unwrap();
                 self.write_raw(text.as_str());
-            } else {
+            } else if self.options.strict {
                 return Err(PreprocessorError::MacroError(at, format!("Unknown macro {}.", name)));
+            } else {
+                self.write_inner(inner_value)?;
             }
         } else if name.eq("$") {
             self.push('$');
             let structure = inner_value;
-            self.write_inner(structure)?;
+            self.math += 1;
+            let result = self.write_inner(structure);
+            self.math -= 1;
+            result?;
             self.push('$');
         } else if name.eq("p") {
-            self.normalize_and_push_str("\\par");
+            self.push_str("\\par");
             self.last_type = LastType::Command;
         } else if name.eq("n") {
-            self.normalize_and_push_str("\\\\");
-        }  else {
+            self.push_str("\\\\");
+        } else if let (Some(text), Some((_, environment))) = (inner_value.as_text(), VERBATIM_TAGS.iter().find(|(tag, _)| *tag == name)) {
+            self.write_verbatim(environment, tag, text.as_str());
+        } else {
             // Regular command.
-            let mut iter = inner_value.iter_as_tuple();
-            if name.ends_with("'") {
-                name = &name[0..name.len() - 1];
-                self.push('\\');
-                self.normalize_and_push_str(name);
-                if let Some(argument) = iter.next() {
-                    match argument {
-                        ParsedValue::Nil(_, at) => {
-                            self.break_opportunity(*at);
-                            self.normalize_and_push_str("[]");
-                        }
-                        ParsedValue::Text(text, at, from) => {
-                            self.break_opportunity(*at);
-                            self.push('[');
-                            self.normalize_and_push_str(&text.as_str());
-                            self.push(']');
-                        }
-                        ParsedValue::Dictionary(dictionary, at, to) => {
-                            return Err(PreprocessorError::IllegalDictionary(*at));
-                        }
-                        ParsedValue::List(table, at, to) => {
-                            return Err(PreprocessorError::IllegalTable(*at));
-                        }
-                        ParsedValue::Compound(compound, at, to) => {
-                            self.break_opportunity(*at);
-                            self.push('[');
-                            self.write_inner(&argument)?;
-                            self.push(']');
-                        }
-                        ParsedValue::Tuple(_, at, _) => {
-                            return Err(PreprocessorError::IllegalTuple(*at));
-                        }
-                        ParsedValue::Tagged(tag, at, to) => {
-                            self.break_opportunity(*at);
-                            self.push('[');
-                            self.write_macro(&tag, *at)?;
-                            self.push(']');
-                        }
-                    }
-                } else {
-                    return Err(PreprocessorError::MissingOptionalArgument(at))
-                }
-            } else {
-                self.push('\\');
-                self.normalize_and_push_str(name);
+            let (math, verbatim) = (self.math, self.verbatim);
+            if TEXT_COMMANDS.contains(&name) {
+                self.math = 0;
+            } else if name == "ensuremath" {
+                self.math = math + 1;
+            } else if NAMING_COMMANDS.contains(&name.trim_end_matches('\'')) {
+                self.verbatim = verbatim + 1;
+            };
+            let result = self.write_command(name, inner_value, at);
+            (self.math, self.verbatim) = (math, verbatim);
+            result?;
+            if name == "documentclass" || name == "documentclass'" {
+                self.preamble = true;
+            };
+            self.track_environment(name, inner_value);
+        };
+        Ok(())
+    }
+
+    /// Load, parse and write a document included with `<include!>:path`.
+    ///
+    /// The document is written on its own, and its lines map to the macro.
+    fn write_include(&mut self, argument: &ParsedValue, at: Position) -> Result<(), PreprocessorError> {
+        let path = match argument.as_text() {
+            Some(path) => path.as_str(),
+            None => return Err(PreprocessorError::MacroError(at, format!("include! must take a path, got {}.", preview(argument, PREVIEW_LENGTH)))),
+        };
+        let loader = match &self.options.loader {
+            Some(loader) => loader,
+            None => return Err(PreprocessorError::MacroError(at, format!("include! of {} requires a loader.", path))),
+        };
+        if self.includes.iter().any(|include| include == path) {
+            let cycle: Vec<&str> = self.includes.iter().map(String::as_str).chain([path]).collect();
+            return Err(PreprocessorError::MacroError(at, format!("include! cycle: {}.", cycle.join(" -> "))));
+        };
+        let source = loader(path).map_err(|error| PreprocessorError::MacroError(at, format!("include! cannot load {}: {}", path, error)))?;
+        let document = parse_value_str(&source).map_err(|errors| {
+            let errors: Vec<String> = errors.iter().map(error_to_string).collect();
+            PreprocessorError::MacroError(at, format!("include! cannot parse {}: {}", path, errors.join(" ")))
+        })?;
+        let mut output = String::new();
+        let mut writer = Writer::new(&mut output, self.options);
+        writer.source = Some(Source::new(&source, self.options.comments));
+        writer.includes = self.includes.clone();
+        writer.includes.push(path.to_string());
+        writer.expansions = self.expansions.as_ref().map(|_| vec![]);
+        writer.write_inner(&document).map_err(|error| match error {
+            PreprocessorError::MacroError(_, message) if message.starts_with("include! cycle") => PreprocessorError::MacroError(at, message),
+            error => PreprocessorError::MacroError(at, format!("In {}: {}", path, error)),
+        })?;
+        writer.write_trailing_comments();
+        for expansion in writer.expansions.take().unwrap_or_default() {
+            self.record_expansion(&expansion.name, at, expansion.uses.len());
+        };
+        let nodes = writer.finish();
+        let start = self.output.len();
+        self.write_raw(&output);
+        self.splice_nodes(nodes, start);
+        Ok(())
+    }
+
+    /// Record uses of a macro or command at a position, with
+    /// [write_tex_report].
+    fn record_expansion(&mut self, name: &str, at: Position, count: usize) {
+        let expansions = match &mut self.expansions {
+            Some(expansions) => expansions,
+            None => return,
+        };
+        let index = match expansions.iter().position(|e| e.name == name) {
+            Some(index) => index,
+            None => {
+                expansions.push(Expansion { name: name.to_string(), uses: vec![] });
+                expansions.len() - 1
             }
-            if inner_value.is_unit() { // No arguments - if followed by whitespace, insert empty {} after due to LaTeX scanner consuming following whitespace.
-                self.last_type = LastType::Command;
+        };
+        expansions[index].uses.extend(std::iter::repeat(at).take(count));
+    }
+
+    /// Write `<sup!>:x` as `^{x}` and `<sub!>:x` as `_{x}` in math mode, and
+    /// as `\textsuperscript{x}` and `\textsubscript{x}` in text mode.
+    fn write_script(&mut self, superscript: bool, argument: &ParsedValue) -> Result<(), PreprocessorError> {
+        if self.math > 0 || self.verbatim > 0 {
+            self.push(if superscript { '^' } else { '_' });
+            self.push('{');
+            self.write_inner(argument)?;
+            self.push('}');
+        } else {
+            self.push('\\');
+            self.push_str(if superscript { "textsuperscript" } else { "textsubscript" });
+            self.write_argument(argument, false)?;
+        };
+        Ok(())
+    }
+
+    /// Write text in a verbatim environment, without escaping and with its
+    /// lines as they are. The attributes of the tag are the options of the
+    /// environment, like `<code language:Rust>` for
+    /// `\begin{lstlisting}[language=Rust]`.
+    fn write_verbatim(&mut self, environment: &str, tag: &ParsedTaggedValue, text: &str) {
+        let start = self.output.len();
+        self.write_raw("\\begin{");
+        self.write_raw(environment);
+        self.write_raw("}");
+        self.open_node(FrameKind::Environment(environment.to_string()), start);
+        if tag.has_attributes() {
+            let options: Vec<String> = tag.iter_attributes().map(|Attribute(key, value)| match value {
+                Some(value) => format!("{}={}", key, value),
+                None => key.to_string(),
+            }).collect();
+            let start = self.output.len();
+            self.write_raw("[");
+            self.open_node(FrameKind::Group(true), start);
+            self.write_raw(&options.join(","));
+            let end = self.output.len();
+            self.write_raw("]");
+            self.close_node(end);
+        };
+        self.split_node(self.output.len());
+        self.write_raw("\n");
+        self.write_raw(text);
+        if !text.ends_with('\n') {
+            self.write_raw("\n");
+        };
+        let end = self.output.len();
+        self.write_raw("\\end{");
+        self.write_raw(environment);
+        self.write_raw("}");
+        self.close_node(end);
+    }
+
+    /// Enter or leave math mode at `<begin>` and `<end>` of a math environment.
+    fn track_environment(&mut self, name: &str, argument: &ParsedValue) {
+        if name != "begin" && name != "end" {
+            return;
+        };
+        let environment = match argument.iter_as_tuple().next().and_then(|e| e.as_text()) {
+            Some(environment) => environment.as_str(),
+            None => return,
+        };
+        let math = MATH_ENVIRONMENTS.contains(&environment);
+        if name == "begin" && environment == "document" {
+            self.preamble = false;
+        };
+        if name == "begin" {
+            if math {
+                self.math += 1;
+            };
+            self.environments.push(environment.to_string());
+        } else if self.environments.last().map(String::as_str) == Some(environment) {
+            if math {
+                self.math -= 1;
+            };
+            self.environments.pop();
+        };
+    }
+
+    /// Write a command. Each apostrophe after the name makes an argument
+    /// optional, like `<foo''>:a:b:c` for `\foo[a][b]{c}`.
+    fn write_command(&mut self, name: &str, inner_value: &ParsedValue, at: Position) -> Result<(), PreprocessorError> {
+        let mut iter = inner_value.iter_as_tuple();
+        let command = name.trim_end_matches('\'');
+        let optionals = name.len() - command.len();
+        let start = self.output.len();
+        self.push('\\');
+        self.push_str(command);
+        self.open_node(FrameKind::Command(command.to_string()), start);
+        for _ in 0..optionals {
+            match iter.next() {
+                Some(argument) => self.write_argument(argument, true)?,
+                None => return Err(PreprocessorError::MissingOptionalArgument(at)),
+            };
+        };
+        if inner_value.is_unit() { // No arguments - if followed by whitespace, insert empty {} after due to LaTeX scanner consuming following whitespace.
+            self.last_type = LastType::Command;
+        }
+        for argument in iter {
+            self.write_argument(argument, false)?;
+        }
+        self.close_node(self.output.len());
+        Ok(())
+    }
+
+    /// Write an argument in `{}`, or in `[]` if it is optional. A dictionary
+    /// is written as keys and values, like `[margin=1in,draft]`.
+    pub fn write_argument(&mut self, argument: &ParsedValue, optional: bool) -> Result<(), PreprocessorError> {
+        self.arguments += 1;
+        let result = self.write_bracketed(argument, optional);
+        self.arguments -= 1;
+        result
+    }
+
+    fn write_bracketed(&mut self, argument: &ParsedValue, optional: bool) -> Result<(), PreprocessorError> {
+        match argument {
+            ParsedValue::Dictionary(dictionary, at, _) => {
+                self.break_opportunity(*at);
+                self.open_group(optional);
+                self.write_key_values(dictionary)?;
+                self.close_group(optional);
             }
-            while let Some(argument) = iter.next() {
-                match argument {
-                    ParsedValue::Nil(at, _) => {
-                        self.break_opportunity(*at);
-                        self.normalize_and_push_str("{}");
-                    }
-                    ParsedValue::Text(text, at, _) => {
-                        self.break_opportunity(*at);
-                        self.push('{');
-                        self.normalize_and_push_str(text.as_str());
-                        self.push('}');
-                    }
-                    ParsedValue::Dictionary(dictionary, at, to) => {
-                        return Err(PreprocessorError::IllegalDictionary(*at));
-                    }
-                    ParsedValue::List(table, at, to) => {
-                        return Err(PreprocessorError::IllegalTable(*at));
-                    }
-                    ParsedValue::Compound(compound, at, to) => {
-                        self.break_opportunity(*at);
-                        self.push('{');
-                        self.write_inner(&argument)?;
-                        self.push('}');
-                    }
-                    ParsedValue::Tuple(_, at, _) => {
-                        return Err(PreprocessorError::IllegalTuple(*at));
-                    }
-                    ParsedValue::Tagged(t, at, to) => {
-                        self.break_opportunity(*at);
-                        if t.get().is_unit() {
-                            self.write_macro(&t, *at)?;
-                        } else {
-                            self.push('{');
-                            self.write_macro(&t, *at)?;
-                            self.push('}');
-                        }
-                    }
+            ParsedValue::Nil(at, _) => {
+                self.break_opportunity(*at);
+                self.open_group(optional);
+                self.close_group(optional);
+            }
+            ParsedValue::Text(text, at, _) => {
+                self.break_opportunity(*at);
+                self.open_group(optional);
+                self.normalize_and_push_str(text.as_str());
+                self.close_group(optional);
+            }
+            ParsedValue::List(_, at, _) => {
+                return Err(PreprocessorError::IllegalTable(*at));
+            }
+            ParsedValue::Compound(_, at, _) => {
+                self.break_opportunity(*at);
+                self.open_group(optional);
+                self.write_inner(argument)?;
+                self.close_group(optional);
+            }
+            ParsedValue::Tuple(_, at, _) => {
+                return Err(PreprocessorError::IllegalTuple(*at));
+            }
+            ParsedValue::Tagged(t, at, _) => {
+                self.break_opportunity(*at);
+                if !optional && t.get().is_unit() {
+                    self.write_macro(t, *at)?;
+                } else {
+                    self.open_group(optional);
+                    self.write_macro(t, *at)?;
+                    self.close_group(optional);
                 }
             }
+        }
+        Ok(())
+    }
+
+    /// Write a command with an argument that names things, like
+    /// `\cite{a,b}`.
+    fn write_keys(&mut self, command: &str, keys: &str) {
+        let start = self.output.len();
+        self.push('\\');
+        self.push_str(command);
+        self.open_node(FrameKind::Command(command.to_string()), start);
+        self.open_group(false);
+        self.verbatim += 1;
+        self.normalize_and_push_str(keys);
+        self.verbatim -= 1;
+        self.close_group(false);
+        self.close_node(self.output.len());
+    }
+
+    /// Write `{`, or `[` if optional, and start a group.
+    fn open_group(&mut self, optional: bool) {
+        let start = self.output.len();
+        self.push(if optional { '[' } else { '{' });
+        self.open_node(FrameKind::Group(optional), start);
+    }
+
+    /// Write `}`, or `]` if optional, and end a group.
+    fn close_group(&mut self, optional: bool) {
+        let end = self.output.len();
+        self.push(if optional { ']' } else { '}' });
+        self.close_node(end);
+    }
+
+    /// Write the entries of a dictionary as `key=value` separated by `,`, like
+    /// the options of `\usepackage` and `\hypersetup` or pgfkeys. A value that
+    /// is not a word is in `{}`, and an entry with the value `<>` is only its
+    /// key. A dictionary value is written as keys and values, and a list value
+    /// as its elements separated by `,`, like `key={a=1,b}` and `key={a,b}`.
+    fn write_key_values(&mut self, dictionary: &ParsedDictionary) -> Result<(), PreprocessorError> {
+        for (i, (key, value)) in dictionary.iter_sorted(KeyOrder::Source).enumerate() {
+            if i != 0 {
+                self.push(',');
+            };
+            self.push_str(key);
+            if value.is_unit() {
+                continue;
+            };
+            self.push('=');
+            match value {
+                ParsedValue::Text(text, ..) if !text.as_str().contains([',', '=', ' ', ']']) => self.normalize_and_push_str(text.as_str()),
+                ParsedValue::Dictionary(dictionary, ..) => {
+                    self.push('{');
+                    self.write_key_values(dictionary)?;
+                    self.push('}');
+                }
+                ParsedValue::List(list, ..) => {
+                    self.push('{');
+                    for (i, element) in list.elements.iter().enumerate() {
+                        if i != 0 {
+                            self.push(',');
+                        };
+                        self.write_inner(element)?;
+                    };
+                    self.push('}');
+                }
+                value => {
+                    self.push('{');
+                    self.write_inner(value)?;
+                    self.push('}');
+                }
+            };
+        };
+        Ok(())
+    }
+
+    /// Write `<env!>:name:arguments...:content` as
+    /// `\begin{name}arguments content\end{name}`.
+    fn write_environment(&mut self, inner_value: &ParsedValue, at: Position) -> Result<(), PreprocessorError> {
+        let arguments: Vec<&ParsedValue> = inner_value.iter_as_tuple().collect();
+        let (environment, arguments, content) = match &arguments[..] {
+            [environment, arguments @ .., content] => (*environment, arguments, *content),
+            _ => return Err(PreprocessorError::MacroError(at, format!("env! must take an environment name and content, got {}.", preview(inner_value, PREVIEW_LENGTH)))),
+        };
+        let environment = match environment.as_text() {
+            Some(environment) => environment.as_str(),
+            None => return Err(PreprocessorError::MacroError(at, format!("env! must take an environment name, got {}.", preview(environment, PREVIEW_LENGTH)))),
+        };
+        let name = environment;
+        let environment = name.trim_end_matches('\'');
+        let optionals = name.len() - environment.len();
+        if arguments.len() < optionals {
+            return Err(PreprocessorError::MissingOptionalArgument(at));
+        };
+        let start = self.output.len();
+        self.push_str("\\begin{");
+        self.push_str(environment);
+        self.push('}');
+        self.open_node(FrameKind::Environment(environment.to_string()), start);
+        for (i, argument) in arguments.iter().enumerate() {
+            self.write_argument(argument, i < optionals)?;
+        }
+        self.split_node(self.output.len());
+        let math = self.math;
+        if MATH_ENVIRONMENTS.contains(&environment) {
+            self.math += 1;
+        } else if environment == "document" {
+            self.preamble = false;
         };
+        let result = self.write_inner(content);
+        self.math = math;
+        result?;
+        let end = self.output.len();
+        self.push_str("\\end{");
+        self.push_str(environment);
+        self.push('}');
+        self.close_node(end);
         Ok(())
     }
 
@@ -428,3 +1660,62 @@ pub enum PreprocessorError {
     MacroError(Position, String),
     MissingOptionalArgument(Position),
 }
+
+impl PreprocessorError {
+
+    /// The position of the error in the document.
+    pub fn position(&self) -> Position {
+        match self {
+            PreprocessorError::IllegalTable(at)
+            | PreprocessorError::IllegalDictionary(at)
+            | PreprocessorError::IllegalTuple(at)
+            | PreprocessorError::ZeroTable(at)
+            | PreprocessorError::MacroError(at, _)
+            | PreprocessorError::MissingOptionalArgument(at) => *at,
+        }
+    }
+
+    /// A stable code identifying the kind of error, such as for counting
+    /// errors by kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PreprocessorError::IllegalTable(..) => "illegal_table",
+            PreprocessorError::IllegalDictionary(..) => "illegal_dictionary",
+            PreprocessorError::IllegalTuple(..) => "illegal_tuple",
+            PreprocessorError::ZeroTable(..) => "zero_table",
+            PreprocessorError::MacroError(..) => "macro_error",
+            PreprocessorError::MissingOptionalArgument(..) => "missing_optional_argument",
+        }
+    }
+
+    /// Get a diagnostic of the error, like those of the parser.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic { code: self.code(), message: self.to_string(), at: Some(self.position()) }
+    }
+
+}
+
+impl Display for PreprocessorError {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreprocessorError::IllegalTable(at) => write!(f, "Illegal table at {}:{}.", at.line, at.column),
+            PreprocessorError::IllegalDictionary(at) => write!(f, "Illegal dictionary at {}:{}.", at.line, at.column),
+            PreprocessorError::IllegalTuple(at) => write!(f, "Illegal tuple at {}:{}.", at.line, at.column),
+            PreprocessorError::ZeroTable(at) => write!(f, "Table cannot be empty at {}:{}.", at.line, at.column),
+            PreprocessorError::MacroError(at, message) => write!(f, "Macro error at {}:{}: {}", at.line, at.column, message),
+            PreprocessorError::MissingOptionalArgument(at) => write!(f, "Missing optional argument at {}:{}.", at.line, at.column),
+        }
+    }
+
+}
+
+impl Debug for PreprocessorError {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+
+}
+
+impl std::error::Error for PreprocessorError {}
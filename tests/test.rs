@@ -1,7 +1,8 @@
 use std::ops::Deref;
-use khi::{Compound, Dictionary, Tagged, Value, List, Element, Tuple};
-use khi::parse::{parse_dictionary_str, parse_list_str, parse_value_str};
-use khi::pdm::ParsedValue;
+use khi::{Component, Compound, Dictionary, Tagged, Value, List, Element, Tuple, Text};
+use khi::parse::{parse_dictionary_str, parse_dictionary_str_with, parse_list_str, parse_value_str, parse_value_str_with, ParseOptions};
+use khi::parse::parser::ParseError;
+use khi::pdm::{diff, Canonical, ConversionError, DiffOp, KeyComparison, KeyNormalization, KeyOrder, ParsedCompound, ParsedValue, Position};
 
 #[test]
 fn test_lexer() { // TODO
@@ -455,8 +456,11 @@ fn test_section() {
     assert_eq!(dictionary.len(), 3);
     let c = dictionary.get("c").unwrap();
     assert!(c.is_dictionary());
+    assert_eq!(c.from().line, 3);
     let c = c.as_dictionary().unwrap();
     assert_eq!(c.len(), 1);
+    let errors = parse_dictionary_str("{a}:\nb: 1\n{a:b}:\nc: d").err().unwrap();
+    assert!(matches!(errors[0], ParseError::KeyNotDictionary(Position { line: 3, column: 1, .. })), "{:?}", errors);
 }
 
 #[test]
@@ -466,3 +470,1325 @@ fn test_compound() {
     let compound = parse.as_compound().unwrap();
     assert!(compound.whitespace.get(0).unwrap());
 }
+
+#[test]
+fn test_key_normalization() {
+    let options = ParseOptions { key_normalization: KeyNormalization::Lowercase, ..ParseOptions::default() };
+    let dictionary = parse_dictionary_str_with("Name: Khi; {Section}: Key: v", &options).unwrap();
+    assert!(dictionary.get("name").is_some());
+    assert!(dictionary.get("NAME").is_some());
+    assert_eq!(dictionary.raw_key("name"), Some("Name"));
+    let section = dictionary.get("section").unwrap().as_dictionary().unwrap();
+    assert_eq!(section.raw_key("KEY"), Some("Key"));
+    assert!(parse_dictionary_str_with("Name: a; name: b", &options).is_err());
+    let value = parse_value_str_with("<Tag>", &options).unwrap();
+    let tag = value.as_tagged().unwrap();
+    assert_eq!(tag.name(), "tag");
+    assert_eq!(tag.raw_name(), "Tag");
+    let dictionary = parse_dictionary_str("Name: Khi").unwrap();
+    assert!(dictionary.get("name").is_none());
+    assert_eq!(dictionary.raw_key("Name"), Some("Name"));
+    // Lowercase is not full case folding.
+    assert_eq!(KeyNormalization::Lowercase.apply("STRASSE"), "strasse");
+    assert_eq!(KeyNormalization::Lowercase.apply("Stra\u{df}e"), "stra\u{df}e");
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn test_key_normalization_nfc() {
+    let options = ParseOptions { key_normalization: KeyNormalization::Nfc, ..ParseOptions::default() };
+    let dictionary = parse_dictionary_str_with("Cafe\u{301}: open", &options).unwrap();
+    assert!(dictionary.get("Caf\u{e9}").is_some());
+    assert!(dictionary.get("Cafe\u{301}").is_some());
+    assert_eq!(dictionary.raw_key("Caf\u{e9}"), Some("Cafe\u{301}"));
+}
+
+#[test]
+fn test_case_insensitive_keys() {
+    let options = ParseOptions { key_comparison: KeyComparison::CaseInsensitive, ..ParseOptions::default() };
+    let dictionary = parse_dictionary_str_with("ServerName: khi; {Paths}: Root: /srv", &options).unwrap();
+    assert!(dictionary.get("servername").is_some());
+    assert!(dictionary.get("SERVERNAME").is_some());
+    assert_eq!(dictionary.raw_key("servername"), Some("ServerName"));
+    assert!(dictionary.iter().any(|(k, _)| k == "ServerName"));
+    let paths = dictionary.get("paths").unwrap().as_dictionary().unwrap();
+    assert!(paths.get("root").is_some());
+    assert!(parse_dictionary_str_with("Name: a; name: b", &options).is_err());
+    let sections = parse_dictionary_str_with("{A}: x: 1\n{a}: y: 2", &options).unwrap();
+    assert_eq!(sections.get("A").unwrap().as_dictionary().unwrap().len(), 2);
+}
+
+#[test]
+fn test_get_ci() {
+    let dictionary = parse_dictionary_str("Name: a\nNAME: b\nPort: 80").unwrap();
+    assert_eq!(dictionary.get_ci("port").unwrap().as_text().unwrap().as_str(), "80");
+    assert_eq!(dictionary.get_ci("NAME").unwrap().as_text().unwrap().as_str(), "b");
+    assert_eq!(dictionary.get_ci("Name").unwrap().as_text().unwrap().as_str(), "a");
+    assert!(dictionary.get_ci("host").is_none());
+    assert!(dictionary.get("port").is_none());
+    let dictionary = parse_dictionary_str("Straße: a\nΣ: b").unwrap();
+    assert!(dictionary.get_ci("STRASSE").is_none());
+    assert_eq!(dictionary.get_ci("σ").unwrap().as_text().unwrap().as_str(), "b");
+    // The first of several matching keys in source order.
+    for _ in 0..16 {
+        let dictionary = parse_dictionary_str("Name: a\nNAME: b\nnAmE: c\nPort: 80").unwrap();
+        assert_eq!(dictionary.get_ci("name").unwrap().as_text().unwrap().as_str(), "a");
+    }
+}
+
+#[test]
+fn test_compound_components() {
+    let document = parse_value_str("a~<b>~c <d>").unwrap();
+    let compound = document.as_compound().unwrap();
+    assert_eq!(compound.element_count(), 4);
+    assert_eq!(compound.len(), 5);
+    let first = compound.get(0).unwrap();
+    assert!(first.value.is_text() && !first.whitespace_before && !first.whitespace_after);
+    let second = compound.get(1).unwrap();
+    assert!(second.value.is_tagged() && !second.whitespace_before && !second.whitespace_after);
+    let third = compound.get(2).unwrap();
+    assert!(!third.whitespace_before && third.whitespace_after);
+    let Component { value, whitespace_before, whitespace_after } = compound.get(3).unwrap();
+    assert_eq!(value.as_tagged().unwrap().name(), "d");
+    assert!(whitespace_before && !whitespace_after);
+    assert!(compound.get(4).is_none());
+    let glued: Vec<bool> = compound.iter_with_separators().map(|c| !c.whitespace_before).collect();
+    assert_eq!(glued, [true, true, true, false]);
+}
+
+#[test]
+fn test_iter_sorted() {
+    let dictionary = parse_dictionary_str("item10: a\nitem2: b\nItem1: c\nitem02x: d").unwrap();
+    let keys = |order| dictionary.iter_sorted(order).map(|(key, _)| key).collect::<Vec<_>>();
+    assert_eq!(keys(KeyOrder::Lexicographic), ["Item1", "item02x", "item10", "item2"]);
+    assert_eq!(keys(KeyOrder::Natural), ["Item1", "item2", "item02x", "item10"]);
+    assert_eq!(keys(KeyOrder::Source), ["item10", "item2", "Item1", "item02x"]);
+    assert_eq!(KeyOrder::Natural.cmp("a2", "a02"), KeyOrder::Lexicographic.cmp("a2", "a02"));
+    let mut dictionary = parse_dictionary_str("b: 1\nc: {x: 2}\nd: 3").unwrap();
+    dictionary.insert("a", ParsedValue::text("4"));
+    dictionary.insert("b", ParsedValue::text("5"));
+    dictionary.remove("c");
+    dictionary.insert("c", ParsedValue::text("6"));
+    let keys: Vec<&str> = dictionary.iter_sorted(KeyOrder::Source).map(|(key, _)| key).collect();
+    assert_eq!(keys, ["b", "d", "a", "c"]);
+}
+
+#[test]
+fn test_retain_filter_map() {
+    let mut dictionary = parse_dictionary_str("name: a\npassword: b\nport: 80").unwrap();
+    let port = dictionary.get("port").unwrap().from();
+    dictionary.retain(|key, _| key != "password");
+    assert_eq!(dictionary.len(), 2);
+    assert_eq!(dictionary.get("port").unwrap().from().index, port.index);
+    let redacted = dictionary.filter_map(|key, value| if key == "name" { Some(ParsedValue::text("*")) } else { Some(value.clone()) });
+    assert_eq!(redacted.get("name").unwrap().as_text().unwrap().as_str(), "*");
+    assert_eq!(redacted.get("port").unwrap().from().index, port.index);
+    let options = ParseOptions { key_normalization: KeyNormalization::Lowercase, ..ParseOptions::default() };
+    let mut dictionary = parse_dictionary_str_with("Name: a\nPort: 80", &options).unwrap();
+    dictionary.retain(|key, _| key == "port");
+    assert_eq!(dictionary.raw_keys.len(), 1);
+    assert_eq!(dictionary.filter_map(|_, v| Some(v.clone())).raw_key("port"), Some("Port"));
+    let mut list = parse_list_str("1;\n2;\n3;\n4").unwrap();
+    list.retain(|e| e.as_text().unwrap().as_str() != "2");
+    assert_eq!(list.len(), 3);
+    assert_eq!(list.get_element(1).unwrap().from().line, 3);
+    let odd = list.filter_map(|e| if e.as_text().unwrap().as_str() == "4" { None } else { Some(e.clone()) });
+    assert_eq!(odd.len(), 2);
+    let value = parse_value_str("a <secret>:x~<b>~c").unwrap();
+    let is_secret = |c: &ParsedValue| c.as_tagged().is_some_and(|t| t.name() == "secret");
+    let compound = value.as_compound().unwrap().clone();
+    assert_eq!(compound.whitespace, vec![true, false, false]);
+    let retained = compound.clone().retain(|c| !is_secret(c));
+    assert_eq!(retained.as_compound().unwrap().components.len(), 3);
+    assert_eq!(retained.as_compound().unwrap().whitespace, vec![true, false]);
+    let texts = compound.filter_map(|c| if c.is_text() { Some(c.clone()) } else { None });
+    assert_eq!(texts.as_compound().unwrap().whitespace, vec![true]);
+    let nothing = compound.clone().retain(|_| false);
+    assert!(nothing.is_nil());
+    let one = compound.filter_map(|c| if is_secret(c) { Some(c.clone()) } else { None });
+    assert!(one.as_compound().is_none());
+    assert!(is_secret(&one));
+    let empty = ParsedCompound { components: vec![], whitespace: vec![] };
+    assert_eq!(empty.iter().count(), 0);
+}
+
+#[test]
+fn test_number_format() {
+    use khi::number::NumberFormat;
+    let default = NumberFormat::default();
+    for text in ["0", "42", "-7", "+7", "-", "+", "", "1.5", ".5", "5.", ".", "1e5", "1E-3", "1e", "e5", "-inf", "NaN", "infinity", "0x1F", "1_000", "12a", "--1", " 1"] {
+        assert_eq!(default.parse::<i64>(text), text.parse::<i64>().ok(), "{}", text);
+        assert_eq!(default.parse::<u8>(text), text.parse::<u8>().ok(), "{}", text);
+        assert_eq!(default.parse::<f64>(text).map(f64::to_bits), text.parse::<f64>().ok().map(f64::to_bits), "{}", text);
+    };
+    let strict = NumberFormat::STRICT;
+    assert!(strict.parse::<i32>("+1").is_none());
+    assert!(strict.parse::<f64>("1e5").is_none());
+    assert!(strict.parse::<f64>("inf").is_none());
+    assert_eq!(strict.parse::<f64>("-2.5"), Some(-2.5));
+    let lenient = NumberFormat::LENIENT;
+    assert_eq!(lenient.parse::<i32>("0x1F"), Some(31));
+    assert_eq!(lenient.parse::<i32>("-0b101"), Some(-5));
+    assert_eq!(lenient.parse::<u16>("0o17"), Some(15));
+    assert_eq!(lenient.parse::<u32>("1e5"), Some(100000));
+    assert_eq!(lenient.parse::<u32>("1e-5"), None);
+    assert_eq!(lenient.parse::<u8>("1e5"), None);
+    assert_eq!(lenient.parse::<i64>("1_000_000"), Some(1000000));
+    assert_eq!(lenient.parse::<f64>("1_000.5"), Some(1000.5));
+    assert_eq!(lenient.parse::<i32>("_1"), None);
+    assert_eq!(lenient.parse::<f64>("0x1F"), None);
+    assert!(lenient.is_number("0x1F"));
+    assert!(lenient.is_integer("1e5"));
+    assert!(!default.is_number("0x1F"));
+    assert!(!default.is_integer("1e5"));
+    assert!(default.is_number("1e5"));
+    let dictionary = parse_dictionary_str("a: 0x1F\nb: <x>").unwrap();
+    assert_eq!(dictionary.get("a").unwrap().to_number_with::<u8>(&lenient).unwrap(), 31);
+    assert!(matches!(dictionary.get("a").unwrap().to_number_with::<u8>(&default), Err(ConversionError::Invalid("u8", ..))));
+    assert!(matches!(u8::try_from(dictionary.get("a").unwrap()), Err(ConversionError::Invalid("u8", ..))));
+    assert!(matches!(dictionary.get("b").unwrap().to_number_with::<u8>(&lenient), Err(ConversionError::Expected("text", ..))));
+    assert!(dictionary.get("a").unwrap().is_number_with(&lenient));
+    assert!(!dictionary.get("b").unwrap().is_number_with(&lenient));
+    for text in ["1e-5", "-1.5E+3", "inf", "-Infinity", "NaN", "1.", ".5", "1e99999"] {
+        assert!(default.is_float(text) && !default.is_integer(text), "{}", text);
+    };
+    for text in ["0x1F", "-0b1"] {
+        assert!(lenient.is_integer(text) && !lenient.is_float(text), "{}", text);
+    };
+    for text in ["1e5", "1_0", "1__0"] {
+        assert!(lenient.is_integer(text) && lenient.is_float(text), "{}", text);
+    };
+    for text in ["1,5", "1,000,000", "1 000", "1_", "_1", "1_.5", "0x_1", "0x", "1e", "1e+", "e5", ".", "--1", "+-1", "inf5", "1e-5i"] {
+        assert!(!lenient.is_number(text), "{}", text);
+    };
+    assert!(!strict.is_float("inf") && !strict.is_float("1e-5") && strict.is_float("-0.5"));
+    assert_eq!(default.parse::<f64>("1e99999"), Some(f64::INFINITY));
+    assert!(lenient.parse::<f64>("-nan").unwrap().is_nan());
+    assert_eq!(lenient.parse::<u8>("1__0"), Some(10));
+}
+
+#[cfg(feature = "decimal")]
+#[test]
+fn test_decimal() {
+    use khi::number::NumberFormat;
+    use rust_decimal::Decimal;
+    let dictionary = parse_dictionary_str("price: 19.99; rate: 1.5e-3; big: 1_000.25; many: 0.12345678901234567890123456789; nan: NaN").unwrap();
+    let entry = |key: &str| dictionary.get(key).unwrap();
+    assert_eq!(Decimal::try_from(entry("price")).unwrap(), Decimal::new(1999, 2));
+    assert_eq!(Decimal::try_from(entry("price")).unwrap().to_string(), "19.99");
+    assert_eq!(Decimal::try_from(entry("rate")).unwrap(), Decimal::new(15, 4));
+    assert_eq!(entry("big").to_number_with::<Decimal>(&NumberFormat::LENIENT).unwrap(), Decimal::new(100025, 2));
+    assert!(matches!(Decimal::try_from(entry("many")), Err(ConversionError::Invalid("decimal", ..))));
+    assert!(Decimal::try_from(entry("nan")).is_err());
+}
+
+#[test]
+fn test_compact() {
+    use khi::pdm::{Interner, SharedStr};
+    let document = parse_dictionary_str("a: {x: same; y: same}\nb: <t k:same>:{same | ~}\nc: [same]").unwrap();
+    let original = document.get("a").unwrap();
+    let text = |value: &ParsedValue| value.as_text().unwrap().str.clone();
+    let compacted = original.compact();
+    assert!(compacted.canonical_eq(original));
+    assert_eq!(compacted.from().index, original.from().index);
+    let x = text(compacted.as_dictionary().unwrap().get("x").unwrap());
+    let y = text(compacted.as_dictionary().unwrap().get("y").unwrap());
+    assert!(SharedStr::ptr_eq(&x, &y));
+    assert!(!SharedStr::ptr_eq(&x, &text(original.as_dictionary().unwrap().get("x").unwrap())));
+    let mut interner = Interner::new();
+    let b = document.get("b").unwrap().compact_into(&mut interner);
+    let c = document.get("c").unwrap().compact_into(&mut interner);
+    assert!(b.canonical_eq(document.get("b").unwrap()));
+    assert_eq!(interner.len(), 3);
+    let tagged = b.as_tagged().unwrap();
+    assert!(SharedStr::ptr_eq(&tagged.attributes[0].1.as_ref().unwrap().strings()[0], &text(c.as_list().unwrap().get_element(0).unwrap())));
+}
+
+#[test]
+fn test_strip_spans() {
+    use khi::model::{SimpleAttributeValue, SimpleTagged, SimpleValue};
+    let value = parse_value_str("<a k:v>:{b | ~}").unwrap();
+    let stripped = value.strip_spans();
+    assert_eq!(stripped, SimpleValue::Tagged(SimpleTagged {
+        name: String::from("a"),
+        attributes: vec![(String::from("k"), Some(SimpleAttributeValue::Text(String::from("v"))))],
+        value: Box::new(SimpleValue::Tuple(vec![SimpleValue::Tuple(vec![SimpleValue::Text(String::from("b")), SimpleValue::Nil])])),
+    }));
+    let restored: ParsedValue = (&stripped).into();
+    assert!(restored.canonical_eq(&value));
+    assert_eq!(restored.from().index, 0);
+    assert!(std::mem::size_of::<SimpleValue>() * 2 < std::mem::size_of::<ParsedValue>());
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_sync() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+    let document = parse_value_str("{name: <Tag attr:x>:Khi}").unwrap();
+    assert_send_sync(&document);
+    let shared = std::sync::Arc::new(document);
+    let handle = {
+        let shared = shared.clone();
+        std::thread::spawn(move || shared.is_dictionary())
+    };
+    assert!(handle.join().unwrap());
+}
+
+#[test]
+fn test_source_slice() {
+    let source = "a: hello world\r\nb: {\r\n  x: 1\r\n}\r\nc: <x a=1>:2\nd: [1|2]\ne: 1\n\nf: ~";
+    let dictionary = parse_dictionary_str(source).unwrap();
+    assert_eq!(dictionary.get("a").unwrap().source_slice(source), "hello world");
+    assert_eq!(dictionary.get("b").unwrap().source_slice(source), "{\r\n  x: 1\r\n}");
+    assert_eq!(dictionary.get("c").unwrap().source_slice(source), "<x a=1>:2");
+    assert_eq!(dictionary.get("d").unwrap().source_slice(source), "[1|2]");
+    assert_eq!(dictionary.get("e").unwrap().source_slice(source), "1");
+    assert_eq!(dictionary.get("f").unwrap().source_slice(source), "~");
+    let inner = dictionary.get("b").unwrap().as_dictionary().unwrap();
+    assert_eq!(inner.get("x").unwrap().source_slice(source), "1");
+    assert_eq!(dictionary.get("a").unwrap().source_slice("short"), "");
+}
+
+#[test]
+fn test_into_iter() {
+    let document = parse_value_str("{a: 1; b: [x; y; z]; c: 1 | 2 | 3}").unwrap();
+    let ParsedValue::Dictionary(dictionary, ..) = document else { panic!() };
+    let mut entries: Vec<_> = dictionary.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let keys: Vec<&str> = entries.iter().map(|(k, _)| k.as_ref()).collect();
+    assert_eq!(keys, ["a", "b", "c"]);
+    let (_, list) = entries.remove(1);
+    let ParsedValue::List(list, ..) = list else { panic!() };
+    let elements: Vec<String> = list.into_iter().map(|e| e.as_text().unwrap().as_str().to_string()).collect();
+    assert_eq!(elements, ["x", "y", "z"]);
+    let (_, tuple) = entries.remove(1);
+    let ParsedValue::Tuple(tuple, ..) = tuple else { panic!() };
+    assert_eq!(tuple.into_elements().len(), 3);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_document_store() {
+    use std::sync::{Arc, Mutex};
+    use khi::store::DocumentStore;
+    let store = DocumentStore::new();
+    let changes = Arc::new(Mutex::new(vec![]));
+    let subscription = {
+        let changes = changes.clone();
+        store.subscribe(move |change| changes.lock().unwrap().push(change.version))
+    };
+    store.insert("config", parse_value_str("{port: 80; host: localhost}").unwrap());
+    let snapshot = store.get("config").unwrap();
+    store.merge("config", parse_value_str("{port: 8080}").unwrap());
+    assert_eq!(snapshot.as_dictionary().unwrap().get("port").unwrap().as_text().unwrap().as_str(), "80");
+    let config = store.get("config").unwrap();
+    let config = config.as_dictionary().unwrap();
+    assert_eq!(config.get("port").unwrap().as_text().unwrap().as_str(), "8080");
+    assert_eq!(config.get("host").unwrap().as_text().unwrap().as_str(), "localhost");
+    let handles: Vec<_> = (0..4).map(|_| {
+        let store = store.clone();
+        std::thread::spawn(move || {
+            for _ in 0..25 {
+                store.update("counter", |old| {
+                    let count = old.map(|v| v.as_text().unwrap().as_str().parse::<usize>().unwrap()).unwrap_or(0);
+                    Some(parse_value_str(&(count + 1).to_string()).unwrap())
+                });
+            }
+        })
+    }).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get("counter").unwrap().as_text().unwrap().as_str(), "100");
+    assert_eq!(*changes.lock().unwrap(), (1..=102).collect::<Vec<u64>>());
+    // A panicking update leaves the document as it was.
+    let panicked = std::panic::catch_unwind(|| store.update("counter", |_| panic!("update failed")));
+    assert!(panicked.is_err());
+    assert_eq!(store.get("counter").unwrap().as_text().unwrap().as_str(), "100");
+    assert_eq!(store.version(), 102);
+    drop(subscription);
+    store.remove("config");
+    assert!(!store.contains("config"));
+    assert_eq!(store.version(), 103);
+    assert_eq!(changes.lock().unwrap().len(), 102);
+}
+
+#[test]
+fn test_try_from() {
+    let dictionary = parse_dictionary_str("name: Khi\nport: 8080\nratio: 0.5\nenabled: true\nports: [80; 443]\nsize: -1\nflags: {a: 1}").unwrap();
+    assert_eq!(String::try_from(dictionary.get("name").unwrap()).unwrap(), "Khi");
+    assert_eq!(u16::try_from(dictionary.get("port").unwrap()).unwrap(), 8080);
+    assert_eq!(f64::try_from(dictionary.get("ratio").unwrap()).unwrap(), 0.5);
+    assert!(bool::try_from(dictionary.get("enabled").unwrap()).unwrap());
+    assert_eq!(Vec::<u32>::try_from(dictionary.get("ports").unwrap()).unwrap(), vec![80, 443]);
+    assert_eq!(i8::try_from(dictionary.get("size").unwrap()).unwrap(), -1);
+    let error = u8::try_from(dictionary.get("port").unwrap()).unwrap_err();
+    assert!(matches!(error, ConversionError::Invalid("u8", _, _)));
+    assert_eq!(error.at().line, 2);
+    assert_eq!(error.to_string(), "Invalid u8 ⟨8080⟩ at 2:7.");
+    assert!(matches!(bool::try_from(dictionary.get("name").unwrap()), Err(ConversionError::Invalid("bool", ..))));
+    assert!(matches!(Vec::<u32>::try_from(dictionary.get("name").unwrap()), Err(ConversionError::Expected("list", ..))));
+    assert!(matches!(u32::try_from(dictionary.get("size").unwrap()), Err(ConversionError::Invalid("u32", ..))));
+    let error = String::try_from(dictionary.get("flags").unwrap()).unwrap_err();
+    assert_eq!(error.at().line, 7);
+}
+
+#[cfg(feature = "watch")]
+#[test]
+fn test_config_watch() {
+    use std::time::Duration;
+    use khi::config::{load, watch_with, WatchOptions};
+
+    struct Config { port: u16 }
+
+    impl TryFrom<&ParsedValue> for Config {
+        type Error = ConversionError;
+
+        fn try_from(value: &ParsedValue) -> Result<Self, Self::Error> {
+            let dictionary = value.as_dictionary().ok_or(ConversionError::Expected("dictionary", value.from()))?;
+            let port = dictionary.get("port").ok_or(ConversionError::Expected("port", value.from()))?;
+            Ok(Config { port: port.try_into()? })
+        }
+    }
+
+    let directory = std::env::temp_dir().join(format!("khi-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("config.khi");
+    std::fs::write(&path, "port: 80").unwrap();
+    assert_eq!(load::<Config>(&path).unwrap().port, 80);
+    let (configs, received) = std::sync::mpsc::channel();
+    let (errors, failed) = std::sync::mpsc::channel();
+    let options = WatchOptions { debounce: Duration::from_millis(50), ..WatchOptions::default() };
+    let watcher = watch_with(&path, options, move |config: Config| configs.send(config.port).unwrap(), move |error| errors.send(error.to_string()).unwrap()).unwrap();
+    assert_eq!(received.recv_timeout(Duration::from_secs(5)).unwrap(), 80);
+    std::fs::write(&path, "port: 100000").unwrap();
+    assert!(failed.recv_timeout(Duration::from_secs(5)).unwrap().starts_with("Invalid u16"));
+    std::fs::write(&path, "port: 8080").unwrap();
+    assert_eq!(received.recv_timeout(Duration::from_secs(5)).unwrap(), 8080);
+    drop(watcher);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_khi_macro() {
+    let document = khi::khi!{
+        name: Khi;
+        version: 16;
+        quoted: "a; b";
+        words: hello world;
+        list: [1; 2.5; -3];
+        nested: {a: ~; b: []; c: {}};
+        tag: <tag attr:x flag size:"12 pt">: body;
+        empty: <br>;
+        expression: (format!("{}-{}", 1, 2));
+    };
+    let dictionary = document.as_dictionary().unwrap();
+    let text = |key: &str| dictionary.get(key).unwrap().as_text().unwrap().as_str().to_string();
+    assert_eq!(text("name"), "Khi");
+    assert_eq!(text("version"), "16");
+    assert_eq!(text("quoted"), "a; b");
+    assert_eq!(text("words"), "hello world");
+    assert_eq!(text("expression"), "1-2");
+    let list: Vec<String> = dictionary.get("list").unwrap().try_into().unwrap();
+    assert_eq!(list, ["1", "2.5", "-3"]);
+    let nested = dictionary.get("nested").unwrap().as_dictionary().unwrap();
+    assert!(nested.get("a").unwrap().is_nil());
+    assert!(nested.get("b").unwrap().as_list().unwrap().is_empty());
+    assert!(nested.get("c").unwrap().as_dictionary().unwrap().is_empty());
+    let tag = dictionary.get("tag").unwrap().as_tagged().unwrap();
+    assert_eq!(tag.name(), "tag");
+    let attributes: Vec<(&str, Option<&str>)> = tag.attributes.iter().map(|a| (a.0.as_ref(), a.1.as_ref().and_then(|v| v.as_text()))).collect();
+    assert_eq!(attributes, [("attr", Some("x")), ("flag", None), ("size", Some("12 pt"))]);
+    assert_eq!(tag.get().as_text().unwrap().as_str(), "body");
+    assert!(dictionary.get("empty").unwrap().as_tagged().unwrap().get().is_nil());
+    assert!(khi::khi!([a; b]).is_list());
+    assert!(khi::khi!().as_dictionary().unwrap().is_empty());
+}
+
+#[test]
+fn test_canonical_hash() {
+    let a = parse_value_str("{a: 1; b: [x; y]; c: <t p:1 q:2>:v}").unwrap();
+    let b = parse_value_str("{\n  c: <t q:2 p:1>:v\n  b: [x; y]\n  a: 1\n}").unwrap();
+    let c = parse_value_str("{a: 1; b: [y; x]; c: <t p:1 q:2>:v}").unwrap();
+    assert!(a.canonical_eq(&b));
+    assert_eq!(a.canonical_hash(), b.canonical_hash());
+    assert!(!a.canonical_eq(&c));
+    assert_ne!(a.canonical_hash(), c.canonical_hash());
+    assert_eq!(parse_value_str("~").unwrap().canonical_hash(), 0xaf63bd4c8601b7df);
+    assert_eq!(a.canonical_hash(), 0xba07f60521877573);
+    assert!(!parse_value_str("a b").unwrap().canonical_eq(&parse_value_str("a~b").unwrap()));
+    let mut set = std::collections::HashSet::new();
+    assert!(set.insert(Canonical(&a)));
+    assert!(!set.insert(Canonical(&b)));
+    assert!(set.insert(Canonical(&c)));
+}
+
+#[test]
+fn test_parse_metrics() {
+    use std::sync::Arc;
+    use khi::parse::ParseCounters;
+    let counters = Arc::new(ParseCounters::new());
+    let options = ParseOptions { metrics: Some(counters.clone()), ..ParseOptions::default() };
+    assert!(parse_value_str_with("{a: 1}", &options).is_ok());
+    assert!(parse_dictionary_str_with("a: 1; a: 2", &options).is_err());
+    assert!(parse_value_str_with("a: 1]", &options).is_err());
+    assert_eq!(counters.attempts(), 3);
+    assert_eq!(counters.failures(), 2);
+    let errors = counters.errors();
+    assert_eq!(errors.get("key_already_assigned"), Some(&1));
+    assert_eq!(errors.values().sum::<u64>(), 2);
+}
+
+#[test]
+fn test_diff() {
+    let old = parse_value_str("{name: a; port: 80; servers: [x; y; z]; link: <a href:x>:text; gone: 1}").unwrap();
+    let new = parse_value_str("{\n  servers: [x; w]\n  port: 8080\n  name: a\n  link: <b href:y rel>:text\n  added: {k: v}\n}").unwrap();
+    let ops: Vec<String> = diff(&old, &new).iter().map(|op| match op {
+        DiffOp::Added(path, value) => format!("+ {} {}", path, value.from().line),
+        DiffOp::Removed(path, _) => format!("- {}", path),
+        DiffOp::Changed(path, a, b) => format!("~ {} {} {}", path, a.as_text().unwrap().as_str(), b.as_text().unwrap().as_str()),
+        DiffOp::TagRenamed(path, ..) => format!("<> {}", path),
+        DiffOp::AttributeChanged(path, name, a, b) => format!("@ {} {} {:?} {:?}", path, name, a, b),
+    }).collect();
+    assert_eq!(ops, [
+        "+ added 6",
+        "- gone",
+        "<> link",
+        "@ link href Some(Some(\"x\")) Some(Some(\"y\"))",
+        "@ link rel None Some(None)",
+        "~ port 80 8080",
+        "~ servers[1] y w",
+        "- servers[2]",
+    ]);
+    assert!(diff(&old, &old.clone()).is_empty());
+    let reordered = parse_value_str("{gone: 1; link: <a href:x>:text; servers: [x; y; z]; port: 80; name: a}").unwrap();
+    assert!(diff(&old, &reordered).is_empty());
+}
+
+#[test]
+fn test_cursor() {
+    use khi::pdm::Cursor;
+    let document = parse_value_str("{name: khi\nservers: [a; {host: b; port: 80}; c]\nlink: <a href:x>:text\nlast: 1}").unwrap();
+    let mut cursor = Cursor::new(&document);
+    assert!(!cursor.parent());
+    assert!(!cursor.next_sibling());
+    assert!(cursor.enter_key("servers"));
+    assert!(cursor.enter_index(1));
+    assert!(cursor.enter_key("port"));
+    assert_eq!(cursor.path().to_string(), "servers[1]:port");
+    assert_eq!(cursor.value().as_text().unwrap().as_str(), "80");
+    let (from, _) = cursor.span();
+    assert_eq!((from.line, from.column), (2, 30));
+    assert!(!cursor.enter_key("missing"));
+    assert!(cursor.parent());
+    assert!(cursor.next_sibling());
+    assert_eq!(cursor.path().to_string(), "servers[2]");
+    assert!(!cursor.next_sibling());
+    assert!(cursor.previous_sibling() && cursor.previous_sibling());
+    assert!(!cursor.previous_sibling());
+    assert_eq!(cursor.value().as_text().unwrap().as_str(), "a");
+    assert!(cursor.parent());
+    assert!(cursor.next_sibling());
+    assert_eq!(cursor.path().to_string(), "link");
+    assert!(cursor.enter_content());
+    assert_eq!(cursor.path().to_string(), "link<>");
+    assert!(cursor.parent() && cursor.next_sibling());
+    assert_eq!(cursor.path().to_string(), "last");
+    assert!(!cursor.next_sibling());
+    assert_eq!(cursor.depth(), 1);
+}
+
+#[test]
+fn test_merge() {
+    use khi::pdm::{ListMerge, MergeStrategy, Resolution};
+    let defaults = parse_dictionary_str("server: {host: localhost; port: 80; paths: [a]}\nlog: info\nname: app").unwrap();
+    let overrides = parse_dictionary_str("server: {port: 8080; paths: [b]; tls: on}\nlog: debug").unwrap();
+    let mut merged = defaults.clone();
+    merged.merge(&overrides, &MergeStrategy::default());
+    let text = |d: &khi::pdm::ParsedDictionary, k: &str| d.get(k).unwrap().as_text().unwrap().as_str().to_string();
+    let server = merged.get("server").unwrap().as_dictionary().unwrap();
+    assert_eq!(text(server, "host"), "localhost");
+    assert_eq!(text(server, "port"), "8080");
+    assert_eq!(text(server, "tls"), "on");
+    assert_eq!(server.get("paths").unwrap().as_list().unwrap().len(), 1);
+    assert_eq!(text(&merged, "log"), "debug");
+    assert_eq!(text(&merged, "name"), "app");
+    let conflicts = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let strategy = MergeStrategy {
+        lists: ListMerge::Append,
+        on_conflict: Some(Box::new({
+            let conflicts = conflicts.clone();
+            move |path, _, _| {
+                conflicts.borrow_mut().push(path.to_string());
+                if path.to_string() == "log" { Resolution::Keep } else { Resolution::Value(ParsedValue::text("443")) }
+            }
+        })),
+    };
+    let mut merged = defaults.clone();
+    merged.merge(&overrides, &strategy);
+    let server = merged.get("server").unwrap().as_dictionary().unwrap();
+    assert_eq!(server.get("paths").unwrap().as_list().unwrap().len(), 2);
+    assert_eq!(text(server, "port"), "443");
+    assert_eq!(text(&merged, "log"), "info");
+    assert_eq!(*conflicts.borrow(), ["server:port", "log"]);
+    let mut merged = khi::pdm::ParsedDictionary { normalization: khi::pdm::KeyNormalization::Lowercase, ..khi::pdm::ParsedDictionary::empty() };
+    merged.merge(&parse_dictionary_str("Z: 2\nA: 3\nM: 4").unwrap(), &MergeStrategy::default());
+    let keys: Vec<&str> = merged.iter_sorted(khi::pdm::KeyOrder::Source).map(|(key, _)| key).collect();
+    assert_eq!(keys, ["z", "a", "m"]);
+    assert_eq!(text(&merged, "a"), "3");
+    assert_eq!(merged.raw_key("z"), Some("Z"));
+}
+
+#[test]
+fn test_patch() {
+    use khi::pdm::{PatchError, PatchOp, Path, PathSegment};
+    let key = |k: &str| PathSegment::Key(k.to_string());
+    let mut config = parse_dictionary_str("server: {host: localhost; paths: [a; c]}\nlog: info").unwrap();
+    config.patch(&[
+        PatchOp::Set(Path(vec![key("server"), key("port")]), ParsedValue::text("8080")),
+        PatchOp::Set(Path(vec![key("log")]), ParsedValue::text("debug")),
+        PatchOp::Insert(Path(vec![key("server"), key("paths"), PathSegment::Index(1)]), ParsedValue::text("b")),
+        PatchOp::Remove(Path(vec![key("server"), key("host")])),
+    ]).unwrap();
+    let value = ParsedValue::Dictionary(config.clone(), khi::pdm::Position::ZERO, khi::pdm::Position::ZERO);
+    assert_eq!(value.get_at(&Path(vec![key("server"), key("port")])).unwrap().as_text().unwrap().as_str(), "8080");
+    assert_eq!(value.get_at(&Path(vec![key("log")])).unwrap().as_text().unwrap().as_str(), "debug");
+    let paths: Vec<&str> = value.get_at(&Path(vec![key("server"), key("paths")])).unwrap().as_list().unwrap().iter().map(|e| e.as_text().unwrap().as_str()).collect();
+    assert_eq!(paths, ["a", "b", "c"]);
+    assert!(value.get_at(&Path(vec![key("server"), key("host")])).is_none());
+    // A failing operation leaves the dictionary unchanged.
+    let error = config.patch(&[
+        PatchOp::Remove(Path(vec![key("log")])),
+        PatchOp::Insert(Path(vec![key("server"), key("paths"), PathSegment::Index(4)]), ParsedValue::text("d")),
+    ]).unwrap_err();
+    assert_eq!(error, PatchError::NotFound(Path(vec![key("server"), key("paths"), PathSegment::Index(4)])));
+    assert_eq!(error.to_string(), "No value at ⟨server:paths[4]⟩.");
+    assert!(config.get("log").is_some());
+    assert!(matches!(config.patch(&[PatchOp::Insert(Path(vec![key("log")]), ParsedValue::text("x"))]), Err(PatchError::Invalid(_))));
+    assert!(matches!(config.patch(&[PatchOp::Set(Path(vec![key("missing"), key("x")]), ParsedValue::text("x"))]), Err(PatchError::NotFound(_))));
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_attribute_shorthand() {
+    use khi::html::{write_html, write_html_with, HtmlOptions, PreprocessorError};
+    let options = HtmlOptions { attribute_shorthand: true, ..HtmlOptions::default() };
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options);
+    let output = html("<div .card #main .wide>:x", &options).ok().unwrap();
+    assert_eq!(output, "<div class=\"card wide\" id=\"main\">x</div>");
+    let output = html("<div class:a .b hidden>:x", &options).ok().unwrap();
+    assert_eq!(output, "<div class=\"a b\" hidden>x</div>");
+    let output = write_html(&parse_value_str("<div .card>:x").unwrap()).ok().unwrap();
+    assert_eq!(output, "<div .card>x</div>");
+    assert!(matches!(html("<div #a #b>:x", &options), Err(PreprocessorError::DuplicateAttribute(_, key)) if key == "id"));
+    let output = html("<div.card.highlight#main>:x", &options).ok().unwrap();
+    assert_eq!(output, "<div class=\"card highlight\" id=\"main\">x</div>");
+    let output = html("<p.note class:wide .dark>:x <.box>:y", &options).ok().unwrap();
+    assert_eq!(output, "<p class=\"note wide dark\">x</p> <div class=\"box\">y</div>");
+    let output = write_html(&parse_value_str("<div.card>:x").unwrap()).ok().unwrap();
+    assert_eq!(output, "<div.card>x</div.card>");
+    assert!(matches!(html("<div#a #b>:x", &options), Err(PreprocessorError::DuplicateAttribute(_, key)) if key == "id"));
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_boolean_attributes() {
+    use khi::html::{write_html_with, BooleanAttributes, HtmlOptions, PreprocessorError};
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options);
+    let source = "<input checked disabled:false type:checkbox>";
+    assert_eq!(html(source, &HtmlOptions::default()).ok().unwrap(), "<input checked disabled=\"false\" type=\"checkbox\">");
+    let options = HtmlOptions { boolean_attributes: BooleanAttributes::Expanded, omit_false: true, ..HtmlOptions::default() };
+    assert_eq!(html(source, &options).ok().unwrap(), "<input checked=\"checked\" type=\"checkbox\">");
+    let options = HtmlOptions { validate_boolean_attributes: true, ..HtmlOptions::default() };
+    assert!(html(source, &options).is_ok());
+    assert!(matches!(html("<input chekced>", &options), Err(PreprocessorError::NotBooleanAttribute(_, key)) if key == "chekced"));
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_escaping() {
+    use khi::html::write_html;
+    let html = |source: &str| write_html(&parse_value_str(source).unwrap()).ok().unwrap();
+    assert_eq!(html(r#"<p>:\a < b && "c" > d\"#), "<p>a &lt; b &amp;&amp; \"c\" &gt; d</p>");
+    assert_eq!(html(r#"<a href:\?a=1&b=2\ title:\say "hi"\>:x"#), "<a href=\"?a=1&amp;b=2\" title=\"say &quot;hi&quot;\">x</a>");
+    assert_eq!(html(r#"<p>:<raw!>:<#>a&nbsp;<b>b</b><#>"#), "<p>a&nbsp;<b>b</b></p>");
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_layout() {
+    use khi::html::{write_html_with, HtmlOptions};
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options).ok().unwrap();
+    let source = "<doctype!>:html\n<html>:{<body>:{<h1>:Title <p>:{Some <em>:text here.} <ul>:{<li>:a <li>:b} <img src:a.jpg>}}";
+    let options = HtmlOptions { pretty: true, ..HtmlOptions::default() };
+    assert_eq!(html(source, &options), "<!DOCTYPE html>\n<html>\n  <body>\n    <h1>Title</h1>\n    <p>Some <em>text</em> here.</p>\n    \
+        <ul>\n      <li>a</li>\n      <li>b</li>\n    </ul>\n    <img src=\"a.jpg\">\n  </body>\n</html>");
+    let options = HtmlOptions { pretty: true, indent: 1, block_elements: vec![String::from("body")], ..HtmlOptions::default() };
+    assert_eq!(html(source, &options), "<!DOCTYPE html> <html>\n<body><h1>Title</h1> <p>Some <em>text</em> here.</p> <ul><li>a</li>\n <li>b</li></ul> \
+        <img src=\"a.jpg\"></body>\n</html>");
+    let options = HtmlOptions { compact: true, ..HtmlOptions::default() };
+    assert_eq!(html(source, &options), "<!DOCTYPE html><html><body><h1>Title</h1><p>Some <em>text</em> here.</p><ul><li>a</li><li>b</li></ul><img src=\"a.jpg\"></body></html>");
+    let options = HtmlOptions { wrap: 10, ..HtmlOptions::default() };
+    assert_eq!(html("<p>:{one two three four}", &options), "<p>one two\nthree four</p>");
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_include() {
+    use std::rc::Rc;
+    use khi::html::{write_html_with, HtmlLoader, HtmlOptions, PreprocessorError};
+    let loader: HtmlLoader = Rc::new(|path| match path {
+        "partials/header.khi" => Ok(String::from("<header>:<h1>:Title")),
+        "bad.khi" => Ok(String::from("<p>:x\n  <p>:[a]")),
+        "c.khi" => Ok(String::from("<include!>:d.khi")),
+        "d.khi" => Ok(String::from("<include!>:c.khi")),
+        _ => Err(String::from("not found")),
+    });
+    let options = HtmlOptions { loader: Some(loader), ..HtmlOptions::default() };
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options);
+    let error = |source: &str, options: &HtmlOptions| match html(source, options) {
+        Err(PreprocessorError::MacroError(message)) => message,
+        _ => panic!("Expected macro error in {:?}.", source),
+    };
+    let output = html("<body>:{<include!>:partials/header.khi <p>:Text}", &options).ok().unwrap();
+    assert_eq!(output, "<body><header><h1>Title</h1></header> <p>Text</p></body>");
+    assert!(matches!(html("<include!>:bad.khi", &options), Err(PreprocessorError::Include(path, error))
+        if path == "bad.khi" && matches!(*error, PreprocessorError::IllegalTable(at) if at.line == 2 && at.column == 7)));
+    assert_eq!(error("<include!>:c.khi", &options), "include! cycle: c.khi -> d.khi -> c.khi.");
+    assert_eq!(error("<include!>:x.khi", &options), "include! cannot load x.khi: not found");
+    assert_eq!(error("<include!>:a.khi", &HtmlOptions::default()), "include! of a.khi requires a loader.");
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_attribute_values() {
+    use khi::fmt::format_value;
+    use khi::html::{write_html_with, HtmlOptions};
+    use khi::pdm::{ParsedAttributeValue, SharedStr};
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options).ok().unwrap();
+    let options = HtmlOptions::default();
+    assert_eq!(html("<img width:120px height:2.5em>", &options), "<img width=\"120px\" height=\"2.5em\">");
+    assert_eq!(html("<div class:[card; wide; \\dark mode\\]>:x", &options), "<div class=\"card wide dark mode\">x</div>");
+    assert_eq!(html("<div class:[]>:x", &options), "<div class=\"\">x</div>");
+    assert_eq!(html(r#"<p title:\it's "so"\>:x"#, &options), "<p title=\"it's &quot;so&quot;\">x</p>");
+    let options = HtmlOptions { attribute_shorthand: true, ..HtmlOptions::default() };
+    assert_eq!(html("<div class:[a; b] .c>:x", &options), "<div class=\"a b c\">x</div>");
+    assert!(parse_value_str("<div class:[a; {b}]>").is_err());
+    let options = HtmlOptions { single_quotes: true, ..HtmlOptions::default() };
+    assert_eq!(html(r#"<p title:\say "hi"\>:x"#, &options), "<p title='say \"hi\"'>x</p>");
+    assert_eq!(html(r#"<p title:\it's "so"\>:x"#, &options), "<p title=\"it's &quot;so&quot;\">x</p>");
+    let list = parse_value_str("<div class:[c; d]>").unwrap();
+    let text = parse_value_str("<div class:\\c d\\>").unwrap();
+    assert!(!list.canonical_eq(&text));
+    assert_eq!(format_value(&list), "<div class:[c; d]>");
+    let attribute = &list.as_tagged().unwrap().attributes[0];
+    assert_eq!(attribute.1, Some(ParsedAttributeValue::List(vec![SharedStr::from("c"), SharedStr::from("d")])));
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_xml_dialect() {
+    use khi::html::{write_html_with, HtmlOptions, OutputDialect, PreprocessorError};
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options);
+    let source = "<p>:{a <br> b} <script src:a.js>:<> <input checked>";
+    assert_eq!(html(source, &HtmlOptions::default()).ok().unwrap(), "<p>a <br> b</p> <script src=\"a.js\"></script> <input checked>");
+    let options = HtmlOptions { dialect: OutputDialect::Xml, xml_declaration: true, ..HtmlOptions::default() };
+    assert_eq!(html(source, &options).ok().unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<p>a <br/> b</p> <script src=\"a.js\"/> <input checked=\"checked\"/>");
+    assert_eq!(html("{b: <>}", &options).ok().unwrap(), "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<b/>");
+    let source = "<script>:<cdata!>:<#>if (a < b && c) { x[y[0]]>1 }<#>";
+    assert_eq!(html(source, &HtmlOptions::default()).ok().unwrap(), "<script><![CDATA[if (a < b && c) { x[y[0]]]]><![CDATA[>1 }]]></script>");
+    assert!(matches!(html("<cdata!>:[a]", &options), Err(PreprocessorError::MacroError(message)) if message == "cdata! must take 1 text argument, got [a]."));
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_namespaces() {
+    use khi::html::{write_html_with, HtmlOptions, OutputDialect, PreprocessorError};
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options);
+    let options = HtmlOptions { dialect: OutputDialect::Xml, validate_namespaces: true, ..HtmlOptions::default() };
+    let source = r"<svg xmlns::svg:\http://www.w3.org/2000/svg\>:<svg::rect xml::lang:en>";
+    assert_eq!(html(source, &options).ok().unwrap(), "<svg xmlns:svg=\"http://www.w3.org/2000/svg\"><svg:rect xml:lang=\"en\"/></svg>");
+    let undeclared = |source: &str, options: &HtmlOptions| match html(source, options) {
+        Err(PreprocessorError::UndeclaredPrefix(_, name)) => name,
+        _ => panic!("Expected undeclared prefix in {:?}.", source),
+    };
+    assert_eq!(undeclared("<feed>:<atom::link>", &options), "atom:link");
+    assert_eq!(undeclared(r"<a>:<b xmlns::x:\u\> <c x::y:z>", &options), "x:y");
+    assert_eq!(undeclared("{media::title: x}", &options), "media:title");
+    let namespaces = vec![(String::new(), String::from("http://www.w3.org/2005/Atom")), (String::from("media"), String::from("http://search.yahoo.com/mrss/"))];
+    let options = HtmlOptions { namespaces, ..options };
+    assert_eq!(html("<feed>:<media::title>:x", &options).ok().unwrap(),
+        "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:media=\"http://search.yahoo.com/mrss/\"><media:title>x</media:title></feed>");
+    assert_eq!(html(r"<feed xmlns:\urn:x\>:x", &options).ok().unwrap(), "<feed xmlns=\"urn:x\" xmlns:media=\"http://search.yahoo.com/mrss/\">x</feed>");
+    assert!(html("<feed>:<atom::link>", &HtmlOptions::default()).is_ok());
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_source_map() {
+    use std::rc::Rc;
+    use khi::html::{write_html_mapped, HtmlLoader, HtmlOptions};
+    let loader: HtmlLoader = Rc::new(|_| Ok(String::from("<footer>:{\n  End\n}")));
+    let options = HtmlOptions { loader: Some(loader), pretty: true, ..HtmlOptions::default() };
+    let source = "<body>:{\n  <p>:{Some <em>:text}\n  <raw!>:<#>&nbsp;<#>\n  <include!>:footer.khi\n}";
+    let (html, map) = write_html_mapped(&parse_value_str(source).unwrap(), &options).ok().unwrap();
+    assert_eq!(html, "<body>\n  <p>Some <em>text</em></p>&nbsp;\n  <footer>End</footer>\n</body>");
+    let at = |needle: &str| map.source(html.find(needle).unwrap()).map(|at| (at.line, at.column));
+    assert_eq!(at("<body>"), Some((1, 1)));
+    assert_eq!(at("<p>"), Some((2, 3)));
+    assert_eq!(at("Some"), Some((2, 8)));
+    assert_eq!(at("<em>"), Some((2, 13)));
+    assert_eq!(at("text"), Some((2, 18)));
+    assert_eq!(at("&nbsp;"), Some((3, 3)));
+    assert_eq!(at("End"), Some((4, 3)));
+    assert_eq!(at("\n</body>"), Some((1, 1)));
+    assert!(map.iter().all(|(range, _)| range.end <= html.len()));
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_preserve_whitespace() {
+    use khi::html::{write_html_with, HtmlOptions};
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options).ok().unwrap();
+    let source = "<div>:{<pre>:<code>:<#>\n  if a < b:\n      return  a\n  <#> <p>:{x   y}}";
+    let options = HtmlOptions { pretty: true, wrap: 10, ..HtmlOptions::default() };
+    assert_eq!(html(source, &options), "<div>\n  <pre><code>if a &lt; b:\n    return  a\n</code></pre>\n  <p>x y</p>\n</div>");
+    let options = HtmlOptions { preserve_elements: vec![], ..HtmlOptions::default() };
+    assert_eq!(html(source, &options), "<div><pre><code>if a &lt; b: return a </code></pre> <p>x y</p></div>");
+    let options = HtmlOptions { pretty: true, ..HtmlOptions::default() };
+    assert_eq!(html("<pre>:{a <div>:b c}", &options), "<pre>a <div>b</div> c</pre>");
+    assert_eq!(html("<textarea>:\\a  b\\", &options), "<textarea>a  b</textarea>");
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_document() {
+    use khi::html::{write_html_with, Emission, HtmlOptions, PreprocessorError};
+    let document = parse_value_str("<p>:Hello").unwrap();
+    let options = HtmlOptions::default();
+    assert_eq!(write_html_with(&document, &options).ok().unwrap(), "<p>Hello</p>");
+    let head = parse_dictionary_str("lang: en\ntitle: Fruits\nmeta: {author: A}\nstylesheets: [a.css; b.css]\nscripts: c.js").unwrap();
+    let options = HtmlOptions { emission: Emission::Document(head), compact: true, ..HtmlOptions::default() };
+    assert_eq!(write_html_with(&document, &options).ok().unwrap(), "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\"><title>Fruits</title>\
+        <meta name=\"author\" content=\"A\"><link rel=\"stylesheet\" href=\"a.css\"><link rel=\"stylesheet\" href=\"b.css\">\
+        <script src=\"c.js\"></script></head><body><p>Hello</p></body></html>");
+    let options = HtmlOptions { emission: Emission::Document(parse_dictionary_str("").unwrap()), ..HtmlOptions::default() };
+    assert_eq!(write_html_with(&parse_value_str("").unwrap(), &options).ok().unwrap(), "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"></head><body></body></html>");
+    let options = HtmlOptions { emission: Emission::Document(parse_dictionary_str("titel: Fruits").unwrap()), ..HtmlOptions::default() };
+    assert!(matches!(write_html_with(&document, &options), Err(PreprocessorError::HeadError(..))));
+    let options = HtmlOptions { emission: Emission::Document(parse_dictionary_str("title: <b>:Fruits").unwrap()), ..HtmlOptions::default() };
+    assert!(matches!(write_html_with(&document, &options), Err(PreprocessorError::HeadError(..))));
+}
+
+#[cfg(feature = "html")]
+#[test]
+fn test_html_sanitize() {
+    use khi::html::{write_html_with, Emission, HtmlOptions, PreprocessorError, Sanitize};
+    let html = |source: &str, options: &HtmlOptions| write_html_with(&parse_value_str(source).unwrap(), options);
+    let source = "<p onclick:\\alert(1)\\ class:x>:{<a href:\\ JavaScript:alert(1)\\>:Hi <script>:x <raw!>:y <SCRIPT>:z <a HREF:\\https://x\\>:ok}";
+    let options = HtmlOptions { sanitize: Sanitize::Drop, ..HtmlOptions::default() };
+    assert_eq!(html(source, &options).ok().unwrap(), "<p class=\"x\"><a>Hi</a> <a HREF=\"https://x\">ok</a></p>");
+    assert_eq!(html("{p: a; style: b}", &options).ok().unwrap(), "<p>a</p>");
+    let options = HtmlOptions { sanitize: Sanitize::Reject, ..HtmlOptions::default() };
+    assert!(matches!(html(source, &options), Err(PreprocessorError::DisallowedAttribute(_, key)) if key == "onclick"));
+    assert!(matches!(html("<p>:<script>:x", &options), Err(PreprocessorError::DisallowedElement(_, name)) if name == "script"));
+    assert!(matches!(html("<raw!>:x", &options), Err(PreprocessorError::DisallowedElement(..))));
+    assert!(matches!(html("<img src:\\java\tscript:x\\>", &options), Err(PreprocessorError::ScriptUrl(..))));
+    assert_eq!(html("<a title:\\javascript: a\\>:x", &options).ok().unwrap(), "<a title=\"javascript: a\">x</a>");
+    let allowed_elements = vec![String::from("raw!")];
+    let options = HtmlOptions { sanitize: Sanitize::Reject, allowed_elements, ..HtmlOptions::default() };
+    assert_eq!(html("<raw!>:\\<b>\\", &options).ok().unwrap(), "<b>");
+    let head = parse_dictionary_str("scripts: a.js").unwrap();
+    let options = HtmlOptions { sanitize: Sanitize::Drop, emission: Emission::Document(head), compact: true, ..HtmlOptions::default() };
+    assert_eq!(html("<script>:x", &options).ok().unwrap(), "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><script src=\"a.js\"></script></head><body></body></html>");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_parsed_value() {
+    use khi::model::SimpleValue;
+    use khi::pdm::SerializeOptions;
+    let source = "a: <b x:y f z:[1; 2]>:{c | d}\ne: [f {g: 1}; ~; <>: h | i]\nj: k~{l} m";
+    let value = ParsedValue::Dictionary(parse_dictionary_str(source).unwrap(), khi::pdm::Position::ZERO, khi::pdm::Position::ZERO);
+    let json = serde_json::to_string(&value).unwrap();
+    let from_json: ParsedValue = serde_json::from_str(&json).unwrap();
+    assert!(from_json.canonical_eq(&value));
+    let e = from_json.as_dictionary().unwrap().get("e").unwrap();
+    assert_eq!((e.from().line, e.from().column), (0, 0));
+    let options = SerializeOptions { positions: true };
+    let json = serde_json::to_string(&value.serializable(options)).unwrap();
+    let from_json: ParsedValue = serde_json::from_str(&json).unwrap();
+    let e = from_json.as_dictionary().unwrap().get("e").unwrap();
+    assert_eq!((e.from().line, e.from().column), (2, 4));
+    let bytes = bincode::serialize(&value).unwrap();
+    let from_bincode: ParsedValue = bincode::deserialize(&bytes).unwrap();
+    assert!(from_bincode.canonical_eq(&value));
+    let bytes = bincode::serialize(&value.serializable(options)).unwrap();
+    let from_bincode: ParsedValue = bincode::deserialize(&bytes).unwrap();
+    assert!(from_bincode.canonical_eq(&value));
+    assert_eq!(from_bincode.as_dictionary().unwrap().get("e").unwrap().from().line, 2);
+    let simple = SimpleValue::from(&value);
+    let json = serde_json::to_string(&simple).unwrap();
+    assert!(json.starts_with("{\"Dictionary\":{\"a\":{\"Tagged\""));
+    assert_eq!(serde_json::from_str::<SimpleValue>(&json).unwrap(), simple);
+    assert_eq!(bincode::deserialize::<SimpleValue>(&bincode::serialize(&simple).unwrap()).unwrap(), simple);
+    let converted: ParsedValue = (&simple).into();
+    assert!(converted.canonical_eq(&value));
+    let without_positions: ParsedValue = serde_json::from_str("{\"List\": {\"elements\": [{\"Text\": {\"text\": \"a\"}}]}}").unwrap();
+    assert!(without_positions.canonical_eq(&ParsedValue::list(vec![ParsedValue::text("a")])));
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_notation() {
+    use khi::tex::collect_notation;
+    let source = "<def!>:Log:0:{<operatorname>:Log}\n<def!>:{<Sq>}:1:{#1^2}\nText <textbf>:x.\n<$>:{<Log>:x + <alpha>}\n<begin>:align* <Sq>:<alpha> <end>:align*\n<emph>:y";
+    let document = parse_value_str(source).unwrap();
+    let index = collect_notation(&document);
+    assert_eq!(index.definitions.len(), 2);
+    assert_eq!(index.definition("Log").unwrap().arity, "0");
+    assert_eq!(index.definition("Sq").unwrap().at.line, 2);
+    let names: Vec<&str> = index.symbols.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["Log", "alpha", "Sq"]);
+    assert_eq!(index.symbol("alpha").unwrap().count, 2);
+    assert_eq!(index.symbol("alpha").unwrap().at.line, 4);
+    let summary = parse_dictionary_str(&index.to_khi()).unwrap();
+    assert_eq!(summary.get("definitions").unwrap().as_list().unwrap().len(), 2);
+    assert_eq!(summary.get("symbols").unwrap().as_list().unwrap().len(), 3);
+    // Entries of dictionaries are visited in source order.
+    let document = parse_value_str("{a: <$>:<zeta>; b: <$>:<eta>; c: <$>:<theta>; d: <$>:<iota>; e: <$>:<kappa>}").unwrap();
+    for _ in 0..16 {
+        let names: Vec<String> = collect_notation(&document).symbols.into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["zeta", "eta", "theta", "iota", "kappa"]);
+    }
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_escaping() {
+    use khi::tex::{write_tex_with, BreakMode, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options).ok().unwrap();
+    assert_eq!(tex(r"\a_b^c #1 x`\y 50%\"), r"a\_b\^{}c \#1 x\textbackslash{}y 50\%");
+    assert_eq!(tex(r"<$>:{x_1^2 + \#\}"), r"$x_1^2 + \#$");
+    assert_eq!(tex(r"<$>:{a <text>:\a_b\ <mbox>:{c^d}}"), r"$a \text{a\_b} \mbox{c\^{}d}$");
+    assert_eq!(tex(r"<begin>:align* x_1 <end>:align* y_1"), r"\begin{align*} x_1 \end{align*} y\_1");
+    assert_eq!(tex(r"<ensuremath>:{x_1} <label>:eq_1 <ref>:eq_1"), r"\ensuremath{x_1} \label{eq_1} \ref{eq_1}");
+    assert_eq!(tex(r"<def!>:Sq:1:{#1^2_k}"), r"\newcommand\Sq[1]{#1^2_k}");
+    assert_eq!(tex(r"<def!>:Log:2:e:{<log>_{#1} #2}"), r"\newcommand\Log[2][e]{\log_{#1} #2}");
+    assert_eq!(tex(r"<def! star>:Norm:2:2:{<IfBooleanTF>:#1:{#3}:{<lVert>#3<rVert>_#2}}"), r"\NewDocumentCommand\Norm{s O{2} m}{\IfBooleanTF{#1}{#3}{\lVert #3\rVert_#2}}");
+    assert_eq!(tex(r"<def! star>:Abs:1:{#2}"), r"\NewDocumentCommand\Abs{s m}{#2}");
+    assert_eq!(tex(r"<raw!>:\a_b#\ <$>:\a`\b\"), r"a_b# $a\backslash{}b$");
+    assert_eq!(tex(r"a <n> b <p> c"), r"a \\ b \par{} c");
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_environment() {
+    use khi::tex::{write_tex_with, BreakMode, PreprocessorError, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options).ok().unwrap();
+    assert_eq!(tex(r"<env!>:itemize:{<item> A <item> B}"), r"\begin{itemize}\item{} A \item{} B\end{itemize}");
+    assert_eq!(tex(r"<env!>:figure':h:{<centering> a_b}"), r"\begin{figure}[h]\centering{} a\_b\end{figure}");
+    assert_eq!(tex(r"<env!>:tabular:lr:[a|b; c|d]"), r"\begin{tabular}{lr}a&b\\c&d\\\end{tabular}");
+    assert_eq!(tex(r"<env!>:align*:[x_1|= 2] y_1"), r"\begin{align*}x_1&= 2\\\end{align*} y\_1");
+    assert_eq!(tex(r"<env!>:center:{<env!>:tabular:c:[x]}"), r"\begin{center}\begin{tabular}{c}x\\\end{tabular}\end{center}");
+    let error = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options).err().unwrap();
+    assert!(matches!(error(r"<env!>:figure':{a}"), PreprocessorError::MissingOptionalArgument(_)));
+    assert!(matches!(error(r"<env!>:itemize"), PreprocessorError::MacroError(..)));
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_macro_error_preview() {
+    use khi::tex::{write_tex, PreprocessorError};
+    let error = |source: &str| match write_tex(&parse_value_str(source).unwrap()) {
+        Err(PreprocessorError::MacroError(_, message)) => message,
+        _ => panic!("Expected macro error in {:?}.", source),
+    };
+    assert_eq!(error("<raw!>:[a; b]"), "raw! must take 1 text argument, got [a; b].");
+    assert_eq!(error("<def!>:{<Sq>}:1:{#1^2}"), "def! must take a command name, got <Sq>.");
+    assert_eq!(error("<def!>:Sq:{#1^2}"), "def! must take 3 or 4 arguments, got Sq | #1^2.");
+    assert_eq!(error("<def! star>:Sq:n:{#2^2}"), "def! star must take a number of arguments, got n.");
+    let message = error("<lines!>:{<#>\n  first line\n  second line of a long paragraph\n<#>}:x");
+    assert!(message.starts_with("lines! takes 1 text argument, got \\first line`nsecond") && message.ends_with("...."), "{}", message);
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_macro_registry() {
+    use khi::tex::{write_tex_with, BreakMode, PreprocessorError, TexMacroRegistry, TexOptions};
+    let mut macros = TexMacroRegistry::new();
+    macros.register("raw!", |tag, writer| {
+        writer.write_raw("%");
+        writer.write_text(tag.value.as_text().unwrap().as_str());
+        Ok(())
+    });
+    macros.register("norm", |tag, writer| {
+        writer.write_raw("\\lVert ");
+        writer.write_value(&tag.value)?;
+        writer.write_raw("\\rVert ");
+        Ok(())
+    });
+    macros.register("fail!", |tag, _| Err(PreprocessorError::MacroError(tag.value.from(), String::from("fail"))));
+    let options = TexOptions { break_mode: BreakMode::Never, macros, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options);
+    assert_eq!(tex(r"<raw!>:a_b <$>:<norm>:{x_1}").ok().unwrap(), r"%a\_b $\lVert x_1\rVert $");
+    assert_eq!(tex(r"<env!>:center:{<norm>:v}").ok().unwrap(), r"\begin{center}\lVert v\rVert \end{center}");
+    assert!(matches!(tex("<fail!>:x"), Err(PreprocessorError::MacroError(_, message)) if message == "fail"));
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_source_map() {
+    use khi::tex::{write_tex_mapped, write_tex_with, BreakMode, TexOptions};
+    let source = "<section>:A\n\n  x <textbf>:y\n<raw!>:\\a\nb\\ c\n<p>";
+    let document = parse_value_str(source).unwrap();
+    let (tex, map) = write_tex_mapped(&document, &TexOptions::default()).ok().unwrap();
+    assert_eq!(tex, write_tex_with(&document, &TexOptions::default()).ok().unwrap());
+    assert_eq!(tex, "\\section{A}\n%\nx \\textbf{y}\na\nb c\n\\par");
+    let lines: Vec<(usize, usize, usize)> = map.iter().map(|(line, at)| (line, at.line, at.column)).collect();
+    assert_eq!(lines, [(1, 1, 1), (3, 3, 3), (4, 4, 1), (5, 5, 1), (6, 6, 1)]);
+    assert_eq!(map.source(2).unwrap().line, 1);
+    assert!(map.source(0).is_none());
+    // Lines written by a macro map to the macro.
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let (tex, map) = write_tex_mapped(&parse_value_str("x\n<raw!>:\\a`nb\\ y").unwrap(), &options).ok().unwrap();
+    assert_eq!(tex, "x a\nb y");
+    assert_eq!((map.source(2).unwrap().line, map.source(2).unwrap().column), (2, 1));
+    let options = TexOptions { break_mode: BreakMode::Margin, margin: 4, annotate: true, ..TexOptions::default() };
+    let (tex, map) = write_tex_mapped(&document, &options).ok().unwrap();
+    assert_eq!(tex, "\\section %&khi:1\n{A} %&khi:1\nx \\textbf %&khi:3\n{y} %&khi:3\nab c %&khi:4\n\\par");
+    assert_eq!(map.source(5).unwrap().line, 4);
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_tabular() {
+    use khi::tex::{write_tex_with, BreakMode, PreprocessorError, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options);
+    assert_eq!(tex(r"<tabular!>:[a|b; c|d|e]").ok().unwrap(), r"\begin{tabular}{lll}a&b\\c&d&e\\\end{tabular}");
+    assert_eq!(
+        tex(r"<tabular! columns:lr header rules:booktabs>:[Name|Mass; <$>:{H_2}|2]").ok().unwrap(),
+        r"\begin{tabular}{lr}\toprule Name&Mass\\\midrule $H_2$&2\\\bottomrule \end{tabular}",
+    );
+    assert_eq!(
+        tex(r"<tabular! columns:\l|c\ header rules:lines long>:[|a|b| |c|d|]").ok().unwrap(),
+        r"\begin{longtable}{l|c}\hline a&b\\\hline \endhead c&d\\\hline \end{longtable}",
+    );
+    assert!(matches!(tex(r"<tabular! rules:dotted>:[a]"), Err(PreprocessorError::MacroError(..))));
+    assert!(matches!(tex(r"<tabular!>:a"), Err(PreprocessorError::MacroError(..))));
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_verbatim() {
+    use khi::tex::{write_tex_mapped, write_tex_with, BreakMode, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options).ok().unwrap();
+    let source = "See <code language:Rust>:<#>\n  fn main() {\n      let a_b = 1 % 2; // \\ & #\n  }\n<#> <verbatim>:\\$x^2$\\";
+    assert_eq!(tex(source), "See \\begin{lstlisting}[language=Rust]\nfn main() {\n    let a_b = 1 % 2; // \\ & #\n}\n\\end{lstlisting} \\begin{verbatim}\n$x^2$\n\\end{verbatim}");
+    assert_eq!(tex("<code>:{a_b <alpha>}"), "\\code{a\\_b \\alpha}");
+    let (_, map) = write_tex_mapped(&parse_value_str(source).unwrap(), &TexOptions::default()).ok().unwrap();
+    assert_eq!((map.source(3).unwrap().line, map.source(3).unwrap().column), (1, 5));
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_optional_arguments() {
+    use khi::tex::{write_tex_with, BreakMode, PreprocessorError, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options);
+    assert_eq!(tex(r"<sqrt'>:3:27").ok().unwrap(), r"\sqrt[3]{27}");
+    assert_eq!(tex(r"<newtheorem''>:a:b:c:d").ok().unwrap(), r"\newtheorem[a][b]{c}{d}");
+    assert_eq!(tex(r"<usepackage'>:{margin: 1in; top: 2cm; draft: <>}:geometry").ok().unwrap(), r"\usepackage[margin=1in,top=2cm,draft]{geometry}");
+    assert_eq!(tex(r"<usepackage'>:{pdftitle: A, B; linkcolor: <textbf>:x}:hyperref").ok().unwrap(), r"\usepackage[pdftitle={A, B},linkcolor={\textbf{x}}]{hyperref}");
+    assert_eq!(tex(r"<env!>:minipage'':t:4cm:5cm:x").ok().unwrap(), r"\begin{minipage}[t][4cm]{5cm}x\end{minipage}");
+    assert!(matches!(tex(r"<foo''>:a"), Err(PreprocessorError::MissingOptionalArgument(_))));
+    assert!(matches!(tex(r"{a: b}"), Err(PreprocessorError::IllegalDictionary(_))));
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_scripts() {
+    use khi::tex::{write_tex_with, BreakMode, Scripts, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let scripts = TexOptions { scripts: Scripts::Grouped, ..options.clone() };
+    let grouped = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &scripts).ok().unwrap();
+    let glued = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options).ok().unwrap();
+    assert_eq!(grouped(r"<$>:{x^ab + y_1 + z^-1 + e^{i <pi>}} a_b^c"), r"$x^{ab} + y_1 + z^{-1} + e^{i \pi}$ a\_b\^{}c");
+    assert_eq!(glued(r"<$>:{x^ab + z^-1}"), r"$x^ab + z^-1$");
+    assert_eq!(glued(r"<$>:{x<sup!>:{n + 1} <sub!>:k} H<sub!>:2 O"), r"$x^{n + 1}_{k}$ H\textsubscript{2} O");
+    assert_eq!(glued(r"<$>:{<sup>:A}"), r"$\sup{A}$");
+    let index = khi::tex::collect_notation(&parse_value_str(r"<$>:{e<sup!>:{<pi> i}}").unwrap());
+    assert_eq!(index.symbol("pi").unwrap().count, 1);
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_options() {
+    use khi::tex::{collect_notation_with, write_tex_with, BreakMode, Escaping, Newlines, PreprocessorError, TexOptions};
+    let tex = |source: &str, options: &TexOptions| write_tex_with(&parse_value_str(source).unwrap(), options);
+    let source = "<section>:A\n\n\n  x <textbf>:y";
+    assert_eq!(tex(source, &TexOptions::default()).ok().unwrap(), "\\section{A}\n%\n%\nx \\textbf{y}");
+    let options = TexOptions { newlines: Newlines::Collapsed, ..TexOptions::default() };
+    assert_eq!(tex(source, &options).ok().unwrap(), "\\section{A}\nx \\textbf{y}");
+    let options = TexOptions { break_mode: BreakMode::Margin, margin: 16, ..TexOptions::default() };
+    assert_eq!(tex("<emph>:a <emph>:b <emph>:c", &options).ok().unwrap(), "\\emph{a} \\emph{b}\n\\emph{c}");
+    assert_eq!(tex("<emph>:漢字 <emph>:b <emph>:c", &options).ok().unwrap(), "\\emph{漢字} \\emph\n{b} \\emph{c}");
+    assert_eq!(tex("<emph>:e\u{301} <emph>:b <emph>:c", &options).ok().unwrap(), "\\emph{e\u{301}} \\emph{b}\n\\emph{c}");
+    let source = "<$>:{<alpha> <beta> <gamma> <delta>} <emph>:c";
+    assert_eq!(tex(source, &options).ok().unwrap(), "$\\alpha{} \\beta{} \\gamma\n\\delta$ \\emph{c}");
+    let options = TexOptions { break_mode: BreakMode::Margin, margin: 16, break_math: false, ..TexOptions::default() };
+    assert_eq!(tex(source, &options).ok().unwrap(), "$\\alpha{} \\beta{} \\gamma{} \\delta$\n\\emph{c}");
+    let options = TexOptions { break_mode: BreakMode::Never, macro_prefix: String::from("k."), ..TexOptions::default() };
+    assert_eq!(tex(r"<k.env!>:center:x", &options).ok().unwrap(), r"\begin{center}x\end{center}");
+    assert!(matches!(tex(r"<env!>:center:x", &options), Err(PreprocessorError::MacroError(..))));
+    let index = collect_notation_with(&parse_value_str(r"<k.env!>:align*:{<alpha>}").unwrap(), &options);
+    assert_eq!(index.symbol("alpha").unwrap().count, 1);
+    let options = TexOptions { break_mode: BreakMode::Never, strict: false, ..TexOptions::default() };
+    assert_eq!(tex(r"<foo!>:{a_b} c", &options).ok().unwrap(), r"a\_b c");
+    let options = TexOptions { break_mode: BreakMode::Never, escaping: Escaping::Minimal, ..TexOptions::default() };
+    assert_eq!(tex(r"\a_b^c #1 50% `\alpha\", &options).ok().unwrap(), r"a_b^c #1 50\% \alpha");
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_include() {
+    use std::rc::Rc;
+    use khi::tex::{collect_notation_with, write_tex_with, BreakMode, PreprocessorError, TexLoader, TexOptions};
+    let loader: TexLoader = Rc::new(|path| match path {
+        "a.khi" => Ok(String::from("<section>:A\n<$>:<alpha>")),
+        "b.khi" => Ok(String::from("<foo''>:a")),
+        "c.khi" => Ok(String::from("<include!>:d.khi")),
+        "d.khi" => Ok(String::from("<include!>:c.khi")),
+        _ => Err(String::from("not found")),
+    });
+    let options = TexOptions { break_mode: BreakMode::Never, loader: Some(loader), ..TexOptions::default() };
+    let tex = |source: &str, options: &TexOptions| write_tex_with(&parse_value_str(source).unwrap(), options);
+    let error = |source: &str, options: &TexOptions| match tex(source, options) {
+        Err(PreprocessorError::MacroError(_, message)) => message,
+        _ => panic!("Expected macro error in {:?}.", source),
+    };
+    assert_eq!(tex("Intro <include!>:a.khi end", &options).ok().unwrap(), "Intro \\section{A} $\\alpha$ end");
+    assert_eq!(error("<include!>:c.khi", &options), "include! cycle: c.khi -> d.khi -> c.khi.");
+    assert_eq!(error("<include!>:b.khi", &options), "In b.khi: Missing optional argument at 1:1.");
+    assert_eq!(error("<include!>:x.khi", &options), "include! cannot load x.khi: not found");
+    assert_eq!(error("<include!>:a.khi", &TexOptions::default()), "include! of a.khi requires a loader.");
+    let index = collect_notation_with(&parse_value_str("<include!>:a.khi <include!>:c.khi").unwrap(), &options);
+    assert_eq!(index.symbol("alpha").unwrap().count, 1);
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_error_diagnostic() {
+    use khi::tex::write_tex;
+    let error = write_tex(&parse_value_str("a\n  <raw!>:[x]").unwrap()).err().unwrap();
+    assert_eq!(error.to_string(), "Macro error at 2:3: raw! must take 1 text argument, got [x].");
+    let diagnostic = error.to_diagnostic();
+    assert_eq!(diagnostic.code, "macro_error");
+    assert_eq!(diagnostic.at.map(|at| (at.line, at.column)), Some((2, 3)));
+    let error: Box<dyn std::error::Error> = Box::new(write_tex(&parse_value_str("<foo'>").unwrap()).err().unwrap());
+    assert_eq!(error.to_string(), "Missing optional argument at 1:1.");
+    let errors = parse_value_str("a: b\n  c: d: e").err().unwrap();
+    let diagnostic = errors[0].to_diagnostic();
+    assert_eq!(diagnostic.code, errors[0].code());
+    assert!(diagnostic.at.is_some());
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_paragraphs() {
+    use khi::tex::{write_tex_source, write_tex_with, BreakMode, TexOptions};
+    let tex = |source: &str, options: &TexOptions| write_tex_source(source, &parse_value_str(source).unwrap(), options).ok().unwrap().0;
+    let source = "<documentclass>:article\n\n<usepackage>:amsmath\n\n<begin>:document\n\nFirst\nparagraph.\n\nSecond <textbf>:{a\n\nb}.\n# Comment\n\n<begin>:align* x\n\n= y <end>:align*\n\n\nThird. <end>:document";
+    let options = TexOptions { paragraphs: true, ..TexOptions::default() };
+    assert_eq!(
+        tex(source, &options),
+        "\\documentclass{article}\n%\n\\usepackage{amsmath}\n%\n\\begin{document}\n\\par\nFirst paragraph.\n%\n\\par\nSecond \\textbf{a b}\n%\n.\n%\n\\par\n\\begin{align*} x = y\n%\n\\end{align*}\n%\n\\par\nThird. \\end{document}",
+    );
+    assert_eq!(tex(source, &TexOptions::default()).matches("\\par").count(), 0);
+    let document = parse_value_str(source).unwrap();
+    assert_eq!(write_tex_with(&document, &options).ok().unwrap().matches("\\par").count(), 0);
+    let options = TexOptions { break_mode: BreakMode::Never, paragraphs: true, ..TexOptions::default() };
+    assert_eq!(tex("First\n\nSecond\n<textbf>:x\n\n<emph>:y \\quoted\\\n\nz", &options), "First \\par Second \\textbf{x} \\par\\emph{y} quoted z");
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_matrix() {
+    use khi::tex::{collect_notation, write_tex_with, BreakMode, PreprocessorError, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options);
+    assert_eq!(tex(r"<$>:{A = <pmatrix!>:[a_1|0; 0|<alpha>]}").ok().unwrap(), r"$A = \begin{pmatrix}a_1&0\\0&\alpha\end{pmatrix}$");
+    assert_eq!(
+        tex(r"<env!>:equation*:{f(x) = <cases!>:[x|<text>:{if x_0}; -x|<text>:otherwise]}").ok().unwrap(),
+        r"\begin{equation*}f(x) = \begin{cases}x&\text{if x\_0}\\-x&\text{otherwise}\end{cases}\end{equation*}",
+    );
+    assert_eq!(tex(r"<$>:<matrix!>:[1|2]").ok().unwrap(), r"$\begin{matrix}1&2\end{matrix}$");
+    assert!(matches!(tex(r"<$>:<bmatrix!>:{a}"), Err(PreprocessorError::MacroError(_, message)) if message == "bmatrix! must take a table, got a."));
+    let index = collect_notation(&parse_value_str(r"<$>:{<vmatrix!>:[<beta>]} <matrix!>:[<gamma>]").unwrap());
+    assert_eq!(index.symbol("beta").unwrap().count, 1);
+    assert_eq!(index.symbol("gamma").unwrap().count, 1);
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_key_values() {
+    use khi::tex::{write_tex_with, BreakMode, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options).ok().unwrap();
+    assert_eq!(tex(r"<hypersetup>:{colorlinks: true; linkcolor: blue}"), r"\hypersetup{colorlinks=true,linkcolor=blue}");
+    assert_eq!(tex(r"<usepackage'>:{margin: 1in; draft: <>}:geometry"), r"\usepackage[margin=1in,draft]{geometry}");
+    assert_eq!(
+        tex(r"<tikzset>:{\every node/.style\: {draw: <>; fill: red!20}; names: [a; b_1]; title: A, B}"),
+        r"\tikzset{every node/.style={draw,fill=red!20},names={a,b\_1},title={A, B}}",
+    );
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_comments() {
+    use khi::parse::parse_value_str_styled;
+    use khi::tex::{write_tex_source, BreakMode, TexOptions};
+    let source = "# Title\n<section>:Intro # Trailing\n\n# Between\nText.\n# End";
+    let comments = parse_value_str_styled(source, &ParseOptions { comments: true, ..ParseOptions::default() }).ok().unwrap().comments;
+    assert_eq!(comments.iter().map(|comment| (comment.at.line, comment.text.as_str())).collect::<Vec<_>>(), vec![(1, " Title"), (2, " Trailing"), (4, " Between"), (6, " End")]);
+    assert!(parse_value_str_styled(source, &ParseOptions::default()).ok().unwrap().comments.is_empty());
+    let tex = |options: &TexOptions| write_tex_source(source, &parse_value_str(source).unwrap(), options).ok().unwrap().0;
+    let options = TexOptions { comments: true, ..TexOptions::default() };
+    assert_eq!(tex(&options), "% Title\n\\section{Intro} % Trailing\n%\n% Between\nText.\n% End\n");
+    assert_eq!(tex(&TexOptions::default()), "\n\\section{Intro}\n%\n%\nText.");
+    let options = TexOptions { break_mode: BreakMode::Never, comments: true, ..TexOptions::default() };
+    assert_eq!(tex(&options), "\\section{Intro} Text.");
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_lint_commands() {
+    use khi::tex::{lint_commands, write_tex_with, BreakMode, PreprocessorError, TexOptions};
+    let lint = |source: &str, options: &TexOptions| lint_commands(&parse_value_str(source).unwrap(), options)
+        .iter().map(|warning| (warning.message.clone(), warning.at.unwrap().line)).collect::<Vec<_>>();
+    let source = "<preamble!>:[mathfrak; section]\n<def!>:Log:0:{<operatorname>:Log}\n<section>:A <$>:{<frak>:g <Log>}\n<secton>:B <begin>:align* <end>:align*";
+    assert_eq!(lint(source, &TexOptions::default()), vec![
+        (String::from("Unknown command operatorname."), 2),
+        (String::from("Unknown command frak. Did you mean mathfrak?"), 3),
+        (String::from("Unknown command secton. Did you mean section?"), 4),
+    ]);
+    let options = TexOptions { commands: vec![String::from("operatorname")], ..TexOptions::default() };
+    assert_eq!(lint(source, &options).len(), 2);
+    assert!(lint("<frak>:g <$>:<alpha>", &TexOptions::default()).is_empty());
+    assert_eq!(lint("<emph>:a <textbf>:b", &options), vec![(String::from("Unknown command emph."), 1), (String::from("Unknown command textbf."), 1)]);
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options);
+    assert_eq!(tex("<preamble!>:{emph textbf} <emph>:a").ok().unwrap(), r"\emph{a}");
+    assert!(matches!(tex("<preamble!>:{a: b}"), Err(PreprocessorError::MacroError(_, message)) if message == "preamble! must take command names, got {a: b}."));
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_nodes() {
+    use khi::tex::{build_tex_with, render_tex, write_tex_with, BreakMode, TexNode, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let text = |text: &str| TexNode::Text(text.to_string());
+    let group = |optional: bool, content: Vec<TexNode>| TexNode::Group { optional, content };
+    let document = parse_value_str(r"<env!>:figure':h:{<label>:a_1 <tabular! rules:lines>:[x|<emph>:y]}").unwrap();
+    let nodes = build_tex_with(&document, &options).ok().unwrap();
+    assert_eq!(nodes, vec![TexNode::Environment {
+        name: String::from("figure"),
+        arguments: vec![group(true, vec![text("h")])],
+        content: vec![
+            TexNode::Command { name: String::from("label"), arguments: vec![group(false, vec![text("a_1")])] },
+            text(" "),
+            TexNode::Tabular {
+                environment: String::from("tabular"),
+                arguments: vec![group(false, vec![text("ll")])],
+                content: vec![
+                    text("\\hline "),
+                    TexNode::Row(vec![vec![text("x")], vec![TexNode::Command { name: String::from("emph"), arguments: vec![group(false, vec![text("y")])] }]]),
+                    text("\\\\\\hline "),
+                ],
+            },
+        ],
+    }]);
+    assert_eq!(render_tex(&nodes), write_tex_with(&document, &options).ok().unwrap());
+    let document = parse_value_str(r"<$>:{<pmatrix!>:[a|b; c|d]} <code language:Rust>:{<#>fn main() {}<#>}").unwrap();
+    let nodes = build_tex_with(&document, &options).ok().unwrap();
+    assert!(matches!(&nodes[1], TexNode::Tabular { environment, content, .. } if environment == "pmatrix" && content.len() == 3));
+    assert!(matches!(&nodes[3], TexNode::Environment { name, arguments, .. } if name == "lstlisting" && arguments == &vec![group(true, vec![text("language=Rust")])]));
+    assert_eq!(render_tex(&nodes), write_tex_with(&document, &options).ok().unwrap());
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_conditional() {
+    use khi::tex::{collect_notation_with, write_tex_with, BreakMode, PreprocessorError, TexOptions};
+    let mut options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    options.variables.insert(String::from("class"), String::from("beamer"));
+    options.variables.insert(String::from("draft"), String::new());
+    let tex = |source: &str, options: &TexOptions| write_tex_with(&parse_value_str(source).unwrap(), options);
+    let source = r"<if!>:class:beamer:{<frame>:A}:{<section>:A} <ifdef!>:draft:{<listoftodos>}";
+    assert_eq!(tex(source, &options).ok().unwrap(), r"\frame{A} \listoftodos");
+    assert_eq!(tex(source, &TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() }).ok().unwrap(), r"\section{A} ");
+    assert_eq!(tex(r"<if!>:class:article:x <ifdef!>:final:y:z", &options).ok().unwrap(), "z");
+    let error = |source: &str| match tex(source, &options) {
+        Err(PreprocessorError::MacroError(_, message)) => message,
+        _ => panic!("Expected macro error in {:?}.", source),
+    };
+    assert_eq!(error(r"<if!>:class:x"), "if! must take a variable, a value and content, got class | x.");
+    assert_eq!(error(r"<ifdef!>:{<draft>}:x"), "ifdef! must take a variable name, got <draft>.");
+    let index = collect_notation_with(&parse_value_str(r"<$>:{<if!>:class:beamer:{<alpha>}:{<beta>}}").unwrap(), &options);
+    assert!(index.symbol("alpha").is_some() && index.symbol("beta").is_none());
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_references() {
+    use std::rc::Rc;
+    use khi::tex::{lint_references, write_tex_with, BreakMode, PreprocessorError, TexLoader, TexOptions};
+    let options = TexOptions { break_mode: BreakMode::Never, ..TexOptions::default() };
+    let tex = |source: &str| write_tex_with(&parse_value_str(source).unwrap(), &options);
+    assert_eq!(tex(r"See <cite!>:knuth_84 and <cite!>:[a; b], <ref!>:eq_1 above.").ok().unwrap(), r"See \cite{knuth_84} and \cite{a,b}, \ref{eq_1} above.");
+    assert_eq!(tex(r"<bib! style:plain>:refs").ok().unwrap(), r"\bibliographystyle{plain}\bibliography{refs}");
+    assert!(matches!(tex(r"<cite!>:{}"), Err(PreprocessorError::MacroError(_, message)) if message == "cite! must take keys, got {}."));
+    let loader: TexLoader = Rc::new(|path| match path {
+        "refs.bib" => Ok(String::from("@comment{x, y}\n@book{knuth84,\n  title = {TeX}}\n@Article{ lamport94 , title={LaTeX}}")),
+        _ => Err(String::from("not found")),
+    });
+    let options = TexOptions { loader: Some(loader), ..TexOptions::default() };
+    let lint = |source: &str, options: &TexOptions| lint_references(&parse_value_str(source).unwrap(), options)
+        .iter().map(|warning| (warning.code, warning.message.clone())).collect::<Vec<_>>();
+    let source = "<label>:eq_1 <cite!>:[knuth84; knuth48] <ref!>:eq_1 <ref!>:eq_2 <cite!>:lamport94 <bib!>:refs";
+    assert_eq!(lint(source, &options), vec![
+        ("unknown_citation", String::from("Unknown citation key knuth48. Did you mean knuth84?")),
+        ("unknown_label", String::from("Unknown label eq_2. Did you mean eq_1?")),
+    ]);
+    assert_eq!(lint(source, &TexOptions::default()), vec![("unknown_label", String::from("Unknown label eq_2. Did you mean eq_1?"))]);
+    let options = TexOptions { citations: vec![String::from("lamport94")], ..TexOptions::default() };
+    assert_eq!(lint("<cite!>:lamport94 <cite!>:knuth84", &options), vec![("unknown_citation", String::from("Unknown citation key knuth84."))]);
+}
+
+#[cfg(feature = "tex")]
+#[test]
+fn test_tex_report() {
+    use std::rc::Rc;
+    use khi::tex::{write_tex_report, TexLoader, TexOptions};
+    let loader: TexLoader = Rc::new(|path| match path {
+        "part" => Ok(String::from("<frac>:1:2 <emph>:b")),
+        _ => Err(String::from("not found")),
+    });
+    let options = TexOptions { loader: Some(loader), ..TexOptions::default() };
+    let source = "<section>:Title\n<$>:{<frac>:a:b + <frac>:c:d}\n<include!>:part\n";
+    let (tex, report) = write_tex_report(&parse_value_str(source).unwrap(), &options).ok().unwrap();
+    assert_eq!(report.lines, tex.lines().count());
+    assert_eq!(report.count("frac"), 3);
+    assert_eq!(report.count("emph"), 1);
+    assert_eq!(report.count("include!"), 1);
+    assert_eq!(report.count("textbf"), 0);
+    let lines: Vec<usize> = report.expansion("frac").unwrap().uses.iter().map(|at| at.line).collect();
+    assert_eq!(lines, vec![2, 2, 3]);
+    assert_eq!(report.source_map.source(3).map(|at| at.line), Some(3));
+    assert!(report.to_khi().starts_with("lines: 3\nexpansions: [{name: frac; count: 3; lines: [2; 3]}"));
+}
@@ -0,0 +1,217 @@
+#![cfg(all(feature = "enc", feature = "parse"))]
+
+use std::collections::BTreeMap;
+use std::num::{NonZeroI32, NonZeroI64, NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU8};
+use khi::enc::encode::encode_number;
+use khi::enc::{DecodeError, DecodeTextExt, DecodeValueExt};
+use khi::enc::quantity::Units;
+use khi::boolean::BoolFormat;
+use khi::number::NumberFormat;
+use khi::parse::parse_value_str;
+use khi::{Dictionary, Value};
+
+#[test]
+fn test_decode_text() {
+    let value = parse_value_str("[true; -12; 2.5e3; x; 1,5]").unwrap();
+    let list = value.as_list().unwrap();
+    let text = |index: usize| list.elements[index].as_text().unwrap();
+    assert_eq!(text(0).decode_bool(), Ok(true));
+    assert_eq!(text(1).decode_number::<i8>(), Ok(-12));
+    assert_eq!(text(1).decode_number::<u8>(), Err(DecodeError::Invalid("u8", String::from("-12"))));
+    assert_eq!(text(2).decode_float(), Ok(2500.0));
+    assert_eq!(text(2).decode_int(), Err(DecodeError::Invalid("i64", String::from("2.5e3"))));
+    assert_eq!(text(3).decode_char(), Ok('x'));
+    assert_eq!(text(4).decode_char().unwrap_err().to_string(), "Invalid char ⟨1,5⟩.");
+    let value = parse_value_str("[yes; OFF; 0]").unwrap();
+    assert_eq!(value.decode_list(|x| x.decode_bool_with(&BoolFormat::LENIENT)), Ok(vec![true, false, false]));
+    assert_eq!(value.decode_list(|x| x.decode_bool()), Err(DecodeError::Invalid("bool", String::from("yes"))));
+}
+
+#[test]
+fn test_decode_number_formats() {
+    let value = parse_value_str("[0x1F; +5; 1_000_000; 0b101; 12]").unwrap();
+    let list = value.as_list().unwrap();
+    let text = |index: usize| list.elements[index].as_text().unwrap();
+    assert_eq!(text(0).decode_number_with::<u8>(&NumberFormat::LENIENT), Ok(31));
+    assert_eq!(text(0).decode_int(), Err(DecodeError::Invalid("i64", String::from("0x1F"))));
+    assert_eq!(text(1).decode_number_with::<i32>(&NumberFormat::LENIENT), Ok(5));
+    assert_eq!(text(1).decode_number_with::<i32>(&NumberFormat::STRICT), Err(DecodeError::Invalid("i32", String::from("+5"))));
+    assert_eq!(text(2).decode_number_with::<u32>(&NumberFormat::LENIENT), Ok(1_000_000));
+    assert_eq!(text(2).decode_number_with::<f64>(&NumberFormat::LENIENT), Ok(1e6));
+    assert_eq!(text(2).decode_number::<u32>(), Err(DecodeError::Invalid("u32", String::from("1_000_000"))));
+    assert_eq!(list.elements[3].decode_number_with::<u8>(&NumberFormat::LENIENT), Ok(5));
+    assert_eq!(text(4).decode_number_with::<u8>(&NumberFormat::STRICT), Ok(12));
+    assert_eq!(value.decode_number_with::<u8>(&NumberFormat::LENIENT), Err(DecodeError::Expected("text")));
+}
+
+#[test]
+fn test_decode_wide_and_nonzero_numbers() {
+    let value = parse_value_str(&format!("[{}; {}; 0; -7; 0x10]", i128::MIN, u128::MAX)).unwrap();
+    let list = value.as_list().unwrap();
+    let text = |index: usize| list.elements[index].as_text().unwrap();
+    assert_eq!(text(0).decode_number::<i128>(), Ok(i128::MIN));
+    assert_eq!(text(0).decode_number::<i64>(), Err(DecodeError::Invalid("i64", i128::MIN.to_string())));
+    assert_eq!(text(1).decode_number::<u128>(), Ok(u128::MAX));
+    assert_eq!(text(1).decode_number::<NonZeroU128>(), Ok(NonZeroU128::new(u128::MAX).unwrap()));
+    assert_eq!(text(2).decode_number::<NonZeroU8>(), Err(DecodeError::Invalid("NonZeroU8", String::from("0"))));
+    assert_eq!(text(2).decode_number::<u8>(), Ok(0));
+    assert_eq!(text(3).decode_number::<NonZeroI32>(), Ok(NonZeroI32::new(-7).unwrap()));
+    assert_eq!(text(3).decode_number::<NonZeroU32>(), Err(DecodeError::Invalid("NonZeroU32", String::from("-7"))));
+    assert_eq!(text(4).decode_number_with::<NonZeroU16>(&NumberFormat::LENIENT), Ok(NonZeroU16::new(16).unwrap()));
+    assert_eq!(encode_number(NonZeroI64::new(-1234567).unwrap(), true), "-1_234_567");
+}
+
+#[test]
+fn test_decode_quantity() {
+    let value = parse_value_str("{density: 2.70 g/cm3; mass: 1.5t; count: 12; width: 1e3mm; size: 2em; bad: heavy}").unwrap();
+    let dictionary = value.as_dictionary().unwrap();
+    let entry = |key: &str| dictionary.get(key).unwrap();
+    assert_eq!(entry("density").decode_quantity(), Ok((2.7, "g/cm3")));
+    assert_eq!(entry("count").decode_quantity(), Ok((12.0, "")));
+    assert_eq!(entry("width").decode_quantity(), Ok((1000.0, "mm")));
+    assert_eq!(entry("size").decode_quantity(), Ok((2.0, "em")));
+    assert_eq!(entry("bad").decode_quantity(), Err(DecodeError::Invalid("quantity", String::from("heavy"))));
+    let units = Units::new()
+        .with("g", 0.001, "kg").with("t", 1000.0, "kg")
+        .with("mm", 0.001, "m").with("km", 1000.0, "m")
+        .with("g/cm3", 1000.0, "kg/m3");
+    assert_eq!(entry("mass").decode_quantity_in("kg", &units), Ok(1500.0));
+    assert_eq!(entry("mass").decode_quantity_in("g", &units), Ok(1_500_000.0));
+    assert_eq!(entry("width").decode_quantity_in("m", &units), Ok(1.0));
+    assert_eq!(entry("density").decode_quantity_in("kg/m3", &units), Ok(2700.0));
+    assert_eq!(entry("mass").decode_quantity_in("m", &units), Err(DecodeError::Invalid("quantity", String::from("1.5t"))));
+}
+
+#[test]
+fn test_decode_bytes() {
+    let value = parse_value_str("[00ff; 0g; abc; AP8=; AP*=; AP8]").unwrap();
+    let list = value.as_list().unwrap();
+    let text = |index: usize| list.elements[index].as_text().unwrap();
+    assert_eq!(text(0).decode_hex(), Ok(vec![0, 255]));
+    assert_eq!(text(1).decode_hex(), Err(DecodeError::InvalidAt("hex", String::from("0g"), 1)));
+    assert_eq!(text(2).decode_hex().unwrap_err().to_string(), "Invalid hex ⟨abc⟩ at byte 3.");
+    assert_eq!(list.elements[3].decode_base64(), Ok(vec![0, 255]));
+    assert_eq!(text(4).decode_base64(), Err(DecodeError::InvalidAt("base64", String::from("AP*="), 2)));
+    assert_eq!(text(5).decode_base64(), Err(DecodeError::InvalidAt("base64", String::from("AP8"), 3)));
+    assert_eq!(value.decode_hex(), Err(DecodeError::Expected("text")));
+}
+
+#[test]
+fn test_decode_from_str() {
+    use std::net::{IpAddr, Ipv4Addr};
+    let value = parse_value_str(r"[\127.0.0.1\; localhost]").unwrap();
+    let list = value.as_list().unwrap();
+    assert_eq!(list.elements[0].decode_from_str(), Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    assert_eq!(list.elements[1].decode_from_str::<IpAddr>(), Err(DecodeError::Invalid("IpAddr", String::from("localhost"))));
+    assert_eq!(value.decode_from_str::<u8>(), Err(DecodeError::Expected("text")));
+}
+
+#[cfg(feature = "decimal")]
+#[test]
+fn test_decode_decimal() {
+    use std::str::FromStr;
+    use rust_decimal::Decimal;
+    let value = parse_value_str("[19.99; 0.1; 1e2; 0.12345678901234567890123456789012; x]").unwrap();
+    let list = value.as_list().unwrap();
+    assert_eq!(list.elements[0].decode_decimal(), Ok(Decimal::from_str("19.99").unwrap()));
+    assert_eq!(list.elements[1].decode_decimal().unwrap() * Decimal::from(3), Decimal::from_str("0.3").unwrap());
+    assert_eq!(list.elements[2].decode_decimal(), Ok(Decimal::from(100)));
+    assert_eq!(list.elements[3].decode_decimal(), Err(DecodeError::Invalid("decimal", String::from("0.12345678901234567890123456789012"))));
+    assert_eq!(list.elements[4].decode_decimal(), Err(DecodeError::Invalid("decimal", String::from("x"))));
+    assert_eq!(value.decode_decimal(), Err(DecodeError::Expected("text")));
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn test_decode_uuid() {
+    let value = parse_value_str("{id: 67e55044-10b1-426f-9247-bb680e5fe0c8; short: 67e5504410b1426f9247bb680e5fe0c8; bad: 67e55044}").unwrap();
+    let dictionary = value.as_dictionary().unwrap();
+    let id = uuid::Uuid::from_u128(0x67e55044_10b1_426f_9247_bb680e5fe0c8);
+    assert_eq!(dictionary.get("id").unwrap().decode_uuid(), Ok(id));
+    assert_eq!(dictionary.get("short").unwrap().decode_uuid(), Ok(id));
+    assert_eq!(dictionary.get("id").unwrap().decode_from_str(), Ok(id));
+    assert_eq!(dictionary.get("bad").unwrap().decode_uuid(), Err(DecodeError::Invalid("uuid", String::from("67e55044"))));
+}
+
+#[test]
+fn test_decode_value() {
+    let value = parse_value_str("{name: web; ports: [80; 443]; pair: a|b; limits: {low: 1; high: 9}; flag: false}").unwrap();
+    let dictionary = value.as_dictionary().unwrap();
+    let entry = |key: &str| dictionary.get(key).unwrap();
+    assert_eq!(entry("name").decode_str(), Ok("web"));
+    assert_eq!(entry("flag").decode_bool(), Ok(false));
+    assert_eq!(entry("ports").decode_list(|port| port.decode_number::<u16>()), Ok(vec![80, 443]));
+    assert_eq!(entry("pair").decode_list(|x| x.decode_char()), Ok(vec!['a', 'b']));
+    assert_eq!(entry("limits").decode_dictionary(|limit| limit.decode_int()), Ok(BTreeMap::from([(String::from("high"), 9), (String::from("low"), 1)])));
+    assert_eq!(entry("name").decode_list(|x| x.decode_int()), Err(DecodeError::Expected("list")));
+    assert_eq!(entry("ports").decode_int(), Err(DecodeError::Expected("text")));
+    assert_eq!(entry("ports").decode_dictionary(|x| x.decode_int()).unwrap_err().to_string(), "Expected dictionary.");
+    assert_eq!(entry("pair").decode_list(|x| x.decode_bool()), Err(DecodeError::Invalid("bool", String::from("a"))));
+}
+
+#[test]
+fn test_decode_datetime() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use khi::enc::encode::{encode_datetime, encode_duration};
+    let value = parse_value_str(r"{created: \2024-05-01T12:00:00.5+02:00\; seen: \2024/05/01 10:00 UTC\; timeout: 5m 30s; ttl: PT1H; idle: \1:05:30\; bad: \2024-02-30\}").unwrap();
+    let dictionary = value.as_dictionary().unwrap();
+    let entry = |key: &str| dictionary.get(key).unwrap();
+    let created: SystemTime = entry("created").decode_datetime().unwrap();
+    assert_eq!(created, UNIX_EPOCH + Duration::new(1714557600, 500_000_000));
+    assert_eq!(encode_datetime(&created), "2024-05-01T10:00:00.5Z");
+    assert_eq!(entry("seen").decode_datetime(), Ok(UNIX_EPOCH + Duration::from_secs(1714557600)));
+    assert_eq!(entry("timeout").decode_duration(), Ok(Duration::from_secs(330)));
+    assert_eq!(entry("ttl").decode_duration(), Ok(Duration::from_secs(3600)));
+    assert_eq!(entry("idle").decode_duration(), Ok(Duration::from_secs(3930)));
+    assert_eq!(entry("bad").decode_datetime::<SystemTime>(), Err(DecodeError::Invalid("timestamp", String::from("2024-02-30"))));
+    assert_eq!(entry("created").decode_duration::<Duration>(), Err(DecodeError::Invalid("duration", String::from("2024-05-01T12:00:00.5+02:00"))));
+    assert_eq!(encode_duration(&Duration::new(3930, 5_000_000)), "1h 5m 30s 5ms");
+    assert_eq!(encode_duration(&Duration::ZERO), "0s");
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_decode_chrono() {
+    use chrono::{FixedOffset, NaiveDate, TimeDelta, TimeZone, Utc};
+    use khi::enc::encode::{encode_date, encode_datetime, encode_duration};
+    let value = parse_value_str(r"[\2024-05-01T12:00:00+02:00\; 1 May 2024; May 1, 2024; -1m 30s; \2024-05-01T12:00:00.123Z\]").unwrap();
+    let list = value.as_list().unwrap();
+    let text = |index: usize| list.elements[index].as_text().unwrap();
+    let offset = FixedOffset::east_opt(7200).unwrap();
+    let datetime: chrono::DateTime<FixedOffset> = text(0).decode_datetime().unwrap();
+    assert_eq!(datetime, offset.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap());
+    assert_eq!(encode_datetime(&datetime), "2024-05-01T12:00:00+02:00");
+    let utc: chrono::DateTime<Utc> = text(0).decode_datetime().unwrap();
+    assert_eq!(encode_datetime(&utc), "2024-05-01T10:00:00Z");
+    let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+    assert_eq!(text(1).decode_date(), Ok(date));
+    assert_eq!(text(2).decode_date(), Ok(date));
+    assert_eq!(encode_date(&date), "2024-05-01");
+    let delta: TimeDelta = text(3).decode_duration().unwrap();
+    assert_eq!(delta, TimeDelta::seconds(-90));
+    assert_eq!(encode_duration(&delta), "-1m 30s");
+    let precise: chrono::DateTime<Utc> = text(4).decode_datetime().unwrap();
+    assert_eq!(encode_datetime(&precise), "2024-05-01T12:00:00.123Z");
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn test_decode_time() {
+    use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+    use khi::enc::encode::{encode_date, encode_datetime, encode_duration};
+    let value = parse_value_str(r"[\2024-05-01 12:30 -0130\; 01 jan 2000; \-0:00:01.5\; \2024-05-01T12:30:00\]").unwrap();
+    let list = value.as_list().unwrap();
+    let text = |index: usize| list.elements[index].as_text().unwrap();
+    let datetime: OffsetDateTime = text(0).decode_datetime().unwrap();
+    assert_eq!(datetime.offset(), UtcOffset::from_hms(-1, -30, 0).unwrap());
+    assert_eq!(encode_datetime(&datetime), "2024-05-01T12:30:00-01:30");
+    let date = Date::from_calendar_date(2000, Month::January, 1).unwrap();
+    assert_eq!(text(1).decode_date(), Ok(date));
+    assert_eq!(encode_date(&date), "2000-01-01");
+    let duration: time::Duration = text(2).decode_duration().unwrap();
+    assert_eq!(duration, time::Duration::milliseconds(-1500));
+    assert_eq!(encode_duration(&duration), "-1s 500ms");
+    let primitive: PrimitiveDateTime = text(3).decode_datetime().unwrap();
+    assert_eq!(primitive, PrimitiveDateTime::new(Date::from_calendar_date(2024, Month::May, 1).unwrap(), Time::from_hms(12, 30, 0).unwrap()));
+    assert_eq!(encode_datetime(&primitive), "2024-05-01T12:30:00Z");
+}
@@ -0,0 +1,354 @@
+use std::fs::File;
+use std::io::Read;
+use khi::enc::encode::{encode_bool, encode_char, encode_number, encode_str, write_number, write_text_block};
+use khi::fmt::{format_dictionary_with, format_list_with, format_value_with, preview, write_value_with, Indent, ListNotation, Newline, Quoting, Style};
+use khi::parse::{parse_dictionary_str, parse_dictionary_str_styled, parse_list_str, parse_value_str, ParseOptions};
+use khi::number::NumberFormat;
+use khi::pdm::{ParsedValue, Position};
+use khi::{Dictionary, Text, Value};
+
+#[test]
+fn test_format_values() {
+    for source in [
+        "", "~", "a", "a b", "\\a  b\\", "\\\\", "\\a`nb\\", "\\a`tb\\", "x#y", "#x", "\\a#\\", "\\a ## b\\",
+        "{}", "{a: 1; b: 2}", "{\\a:b\\: 1}", "{\\\\: 1}", "{a:b: 1}", "a => b", "{a => 1 | \\b c\\ => {x | y}}", "{a => ~ | \\b c\\ => {d: 1} | e => <f>:g}",
+        "[]", "[~]", "[<>]", "[a; b | c; [d]]",
+        "a | b", "{a | b} | c", "<> | a", "~ | a", "<>: a | b", "{<>: a | b} | c",
+        "<a>", "<a x:y flag>", "<a x:\\y z\\>:b", "<a>:b:c", "<a>:{b c}", "<a>:{b | c}", "<a>:{b | c}:{d}",
+        "<a>:{<>}:{b}", "<a>:{~}", "<a>:{}", "<a>:{x: 1}", "<a>:[x]", "<a>:<b>", "<a>: <b>: c",
+        "a {b: 1} c", "a~{b: 1}~c", "{a: 1} ~ {b: 2}", "x {a | b}", "x {<>}", "x <a>:{b} y", "a~<b>~c",
+        "<#>\n  text\n  block\n<#>",
+    ] {
+        assert_stable_value(source);
+    };
+}
+
+#[test]
+fn test_format_semicolons() {
+    // Values ending with ~ join the following newline, so the entries are
+    // separated with ;.
+    assert_stable_value("{a: ~; b: x | ~; c: <>: x | ~; d: e}");
+    assert_stable_dictionary("a: ~;\nb: x;\nc: d");
+    let dictionary = parse_dictionary_str("a: x | ~; b: y").unwrap();
+    assert_eq!(format_dictionary_with(&dictionary, &Style::default()), "a: x | ~;\nb: y");
+    let dictionary = parse_dictionary_str("a: x\nb: y").unwrap();
+    assert_eq!(format_dictionary_with(&dictionary, &Style::default()), "a: x\nb: y");
+}
+
+#[test]
+fn test_format_examples() {
+    for path in ["examples/equations.tex.khi", "examples/frontpage.html.khi", "examples/fruits.xml.khi", "examples/style.khi"] {
+        assert_stable_value(&read_document_file(path));
+    };
+    for path in ["examples/aluminium.a", "examples/materials.khi"] {
+        assert_stable_dictionary(&read_document_file(path));
+    };
+    for path in [
+        "examples/elements.khi", "examples/inventory-log.khi", "examples/primes.khi", "examples/server-log.khi",
+        "examples/text-blocks.khi", "examples/words.khi",
+    ] {
+        assert_stable_list(&read_document_file(path));
+    };
+}
+
+#[test]
+fn test_format_fuzz() {
+    let mut random = Random(0x2545f4914f6cdd1d);
+    let mut parsed = 0;
+    for _ in 0..5000 {
+        let mut source = String::new();
+        generate_value(&mut random, &mut source, 3);
+        if parse_value_str(&source).is_ok() {
+            assert_stable_value(&source);
+            parsed += 1;
+        };
+    };
+    assert!(parsed > 1000);
+}
+
+#[cfg(feature = "fuzzing")]
+#[test]
+fn test_format_arbitrary() {
+    use arbitrary::{Arbitrary, Unstructured};
+    let mut random = Random(0x9e3779b97f4a7c15);
+    for _ in 0..2000 {
+        let bytes: Vec<u8> = (0..random.next(256)).map(|_| random.next(256) as u8).collect();
+        let value = ParsedValue::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+        for style in &STYLES {
+            let formatted = format_value_with(&value, style);
+            let reparsed = parse_value_str(&formatted).unwrap_or_else(|_| panic!("Formatted as unparsable {:?}.", formatted));
+            assert!(reparsed.canonical_eq(&value), "Formatting as {:?} changed the value.", formatted);
+        };
+    };
+    let empty = ParsedValue::arbitrary(&mut Unstructured::new(&[])).unwrap();
+    assert!(empty.canonical_eq(&ParsedValue::text("")));
+    // Generated values may have nil components and empty keys, as parsed ones.
+    assert!(parse_value_str("a{~}").unwrap().as_compound().unwrap().components[1].is_nil());
+    assert!(parse_value_str("{\\\\: 1}").unwrap().as_dictionary().unwrap().get("").is_some());
+}
+
+#[test]
+fn test_format_style() {
+    let detect = |source: &str| Style::detect(source);
+    assert_eq!(detect("a: 1\nb: 2"), Style::default());
+    assert_eq!(detect("a: {\n    b: 1\n    c: {\n        d: 2\n    }\n}\n"), Style { indent: Some(Indent::Spaces(4)), newline: Newline::Lf, ..Style::default() });
+    assert_eq!(detect("a: {\r\n\tb: 1\r\n}\r\n"), Style { indent: Some(Indent::Tabs), newline: Newline::CrLf, ..Style::default() });
+    let document = parse_dictionary_str_styled("a: 1\r\nb: {\r\n\tc: 2\r\n\td: [x; y]\r\n}\r\ne: [{f: 3}]", &ParseOptions::default()).unwrap();
+    assert_eq!(document.format(), "a: 1\r\nb: {\r\n\tc: 2\r\n\td: [x; y]\r\n}\r\ne: [\r\n\t{\r\n\t\tf: 3\r\n\t}\r\n]");
+    let document = parse_dictionary_str_styled(&read_document_file("examples/materials.khi"), &ParseOptions::default()).unwrap();
+    assert_eq!(Style::detect(&document.format()), document.style);
+}
+
+#[test]
+fn test_format_pretty() {
+    let style = Style::pretty();
+    let dictionary = parse_dictionary_str("name: main\nport: 8080\nserver: {host: a; \\long key\\: b}\nempty: {}\nlimits: {low: ~; high: 2}\ntags: [x; y]").unwrap();
+    assert_eq!(
+        format_dictionary_with(&dictionary, &style),
+        "name:  main\nport:  8080\nempty: {}\ntags:  [x; y]\n\n{server}:\nhost:       a\n\\long key\\: b\n\n{limits}:\nlow:  ~;\nhigh: 2",
+    );
+    let value = parse_value_str("{code: <#>\n  fn f() {\n      x\n  }\n<#>; prose: \\first`nsecond\\; indented: \\ a`n b\\}").unwrap();
+    assert_eq!(
+        format_value_with(&value, &style),
+        "{\n  code:     <#>\n    fn f() {\n        x\n    }\n  <#>\n  prose:    <# l>\n    first\n    second<#>\n  indented: \\ a`n b\\\n}",
+    );
+    for text in ["a\n", "a\nb", "a\n\nb\n", "\n", "\na", "a\n\n", "  \na\n", "a\n  b", "\ta\nb\n", "a<#\nb", "a\nb<", "a\n  \n", "x <#> y\nz"] {
+        let mut dictionary = parse_dictionary_str("k: v").unwrap();
+        dictionary.entries.insert("k".into(), ParsedValue::text(text));
+        let at = Position { index: 0, line: 1, column: 1 };
+        let value = ParsedValue::Dictionary(dictionary, at, at);
+        for style in &STYLES {
+            let formatted = format_value_with(&value, style);
+            let reparsed = parse_value_str(&formatted).unwrap_or_else(|_| panic!("Formatted {:?} as unparsable {:?}.", text, formatted));
+            assert!(reparsed.canonical_eq(&value), "Formatting {:?} as {:?} changed the value.", text, formatted);
+        };
+    };
+}
+
+#[test]
+fn test_format_quoting() {
+    let value = parse_value_str("[word; 1.5; -3; 0x1F; inf; \\a b\\; \\a=>b\\; 1a; <t>:2]").unwrap();
+    let style = Style { quoting: Quoting::Canonical, ..Style::default() };
+    assert_eq!(format_value_with(&value, &style), "[word; \\1.5\\; \\-3\\; \\0x1F\\; \\inf\\; \\a b\\; \\a=>b\\; 1a; <t>:{\\2\\}]");
+    assert_eq!(format_value_with(&value, &Style::default()), "[word; 1.5; -3; 0x1F; inf; a b; \\a=>b\\; 1a; <t>:2]");
+}
+
+struct Displayed(ParsedValue);
+
+impl std::fmt::Display for Displayed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_value_with(f, &self.0, &Style::default())
+    }
+}
+
+#[test]
+fn test_write_value() {
+    let value = parse_value_str("{a: [1; x y]; b: <t>:{c d}}").unwrap();
+    assert_eq!(Displayed(value.clone()).to_string(), format_value_with(&value, &Style::default()));
+    let mut unit = String::from("x");
+    write_value_with(&mut unit, &parse_value_str("").unwrap(), &Style::default()).unwrap();
+    assert_eq!(unit, "x");
+}
+
+#[test]
+fn test_format_lists() {
+    let value = parse_value_str("{rows: [a | 1; b c | 2]; mixed: [a | 1; b]; items: [x; {k: v}]; empty: []}").unwrap();
+    let style = |indent, lists| Style { indent, lists, ..Style::default() };
+    assert_eq!(
+        format_value_with(&value, &style(None, ListNotation::Tabular)),
+        "{rows: [|a|1| |b c|2|]; mixed: [a | 1; b]; items: [x; {k: v}]; empty: []}",
+    );
+    assert_eq!(
+        format_value_with(&value, &style(None, ListNotation::Aligned)),
+        "{rows: [> a | 1 > b c | 2]; mixed: [> a | 1 > b]; items: [> x > {k: v}]; empty: []}",
+    );
+    assert_eq!(
+        format_value_with(&value, &style(Some(Indent::Spaces(2)), ListNotation::Tabular)),
+        "{\n  rows: [\n    |a|1|\n    |b c|2|\n  ]\n  mixed: [a | 1; b]\n  items: [\n    x;\n    {\n      k: v\n    }\n  ]\n  empty: []\n}",
+    );
+    assert_eq!(
+        format_value_with(&parse_value_str("[a | b; ~]").unwrap(), &style(Some(Indent::Spaces(2)), ListNotation::Aligned)),
+        "[a | b; ~]",
+    );
+    let list = parse_list_str("a | 1; b | 2").unwrap();
+    assert_eq!(format_list_with(&list, &style(None, ListNotation::Tabular)), "|a|1|\n|b|2|");
+    assert_eq!(format_list_with(&list, &style(None, ListNotation::Aligned)), "> a | 1\n> b | 2");
+    assert_stable_list("|a|b|\n|c|d|");
+    assert_stable_value("[|a|b| |c|]");
+    assert_eq!(format_value_with(&parse_value_str("[|a|b|\n|c|]").unwrap(), &style(None, ListNotation::Tabular)), "[a | b; c]");
+}
+
+#[test]
+fn test_preview() {
+    let value = parse_value_str("{a: 1; b: <#>\n  x\n  y\n<#>}").unwrap();
+    assert_eq!(preview(&value, 40), "{a: 1; b: \\x`ny`n\\}");
+    assert_eq!(preview(&value, 10), "{a: 1; ...");
+    assert_eq!(preview(&value, 2), "..");
+    assert_eq!(preview(&parse_value_str("ééééé").unwrap(), 4), "é...");
+    assert_eq!(preview(&parse_value_str("").unwrap(), 10), "<>");
+}
+
+#[test]
+fn test_encode() {
+    for text in ["a", "a b", "a  b", "1.5", "", "a: b", "x#", "a`b\\c", "a\nb\tc"] {
+        for quoting in [Quoting::Minimal, Quoting::Canonical] {
+            let encoded = encode_str(text, quoting);
+            assert_eq!(parse_value_str(&encoded).unwrap().as_text().unwrap().as_str(), text, "{}", encoded);
+        };
+    };
+    assert_eq!(encode_str("1.5", Quoting::Minimal), "1.5");
+    assert_eq!(encode_str("a\r\nb", Quoting::Minimal), "\\a\r`nb\\");
+    assert_eq!(encode_str("1.5", Quoting::Canonical), "\\1.5\\");
+    assert_eq!(encode_str("a`b\\c", Quoting::Minimal), "\\a``b`\\c\\");
+    assert_eq!(encode_bool(true), "true");
+    assert_eq!(encode_char('a'), "a");
+    assert_eq!(encode_char(' '), "\\ \\");
+    assert_eq!(encode_char('\n'), "\\`n\\");
+    assert_eq!(encode_number(0u8, true), "0");
+    assert_eq!(encode_number(123, true), "123");
+    assert_eq!(encode_number(1234, true), "1_234");
+    assert_eq!(encode_number(-1234567i64, true), "-1_234_567");
+    assert_eq!(encode_number(u128::MAX, false), u128::MAX.to_string());
+    assert_eq!(encode_number(1234567.25f64, true), "1_234_567.25");
+    assert_eq!(encode_number(f64::NAN, true), "NaN");
+    let mut output = String::from("n: ");
+    write_number(&mut output, -1234567.5f32, true).unwrap();
+    assert_eq!(output, "n: -1_234_567.5");
+    let format = NumberFormat { separators: true, ..NumberFormat::default() };
+    assert_eq!(format.parse::<i64>(&encode_number(-1234567i64, true)), Some(-1234567));
+    assert_eq!(format.parse::<f64>(&encode_number(1234567.25f64, true)), Some(1234567.25));
+    let mut block = String::from("a: ");
+    assert!(write_text_block(&mut block, "x\n  y\n", "\n", "  ", "").unwrap());
+    assert_eq!(block, "a: <#>\n  x\n    y\n<#>");
+    assert_eq!(parse_dictionary_str(&block).unwrap().get("a").unwrap().as_text().unwrap().as_str(), "x\n  y\n");
+    let mut block = String::from("a: ");
+    assert!(write_text_block(&mut block, "x\ny", "\n", "  ", "").unwrap());
+    assert_eq!(parse_dictionary_str(&block).unwrap().get("a").unwrap().as_text().unwrap().as_str(), "x\ny");
+    assert!(!write_text_block(&mut String::new(), "x", "\n", "  ", "").unwrap());
+    assert!(!write_text_block(&mut String::new(), "x\n y", "\n", "  ", "").unwrap());
+}
+
+const STYLES: [Style; 6] = [
+    Style { indent: None, newline: Newline::Lf, align: false, sections: false, text_blocks: false, lists: ListNotation::Delimited, quoting: Quoting::Minimal },
+    Style { indent: Some(Indent::Spaces(2)), newline: Newline::Lf, align: false, sections: false, text_blocks: false, lists: ListNotation::Aligned, quoting: Quoting::Minimal },
+    Style { indent: Some(Indent::Tabs), newline: Newline::CrLf, align: false, sections: false, text_blocks: false, lists: ListNotation::Delimited, quoting: Quoting::Minimal },
+    Style { indent: None, newline: Newline::Lf, align: true, sections: true, text_blocks: true, lists: ListNotation::Tabular, quoting: Quoting::Canonical },
+    Style { indent: None, newline: Newline::Lf, align: false, sections: false, text_blocks: false, lists: ListNotation::Aligned, quoting: Quoting::Minimal },
+    Style::pretty(),
+];
+
+fn assert_stable_value(source: &str) {
+    let value = parse_value_str(source).unwrap();
+    for style in &STYLES {
+        let formatted = format_value_with(&value, style);
+        let reparsed = parse_value_str(&formatted).unwrap_or_else(|_| panic!("Formatted {:?} as unparsable {:?}.", source, formatted));
+        assert!(reparsed.canonical_eq(&value), "Formatting {:?} as {:?} changed the value.", source, formatted);
+        assert_eq!(format_value_with(&reparsed, style), formatted);
+    };
+}
+
+fn assert_stable_dictionary(source: &str) {
+    let at = Position { index: 0, line: 1, column: 1 };
+    let dictionary = parse_dictionary_str(source).unwrap();
+    for style in &STYLES {
+        let formatted = format_dictionary_with(&dictionary, style);
+        let reparsed = parse_dictionary_str(&formatted).unwrap();
+        let value = ParsedValue::Dictionary(dictionary.clone(), at, at);
+        let reparsed_value = ParsedValue::Dictionary(reparsed.clone(), at, at);
+        assert!(reparsed_value.canonical_eq(&value));
+        assert_eq!(format_dictionary_with(&reparsed, style), formatted);
+    };
+}
+
+fn assert_stable_list(source: &str) {
+    let at = Position { index: 0, line: 1, column: 1 };
+    let list = parse_list_str(source).unwrap();
+    for style in &STYLES {
+        let formatted = format_list_with(&list, style);
+        let reparsed = parse_list_str(&formatted).unwrap();
+        let value = ParsedValue::List(list.clone(), at, at);
+        let reparsed_value = ParsedValue::List(reparsed.clone(), at, at);
+        assert!(reparsed_value.canonical_eq(&value));
+        assert_eq!(format_list_with(&reparsed, style), formatted);
+    };
+}
+
+/// Linear congruential generator.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % n as u64) as usize
+    }
+}
+
+/// Write a random, often but not always valid, value.
+fn generate_value(random: &mut Random, output: &mut String, depth: usize) {
+    const TERMS: [&str; 12] = ["a", "b c", "1.5", "x#y", "é", "\\a  b\\", "\\\\", "\\a`nb`\\\\", "~", "<>", "<x>", "<x k:v f>"];
+    let choice = if depth == 0 { 0 } else { random.next(9) };
+    match choice {
+        0 => output.push_str(TERMS[random.next(TERMS.len())]),
+        1 => { // Dictionary
+            output.push('{');
+            for i in 0..random.next(3) {
+                if i != 0 {
+                    output.push_str("; ");
+                };
+                output.push_str(["k", "\\a b\\", "l:m"][i]);
+                output.push_str(": ");
+                generate_value(random, output, depth - 1);
+            };
+            output.push('}');
+        }
+        2 => { // List
+            output.push('[');
+            for i in 0..random.next(3) {
+                if i != 0 {
+                    output.push_str("; ");
+                };
+                generate_value(random, output, depth - 1);
+            };
+            output.push(']');
+        }
+        3 => { // Tuple
+            generate_value(random, output, depth - 1);
+            output.push_str(" | ");
+            generate_value(random, output, depth - 1);
+        }
+        4 => { // Bracketed
+            output.push('{');
+            generate_value(random, output, depth - 1);
+            output.push('}');
+        }
+        5 => { // Tag arguments
+            output.push_str(["<t>", "<t a:\\b c\\>", "<>"][random.next(3)]);
+            for _ in 0..random.next(3) {
+                output.push_str(":{");
+                generate_value(random, output, depth - 1);
+                output.push('}');
+            };
+        }
+        6 => { // Tagged value
+            output.push_str(["<t>: ", "<>: "][random.next(2)]);
+            generate_value(random, output, depth - 1);
+        }
+        7 => { // Compound
+            generate_value(random, output, depth - 1);
+            output.push_str([" ", "~", " ~ "][random.next(3)]);
+            generate_value(random, output, depth - 1);
+        }
+        _ => { // Text block
+            output.push_str("<#>\n  ");
+            output.push_str(TERMS[random.next(TERMS.len())]);
+            output.push_str("\n<#>");
+        }
+    };
+}
+
+fn read_document_file(path: &str) -> String {
+    let mut file = File::open(path).unwrap();
+    let mut document = String::new();
+    file.read_to_string(&mut document).unwrap();
+    document
+}
@@ -0,0 +1,21 @@
+#![cfg(feature = "json")]
+
+use khi::json::{json_to_khi_string, khi_to_json_string};
+
+#[test]
+fn test_khi_to_json() {
+    assert_eq!(khi_to_json_string("{a: 1; b: [x; ~]; c: <t>:y; d: <>; e: 1 | 2}").unwrap(), r#"{"a":"1","b":["x",null],"c":{"t":"y"},"d":null,"e":["1","2"]}"#);
+    assert_eq!(khi_to_json_string("a b").unwrap(), r#""a b""#);
+    assert!(khi_to_json_string("{a: 1").is_err());
+}
+
+#[test]
+fn test_json_to_khi() {
+    assert_eq!(json_to_khi_string(r#"{"a": 1, "b": [true, null], "c": {"d": "e f"}}"#).unwrap(), "{a: 1; b: [true; <>]; c: {d: e f}}");
+    assert_eq!(json_to_khi_string("[]").unwrap(), "[]");
+    assert!(json_to_khi_string("{").is_err());
+    assert!(json_to_khi_string("1 2").is_err());
+    assert_eq!(json_to_khi_string(r#"{"a": 1, "a": 2}"#).unwrap_err().to_string(), "Duplicate key a. at line 1 column 16");
+    let document = "{name: x; list: [a; {b: c}]}";
+    assert_eq!(json_to_khi_string(&khi_to_json_string(document).unwrap()).unwrap(), document);
+}
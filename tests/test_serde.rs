@@ -0,0 +1,824 @@
+#![cfg(feature = "serde")]
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use khi::{Dictionary, Text, Value};
+use khi::boolean::BoolFormat;
+use khi::model::SimpleValue;
+use khi::parse::{parse_dictionary_str, parse_list_str, parse_value_str};
+use khi::pdm::{ParsedValue, Position};
+use khi::de::{from_dictionary, from_dictionary_str, from_dictionary_str_with, from_list, from_list_str, from_reader, from_str, from_str_with, from_value, from_value_seed, DeserializerOptions, Segment, UnknownFields};
+use khi::ser::{to_dictionary_string, to_dictionary_string_pretty, to_dictionary_string_with, to_string, to_string_pretty, to_string_with, to_value, to_writer, ByteRepresentation, EnumRepresentation, Error, NewtypeRepresentation, OptionRepresentation, Quoting, SequenceStyle, SerializerOptions, StructRepresentation, UnitRepresentation};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Server {
+    name: String,
+    port: u16,
+    ratio: f64,
+    enabled: bool,
+    tags: Vec<String>,
+    fallback: Option<Box<Server>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+enum Shape {
+    Empty,
+    Circle(f32),
+    Rectangle(u32, u32),
+    Polygon { sides: u8, regular: bool },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Unit;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Meters(f64);
+
+#[derive(Serialize)]
+struct Pair(i32, &'static str);
+
+struct Bytes(&'static [u8]);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+#[test]
+fn test_ser_scalars() {
+    assert_eq!(to_string(&true).unwrap(), "true");
+    assert_eq!(to_string(&-42i8).unwrap(), "-42");
+    assert_eq!(to_string(&u128::MAX).unwrap(), u128::MAX.to_string());
+    assert_eq!(to_string(&2.5f64).unwrap(), "2.5");
+    assert_eq!(to_string(&'x').unwrap(), "x");
+    assert_eq!(to_string("word").unwrap(), "word");
+    assert_eq!(to_string("two words").unwrap(), "two words");
+    assert_eq!(to_string(&Bytes(&[0, 15, 255])).unwrap(), "000fff");
+    assert_eq!(to_string(&Meters(1.5)).unwrap(), "1.5");
+    assert_eq!(to_string(&None::<u8>).unwrap(), "~");
+    assert_eq!(to_string(&Some(1)).unwrap(), "1");
+    assert_eq!(to_string(&()).unwrap(), "");
+    assert_eq!(to_string(&vec![()]).unwrap(), "[<>]");
+    assert_eq!(to_string(&Unit).unwrap(), "");
+}
+
+/// Serialized with `collect_str`.
+struct Displayed(String);
+
+impl Serialize for Displayed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{}:{}", self.0, self.0.len()))
+    }
+}
+
+#[test]
+fn test_ser_collect_str() {
+    assert_eq!(to_string(&Displayed(String::from("id"))).unwrap(), "\\id:2\\");
+    let long = "é".repeat(40);
+    assert_eq!(to_value(&Displayed(long.clone())).unwrap().as_text().map(|t| t.as_str().to_string()), Some(format!("{}:80", long)));
+    assert_eq!(to_string(&(i128::MIN, 0u8, -1i8, 1.0e-7f32)).unwrap(), format!("{} | 0 | -1 | 0.0000001", i128::MIN));
+}
+
+#[test]
+fn test_ser_escaping() {
+    for text in ["", " ", "a  b", " a", "a:b", "x;y", "a|b", "{}", "[x]", "<t>", "~", "`", "\\", "a#", "a##b", "line\nbreak", "tab\there", "é ü"] {
+        let khi = to_string(text).unwrap();
+        let parsed = parse_value_str(&khi).unwrap_or_else(|_| panic!("Serialized {:?} as unparsable {:?}.", text, khi));
+        assert_eq!(parsed.as_text().map(|t| t.as_str()), Some(text), "Serialized {:?} as {:?}.", text, khi);
+    };
+}
+
+#[test]
+fn test_ser_structs() {
+    let server = Server {
+        name: String::from("main server"), port: 8080, ratio: 0.75, enabled: true, tags: vec![String::from("a"), String::from("b c")],
+        fallback: Some(Box::new(Server { name: String::from("backup"), port: 8081, ratio: 1.0, enabled: false, tags: vec![], fallback: None })),
+    };
+    assert_eq!(
+        to_string(&server).unwrap(),
+        "{name: main server; port: 8080; ratio: 0.75; enabled: true; tags: [a; b c]; fallback: {name: backup; port: 8081; ratio: 1; enabled: false; tags: []; fallback: ~}}",
+    );
+    let document = to_dictionary_string(&server).unwrap();
+    assert!(document.starts_with("name: main server\nport: 8080\n"));
+    let at = Position { index: 0, line: 1, column: 1 };
+    let parsed = ParsedValue::Dictionary(parse_dictionary_str(&document).unwrap(), at, at);
+    assert!(parsed.canonical_eq(&to_value(&server).unwrap()));
+    assert_eq!(to_dictionary_string(&1), Err(Error::NotDictionary));
+}
+
+#[test]
+fn test_io() {
+    let servers = vec![
+        Server { name: String::from("a"), port: 1, ratio: 0.5, enabled: true, tags: vec![String::from("x")], fallback: None },
+        Server { name: String::from("b"), port: 2, ratio: 1.0, enabled: false, tags: vec![], fallback: None },
+    ];
+    let mut buffer = Vec::new();
+    to_writer(&mut buffer, &servers).unwrap();
+    assert_eq!(buffer, to_string(&servers).unwrap().into_bytes());
+    assert_eq!(from_reader::<_, Vec<Server>>(std::io::BufReader::new(buffer.as_slice())).unwrap(), servers);
+    let error = to_writer(&mut [0u8; 4][..], &servers).unwrap_err();
+    assert_eq!(error, Error::Io(std::io::ErrorKind::WriteZero.into()));
+    assert!(from_reader::<_, String>(&[0xFFu8][..]).unwrap_err().to_string().contains("UTF-8"));
+    let mut writes = Writes(0);
+    to_writer(&mut writes, &servers).unwrap();
+    assert!(writes.0 > 1);
+}
+
+/// Counts the writes to it.
+struct Writes(usize);
+
+impl std::io::Write for Writes {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += 1;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_ser_pretty() {
+    let server = Server {
+        name: String::from("main"), port: 8080, ratio: 0.5, enabled: true, tags: vec![String::from("a")],
+        fallback: Some(Box::new(Server { name: String::from("backup"), port: 8081, ratio: 1.0, enabled: false, tags: vec![], fallback: None })),
+    };
+    assert_eq!(
+        to_dictionary_string_pretty(&server).unwrap(),
+        "name:    main\nport:    8080\nratio:   0.5\nenabled: true\ntags:    [a]\n\n{fallback}:\nname:     backup;\nport:     8081;\nratio:    1;\nenabled:  false;\ntags:     [];\nfallback: ~",
+    );
+    assert_eq!(from_dictionary_str::<Server>(&to_dictionary_string_pretty(&server).unwrap()).unwrap(), server);
+    assert_eq!(to_string_pretty(&vec![(1, "a\nb")]).unwrap(), "[1 | <# l>\n    a\n    b<#>]");
+    assert_eq!(to_string_pretty(&BTreeMap::from([("k", vec![BTreeMap::from([("x", 1)])])])).unwrap(), "{\n  k: [\n    {\n      x: 1\n    }\n  ]\n}");
+    assert_eq!(to_dictionary_string_pretty(&1), Err(Error::NotDictionary));
+    let points = BTreeMap::from([("points", vec![(0, 0, "origin"), (1, 2, "a b")])]);
+    assert_eq!(to_string_pretty(&points).unwrap(), "{\n  points: [\n    |0|0|origin|\n    |1|2|a b|\n  ]\n}");
+    assert_eq!(from_str::<BTreeMap<String, Vec<(u8, u8, String)>>>(&to_string_pretty(&points).unwrap()).unwrap()["points"][1].2, "a b");
+}
+
+#[test]
+fn test_ser_enums() {
+    assert_eq!(to_string(&Shape::Empty).unwrap(), "<Empty>");
+    assert_eq!(to_string(&Shape::Circle(2.0)).unwrap(), "<Circle>:2");
+    assert_eq!(to_string(&Shape::Rectangle(3, 4)).unwrap(), "<Rectangle>:3:4");
+    assert_eq!(to_string(&Shape::Polygon { sides: 6, regular: true }).unwrap(), "<Polygon>:{sides: 6; regular: true}");
+    assert_eq!(to_string(&vec![Shape::Empty, Shape::Circle(1.0)]).unwrap(), "[<Empty>; <Circle>:1]");
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Circle {
+    radius: f32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+enum Figure {
+    Point,
+    Circle(Circle),
+    Polygon { sides: u8, regular: bool },
+}
+
+#[test]
+fn test_ser_enum_representations() {
+    let internal = SerializerOptions { enums: EnumRepresentation::Internal { tag: "type" }, ..Default::default() };
+    assert_eq!(to_string_with(&Figure::Point, &internal).unwrap(), "{type: Point}");
+    assert_eq!(to_string_with(&Figure::Circle(Circle { radius: 2.0 }), &internal).unwrap(), "{type: Circle; radius: 2}");
+    assert_eq!(to_string_with(&Figure::Polygon { sides: 5, regular: true }, &internal).unwrap(), "{type: Polygon; sides: 5; regular: true}");
+    assert_eq!(to_string_with(&Shape::Circle(1.0), &internal), Err(Error::NotTaggable("Circle")));
+    assert_eq!(to_string_with(&Shape::Rectangle(1, 2), &internal), Err(Error::NotTaggable("Rectangle")));
+    let untagged = SerializerOptions { enums: EnumRepresentation::Untagged, ..Default::default() };
+    assert_eq!(to_string_with(&vec![Shape::Empty, Shape::Circle(1.0), Shape::Rectangle(3, 4)], &untagged).unwrap(), "[Empty; 1; 3 | 4]");
+    assert_eq!(to_string_with(&Shape::Polygon { sides: 6, regular: false }, &untagged).unwrap(), "{sides: 6; regular: false}");
+}
+
+#[test]
+fn test_de_enum_representations() {
+    let internal = DeserializerOptions { enums: EnumRepresentation::Internal { tag: "type" }, ..Default::default() };
+    let figures = vec![Figure::Point, Figure::Circle(Circle { radius: 1.5 }), Figure::Polygon { sides: 3, regular: false }];
+    let document = to_string_with(&figures, &SerializerOptions { enums: internal.enums, ..Default::default() }).unwrap();
+    assert_eq!(from_str_with::<Vec<Figure>>(&document, &internal).unwrap(), figures);
+    assert_eq!(from_str_with::<Figure>("{radius: 1; type: Circle}", &internal).unwrap(), Figure::Circle(Circle { radius: 1.0 }));
+    assert_eq!(from_str_with::<Figure>("{radius: 1}", &internal).unwrap_err().to_string(), "Dictionary must have a variant entry type at 1:1.");
+    assert_eq!(from_str_with::<Figure>("Point", &internal).unwrap_err().to_string(), "Expected dictionary at 1:1.");
+    let untagged = DeserializerOptions { enums: EnumRepresentation::Untagged, ..Default::default() };
+    assert_eq!(from_str_with::<Vec<Figure>>("[Point; Point]", &untagged).unwrap(), vec![Figure::Point, Figure::Point]);
+    assert!(from_str_with::<Figure>("{radius: 1}", &untagged).is_err());
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+struct Drawing {
+    origin: Figure,
+    figures: Vec<Figure>,
+}
+
+#[test]
+fn test_de_tagged_variants() {
+    let figures = vec![Figure::Point, Figure::Polygon { sides: 4, regular: true }, Figure::Circle(Circle { radius: 2.0 })];
+    let drawing = Drawing { origin: Figure::Point, figures: vec![Figure::Circle(Circle { radius: 1.0 }), Figure::Point] };
+    for enums in [EnumRepresentation::External, EnumRepresentation::Internal { tag: "type" }, EnumRepresentation::Untagged] {
+        let options = DeserializerOptions { enums, ..Default::default() };
+        assert_eq!(from_str_with::<Vec<Figure>>("[<Point>; <Polygon>:{sides: 4; regular: true}; <Circle>:{radius: 2}]", &options).unwrap(), figures);
+        assert_eq!(from_str_with::<Drawing>("{origin: <Point>; figures: [<Circle>:{radius: 1}; <Point>]}", &options).unwrap(), drawing);
+        assert_eq!(from_str_with::<Option<Figure>>("<Point>", &options).unwrap(), Some(Figure::Point));
+        assert_eq!(from_str_with::<Vec<Figure>>("[<Point>:{sides: 1}]", &options).unwrap_err().to_string(), "[0]: Expected unit variant at 1:10.");
+    };
+    let internal = DeserializerOptions { enums: EnumRepresentation::Internal { tag: "type" }, ..Default::default() };
+    assert_eq!(from_str_with::<Vec<Figure>>("[<Point>; {type: Circle; radius: 1}]", &internal).unwrap(), vec![Figure::Point, Figure::Circle(Circle { radius: 1.0 })]);
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+struct Extension {
+    name: String,
+    settings: SimpleValue,
+}
+
+#[test]
+fn test_de_simple_value() {
+    for document in ["text", "{a: 1; b: [x; y]; c: <t k:v flag>:{x | ~}; d: ~; e: <>; f: a b{c}}", "<t>", "[a; b|c; [d]]", "~"] {
+        let value = parse_value_str(document).unwrap();
+        assert_eq!(from_str::<SimpleValue>(document).unwrap(), SimpleValue::from(&value));
+        assert_eq!(from_value::<SimpleValue>(&value).unwrap(), SimpleValue::from(&value));
+    };
+    let extension = from_str::<Extension>("{name: lint; settings: {level: <Deny>; rules: [a; b]}}").unwrap();
+    assert_eq!(extension.settings, SimpleValue::from(&parse_value_str("{level: <Deny>; rules: [a; b]}").unwrap()));
+    let enums = DeserializerOptions { enums: EnumRepresentation::Internal { tag: "type" }, ..Default::default() };
+    assert_eq!(from_str_with::<SimpleValue>("<t>:x", &enums).unwrap(), SimpleValue::from(&parse_value_str("<t>:x").unwrap()));
+    // An enum of the same name and variants is read from tags.
+    mod own {
+        #[derive(serde::Deserialize, PartialEq, Debug)]
+        pub enum SimpleValue { Text(String), Tagged(String), Tuple(Vec<String>), Dictionary(String), List(Vec<String>), Compound(String), Nil }
+    }
+    assert_eq!(from_str::<own::SimpleValue>("<Tagged>:a").unwrap(), own::SimpleValue::Tagged("a".to_string()));
+    assert_eq!(from_str::<own::SimpleValue>("Nil").unwrap(), own::SimpleValue::Nil);
+}
+
+#[test]
+fn test_ser_canonical_quoting() {
+    let canonical = SerializerOptions { quoting: Quoting::Canonical, ..Default::default() };
+    let texts = vec!["word", "1.5", "-3", "1e5", "NaN", "a b", "a  b", " a", "", "~", "a:b", "a\nb", "x#", "é"];
+    let document = to_string_with(&texts, &canonical).unwrap();
+    assert_eq!(document, r"[word; \1.5\; \-3\; \1e5\; \NaN\; \a b\; \a  b\; \ a\; \\; \~\; \a:b\; \a`nb\; \x#\; é]");
+    assert_eq!(from_str::<Vec<String>>(&document).unwrap(), texts);
+    assert_eq!(to_string(&texts[..6]).unwrap(), "[word; 1.5; -3; 1e5; NaN; a b]");
+    let map = BTreeMap::from([("port", "8080"), ("name", "web server")]);
+    assert_eq!(to_dictionary_string_with(&map, &canonical).unwrap(), "name: \\web server\\\nport: \\8080\\");
+}
+
+#[test]
+fn test_option_representations() {
+    let values = vec![Some(1), None];
+    let nested = vec![Some(Some(1)), Some(None), None];
+    let options = |optionals| (SerializerOptions { optionals, ..Default::default() }, DeserializerOptions { optionals, ..Default::default() });
+    let (ser, de) = options(OptionRepresentation::Empty);
+    assert_eq!(to_string_with(&values, &ser).unwrap(), "[1; <>]");
+    assert_eq!(from_str_with::<Vec<Option<u8>>>("[1; <>; ~]", &de).unwrap(), vec![Some(1), None, None]);
+    let (ser, de) = options(OptionRepresentation::Tagged);
+    assert_eq!(to_string_with(&values, &ser).unwrap(), "[<?>:1; <?>]");
+    assert_eq!(to_string_with(&nested, &ser).unwrap(), "[<?>:{<?>:1}; <?>:{<?>}; <?>]");
+    assert_eq!(from_str_with::<Vec<Option<Option<u8>>>>(&to_string_with(&nested, &ser).unwrap(), &de).unwrap(), nested);
+    assert_eq!(from_str_with::<Option<u8>>("1", &de).unwrap_err().to_string(), "Expected optional tag at 1:1.");
+    let (_, de) = options(OptionRepresentation::Nil);
+    assert_eq!(from_str_with::<Vec<Option<()>>>("[<>; ~]", &de).unwrap(), vec![Some(()), None]);
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Blob {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    digest: serde_bytes::ByteBuf,
+}
+
+#[test]
+fn test_unit_and_newtype_representations() {
+    let options = |units, newtypes| SerializerOptions { units, newtypes, ..Default::default() };
+    let value = (Unit, (), Meters(1.5));
+    for (units, newtypes, expected) in [
+        (UnitRepresentation::Empty, NewtypeRepresentation::Transparent, "<> | <> | 1.5"),
+        (UnitRepresentation::Nil, NewtypeRepresentation::Transparent, "~ | ~ | 1.5"),
+        (UnitRepresentation::Name, NewtypeRepresentation::Tagged, "Unit | <> | <Meters>:1.5"),
+    ] {
+        let khi = to_string_with(&value, &options(units, newtypes)).unwrap();
+        assert_eq!(khi, expected);
+        assert_eq!(from_str::<(Unit, (), Meters)>(&khi).unwrap(), value);
+    };
+    assert_eq!(from_str::<Unit>("<Unit>").unwrap(), Unit);
+    assert_eq!(from_str::<Unit>("Other").unwrap_err().to_string(), "Expected unit at 1:1.");
+    assert_eq!(from_str::<Meters>("<Other>:1").unwrap_err().to_string(), "Expected text at 1:1.");
+    assert_eq!(to_string_with(&Shape::Empty, &options(UnitRepresentation::Nil, NewtypeRepresentation::Tagged)).unwrap(), "<Empty>");
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Fruit {
+    name: String,
+    amount: u32,
+}
+
+#[test]
+fn test_struct_representations() {
+    let fruits = vec![Fruit { name: String::from("Apple"), amount: 30 }, Fruit { name: String::from("Mango"), amount: 20 }];
+    let tagged = SerializerOptions { structs: StructRepresentation::Tagged, ..Default::default() };
+    let khi = to_string_with(&fruits, &tagged).unwrap();
+    assert_eq!(khi, "[<Fruit>:{name: Apple; amount: 30}; <Fruit>:{name: Mango; amount: 20}]");
+    assert_eq!(from_str::<Vec<Fruit>>(&khi).unwrap(), fruits);
+    assert_eq!(from_str::<Vec<Fruit>>(&to_string(&fruits).unwrap()).unwrap(), fruits);
+    assert_eq!(to_string_with(&BTreeMap::from([("a", 1)]), &tagged).unwrap(), "{a: 1}");
+    assert_eq!(from_str::<Fruit>("<Vegetable>:{name: Leek; amount: 1}").unwrap_err().to_string(), "Expected dictionary at 1:1.");
+    let deny = DeserializerOptions { unknown_fields: UnknownFields::Deny, ..Default::default() };
+    assert_eq!(from_str_with::<Fruit>("<Fruit>:{name: Leek; amount: 1; x: 2}", &deny).unwrap_err().to_string(), "x: unknown field `x`, expected `name` or `amount` at 1:36.");
+}
+
+#[test]
+fn test_sequence_styles() {
+    let rows = vec![(1, "a"), (2, "b c")];
+    let tabular = SerializerOptions { sequences: SequenceStyle::Tabular, ..Default::default() };
+    let khi = to_string_with(&rows, &tabular).unwrap();
+    assert_eq!(khi, "[|1|a| |2|b c|]");
+    assert_eq!(from_str::<Vec<(u8, String)>>(&khi).unwrap(), vec![(1, String::from("a")), (2, String::from("b c"))]);
+    assert_eq!(to_string_with(&vec![vec![(1, 2)], vec![(3, 4), (5, 6)]], &tabular).unwrap(), "[[1 | 2]; [|3|4| |5|6|]]");
+    assert_eq!(to_string_with(&vec![(1, 2), (3, 4)], &SerializerOptions::default()).unwrap(), "[1 | 2; 3 | 4]");
+    let aligned = SerializerOptions { sequences: SequenceStyle::Aligned, ..Default::default() };
+    let khi = to_string_with(&vec![1, 2], &aligned).unwrap();
+    assert_eq!(khi, "[> 1 > 2]");
+    assert_eq!(from_str::<Vec<u8>>(&khi).unwrap(), vec![1, 2]);
+    assert_eq!(to_string_with(&Vec::<u8>::new(), &aligned).unwrap(), "[]");
+}
+
+#[test]
+fn test_bool_words() {
+    let lenient = DeserializerOptions { bools: BoolFormat::LENIENT, ..Default::default() };
+    assert_eq!(from_str_with::<Vec<bool>>("[yes; off; 1; False; true]", &lenient).unwrap(), vec![true, false, true, false, true]);
+    assert_eq!(from_str_with::<BTreeMap<bool, u8>>("{on: 1; no: 0}", &lenient).unwrap(), BTreeMap::from([(true, 1), (false, 0)]));
+    assert_eq!(from_str::<Vec<bool>>("[true; yes]").unwrap_err().to_string(), "[1]: Invalid bool ⟨yes⟩ at 1:8.");
+    let words = DeserializerOptions { bools: BoolFormat { yes_no: true, ..BoolFormat::STRICT }, ..Default::default() };
+    assert_eq!(from_str_with::<Vec<bool>>("[yes; no]", &words).unwrap(), vec![true, false]);
+    assert!(from_str_with::<bool>("Yes", &words).is_err());
+    assert_eq!(to_string(&vec![true, false]).unwrap(), "[true; false]");
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Link {
+    id: String,
+    class: Option<String>,
+    hidden: Option<()>,
+    weight: u8,
+    href: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+enum Node {
+    Link { id: u32, text: String },
+    Group { children: Vec<Node> },
+}
+
+#[test]
+fn test_attribute_fields() {
+    let link = Link { id: String::from("home"), class: None, hidden: Some(()), weight: 2, href: String::from("index") };
+    let options = SerializerOptions { structs: StructRepresentation::Tagged, attributes: &["id", "class", "hidden", "weight"], ..Default::default() };
+    let khi = to_string_with(&link, &options).unwrap();
+    assert_eq!(khi, "<Link id:home hidden weight:2>:{href: index}");
+    assert_eq!(from_str::<Link>(&khi).unwrap(), link);
+    assert_eq!(to_string_with(&link, &SerializerOptions { attributes: options.attributes, ..Default::default() }).unwrap(), "{id: home; class: ~; hidden: <>; weight: 2; href: index}");
+    let node = Node::Link { id: 7, text: String::from("Start") };
+    assert_eq!(to_string_with(&node, &options).unwrap(), "<Link id:7>:{text: Start}");
+    assert_eq!(from_str::<Node>("<Link id:7>:{text: Start}").unwrap(), node);
+    assert_eq!(to_string_with(&Node::Group { children: vec![node] }, &SerializerOptions { attributes: &["children"], ..Default::default() }), Err(Error::NonTextAttribute("children")));
+    assert_eq!(from_str::<Link>("<Link id:a weight:x>:{href: b}").unwrap_err().to_string(), "weight: Invalid u8 ⟨x⟩ at 1:1.");
+    let deny = DeserializerOptions { unknown_fields: UnknownFields::Deny, ..Default::default() };
+    assert_eq!(from_str_with::<Node>("<Link id:1 rel:x>:{text: a}", &deny).unwrap_err().to_string(), "rel: unknown field `rel`, expected `id` or `text` at 1:1.");
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Card { class: Vec<String>, text: String }
+    let card = Card { class: vec![String::from("a"), String::from("b c")], text: String::from("x") };
+    let options = SerializerOptions { structs: StructRepresentation::Tagged, attributes: &["class"], ..Default::default() };
+    let khi = to_string_with(&card, &options).unwrap();
+    assert_eq!(khi, "<Card class:[a; \\b c\\]>:{text: x}");
+    assert_eq!(from_str::<Card>(&khi).unwrap(), card);
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Record {
+    #[serde(with = "khi::serde_helpers::hex")]
+    key: Vec<u8>,
+    #[serde(with = "khi::serde_helpers::base64")]
+    data: Vec<u8>,
+    #[serde(with = "khi::serde_helpers::rfc3339")]
+    created: std::time::SystemTime,
+    #[serde(with = "khi::serde_helpers::duration")]
+    timeout: std::time::Duration,
+    #[serde(with = "khi::serde_helpers::comma_decimal")]
+    price: f64,
+}
+
+#[test]
+fn test_serde_helpers() {
+    use std::time::{Duration, UNIX_EPOCH};
+    let record = Record {
+        key: vec![0, 255], data: vec![0, 1], created: UNIX_EPOCH + Duration::new(1714564800, 250_000_000),
+        timeout: Duration::from_millis(330_500), price: 3.5,
+    };
+    let khi = to_string(&record).unwrap();
+    assert_eq!(khi, "{key: 00ff; data: AAE=; created: \\2024-05-01T12:00:00.25Z\\; timeout: 5m 30s 500ms; price: 3,5}");
+    assert_eq!(from_str::<Record>(&khi).unwrap(), record);
+    let parse = |created: &str| from_str::<Record>(&format!("{{key: 00; data: AA==; created: \\{}\\; timeout: 0s; price: 1}}", created)).map(|r| r.created);
+    assert_eq!(parse("2024-05-01T14:00:00+02:00").unwrap(), UNIX_EPOCH + Duration::from_secs(1714564800));
+    assert_eq!(parse("1969-12-31 23:59:59.5z").unwrap(), UNIX_EPOCH - Duration::from_millis(500));
+    assert_eq!(parse("2000-02-29T00:00:00Z").unwrap(), UNIX_EPOCH + Duration::from_secs(951782400));
+    for invalid in ["2023-02-29T00:00:00Z", "2024-05-01T12:00:00", "2024-05-01T24:00:00Z", "2024-05-01T12:00:00.Z", "2024-5-01T12:00:00Z"] {
+        assert!(parse(invalid).is_err(), "Parsed {}.", invalid);
+    };
+    let error = from_str::<Record>("{key: 0g; data: AA==; created: \\2024-05-01T12:00:00Z\\; timeout: 0s; price: 1}").unwrap_err();
+    assert_eq!(error.to_string(), "key: invalid value: string \"0g\", expected hexadecimal bytes at 1:7.");
+    let timeout = |timeout: &str| from_str::<Record>(&format!("{{key: 00; data: AA==; created: \\1970-01-01T00:00:00Z\\; timeout: {}; price: 1}}", timeout)).map(|r| r.timeout);
+    assert_eq!(timeout("1d 2h 1h 7ns").unwrap(), Duration::new(97200, 7));
+    assert_eq!(timeout("PT5M30S").unwrap(), Duration::from_secs(330));
+    assert!(timeout("-5m").is_err());
+    assert!(timeout("5").is_err());
+    assert!(timeout("5x").is_err());
+    assert!(timeout("5m30s").is_err());
+    let price = |price: &str| from_str::<Record>(&format!("{{key: 00; data: AA==; created: \\1970-01-01T00:00:00Z\\; timeout: 0s; price: {}}}", price)).map(|r| r.price);
+    assert_eq!(price("-2,25").unwrap(), -2.25);
+    assert_eq!(price("2.25").unwrap(), 2.25);
+    assert!(price("1.000,5").is_err());
+}
+
+#[test]
+fn test_byte_representations() {
+    let blob = Blob { data: vec![0, 15, 255], digest: serde_bytes::ByteBuf::from(vec![104, 105]) };
+    let options = |bytes| (SerializerOptions { bytes, ..Default::default() }, DeserializerOptions { bytes, ..Default::default() });
+    for (bytes, document) in [
+        (ByteRepresentation::Hex, "{data: 000fff; digest: 6869}"),
+        (ByteRepresentation::Base64, "{data: AA//; digest: aGk=}"),
+        (ByteRepresentation::List, "{data: [0; 15; 255]; digest: [104; 105]}"),
+    ] {
+        let (ser, de) = options(bytes);
+        assert_eq!(to_string_with(&blob, &ser).unwrap(), document);
+        assert_eq!(from_str_with::<Blob>(document, &de).unwrap(), blob);
+    };
+    let (_, de) = options(ByteRepresentation::Base64);
+    assert_eq!(from_str_with::<Blob>("{data: A; digest: aGk=}", &de).unwrap_err().to_string(), "data: Invalid bytes ⟨A⟩ at 1:8.");
+    let (_, de) = options(ByteRepresentation::List);
+    assert_eq!(from_str_with::<Blob>("{data: [1; 256]; digest: []}", &de).unwrap_err().to_string(), "data[1]: Invalid u8 ⟨256⟩ at 1:12.");
+    assert_eq!(from_str_with::<Blob>("{data: 00; digest: []}", &de).unwrap_err().to_string(), "data: Expected list at 1:8.");
+}
+
+#[test]
+fn test_ser_collections() {
+    assert_eq!(to_string(&(1, "a", true)).unwrap(), "1 | a | true");
+    assert_eq!(to_string(&Pair(-1, "x y")).unwrap(), "-1 | x y");
+    assert_eq!(to_string(&vec![(1, 2), (3, 4)]).unwrap(), "[1 | 2; 3 | 4]");
+    assert_eq!(to_string(&vec![vec![1], vec![]]).unwrap(), "[[1]; []]");
+    let mut map = BTreeMap::new();
+    map.insert("b key", vec![1]);
+    map.insert("a", vec![]);
+    assert_eq!(to_string(&map).unwrap(), "{a => [] | \\b key\\ => [1]}");
+    let mut map = BTreeMap::new();
+    map.insert(2, 'x');
+    map.insert(10, 'y');
+    assert_eq!(to_string(&map).unwrap(), "{2: x; 10: y}");
+    let mut map = BTreeMap::new();
+    map.insert((1, 2), 'x');
+    assert_eq!(to_string(&map), Err(Error::NonTextKey));
+}
+
+struct Entries(Vec<(&'static str, u8)>);
+
+impl Serialize for Entries {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, v)))
+    }
+}
+
+#[test]
+fn test_ser_entry_order() {
+    let entries = Entries(vec![("z", 1), ("b", 2), ("a", 3)]);
+    assert_eq!(to_string(&entries).unwrap(), "{z: 1; b: 2; a: 3}");
+    let value = to_value(&entries).unwrap();
+    assert_eq!(khi::fmt::format_value(&value), "{z: 1; b: 2; a: 3}");
+    let entries = Entries(vec![("a", 1), ("b", 2), ("a", 3)]);
+    assert_eq!(to_string(&entries), Err(Error::DuplicateKey(String::from("a"))));
+}
+
+#[test]
+fn test_mapped_keys() {
+    let map = BTreeMap::from([(String::from("a|b"), 2), (String::from("c d"), 3), (String::from("e"), 4)]);
+    let khi = to_string(&map).unwrap();
+    assert_eq!(khi, "{\\a|b\\ => 2 | \\c d\\ => 3 | e => 4}");
+    assert_eq!(from_str::<BTreeMap<String, u32>>(&khi).unwrap(), map);
+    let map = BTreeMap::from([(1, String::from("x y")), (10, String::from("z"))]);
+    assert_eq!(from_str::<BTreeMap<u32, String>>("1 => {x y} | 10 => z").unwrap(), map);
+    assert_eq!(from_str::<BTreeMap<u32, String>>(&to_string(&map).unwrap()).unwrap(), map);
+    assert_eq!(from_str::<BTreeMap<u8, char>>("1 => x | y => z").unwrap_err().to_string(), "y: Invalid u8 ⟨y⟩ at 1:15.");
+    assert_eq!(to_string(&BTreeMap::from([(vec![1, 2], 'x')])), Err(Error::NonTextKey));
+}
+
+#[test]
+fn test_ser_round_trip() {
+    let mut map = BTreeMap::new();
+    map.insert("key:with:colons", Shape::Polygon { sides: 3, regular: false });
+    map.insert("", Shape::Rectangle(0, 0));
+    let values = [
+        to_value(&map).unwrap(),
+        to_value(&(Some("a"), None::<u8>, ())).unwrap(),
+        to_value(&vec![Some(vec!["x", "y z"]), None]).unwrap(),
+        to_value(&((1, 2), (3,))).unwrap(),
+        to_value(&vec![Shape::Circle(0.5), Shape::Empty, Shape::Rectangle(1, 2)]).unwrap(),
+        to_value(&Shape::Circle(f32::NAN)).unwrap(),
+    ];
+    for value in values {
+        let khi = khi::fmt::format_value(&value);
+        let parsed = parse_value_str(&khi).unwrap_or_else(|_| panic!("Serialized as unparsable {:?}.", khi));
+        assert!(parsed.canonical_eq(&value), "Serialized as {:?}, which parses differently.", khi);
+        assert_eq!(SimpleValue::from(&parsed), SimpleValue::from(&value));
+    };
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Config {
+    title: String,
+    shapes: Vec<Shape>,
+    limits: BTreeMap<u32, Option<f32>>,
+    origin: (i64, i64),
+    unit: Unit,
+    length: Meters,
+    initial: char,
+    data: ByteBuf,
+}
+
+#[derive(PartialEq, Debug)]
+struct ByteBuf(Vec<u8>);
+
+impl Serialize for ByteBuf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+        impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+            type Value = ByteBuf;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "bytes")
+            }
+            fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(bytes))
+            }
+        }
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+#[test]
+fn test_de_documents() {
+    let source = "title: Shapes\nshapes: [<Empty>; <Circle>:1.5; <Rectangle>:2:3; <Polygon>:{sides: 5; regular: true}]\nlimits: {1: 0.5; 10: ~}\norigin: -1 | +2\nunit: <>\nlength: 1e3\ninitial: x\ndata: 00ff";
+    let config: Config = from_dictionary_str(source).unwrap();
+    assert_eq!(config.title, "Shapes");
+    assert_eq!(config.shapes, vec![Shape::Empty, Shape::Circle(1.5), Shape::Rectangle(2, 3), Shape::Polygon { sides: 5, regular: true }]);
+    assert_eq!(config.limits.get(&1), Some(&Some(0.5)));
+    assert_eq!(config.limits.get(&10), Some(&None));
+    assert_eq!(config.origin, (-1, 2));
+    assert_eq!(config.length, Meters(1000.0));
+    assert_eq!(config.data, ByteBuf(vec![0, 255]));
+    assert_eq!(from_str::<Shape>("Empty").unwrap(), Shape::Empty);
+    assert_eq!(from_list_str::<Vec<(String, bool)>>("a | true; b c | false").unwrap(), vec![(String::from("a"), true), (String::from("b c"), false)]);
+    assert_eq!(from_str::<Vec<u8>>("[]").unwrap(), Vec::<u8>::new());
+    assert_eq!(from_str::<()>("").unwrap(), ());
+    assert_eq!(from_str::<Option<u8>>("~").unwrap(), None);
+    assert_eq!(from_str::<String>("\\\\").unwrap(), "");
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+struct Host<'a> {
+    name: &'a str,
+    port: u16,
+}
+
+/// Interns names as symbols.
+struct Interner(Vec<String>);
+
+/// Seed deserializing a list of names as symbols.
+struct Symbols<'a>(&'a mut Interner);
+
+impl<'de> serde::de::DeserializeSeed<'de> for Symbols<'_> {
+    type Value = Vec<usize>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let names = Vec::<&str>::deserialize(deserializer)?;
+        let interner = self.0;
+        Ok(names.into_iter().map(|name| match interner.0.iter().position(|n| n == name) {
+            Some(symbol) => symbol,
+            None => {
+                interner.0.push(name.to_string());
+                interner.0.len() - 1
+            }
+        }).collect())
+    }
+}
+
+#[test]
+fn test_wide_and_nonzero_integers() {
+    use std::num::{NonZeroI128, NonZeroU16, NonZeroU128};
+    for value in [i128::MIN, -1, 0, i128::MAX] {
+        assert_eq!(from_str::<i128>(&to_string(&value).unwrap()).unwrap(), value);
+    };
+    for value in [0, u64::MAX as u128 + 1, u128::MAX] {
+        assert_eq!(from_str::<u128>(&to_string(&value).unwrap()).unwrap(), value);
+    };
+    assert_eq!(from_str::<BTreeMap<u128, i128>>("{340282366920938463463374607431768211455: -1}").unwrap()[&u128::MAX], -1);
+    assert_eq!(from_str::<i128>("170141183460469231731687303715884105728").unwrap_err().to_string(), "Invalid i128 ⟨170141183460469231731687303715884105728⟩ at 1:1.");
+    let value = NonZeroU128::new(u128::MAX).unwrap();
+    assert_eq!(to_string(&value).unwrap(), u128::MAX.to_string());
+    assert_eq!(from_str::<NonZeroU128>(&to_string(&value).unwrap()).unwrap(), value);
+    assert_eq!(from_str::<NonZeroI128>("-5").unwrap(), NonZeroI128::new(-5).unwrap());
+    assert_eq!(from_str::<Vec<NonZeroU16>>("[1; 2]").unwrap(), vec![NonZeroU16::new(1).unwrap(), NonZeroU16::new(2).unwrap()]);
+    assert_eq!(from_str::<Vec<NonZeroU16>>("[1; 0]").unwrap_err().to_string(), "[1]: invalid value: integer `0`, expected a nonzero u16 at 1:5.");
+}
+
+#[test]
+fn test_de_seed() {
+    let mut interner = Interner(vec![]);
+    let value = parse_value_str("[a; b; a; c]").unwrap();
+    assert_eq!(from_value_seed(Symbols(&mut interner), &value).unwrap(), vec![0, 1, 0, 2]);
+    let value = parse_value_str("[c; d]").unwrap();
+    assert_eq!(from_value_seed(Symbols(&mut interner), &value).unwrap(), vec![2, 3]);
+    assert_eq!(interner.0, vec!["a", "b", "c", "d"]);
+    let value = parse_value_str("{}").unwrap();
+    assert!(from_value_seed(Symbols(&mut interner), &value).is_err());
+}
+
+#[test]
+fn test_de_root_documents() {
+    let hosts: Vec<Server> = from_list_str("{name: a; port: 1; ratio: 0; enabled: true; tags: []; fallback: ~};\n{name: b; port: 2; ratio: 1; enabled: false; tags: [x]; fallback: ~}").unwrap();
+    assert_eq!(hosts.iter().map(|h| (h.name.as_str(), h.port)).collect::<Vec<_>>(), [("a", 1), ("b", 2)]);
+    assert_eq!(from_list_str::<Vec<Shape>>("<Empty>; <Circle>:1").unwrap(), vec![Shape::Empty, Shape::Circle(1.0)]);
+    assert_eq!(from_list_str::<Vec<u8>>("").unwrap(), Vec::<u8>::new());
+    assert_eq!(from_dictionary_str::<BTreeMap<String, u8>>("").unwrap(), BTreeMap::new());
+    assert_eq!(from_dictionary_str::<BTreeMap<String, u8>>("a: 1\n{s}: b: 2").unwrap_err().to_string(), "s: Expected text at 2:1.");
+    let dictionary = parse_dictionary_str("name: local host\nport: 80").unwrap();
+    let host: Host = from_dictionary(&dictionary).unwrap();
+    assert_eq!(host, Host { name: "local host", port: 80 });
+    let list = parse_list_str("{name: a; port: 1}; {name: b; port: 2}").unwrap();
+    let hosts: Vec<Host> = from_list(&list).unwrap();
+    assert_eq!(hosts, [Host { name: "a", port: 1 }, Host { name: "b", port: 2 }]);
+    assert_eq!(from_list::<Vec<u16>>(&parse_list_str("1; x").unwrap()).unwrap_err().to_string(), "[1]: Invalid u16 ⟨x⟩ at 1:4.");
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+struct Service {
+    #[serde(alias = "title")]
+    name: String,
+    #[serde(default = "default_replicas")]
+    replicas: u8,
+    #[serde(default)]
+    hosts: Vec<String>,
+    #[serde(flatten)]
+    owner: Owner,
+    #[serde(flatten)]
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+struct Owner {
+    team: String,
+    #[serde(rename = "contact-email")]
+    email: Option<String>,
+}
+
+fn default_replicas() -> u8 {
+    1
+}
+
+#[test]
+fn test_de_attributes() {
+    let service: Service = from_dictionary_str("title: api\nteam: core\ncontact-email: a@b.c\nregion: eu\ntier: gold").unwrap();
+    assert_eq!(service.name, "api");
+    assert_eq!((service.replicas, service.hosts.len()), (1, 0));
+    assert_eq!(service.owner, Owner { team: String::from("core"), email: Some(String::from("a@b.c")) });
+    assert_eq!(service.labels, BTreeMap::from([(String::from("region"), String::from("eu")), (String::from("tier"), String::from("gold"))]));
+    let service: Service = from_dictionary_str("name: api\nreplicas: 3\nhosts: [a; b]\nteam: core").unwrap();
+    assert_eq!((service.name.as_str(), service.replicas, service.hosts.len(), service.owner.email, service.labels.len()), ("api", 3, 2, None, 0));
+    assert_eq!(from_dictionary_str::<Service>("name: api\nreplicas: x\nteam: core").unwrap_err().to_string(), "replicas: Invalid u8 ⟨x⟩ at 2:11.");
+    assert_eq!(from_dictionary_str::<Service>("name: api").unwrap_err().to_string(), "missing field `team` at 1:1.");
+    assert_eq!(from_dictionary_str::<Service>("name: api\nteam: core\nregion: {x: 1}").unwrap_err().to_string(), "invalid type: map, expected a string at 1:1.");
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+struct Strict {
+    #[serde(alias = "n")]
+    name: String,
+}
+
+#[test]
+fn test_de_unknown_fields() {
+    assert_eq!(from_str::<Strict>("{n: a}").unwrap(), Strict { name: String::from("a") });
+    assert_eq!(from_str::<Strict>("{name: a; extra: 1}").unwrap_err().to_string(), "extra: unknown field `extra`, expected `n` or `name` at 1:18.");
+    let source = "title: Shapes\nshapes: [<Polygon>:{sides: 3; regular: true; color: red}]\nlimits: {}\norigin: 0 | 0\nunit: <>\nlength: 1\ninitial: x\ndata: 00\nextra: [ignored; {x: <t>:1}]";
+    let config: Config = from_dictionary_str(source).unwrap();
+    assert_eq!(config.shapes, vec![Shape::Polygon { sides: 3, regular: true }]);
+    let deny = DeserializerOptions { unknown_fields: UnknownFields::Deny, ..Default::default() };
+    let error = from_dictionary_str_with::<Config>(source, &deny).unwrap_err();
+    assert!(error.to_string().starts_with("extra: unknown field `extra`, expected one of `title`, `shapes`"));
+    let error = from_dictionary_str_with::<Config>(source.split("\nextra").next().unwrap(), &deny).unwrap_err();
+    assert_eq!(error.to_string(), "shapes[0]:color: unknown field `color`, expected `sides` or `regular` at 2:53.");
+    let internal = DeserializerOptions { enums: EnumRepresentation::Internal { tag: "type" }, unknown_fields: UnknownFields::Deny, ..Default::default() };
+    assert_eq!(from_str_with::<Figure>("{type: Polygon; sides: 3; regular: true}", &internal).unwrap(), Figure::Polygon { sides: 3, regular: true });
+    assert_eq!(from_str_with::<Figure>("{type: Polygon; sides: 3; regular: true; x: 1}", &internal).unwrap_err().to_string(), "x: unknown field `x`, expected `sides` or `regular` at 1:45.");
+    assert_eq!(from_str::<BTreeMap<String, serde::de::IgnoredAny>>("{a: [1; {b: ~}]; c: <t>:x}").unwrap().len(), 2);
+}
+
+#[test]
+fn test_de_errors() {
+    let error = |source: &str| from_dictionary_str::<Server>(source).unwrap_err().to_string();
+    assert_eq!(error("name: a\nport: x\nratio: 1\nenabled: true\ntags: []\nfallback: ~"), "port: Invalid u16 ⟨x⟩ at 2:7.");
+    assert_eq!(error("name: a\nport: 1\nratio: 1\nenabled: yes\ntags: []\nfallback: ~"), "enabled: Invalid bool ⟨yes⟩ at 4:10.");
+    assert_eq!(error("name: a\nport: 1\nratio: 1\nenabled: true\ntags: x\nfallback: ~"), "tags: Expected list at 5:7.");
+    assert_eq!(error("name: a\nport: 1\nratio: 1\nenabled: true\ntags: []\nfallback: {name: b}"), "fallback: missing field `port` at 6:11.");
+    assert_eq!(from_str::<Shape>("<Hexagon>").unwrap_err().to_string(), "unknown variant `Hexagon`, expected one of `Empty`, `Circle`, `Rectangle`, `Polygon` at 1:1.");
+    let error = from_dictionary_str::<BTreeMap<String, Vec<Server>>>("servers: [{name: a; port: 1; ratio: 0; enabled: true; tags: [x; {}]; fallback: ~}]").unwrap_err();
+    assert_eq!(error.to_string(), "servers[0]:tags[1]: Expected text at 1:65.");
+    assert_eq!(error.path().map(|path| path.0.clone()), Some(vec![Segment::Key(String::from("servers")), Segment::Index(0), Segment::Key(String::from("tags")), Segment::Index(1)]));
+    assert_eq!(error.at().map(|at| (at.line, at.column)), Some((1, 65)));
+    assert_eq!(from_str::<BTreeMap<u8, u8>>("{x: 1}").unwrap_err().to_string(), "x: Invalid u8 ⟨x⟩ at 1:5.");
+    let error = from_str::<u8>("{").unwrap_err();
+    assert!(matches!(error, khi::de::Error::Parse(..)) && error.at().is_none());
+    let error = from_str::<u8>("300").unwrap_err();
+    assert_eq!(error.at().map(|at| (at.line, at.column)), Some((1, 1)));
+}
+
+#[test]
+fn test_de_round_trip() {
+    let server = Server {
+        name: String::from("a  b"), port: 1, ratio: -0.5, enabled: false, tags: vec![String::from(""), String::from("x:y")],
+        fallback: Some(Box::new(Server { name: String::from("`"), port: 2, ratio: 1e-10, enabled: true, tags: vec![], fallback: None })),
+    };
+    assert_eq!(from_str::<Server>(&to_string(&server).unwrap()).unwrap(), server);
+    assert_eq!(from_dictionary_str::<Server>(&to_dictionary_string(&server).unwrap()).unwrap(), server);
+    let mut limits = BTreeMap::new();
+    limits.insert(3, Some(f32::INFINITY));
+    limits.insert(4, None);
+    let config = Config {
+        title: String::from("line\nbreak"), shapes: vec![Shape::Empty, Shape::Circle(0.25), Shape::Rectangle(1, 2), Shape::Polygon { sides: 3, regular: false }],
+        limits, origin: (i64::MIN, i64::MAX), unit: Unit, length: Meters(2.5), initial: '{', data: ByteBuf(vec![1, 2, 254]),
+    };
+    assert_eq!(from_str::<Config>(&to_string(&config).unwrap()).unwrap(), config);
+    let value = to_value(&config).unwrap();
+    assert_eq!(from_value::<Config>(&value).unwrap(), config);
+    let nested: Vec<Option<(u8, Vec<Shape>)>> = vec![Some((1, vec![Shape::Circle(2.0)])), None, Some((0, vec![]))];
+    assert_eq!(from_str::<Vec<Option<(u8, Vec<Shape>)>>>(&to_string(&nested).unwrap()).unwrap(), nested);
+}
+
+#[derive(Deserialize)]
+struct Borrowed<'a> {
+    name: &'a str,
+    #[serde(borrow)]
+    label: std::borrow::Cow<'a, str>,
+    words: Vec<&'a str>,
+    entries: BTreeMap<&'a str, &'a str>,
+}
+
+#[test]
+fn test_de_borrowed() {
+    let value = parse_dictionary_str("name: a b\nlabel: \\x`ny\\\nwords: [c; d]\nentries: {k: v}").unwrap();
+    let at = Position { index: 0, line: 1, column: 1 };
+    let value = ParsedValue::Dictionary(value, at, at);
+    let borrowed: Borrowed = from_value(&value).unwrap();
+    let text = |key: &str| value.as_dictionary().unwrap().get(key).unwrap().as_text().unwrap().as_str();
+    assert_eq!(borrowed.name, "a b");
+    assert!(std::ptr::eq(borrowed.name, text("name")));
+    assert!(matches!(borrowed.label, std::borrow::Cow::Borrowed("x\ny")));
+    assert_eq!(borrowed.words, ["c", "d"]);
+    assert_eq!(borrowed.entries.get("k"), Some(&"v"));
+    assert!(from_str::<Vec<String>>("[a; b]").is_ok());
+}
+
+#[cfg(feature = "decimal")]
+#[test]
+fn test_decimal_serde() {
+    use rust_decimal::Decimal;
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Invoice {
+        total: Decimal,
+        tax: Decimal,
+    }
+    let invoice = Invoice { total: Decimal::new(10010, 2), tax: Decimal::new(-5, 1) };
+    let khi = to_string(&invoice).unwrap();
+    assert_eq!(khi, "{total: 100.10; tax: -0.5}");
+    assert_eq!(from_str::<Invoice>(&khi).unwrap(), invoice);
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn test_uuid_serde() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Account {
+        id: uuid::Uuid,
+        owners: Vec<uuid::Uuid>,
+    }
+    let account = Account { id: uuid::Uuid::from_u128(1), owners: vec![uuid::Uuid::nil()] };
+    let khi = to_string(&account).unwrap();
+    assert_eq!(khi, "{id: 00000000-0000-0000-0000-000000000001; owners: [00000000-0000-0000-0000-000000000000]}");
+    assert_eq!(from_str::<Account>(&khi).unwrap(), account);
+}